@@ -0,0 +1,64 @@
+#ifndef AUDIO_VISUALISER_H
+#define AUDIO_VISUALISER_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Turns mono samples into smoothed bar heights and a chromagram, the same way the bars mode
+// does
+//
+// Opaque to C, created with `vis_create` and freed with `vis_destroy`
+typedef struct VisAnalyser VisAnalyser;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates an analyser for `sample_rate`Hz audio producing `num_bars` bars, or returns null
+// if `fft_size` isn't a power of two or `num_bars` is 0
+VisAnalyser *vis_create(uint32_t sample_rate, uint32_t fft_size, uint32_t num_bars);
+
+// Frees an analyser made by `vis_create`
+//
+// # Safety
+//
+// `vis` must be null or a pointer returned by `vis_create` that hasn't been destroyed yet
+void vis_destroy(VisAnalyser *vis);
+
+// Adds `len` mono samples, updating the bars and chromagram once at least `fft_size`
+// samples have been fed in total
+//
+// # Safety
+//
+// `vis` must be a live pointer from `vis_create` and `samples` must point to `len` floats.
+// Either being null does nothing
+void vis_feed_samples(VisAnalyser *vis, const float *samples, size_t len);
+
+// Copies up to `len` bar heights, from 0.0 to 1.0, into `bars` and returns how many were
+// copied
+//
+// # Safety
+//
+// `vis` must be a live pointer from `vis_create` and `bars` must have room for `len` floats.
+// Either being null copies nothing
+size_t vis_get_bars(const VisAnalyser *vis, float *bars, size_t len);
+
+// Copies the chromagram of the latest window, the energy in each pitch class from C to B,
+// into `chromagram` and returns 12
+//
+// # Safety
+//
+// `vis` must be a live pointer from `vis_create` and `chromagram` must have room for 12
+// floats. Either being null copies nothing and returns 0
+size_t vis_get_chromagram(const VisAnalyser *vis, float *chromagram);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AUDIO_VISUALISER_H */
@@ -0,0 +1,182 @@
+//! C API over the bar and chromagram pipeline, for applications not written in Rust
+//!
+//! The header is generated into `include/audio_visualiser.h` by running
+//! `cbindgen --config cbindgen.toml --output include/audio_visualiser.h` in this directory,
+//! and the library built as `libaudio_visualiser.so`/`.a` by `cargo build --release`. From
+//! Python, load the shared library with `ctypes.CDLL`.
+//!
+//! ```c
+//! VisAnalyser *vis = vis_create(44100, 2048, 24);
+//! float bars[24];
+//!
+//! while (read_audio(samples, count)) {
+//!     vis_feed_samples(vis, samples, count);
+//!     vis_get_bars(vis, bars, 24);
+//! }
+//! vis_destroy(vis);
+//! ```
+
+use std::{collections::VecDeque, ptr, slice};
+
+use rust_audio_visualiser::{
+    chroma::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram},
+    gain::AutoGain,
+    grouping::GroupingStrategy,
+    pipeline::{Frame, Pipeline},
+    smoothing::SmoothingStrategy,
+    spectra::FourierTransform,
+};
+
+/// Turns mono samples into smoothed bar heights and a chromagram, the same way the bars mode
+/// does
+///
+/// Opaque to C, created with `vis_create` and freed with `vis_destroy`
+pub struct VisAnalyser {
+    pipeline: Pipeline,
+    fft_size: usize,
+    samples: VecDeque<f32>,
+    // Bar heights from 0.0 to 1.0, for handing out
+    levels: Vec<f32>,
+    chromagram: [f32; 12],
+    sample_rate: usize,
+}
+
+impl VisAnalyser {
+    fn new(sample_rate: usize, fft_size: usize, num_bars: usize) -> Self {
+        Self {
+            pipeline: Pipeline::from_samples(
+                FourierTransform::new(fft_size),
+                sample_rate,
+                GroupingStrategy::LogMax {
+                    num_groups: num_bars,
+                },
+                SmoothingStrategy::RiseFall {
+                    rise: 0.5,
+                    fall: 0.9,
+                },
+                None,
+                // No attack or release follows every peak, scaling the tallest bar to full
+                // height each window as the bars mode does by default
+                AutoGain::new(0.0, 0.0, sample_rate / fft_size),
+            ),
+            fft_size,
+            samples: VecDeque::with_capacity(fft_size),
+            levels: vec![0.0; num_bars],
+            chromagram: [0.0; 12],
+            sample_rate,
+        }
+    }
+
+    fn feed(&mut self, samples: &[f32]) {
+        self.samples.extend(samples);
+        while self.samples.len() > self.fft_size {
+            self.samples.pop_front();
+        }
+        if self.samples.len() < self.fft_size {
+            return;
+        }
+
+        let frame = self
+            .pipeline
+            .process(Frame::from_samples(self.samples.iter().copied().collect()));
+        self.chromagram = pitch_spectrum_to_chromagram(&frequency_to_pitch_spectrum(
+            &frame.spectrum,
+            self.sample_rate,
+        ));
+        self.levels = frame.bars;
+    }
+}
+
+/// Creates an analyser for `sample_rate`Hz audio producing `num_bars` bars, or returns null
+/// if `fft_size` isn't a power of two or `num_bars` is 0
+#[unsafe(no_mangle)]
+pub extern "C" fn vis_create(sample_rate: u32, fft_size: u32, num_bars: u32) -> *mut VisAnalyser {
+    if !fft_size.is_power_of_two() || num_bars == 0 || sample_rate == 0 {
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(VisAnalyser::new(
+        sample_rate as usize,
+        fft_size as usize,
+        num_bars as usize,
+    )))
+}
+
+/// Frees an analyser made by `vis_create`
+///
+/// # Safety
+///
+/// `vis` must be null or a pointer returned by `vis_create` that hasn't been destroyed yet
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vis_destroy(vis: *mut VisAnalyser) {
+    if !vis.is_null() {
+        drop(unsafe { Box::from_raw(vis) });
+    }
+}
+
+/// Adds `len` mono samples, updating the bars and chromagram once at least `fft_size`
+/// samples have been fed in total
+///
+/// # Safety
+///
+/// `vis` must be a live pointer from `vis_create` and `samples` must point to `len` floats.
+/// Either being null does nothing
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vis_feed_samples(vis: *mut VisAnalyser, samples: *const f32, len: usize) {
+    let Some(vis) = (unsafe { vis.as_mut() }) else {
+        return;
+    };
+    if samples.is_null() {
+        return;
+    }
+
+    vis.feed(unsafe { slice::from_raw_parts(samples, len) });
+}
+
+/// Copies up to `len` bar heights, from 0.0 to 1.0, into `bars` and returns how many were
+/// copied
+///
+/// # Safety
+///
+/// `vis` must be a live pointer from `vis_create` and `bars` must have room for `len` floats.
+/// Either being null copies nothing
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vis_get_bars(
+    vis: *const VisAnalyser,
+    bars: *mut f32,
+    len: usize,
+) -> usize {
+    let Some(vis) = (unsafe { vis.as_ref() }) else {
+        return 0;
+    };
+    if bars.is_null() {
+        return 0;
+    }
+
+    let count = len.min(vis.levels.len());
+    unsafe { ptr::copy_nonoverlapping(vis.levels.as_ptr(), bars, count) };
+    count
+}
+
+/// Copies the chromagram of the latest window, the energy in each pitch class from C to B,
+/// into `chromagram` and returns 12
+///
+/// # Safety
+///
+/// `vis` must be a live pointer from `vis_create` and `chromagram` must have room for 12
+/// floats. Either being null copies nothing and returns 0
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vis_get_chromagram(
+    vis: *const VisAnalyser,
+    chromagram: *mut f32,
+) -> usize {
+    let Some(vis) = (unsafe { vis.as_ref() }) else {
+        return 0;
+    };
+    if chromagram.is_null() {
+        return 0;
+    }
+
+    unsafe { ptr::copy_nonoverlapping(vis.chromagram.as_ptr(), chromagram, 12) };
+    12
+}
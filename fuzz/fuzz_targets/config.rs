@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::config::Config;
+
+fuzz_target!(|source: &str| {
+    if let Ok(config) = Config::parse(source) {
+        // Everything built from a config that parsed should be usable
+        config.mode();
+        config
+            .grouping
+            .strategy()
+            .create_ranges(config.sample_rate, config.fft_size);
+        config.colour.mapper(config.sample_rate);
+        for profile in &config.profiles {
+            profile.matches("", None);
+        }
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::expression::{Binding, Expression};
+
+fuzz_target!(|source: &str| {
+    let _ = Expression::parse(source);
+    let _ = Binding::parse(source);
+});
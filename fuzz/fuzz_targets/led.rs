@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::led::{E131_PACKET_LEN, LedProtocol, e131_packet, wled_packet};
+
+// The first two bytes are the universe and the third the sequence number, then every three
+// bytes are a pixel
+fuzz_target!(|data: &[u8]| {
+    let [low, high, sequence, data @ ..] = data else {
+        return;
+    };
+    let pixels: Vec<[u8; 3]> = data
+        .chunks_exact(3)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+
+    for protocol in [LedProtocol::Warls, LedProtocol::Drgb] {
+        wled_packet(protocol, &pixels);
+    }
+    let packet = e131_packet(u16::from_le_bytes([*low, *high]), *sequence, &pixels);
+    assert_eq!(packet.len(), E131_PACKET_LEN);
+});
@@ -0,0 +1,30 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::osc::message;
+
+// The first byte is the length of the address, then the address, then native-endian f32
+// arguments
+fuzz_target!(|data: &[u8]| {
+    let Some((&len, data)) = data.split_first() else {
+        return;
+    };
+    let (address, arguments) = data.split_at((len as usize).min(data.len()));
+    let address = String::from_utf8_lossy(address);
+    let arguments: Vec<f32> = arguments
+        .chunks_exact(4)
+        .map(|chunk| f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+
+    let message = message(&address, &arguments);
+    // Every part is padded to a multiple of 4 bytes
+    assert_eq!(message.len() % 4, 0);
+    assert!(
+        message.ends_with(
+            &arguments
+                .iter()
+                .flat_map(|argument| argument.to_be_bytes())
+                .collect::<Vec<u8>>()
+        )
+    );
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::presets::PresetConfig;
+
+fuzz_target!(|source: &str| {
+    if let Ok(preset) = PresetConfig::parse(source) {
+        // Everything a preset that parsed switches to should be usable
+        if let Some(grouping) = preset.grouping {
+            grouping.strategy().create_ranges(44_100, 2048);
+        }
+        if let Some(colour) = &preset.colour {
+            colour.mapper(44_100);
+        }
+    }
+});
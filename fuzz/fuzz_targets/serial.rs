@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use macroquad::color::Color;
+use rust_audio_visualiser::serial::packet;
+
+// The first byte's bits are the onset and beat flags, whether colours are sent and how many
+// colours the floats after it start with, the rest being levels
+fuzz_target!(|data: &[u8]| {
+    let Some((&settings, data)) = data.split_first() else {
+        return;
+    };
+    let floats: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|chunk| f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    let (channels, levels) = floats.split_at(((settings as usize >> 3) * 3).min(floats.len()));
+    let colours: Vec<Color> = channels
+        .chunks_exact(3)
+        .map(|channel| Color::new(channel[0], channel[1], channel[2], 1.0))
+        .collect();
+
+    let packet = packet(
+        settings & 1 != 0,
+        settings & 2 != 0,
+        levels,
+        (settings & 4 != 0).then_some(colours.as_slice()),
+    );
+    // Start byte, length and checksum around what the length counts
+    let len = u16::from_le_bytes([packet[1], packet[2]]) as usize;
+    assert_eq!(packet.len(), len + 4);
+});
@@ -0,0 +1,63 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::{
+    analysis::Analyser,
+    gain::AutoGain,
+    grouping::GroupingStrategy,
+    smoothing::SmoothingStrategy,
+    spectra::FourierTransform,
+    visualiser::{AmplitudeScale, update_bar_levels},
+};
+
+const SAMPLE_RATE: usize = 44_100;
+
+// The first byte picks the FFT size and number of bars, the rest are native-endian f32
+// samples, including NaNs, infinities and denormals
+fuzz_target!(|data: &[u8]| {
+    let Some((&settings, data)) = data.split_first() else {
+        return;
+    };
+    let fft_size = 64 << (settings % 6);
+    let num_groups = 1 + (settings >> 3) as usize;
+
+    let mut samples: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|chunk| f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    samples.resize(fft_size, 0.0);
+
+    let fft = FourierTransform::new(fft_size);
+    let mut analyser = Analyser::new(SAMPLE_RATE, 60);
+    let smoothing = SmoothingStrategy::RiseFall {
+        rise: 0.5,
+        fall: 0.9,
+    };
+
+    let scales = [
+        AmplitudeScale::Linear,
+        AmplitudeScale::Log2,
+        AmplitudeScale::Dbfs { floor: -70.0 },
+    ];
+    for (grouping, scale) in [
+        GroupingStrategy::NoGrouping { num_groups },
+        GroupingStrategy::LogMax { num_groups },
+        GroupingStrategy::LogMean { num_groups },
+    ]
+    .into_iter()
+    .zip(scales)
+    {
+        let ranges = grouping.create_ranges(SAMPLE_RATE, fft_size);
+        let mut bars = vec![0.0; ranges.len()];
+        let analysis = analyser.analyse(&samples, fft.compute(&samples), 0.0);
+        update_bar_levels(
+            &grouping,
+            &ranges,
+            &smoothing,
+            &scale,
+            &mut AutoGain::new(0.05, 2.0, 60),
+            &mut bars,
+            &analysis.spectrum,
+        );
+    }
+});
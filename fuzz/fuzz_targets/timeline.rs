@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::timeline::Timeline;
+
+fuzz_target!(|source: &str| {
+    if let Ok(mut timeline) = Timeline::parse(source) {
+        for elapsed in [0.0, 1.0, 60.0, f64::MAX] {
+            timeline.update(elapsed);
+        }
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_audio_visualiser::server::{frame, handshake_response};
+
+// The whole input is tried as a client's upgrade request, then sent back as a message
+fuzz_target!(|data: &[u8]| {
+    if let Some(response) = handshake_response(data) {
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    let frame = frame(data);
+    assert!(frame.ends_with(data));
+    let header = match frame[1] {
+        126 => 4,
+        127 => 10,
+        len => {
+            assert_eq!(len as usize, data.len());
+            2
+        }
+    };
+    assert_eq!(frame.len(), header + data.len());
+});
@@ -0,0 +1,335 @@
+//! Python bindings to the analysis the visualiser uses, for prototyping in notebooks
+//!
+//! Build and install into the current virtualenv with `maturin develop --release` in this
+//! directory. Arrays go in and come out as 1D `float32` NumPy arrays:
+//!
+//! ```python
+//! import numpy as np
+//! import audio_visualiser as av
+//!
+//! fft = av.FourierTransform(2048)
+//! grouping = av.Grouping("log-max", bars=24, sample_rate=44100, fft_size=2048)
+//! analyser = av.Analyser(sample_rate=44100, frame_rate=60)
+//!
+//! window = np.sin(np.arange(2048) * 2 * np.pi * 440 / 44100).astype(np.float32)
+//! spectrum = fft.compute(window)
+//! bars = grouping.group(spectrum)
+//! frame = analyser.analyse(window, spectrum, time=0.0)
+//! print(frame.loudness, frame.chromagram)
+//! ```
+
+use numpy::{PyArray1, PyReadonlyArray1};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use rust_audio_visualiser::{
+    analysis::{Analyser, FrameAnalysis},
+    chroma::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram},
+    grouping::GroupingStrategy,
+    smoothing::SmoothingStrategy,
+    spectra,
+};
+
+/// FourierTransform(fft_size)
+///
+/// Hann-windowed FFT returning the power spectrum from 0Hz to the Nyquist frequency
+#[pyclass(module = "audio_visualiser")]
+struct FourierTransform {
+    fft: spectra::FourierTransform,
+    fft_size: usize,
+}
+
+#[pymethods]
+impl FourierTransform {
+    #[new]
+    fn new(fft_size: usize) -> PyResult<Self> {
+        if fft_size == 0 {
+            return Err(PyValueError::new_err("fft_size must be at least 1"));
+        }
+
+        Ok(Self {
+            fft: spectra::FourierTransform::new(fft_size),
+            fft_size,
+        })
+    }
+
+    #[getter]
+    fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    /// Power spectrum of `samples`, which must hold exactly `fft_size` values. The result
+    /// has `fft_size / 2 + 1` bins, from 0Hz up to and including the Nyquist frequency
+    fn compute<'py>(
+        &self,
+        py: Python<'py>,
+        samples: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let samples = samples.as_slice()?;
+        if samples.len() != self.fft_size {
+            return Err(PyValueError::new_err(format!(
+                "expected {} samples, got {}",
+                self.fft_size,
+                samples.len()
+            )));
+        }
+
+        Ok(PyArray1::from_vec(py, self.fft.compute(samples)))
+    }
+}
+
+/// Grouping(strategy, bars, sample_rate, fft_size, gamma=2.0)
+///
+/// Groups spectra into bars the way the bars mode does. `strategy` is one of the config
+/// file's names: none, log-max, log-mean or gamma-corrected
+#[pyclass(module = "audio_visualiser")]
+struct Grouping {
+    strategy: GroupingStrategy,
+    ranges: Vec<(usize, usize)>,
+}
+
+#[pymethods]
+impl Grouping {
+    #[new]
+    #[pyo3(signature = (strategy, bars, sample_rate, fft_size, gamma = 2.0))]
+    fn new(
+        strategy: &str,
+        bars: usize,
+        sample_rate: usize,
+        fft_size: usize,
+        gamma: f32,
+    ) -> PyResult<Self> {
+        let num_groups = bars.max(1);
+        let strategy = match strategy {
+            "none" => GroupingStrategy::NoGrouping { num_groups },
+            "log-max" => GroupingStrategy::LogMax { num_groups },
+            "log-mean" => GroupingStrategy::LogMean { num_groups },
+            "gamma-corrected" => GroupingStrategy::GammaCorrected { num_groups, gamma },
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown grouping strategy `{strategy}`"
+                )));
+            }
+        };
+
+        Ok(Self {
+            ranges: strategy.create_ranges(sample_rate, fft_size),
+            strategy,
+        })
+    }
+
+    /// Start and end FFT bin of each bar
+    #[getter]
+    fn ranges(&self) -> Vec<(usize, usize)> {
+        self.ranges.clone()
+    }
+
+    #[getter]
+    fn num_bars(&self) -> usize {
+        self.strategy.num_bars()
+    }
+
+    /// Bar heights, before smoothing or normalising, for a spectrum from
+    /// `FourierTransform.compute`
+    fn group<'py>(
+        &self,
+        py: Python<'py>,
+        spectrum: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let spectrum = spectrum.as_slice()?;
+        // Every range has to fit, otherwise grouping would index past the end
+        let needed = self.ranges.iter().map(|&(_, end)| end).max().unwrap_or(0);
+        if spectrum.len() < needed {
+            return Err(PyValueError::new_err(format!(
+                "spectrum has {} bins but the grouping needs {needed}",
+                spectrum.len()
+            )));
+        }
+
+        Ok(PyArray1::from_vec(
+            py,
+            self.strategy.group_spectrum(spectrum, &self.ranges),
+        ))
+    }
+}
+
+/// Smoothing(bars, rise=0.5, fall=0.9)
+///
+/// Rise and fall smoothing of successive bar heights, keeping the previous heights between
+/// calls
+#[pyclass(module = "audio_visualiser")]
+struct Smoothing {
+    strategy: SmoothingStrategy,
+    bars: Vec<f32>,
+}
+
+#[pymethods]
+impl Smoothing {
+    #[new]
+    #[pyo3(signature = (bars, rise = 0.5, fall = 0.9))]
+    fn new(bars: usize, rise: f32, fall: f32) -> Self {
+        Self {
+            strategy: SmoothingStrategy::RiseFall { rise, fall },
+            bars: vec![0.0; bars],
+        }
+    }
+
+    /// Smooths `bars` towards the previous call's result and returns it
+    fn smooth<'py>(
+        &mut self,
+        py: Python<'py>,
+        bars: PyReadonlyArray1<'py, f32>,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let bars = bars.as_slice()?;
+        if bars.len() != self.bars.len() {
+            return Err(PyValueError::new_err(format!(
+                "expected {} bars, got {}",
+                self.bars.len(),
+                bars.len()
+            )));
+        }
+
+        self.strategy.smooth(&mut self.bars, bars);
+        Ok(PyArray1::from_slice(py, &self.bars))
+    }
+}
+
+/// Analyser(sample_rate, frame_rate)
+///
+/// Extracts the features scenes react to from each window, expecting `frame_rate` windows a
+/// second. Onsets, tempo and beats depend on the windows that came before, so feed every
+/// window in order
+#[pyclass(name = "Analyser", module = "audio_visualiser")]
+struct PyAnalyser {
+    analyser: Analyser,
+}
+
+#[pymethods]
+impl PyAnalyser {
+    #[new]
+    fn new(sample_rate: usize, frame_rate: usize) -> Self {
+        Self {
+            analyser: Analyser::new(sample_rate, frame_rate.max(1)),
+        }
+    }
+
+    /// Features of the window `samples` with power spectrum `spectrum`, taken at `time`
+    /// seconds
+    fn analyse(
+        &mut self,
+        samples: PyReadonlyArray1<'_, f32>,
+        spectrum: PyReadonlyArray1<'_, f32>,
+        time: f64,
+    ) -> PyResult<Frame> {
+        let samples = samples.as_slice()?;
+        let spectrum = spectrum.as_slice()?.to_vec();
+        if spectrum.is_empty() {
+            return Err(PyValueError::new_err("spectrum is empty"));
+        }
+
+        Ok(Frame(self.analyser.analyse(samples, spectrum, time)))
+    }
+}
+
+/// Features of one window, from `Analyser.analyse`
+#[pyclass(module = "audio_visualiser")]
+struct Frame(FrameAnalysis);
+
+#[pymethods]
+impl Frame {
+    #[getter]
+    fn time(&self) -> f64 {
+        self.0.time
+    }
+
+    /// Loudness in dBFS
+    #[getter]
+    fn loudness(&self) -> f32 {
+        self.0.loudness
+    }
+
+    /// Energy in the band relative to its recent peak, from 0.0 to 1.0
+    #[getter]
+    fn bass(&self) -> f32 {
+        self.0.bass
+    }
+
+    #[getter]
+    fn mids(&self) -> f32 {
+        self.0.mids
+    }
+
+    #[getter]
+    fn treble(&self) -> f32 {
+        self.0.treble
+    }
+
+    /// Spectral flux from the previous window
+    #[getter]
+    fn flux(&self) -> f32 {
+        self.0.flux
+    }
+
+    #[getter]
+    fn onset(&self) -> bool {
+        self.0.onset
+    }
+
+    /// Confidence from 0.0 to 1.0 if a beat was detected on this window, otherwise None
+    #[getter]
+    fn beat(&self) -> Option<f32> {
+        self.0.beat.map(|beat| beat.confidence)
+    }
+
+    #[getter]
+    fn bpm(&self) -> Option<f32> {
+        self.0.bpm
+    }
+
+    #[getter]
+    fn beat_phase(&self) -> Option<f32> {
+        self.0.beat_phase
+    }
+
+    /// Energy in each pitch class from C to B
+    #[getter]
+    fn chromagram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_slice(py, &self.0.chromagram)
+    }
+}
+
+/// Energy in each of the 128 MIDI pitches, from a power spectrum
+#[pyfunction]
+fn pitch_spectrum<'py>(
+    py: Python<'py>,
+    spectrum: PyReadonlyArray1<'py, f32>,
+    sample_rate: usize,
+) -> PyResult<Bound<'py, PyArray1<f32>>> {
+    let pitches = frequency_to_pitch_spectrum(spectrum.as_slice()?, sample_rate);
+    Ok(PyArray1::from_slice(py, &pitches))
+}
+
+/// Energy in each pitch class from C to B, from a power spectrum
+#[pyfunction]
+fn chromagram<'py>(
+    py: Python<'py>,
+    spectrum: PyReadonlyArray1<'py, f32>,
+    sample_rate: usize,
+) -> PyResult<Bound<'py, PyArray1<f32>>> {
+    let pitches = frequency_to_pitch_spectrum(spectrum.as_slice()?, sample_rate);
+    Ok(PyArray1::from_slice(
+        py,
+        &pitch_spectrum_to_chromagram(&pitches),
+    ))
+}
+
+#[pymodule]
+fn audio_visualiser(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<FourierTransform>()?;
+    module.add_class::<Grouping>()?;
+    module.add_class::<Smoothing>()?;
+    module.add_class::<PyAnalyser>()?;
+    module.add_class::<Frame>()?;
+    module.add_function(wrap_pyfunction!(pitch_spectrum, module)?)?;
+    module.add_function(wrap_pyfunction!(chromagram, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,570 @@
+//! Soak test of the analysis, feeding hours of synthetic audio through every pipeline as
+//! fast as it'll go while watching the heap
+//!
+//! ```text
+//! cargo run --release -- --hours 6 --report 30 --memory-budget 48
+//! ```
+//!
+//! The audio cycles through test signals, from silence to a clipped square wave, captured at
+//! a rate that has to be resampled. It goes through the analyser with every feature on, the
+//! bar pipeline with each grouping strategy, spectral averaging, note, drop and pitch
+//! tracking, session recording and a sink paced at 100 frames a second, as the visualiser
+//! runs them. Drawing needs a window, so it's left out.
+//!
+//! A counting global allocator sees every allocation, so each stage reports how many it
+//! makes a window and how much memory it's holding on to. Once the warm-up's over every
+//! buffer should have reached its full size, so a stage still growing after it is leaking,
+//! and a visualiser left running for days would run out of memory. Reports are printed
+//! every `--report` minutes of audio with the process's peak RSS, and the exit status is 1
+//! if any stage leaked. Note tracking and session recording keep every note unless they're
+//! given the share of `--memory-budget` MiB the visualiser would give them
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    f64::consts::TAU,
+    process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicIsize, AtomicU64, Ordering::Relaxed},
+    },
+    time::Instant,
+};
+
+use rust_audio_visualiser::{
+    analysis::Analyser,
+    budget::MemoryBudget,
+    drops::DropPredictor,
+    gain::AutoGain,
+    grouping::{DEFAULT_GAMMA, GroupingStrategy},
+    pacing::Pacer,
+    pipeline::{Frame, Pipeline},
+    pitch::PitchTracker,
+    resample::Resampler,
+    session::SessionRecorder,
+    smoothing::SmoothingStrategy,
+    spectra::{FourierTransform, SpectralAverage, Weighting},
+    transcription::NoteTracker,
+};
+
+// Rate the synthetic audio is captured at, resampled to `SAMPLE_RATE`
+const CAPTURE_RATE: usize = 48_000;
+const SAMPLE_RATE: usize = 44_100;
+const FFT_SIZE: usize = 2048;
+const HOP_SIZE: usize = 512;
+// Samples in each block captured, as an audio callback delivers them
+const BLOCK_LEN: usize = 480;
+// Seconds each test signal plays for before the next
+const SIGNAL_SECONDS: f64 = 20.0;
+// Seconds of audio before growth counts as a leak, long enough for two rounds of signals
+const WARM_UP_SECONDS: f64 = 240.0;
+// Bytes a stage can grow by after the warm-up before it's reported as leaking
+const LEAK_TOLERANCE: isize = 256 * 1024;
+// Seconds after a window's captured that the sink receives it, a frame at 60fps
+const SINK_LATENCY: f64 = 1.0 / 60.0;
+const SINK_INTERVAL: f64 = 1.0 / 100.0;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+// Bytes allocated and not yet freed
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+// The system allocator, counting allocations and the bytes they hold
+struct CountingAllocator;
+
+// SAFETY: every call is passed straight on to the system allocator
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Relaxed);
+            LIVE.fetch_add(layout.size() as isize, Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Relaxed);
+            LIVE.fetch_add(layout.size() as isize, Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size() as isize, Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Relaxed);
+            LIVE.fetch_add(new_size as isize - layout.size() as isize, Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Allocations made by one part of the pipeline, and the memory it's kept
+struct Stage {
+    name: &'static str,
+    allocations: u64,
+    // Allocations made so far in the current window, and the most made in any one window
+    window_allocations: u64,
+    most_allocations: u64,
+    // Bytes allocated by the stage and not yet freed
+    retained: isize,
+    // `retained` at the end of the warm-up
+    baseline: Option<isize>,
+}
+
+impl Stage {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            allocations: 0,
+            window_allocations: 0,
+            most_allocations: 0,
+            retained: 0,
+            baseline: None,
+        }
+    }
+
+    // Runs `run` as part of the stage. Anything it returns that's freed later should be
+    // dropped in another call, so the stage isn't charged for memory it gave away
+    fn measure<T>(&mut self, run: impl FnOnce() -> T) -> T {
+        let (allocations, live) = (ALLOCATIONS.load(Relaxed), LIVE.load(Relaxed));
+        let result = run();
+        let made = ALLOCATIONS.load(Relaxed) - allocations;
+        self.allocations += made;
+        self.window_allocations += made;
+        self.retained += LIVE.load(Relaxed) - live;
+        result
+    }
+
+    fn end_window(&mut self) {
+        self.most_allocations = self.most_allocations.max(self.window_allocations);
+        self.window_allocations = 0;
+    }
+
+    // Bytes retained since the warm-up
+    fn growth(&self) -> isize {
+        self.baseline.map_or(0, |baseline| self.retained - baseline)
+    }
+}
+
+// Every stage, in the order they're run
+struct Stages {
+    resample: Stage,
+    analysis: Stage,
+    averaging: Stage,
+    bars: Stage,
+    notes: Stage,
+    drops: Stage,
+    pitch: Stage,
+    session: Stage,
+    sink: Stage,
+}
+
+impl Stages {
+    fn new() -> Self {
+        Self {
+            resample: Stage::new("resample"),
+            analysis: Stage::new("analysis"),
+            averaging: Stage::new("averaging"),
+            bars: Stage::new("bars"),
+            notes: Stage::new("notes"),
+            drops: Stage::new("drops"),
+            pitch: Stage::new("pitch"),
+            session: Stage::new("session"),
+            sink: Stage::new("sink"),
+        }
+    }
+
+    fn all(&self) -> [&Stage; 9] {
+        [
+            &self.resample,
+            &self.analysis,
+            &self.averaging,
+            &self.bars,
+            &self.notes,
+            &self.drops,
+            &self.pitch,
+            &self.session,
+            &self.sink,
+        ]
+    }
+
+    fn all_mut(&mut self) -> [&mut Stage; 9] {
+        [
+            &mut self.resample,
+            &mut self.analysis,
+            &mut self.averaging,
+            &mut self.bars,
+            &mut self.notes,
+            &mut self.drops,
+            &mut self.pitch,
+            &mut self.session,
+            &mut self.sink,
+        ]
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Signal {
+    Silence,
+    Sweep,
+    Chords,
+    Drums,
+    Noise,
+    ClippedSquare,
+}
+
+const SIGNALS: [Signal; 6] = [
+    Signal::Silence,
+    Signal::Sweep,
+    Signal::Chords,
+    Signal::Drums,
+    Signal::Noise,
+    Signal::ClippedSquare,
+];
+
+// C, F, G and A minor, two seconds each
+const CHORDS: [[f64; 3]; 4] = [
+    [261.63, 329.63, 392.00],
+    [349.23, 440.00, 523.25],
+    [392.00, 493.88, 587.33],
+    [220.00, 261.63, 329.63],
+];
+
+// Plays each of `SIGNALS` for `SIGNAL_SECONDS` in turn, over and over
+struct Generator {
+    sample: u64,
+    // Phase of the sweep, which changes frequency too fast to work out from the time
+    phase: f64,
+    // State of the xorshift generator behind the noise, the same every run
+    seed: u64,
+}
+
+impl Generator {
+    fn new() -> Self {
+        Self {
+            sample: 0,
+            phase: 0.0,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    fn seconds(&self) -> f64 {
+        self.sample as f64 / CAPTURE_RATE as f64
+    }
+
+    fn noise(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        (self.seed >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let time = self.seconds();
+        let signal = SIGNALS[(time / SIGNAL_SECONDS) as usize % SIGNALS.len()];
+        let elapsed = time % SIGNAL_SECONDS;
+        self.sample += 1;
+
+        let value = match signal {
+            Signal::Silence => 0.0,
+            // Up from 20Hz to 20kHz evenly in pitch
+            Signal::Sweep => {
+                let frequency = 20.0 * 1000.0_f64.powf(elapsed / SIGNAL_SECONDS);
+                self.phase = (self.phase + TAU * frequency / CAPTURE_RATE as f64) % TAU;
+                0.5 * self.phase.sin()
+            }
+            Signal::Chords => {
+                let chord = CHORDS[(elapsed / 2.0) as usize % CHORDS.len()];
+                chord
+                    .iter()
+                    .map(|frequency| (TAU * frequency * time).sin())
+                    .sum::<f64>()
+                    * 0.2
+            }
+            // A falling kick on each beat at 120 BPM, with a hi-hat between
+            Signal::Drums => {
+                let since_beat = elapsed % 0.5;
+                let kick = (TAU * (50.0 + 100.0 * (-since_beat * 30.0).exp()) * since_beat).sin()
+                    * (-since_beat * 8.0).exp();
+                let since_hat = (elapsed + 0.25) % 0.5;
+                let hat = self.noise() * 0.3 * (-since_hat * 60.0).exp();
+                0.8 * kick + hat
+            }
+            Signal::Noise => self.noise(),
+            // Full scale, beyond what a limiter would let through
+            Signal::ClippedSquare => {
+                if (time * 110.0).fract() < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+        };
+        value as f32
+    }
+}
+
+struct Options {
+    hours: f64,
+    // Minutes of audio between reports
+    report: f64,
+    // MiB history is kept to, as with `memory_budget` in the config
+    memory_budget: Option<f64>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        hours: 1.0,
+        report: 10.0,
+        memory_budget: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let option = match arg.as_str() {
+            "--hours" => &mut options.hours,
+            "--report" => &mut options.report,
+            "--memory-budget" => options.memory_budget.insert(0.0),
+            _ => {
+                return Err(format!(
+                    "Unknown argument {arg}, expected --hours, --report or --memory-budget"
+                ));
+            }
+        };
+        *option = args
+            .next()
+            .and_then(|value| value.parse().ok())
+            .filter(|value: &f64| *value > 0.0)
+            .ok_or_else(|| format!("{arg} needs a positive number"))?;
+    }
+    Ok(options)
+}
+
+// Peak resident set size of the process, in bytes
+fn max_rss() -> isize {
+    // SAFETY: getrusage only writes to the struct it's given
+    let usage = unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+        usage
+    };
+    // Counted in kilobytes on Linux
+    usage.ru_maxrss as isize * 1024
+}
+
+fn format_bytes(bytes: isize) -> String {
+    let magnitude = bytes.unsigned_abs() as f64;
+    if magnitude >= 1024.0 * 1024.0 {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    } else if magnitude >= 1024.0 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+fn report(stages: &Stages, windows: u64, audio_seconds: f64, started: Instant) {
+    let wall = started.elapsed().as_secs_f64();
+    let seconds = audio_seconds as u64;
+    println!(
+        "{:02}:{:02}:{:02} of audio in {wall:.0}s, {:.0}x real time. Heap {}, peak RSS {}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        audio_seconds / wall.max(f64::EPSILON),
+        format_bytes(LIVE.load(Relaxed)),
+        format_bytes(max_rss()),
+    );
+    println!(
+        "  {:<10} {:>14} {:>12} {:>12} {:>14}",
+        "stage", "allocs/window", "most allocs", "retained", "since warm-up"
+    );
+    for stage in stages.all() {
+        println!(
+            "  {:<10} {:>14.1} {:>12} {:>12} {:>14}",
+            stage.name,
+            stage.allocations as f64 / windows.max(1) as f64,
+            stage.most_allocations,
+            format_bytes(stage.retained),
+            stage
+                .baseline
+                .map_or("-".to_string(), |_| format_bytes(stage.growth())),
+        );
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let fft = FourierTransform::new(FFT_SIZE).with_hop_size(HOP_SIZE);
+    let frame_rate = SAMPLE_RATE / HOP_SIZE;
+    // Each pipeline smooths its own bars, so each is given its own strategy
+    let smoothing = || SmoothingStrategy::RiseFall {
+        rise: 0.5,
+        fall: 0.9,
+    };
+    let mut analyser = Analyser::new(SAMPLE_RATE, frame_rate);
+    let mut resampler = Resampler::new(CAPTURE_RATE, SAMPLE_RATE);
+    let mut average = SpectralAverage::new(4);
+    let mut bar_pipelines: Vec<Pipeline> = [
+        GroupingStrategy::NoGrouping { num_groups: 64 },
+        GroupingStrategy::LogMax { num_groups: 32 },
+        GroupingStrategy::LogMean { num_groups: 32 },
+        GroupingStrategy::GammaCorrected {
+            num_groups: 32,
+            gamma: DEFAULT_GAMMA,
+        },
+    ]
+    .into_iter()
+    .map(|grouping| {
+        Pipeline::bars(
+            SAMPLE_RATE,
+            grouping,
+            smoothing(),
+            Some(Weighting::A),
+            AutoGain::new(0.05, 2.0, frame_rate),
+        )
+    })
+    .collect();
+    let budget = MemoryBudget::new(options.memory_budget.map(|budget| budget as f32));
+    let mut notes = NoteTracker::new(SAMPLE_RATE);
+    if let Some(max_events) = budget.note_events() {
+        notes = notes.with_max_events(max_events);
+    }
+    let mut drops = DropPredictor::new(SAMPLE_RATE, frame_rate);
+    let pitch = PitchTracker::new(SAMPLE_RATE);
+    let mut session = SessionRecorder::new(SAMPLE_RATE);
+    if let Some(max_events) = budget.session_events() {
+        session = session.with_max_events(max_events);
+    }
+    // As a sink with a `rate` runs it
+    let mut sink = Pipeline::bars(
+        SAMPLE_RATE,
+        GroupingStrategy::LogMax { num_groups: 16 },
+        smoothing(),
+        None,
+        AutoGain::new(0.0, 0.0, 1),
+    );
+    let mut pacer = Pacer::new(0.0);
+    let mut next_sink_frame = 0.0;
+
+    let mut stages = Stages::new();
+
+    let total_samples = (options.hours * 3600.0 * CAPTURE_RATE as f64) as u64;
+    let mut generator = Generator::new();
+    let mut block = vec![0.0; BLOCK_LEN];
+    // Resampled audio not yet analysed
+    let mut pending = Vec::with_capacity(FFT_SIZE + BLOCK_LEN);
+    let mut windows = 0u64;
+    let mut next_report = options.report * 60.0;
+    let started = Instant::now();
+
+    while generator.sample < total_samples {
+        block.fill_with(|| generator.next_sample());
+        stages
+            .resample
+            .measure(|| resampler.process(&block, &mut pending));
+
+        while pending.len() >= FFT_SIZE {
+            let window = &pending[..FFT_SIZE];
+            // Capture time of the window's last sample
+            let time = (windows as usize * HOP_SIZE + FFT_SIZE) as f64 / SAMPLE_RATE as f64;
+
+            let frame = stages
+                .analysis
+                .measure(|| analyser.analyse(window, fft.compute(window), time));
+            stages.averaging.measure(|| {
+                average.update(&frame.spectrum);
+            });
+            stages.bars.measure(|| {
+                for pipeline in &mut bar_pipelines {
+                    pipeline.process(Frame::from_spectrum(frame.spectrum.clone()));
+                }
+            });
+            stages.notes.measure(|| notes.update(&frame.spectrum, time));
+            stages.drops.measure(|| {
+                drops.update(&frame.spectrum, time);
+            });
+            stages.pitch.measure(|| pitch.estimate(&frame.spectrum));
+            stages
+                .session
+                .measure(|| session.update(&frame.spectrum, time));
+            stages.sink.measure(|| {
+                let levels = sink.process(Frame::from_spectrum(frame.spectrum.clone()));
+                pacer.push(
+                    time + SINK_LATENCY,
+                    Arc::new(frame.clone()),
+                    levels.bars,
+                    levels.ranges,
+                );
+                while next_sink_frame <= time {
+                    pacer.frame(next_sink_frame + SINK_LATENCY);
+                    next_sink_frame += SINK_INTERVAL;
+                }
+            });
+            stages.analysis.measure(move || drop(frame));
+            stages.resample.measure(|| {
+                pending.drain(..HOP_SIZE);
+            });
+
+            for stage in stages.all_mut() {
+                stage.end_window();
+            }
+            windows += 1;
+        }
+
+        let seconds = generator.seconds();
+        if seconds >= WARM_UP_SECONDS && stages.analysis.baseline.is_none() {
+            for stage in stages.all_mut() {
+                stage.baseline = Some(stage.retained);
+            }
+        }
+        if seconds >= next_report {
+            report(&stages, windows, seconds, started);
+            next_report += options.report * 60.0;
+        }
+    }
+
+    report(&stages, windows, generator.seconds(), started);
+    if stages.analysis.baseline.is_none() {
+        println!(
+            "Too short to check for leaks, which needs more than {} minutes of audio",
+            WARM_UP_SECONDS / 60.0
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    let mut leaked = false;
+    for stage in stages.all() {
+        if stage.growth() > LEAK_TOLERANCE {
+            println!(
+                "{} kept {} more after warming up, which looks like a leak",
+                stage.name,
+                format_bytes(stage.growth())
+            );
+            leaked = true;
+        }
+    }
+    if leaked {
+        ExitCode::FAILURE
+    } else {
+        println!("No leaks");
+        ExitCode::SUCCESS
+    }
+}
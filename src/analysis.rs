@@ -0,0 +1,216 @@
+use core::ops::BitOr;
+
+use crate::{
+    beat::{BeatDetector, BeatEvent},
+    bins,
+    chroma::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram},
+    onset::OnsetDetector,
+    tempo::TempoEstimator,
+};
+
+// Upper edges of the bass and mid bands in Hz; everything above the mids is treble
+const BASS_MAX_HZ: f32 = 250.0;
+const MIDS_MAX_HZ: f32 = 4000.0;
+// Per-frame decay of the running peaks used to normalise band energies
+const PEAK_DECAY: f32 = 0.998;
+
+/// Parts of a `FrameAnalysis` that take work to compute and only some consumers read. The
+/// loudness, samples and spectrum are always there
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Feature {
+    /// `chromagram`
+    Chromagram,
+    /// `bass`, `mids` and `treble`
+    Bands,
+    /// `flux`, `onset` and `onset_threshold`
+    Onsets,
+    /// `beat`, `bpm` and `beat_phase`
+    Beats,
+}
+
+/// A set of `Feature`s, such as those something needs from each frame
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Features(u8);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self::of(&[
+        Feature::Chromagram,
+        Feature::Bands,
+        Feature::Onsets,
+        Feature::Beats,
+    ]);
+
+    pub const fn of(features: &[Feature]) -> Self {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < features.len() {
+            bits |= 1 << features[i] as u8;
+            i += 1;
+        }
+        Self(bits)
+    }
+
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & (1 << feature as u8) != 0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl From<Feature> for Features {
+    fn from(feature: Feature) -> Self {
+        Self::of(&[feature])
+    }
+}
+
+/// Everything known about the audio at one frame, computed once and shared by
+/// every consumer (visualisers, colour mappers, modulation)
+#[derive(Clone)]
+pub struct FrameAnalysis {
+    /// Seconds since the analysis started when the last sample of the window was captured,
+    /// for lining outputs up with the audio
+    pub time: f64,
+    /// Mono samples the spectrum was computed from, oldest first
+    pub samples: Vec<f32>,
+    /// Power spectrum from 0Hz to (sampling_rate / 2)Hz inclusive, laid out as described
+    /// in `bins`
+    pub spectrum: Vec<f32>,
+    /// Power spectra of the left and right channels, when capturing them separately
+    pub channel_spectra: Option<[Vec<f32>; 2]>,
+    pub chromagram: [f32; 12],
+    /// Loudness of the sample window in dBFS
+    pub loudness: f32,
+    /// Energy in each band relative to its recent peak, from 0.0 to 1.0
+    pub bass: f32,
+    pub mids: f32,
+    pub treble: f32,
+    /// Spectral flux from the previous frame
+    pub flux: f32,
+    /// Whether a note or beat onset was detected this frame
+    pub onset: bool,
+    /// Flux had to exceed this to be an onset, see `OnsetDetector::threshold`
+    pub onset_threshold: Option<f32>,
+    /// Beat detected on this frame, see `BeatDetector`
+    pub beat: Option<BeatEvent>,
+    pub bpm: Option<f32>,
+    /// Fraction of the current beat that has elapsed, see `TempoEstimator::beat_phase`
+    pub beat_phase: Option<f32>,
+}
+
+impl FrameAnalysis {
+    /// Loudness mapped from -60dBFS..0dBFS to 0.0..1.0
+    pub fn normalised_loudness(&self) -> f32 {
+        ((self.loudness + 60.0) / 60.0).clamp(0.0, 1.0)
+    }
+}
+
+/// Computes a `FrameAnalysis` from each new window of samples and its spectrum
+///
+/// Only the `Features` set with `set_features` are computed, all of them by default, and the
+/// rest are left empty: zeros, no onset and no beat. Onsets and beats are detected from the
+/// history of recent windows, which isn't kept while they're off, so they take a few seconds
+/// to settle again after being turned back on
+pub struct Analyser {
+    sampling_rate: usize,
+    features: Features,
+    onsets: OnsetDetector,
+    tempo: TempoEstimator,
+    beats: BeatDetector,
+    band_peaks: [f32; 3],
+}
+
+impl Analyser {
+    pub fn new(sampling_rate: usize, frame_rate: usize) -> Self {
+        Self {
+            sampling_rate,
+            features: Features::ALL,
+            onsets: OnsetDetector::new(),
+            tempo: TempoEstimator::new(frame_rate),
+            beats: BeatDetector::new(sampling_rate, frame_rate),
+            band_peaks: [1e-6; 3],
+        }
+    }
+
+    /// Computes only `features` from now on, for whatever is using the analysis at the time
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
+    }
+
+    pub fn analyse(&mut self, samples: &[f32], spectrum: Vec<f32>, time: f64) -> FrameAnalysis {
+        let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+
+        let mut normalised = [0.0; 3];
+        if self.features.contains(Feature::Bands) {
+            let freq_per_bin = bins::bin_width(spectrum.len(), self.sampling_rate);
+            let bass_end = ((BASS_MAX_HZ / freq_per_bin) as usize).min(spectrum.len());
+            let mids_end = ((MIDS_MAX_HZ / freq_per_bin) as usize).min(spectrum.len());
+
+            let bands = [
+                &spectrum[..bass_end],
+                &spectrum[bass_end..mids_end],
+                &spectrum[mids_end..],
+            ]
+            .map(|band| band.iter().sum::<f32>().sqrt());
+
+            for ((peak, value), normalised) in
+                self.band_peaks.iter_mut().zip(bands).zip(&mut normalised)
+            {
+                *peak = value.max(*peak * PEAK_DECAY);
+                *normalised = value / *peak;
+            }
+        }
+
+        let (flux, onset, onset_threshold) = if self.features.contains(Feature::Onsets) {
+            let flux = self.onsets.flux(&spectrum);
+            let onset = self.onsets.update(&spectrum).is_some();
+            (flux, onset, self.onsets.threshold())
+        } else {
+            (0.0, false, None)
+        };
+
+        let (beat, bpm, beat_phase) = if self.features.contains(Feature::Beats) {
+            self.tempo.update(&spectrum);
+            (
+                self.beats.update(&spectrum, time),
+                self.tempo.bpm(),
+                self.tempo.beat_phase(),
+            )
+        } else {
+            (None, None, None)
+        };
+
+        let chromagram = if self.features.contains(Feature::Chromagram) {
+            pitch_spectrum_to_chromagram(&frequency_to_pitch_spectrum(
+                &spectrum,
+                self.sampling_rate,
+            ))
+        } else {
+            [0.0; 12]
+        };
+
+        FrameAnalysis {
+            time,
+            samples: samples.to_vec(),
+            chromagram,
+            loudness: 10.0 * mean_square.max(1e-10).log10(),
+            bass: normalised[0],
+            mids: normalised[1],
+            treble: normalised[2],
+            flux,
+            onset,
+            onset_threshold,
+            beat,
+            bpm,
+            beat_phase,
+            spectrum,
+            channel_spectra: None,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! User images for skinning bars and particles, cut from one texture atlas
+//!
+//! `[atlas]` names regions of a single image, in pixels from its top left corner, which
+//! `bars.skin` and emitter sprites can then refer to by name:
+//!
+//! ```toml
+//! [atlas]
+//! image = "skins.png"
+//! sprites = { segment = [0, 0, 32, 8], logo = [32, 0, 32, 32] }
+//!
+//! [bars]
+//! skin = { sprite = "segment", fit = "tile" }
+//! ```
+//!
+//! Sprites are tinted by the colour they'd otherwise be drawn in, so white images pick up
+//! the colour mappers' colours
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use macroquad::{
+    color::Color,
+    math::{Rect, vec2},
+    texture::{DrawTextureParams, Image, Texture2D, draw_texture_ex},
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AtlasError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Image(#[from] macroquad::Error),
+    #[error("sprite `{0}` goes outside the atlas image")]
+    OutOfBounds(String),
+}
+
+/// How a skin covers each bar
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkinFit {
+    /// Stacks the sprite up the bar at the bar's width, cutting off the top one, like the
+    /// segments of a hardware equaliser
+    #[default]
+    Tile,
+    /// Stretches the sprite over the whole bar
+    Stretch,
+}
+
+/// A texture and the named regions of it that can be drawn
+pub struct TextureAtlas {
+    texture: Texture2D,
+    sprites: HashMap<String, Rect>,
+}
+
+impl TextureAtlas {
+    /// Loads the image at `path`, with each sprite given as `[x, y, width, height]` in pixels
+    pub fn load(path: &Path, sprites: &HashMap<String, [f32; 4]>) -> Result<Self, AtlasError> {
+        let bytes = fs::read(path)?;
+        let image = Image::from_file_with_format(&bytes, None)?;
+
+        let sprites = sprites
+            .iter()
+            .map(|(name, &[x, y, width, height])| {
+                let rect = Rect::new(x, y, width, height);
+                if x < 0.0
+                    || y < 0.0
+                    || width <= 0.0
+                    || height <= 0.0
+                    || rect.right() > image.width as f32
+                    || rect.bottom() > image.height as f32
+                {
+                    return Err(AtlasError::OutOfBounds(name.clone()));
+                }
+                Ok((name.clone(), rect))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            texture: Texture2D::from_image(&image),
+            sprites,
+        })
+    }
+
+    /// Width over height of the named sprite
+    pub fn aspect(&self, sprite: &str) -> Option<f32> {
+        self.sprites.get(sprite).map(|rect| rect.w / rect.h)
+    }
+
+    /// Draws the named sprite stretched over `dest`, multiplied by `tint`
+    pub fn draw(&self, sprite: &str, dest: Rect, tint: Color) {
+        if let Some(&source) = self.sprites.get(sprite) {
+            self.draw_region(source, dest, tint);
+        }
+    }
+
+    /// Covers a bar from its bottom edge up to the top of `bar` with the named sprite
+    pub fn draw_bar(&self, sprite: &str, bar: Rect, fit: SkinFit, tint: Color) {
+        let Some(&source) = self.sprites.get(sprite) else {
+            return;
+        };
+        if bar.h <= 0.0 {
+            return;
+        }
+
+        match fit {
+            SkinFit::Stretch => self.draw_region(source, bar, tint),
+            SkinFit::Tile => {
+                let tile_height = source.h * bar.w / source.w;
+                let mut bottom = bar.bottom();
+                while bottom > bar.y + 0.5 {
+                    let height = tile_height.min(bottom - bar.y);
+                    // Partial tiles keep the bottom of the sprite, as if the bar cuts it off
+                    let cropped = source.h * height / tile_height;
+                    self.draw_region(
+                        Rect::new(source.x, source.bottom() - cropped, source.w, cropped),
+                        Rect::new(bar.x, bottom - height, bar.w, height),
+                        tint,
+                    );
+                    bottom -= tile_height;
+                }
+            }
+        }
+    }
+
+    fn draw_region(&self, source: Rect, dest: Rect, tint: Color) {
+        draw_texture_ex(
+            &self.texture,
+            dest.x,
+            dest.y,
+            tint,
+            DrawTextureParams {
+                dest_size: Some(vec2(dest.w, dest.h)),
+                source: Some(source),
+                ..Default::default()
+            },
+        );
+    }
+}
@@ -0,0 +1,654 @@
+#[cfg(any(feature = "jack", feature = "pipewire"))]
+use std::cell::Cell;
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, AtomicUsize, Ordering, fence},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "jack")]
+use ::jack::Error as JackError;
+#[cfg(feature = "pipewire")]
+use ::pipewire::Error as PipeWireError;
+#[cfg(feature = "pulseaudio")]
+use psimple::Simple;
+#[cfg(feature = "pulseaudio")]
+use pulse::error::PAErr;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[cfg(feature = "jack")]
+use crate::jack::{self, JackCapture};
+#[cfg(feature = "pipewire")]
+use crate::pipewire::{self, PipeWireCapture};
+#[cfg(feature = "pulseaudio")]
+use crate::pulseaudio;
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    /// PulseAudio refused the connection
+    #[cfg(feature = "pulseaudio")]
+    #[error("couldn't connect to PulseAudio: {0}")]
+    Connection(PAErr),
+    /// PulseAudio isn't running or the connection dropped
+    #[error("couldn't connect to PulseAudio")]
+    Disconnected,
+    /// The selection didn't match any device's index or name
+    #[error("no audio device matches `{0}`")]
+    NotFound(String),
+    /// The capture stream couldn't be opened on a source
+    #[cfg(feature = "pulseaudio")]
+    #[error("couldn't open audio source `{0}`: {1}")]
+    Stream(String, PAErr),
+    /// Reading from an open capture stream failed
+    #[cfg(feature = "pulseaudio")]
+    #[error("couldn't read from audio source: {0}")]
+    Read(PAErr),
+    /// The JACK server refused the client, or a port couldn't be registered or connected
+    #[cfg(feature = "jack")]
+    #[error("JACK error: {0}")]
+    Jack(JackError),
+    /// The JACK server stopped running the client, e.g. because it shut down
+    #[cfg(feature = "jack")]
+    #[error("JACK stopped delivering audio")]
+    JackStalled,
+    /// PipeWire refused the connection, or the capture stream couldn't be set up
+    #[cfg(feature = "pipewire")]
+    #[error("PipeWire error: {0}")]
+    PipeWire(PipeWireError),
+    /// The capture stream failed or was disconnected, e.g. because PipeWire restarted
+    #[cfg(feature = "pipewire")]
+    #[error("PipeWire capture stopped: {0}")]
+    PipeWireStopped(String),
+}
+
+/// A source that audio can be captured from
+pub struct Device {
+    /// Name to pass to the backend when opening the source
+    pub name: String,
+    pub description: String,
+    /// Whether this captures what an output is playing rather than an input like a microphone
+    pub is_monitor: bool,
+}
+
+/// Which kind of source is captured when no device is selected
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKind {
+    /// What the default output is playing
+    #[default]
+    Monitor,
+    /// The default input, with a noise gate unless one is configured
+    Microphone,
+}
+
+impl InputKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "monitor" => Some(InputKind::Monitor),
+            "microphone" => Some(InputKind::Microphone),
+            _ => None,
+        }
+    }
+}
+
+/// How the left and right channels are combined into the mono audio that's analysed
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Downmix {
+    /// The mean of the two, which cancels out anything that's out of phase between them
+    #[default]
+    Average,
+    /// The sum turned down by 3dB, so sound panned to either side keeps its level
+    Power,
+    /// Whichever channel is further from zero, so out of phase sound never cancels
+    Max,
+    /// Each channel's spectrum worked out separately and their magnitudes averaged, so phase
+    /// doesn't matter to the spectrum at all. Waveforms and loudness use the average, as does
+    /// anything that doesn't capture the channels separately, like the dual decks
+    Spectral,
+}
+
+impl Downmix {
+    /// The mono sample for one frame
+    pub fn mix(self, left: f32, right: f32) -> f32 {
+        match self {
+            Downmix::Average | Downmix::Spectral => (left + right) / 2.0,
+            Downmix::Power => (left + right) * std::f32::consts::FRAC_1_SQRT_2,
+            Downmix::Max => {
+                if left.abs() >= right.abs() {
+                    left
+                } else {
+                    right
+                }
+            }
+        }
+    }
+
+    /// The mono power spectrum for `Spectral`, from the left and right power spectra
+    pub fn combine_spectra(left: &[f32], right: &[f32]) -> Vec<f32> {
+        left.iter()
+            .zip(right)
+            .map(|(left, right)| {
+                let magnitude = (left.sqrt() + right.sqrt()) / 2.0;
+                magnitude * magnitude
+            })
+            .collect()
+    }
+}
+
+// Looks something up about the source with the given name
+type SourceQuery<T> = fn(&str) -> Result<T, AudioError>;
+
+/// A way of capturing audio, describing itself so one can be chosen at startup from those
+/// built in and working on this system
+pub struct Backend {
+    /// Name used for the backend in the config file and on the command line
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Source captured when no device is selected
+    pub default_source: &'static str,
+    /// Source captured for `InputKind::Microphone` when no device is selected, if the
+    /// backend tells inputs and monitors apart
+    pub default_input: Option<&'static str>,
+    probe: fn() -> Result<(), AudioError>,
+    open: fn(&str, usize) -> Result<Capture, AudioError>,
+    devices: fn() -> Result<Vec<Device>, AudioError>,
+    // Real name of `default_source` or `default_input`, if they stand in for whichever
+    // device is the default
+    resolve_default: Option<SourceQuery<String>>,
+    // Sample rate a source runs at, if the backend can tell
+    native_rate: Option<SourceQuery<usize>>,
+}
+
+/// Every backend in this build, in the order they're tried
+pub const BACKENDS: &[Backend] = &[
+    #[cfg(feature = "pulseaudio")]
+    Backend {
+        name: "pulseaudio",
+        description: "PulseAudio, or PipeWire through its PulseAudio server",
+        default_source: pulseaudio::DEFAULT_MONITOR,
+        default_input: Some(pulseaudio::DEFAULT_SOURCE),
+        probe: pulseaudio::probe,
+        open: |source, sample_rate| {
+            pulseaudio::open_capture(source, sample_rate).map(|stream| Capture::PulseAudio {
+                stream,
+                sample_rate,
+            })
+        },
+        devices: pulseaudio::devices,
+        resolve_default: Some(pulseaudio::resolve_default),
+        native_rate: Some(pulseaudio::source_rate),
+    },
+    #[cfg(feature = "pipewire")]
+    Backend {
+        name: "pipewire",
+        description: "PipeWire directly, able to capture a single application's output",
+        default_source: pipewire::DEFAULT_MONITOR,
+        default_input: Some(pipewire::DEFAULT_SOURCE),
+        probe: pipewire::probe,
+        open: |source, sample_rate| {
+            pipewire::open_capture(source, sample_rate).map(Capture::PipeWire)
+        },
+        devices: pipewire::devices,
+        resolve_default: None,
+        // Streams are resampled to whatever rate is asked for
+        native_rate: None,
+    },
+    #[cfg(feature = "jack")]
+    Backend {
+        name: "jack",
+        description: "JACK, or PipeWire through its JACK server, patched to by hand by default",
+        default_source: jack::UNCONNECTED,
+        default_input: Some(jack::SYSTEM_CAPTURE),
+        probe: jack::probe,
+        open: |source, sample_rate| jack::open_capture(source, sample_rate).map(Capture::Jack),
+        devices: jack::devices,
+        resolve_default: None,
+        native_rate: Some(jack::server_rate),
+    },
+    Backend {
+        name: "silence",
+        description: "No audio at all, so the visualiser still runs without a working backend",
+        default_source: "silence",
+        default_input: None,
+        probe: || Ok(()),
+        open: |_, sample_rate| Ok(Capture::Silence { sample_rate }),
+        devices: || {
+            Ok(vec![Device {
+                name: "silence".to_string(),
+                description: "Silence".to_string(),
+                is_monitor: false,
+            }])
+        },
+        resolve_default: None,
+        native_rate: None,
+    },
+];
+
+impl Backend {
+    /// The backend called `name`, if it's in this build
+    pub fn find(name: &str) -> Option<&'static Backend> {
+        BACKENDS.iter().find(|backend| backend.name == name)
+    }
+
+    /// Picks `preferred` if it's usable, otherwise the first usable backend in `BACKENDS`,
+    /// saying why and which was chosen instead
+    pub fn select(preferred: Option<&str>) -> &'static Backend {
+        let preferred = preferred.and_then(|name| {
+            let backend = Backend::find(name);
+            if backend.is_none() {
+                eprintln!("No audio backend called `{name}` in this build");
+            }
+            backend
+        });
+
+        let mut candidates = preferred.into_iter().chain(BACKENDS);
+        // The silence backend always works, so this only gives up if it's been removed
+        let chosen = candidates
+            .find(|backend| match backend.probe() {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Can't use the {} audio backend: {e}", backend.name);
+                    false
+                }
+            })
+            .expect("the silence backend is always available");
+
+        if preferred.is_none_or(|preferred| !std::ptr::eq(preferred, chosen)) {
+            eprintln!("Using the {} audio backend", chosen.name);
+        }
+
+        chosen
+    }
+
+    /// Checks the backend can be used right now, e.g. that its server is running
+    pub fn probe(&self) -> Result<(), AudioError> {
+        (self.probe)()
+    }
+
+    /// Opens a low latency stereo capture stream on the source called `source_name`
+    pub fn open(&self, source_name: &str, sample_rate: usize) -> Result<Capture, AudioError> {
+        (self.open)(source_name, sample_rate)
+    }
+
+    /// Lists every source audio can be captured from
+    pub fn devices(&self) -> Result<Vec<Device>, AudioError> {
+        (self.devices)()
+    }
+
+    /// Keeps track of the real name of the source being captured from `source`
+    ///
+    /// For a default source that follows another device, like PulseAudio's default monitor
+    /// or input, this polls the backend on a background thread, as the default can change
+    /// while running. Other sources are returned as they are
+    pub fn spawn_source_watcher(
+        &self,
+        source: String,
+        poll_interval: Duration,
+    ) -> Arc<Mutex<String>> {
+        let current = Arc::new(Mutex::new(source.clone()));
+        let Some(resolve_default) = self.resolve_default else {
+            return current;
+        };
+        if source != self.default_source && Some(source.as_str()) != self.default_input {
+            return current;
+        }
+
+        let shared = current.clone();
+        thread::spawn(move || {
+            loop {
+                if let (Ok(name), Ok(mut shared)) = (resolve_default(&source), shared.lock()) {
+                    *shared = name;
+                }
+
+                thread::sleep(poll_interval);
+            }
+        });
+
+        current
+    }
+
+    /// Finds the device matching `selection`, either its index in `devices()` or (part of)
+    /// its name or description
+    pub fn find_device(&self, selection: &str) -> Result<Device, AudioError> {
+        let mut devices = self.devices()?;
+
+        let position = match selection.parse::<usize>() {
+            Ok(index) if index < devices.len() => Some(index),
+            _ => devices
+                .iter()
+                .position(|d| d.name == selection)
+                .or_else(|| {
+                    let selection = selection.to_lowercase();
+                    devices.iter().position(|d| {
+                        d.name.to_lowercase().contains(&selection)
+                            || d.description.to_lowercase().contains(&selection)
+                    })
+                }),
+        };
+
+        match position {
+            Some(index) => Ok(devices.swap_remove(index)),
+            None => Err(AudioError::NotFound(selection.to_string())),
+        }
+    }
+
+    /// Sample rate `source` runs at, if the backend can tell, so audio can be analysed at
+    /// that rate rather than resampled to another
+    pub fn native_rate(&self, source: &str) -> Option<usize> {
+        let native_rate = self.native_rate?;
+        native_rate(source)
+            .inspect_err(|e| eprintln!("Couldn't find the sample rate of `{source}`: {e}"))
+            .ok()
+    }
+
+    /// Name of the source to capture from for `selection`, falling back to the default
+    /// source of the `input` kind if there's no selection or it can't be found
+    pub fn source_name(&self, selection: Option<&str>, input: InputKind) -> String {
+        let default = match (input, self.default_input) {
+            (InputKind::Microphone, Some(default_input)) => default_input,
+            (InputKind::Microphone, None) => {
+                eprintln!(
+                    "The {} audio backend has no default input, using its default source",
+                    self.name
+                );
+                self.default_source
+            }
+            (InputKind::Monitor, _) => self.default_source,
+        };
+        let Some(selection) = selection else {
+            return default.to_string();
+        };
+
+        match self.find_device(selection) {
+            Ok(device) => device.name,
+            Err(e) => {
+                eprintln!("{e}, using the default source");
+                default.to_string()
+            }
+        }
+    }
+}
+
+/// An open stream of interleaved stereo native-endian f32 samples
+pub enum Capture {
+    #[cfg(feature = "pulseaudio")]
+    PulseAudio { stream: Simple, sample_rate: usize },
+    #[cfg(feature = "jack")]
+    Jack(JackCapture),
+    #[cfg(feature = "pipewire")]
+    PipeWire(PipeWireCapture),
+    /// Zeros, delivered at the rate real audio would arrive
+    Silence { sample_rate: usize },
+}
+
+impl Capture {
+    /// Rate samples arrive at, which is the rate asked for unless the backend can't resample
+    pub fn sample_rate(&self) -> usize {
+        match self {
+            #[cfg(feature = "pulseaudio")]
+            Capture::PulseAudio { sample_rate, .. } => *sample_rate,
+            #[cfg(feature = "jack")]
+            Capture::Jack(capture) => capture.sample_rate(),
+            #[cfg(feature = "pipewire")]
+            Capture::PipeWire(capture) => capture.sample_rate(),
+            Capture::Silence { sample_rate } => *sample_rate,
+        }
+    }
+
+    /// Time between audio reaching the source and a read returning it, as far as the backend
+    /// can tell, counting what's buffered but not read yet
+    pub fn latency(&self) -> Duration {
+        match self {
+            #[cfg(feature = "pulseaudio")]
+            Capture::PulseAudio { stream, .. } => stream
+                .get_latency()
+                .map(|latency| Duration::from_micros(latency.0))
+                .unwrap_or_default(),
+            #[cfg(feature = "jack")]
+            Capture::Jack(capture) => capture.latency(),
+            #[cfg(feature = "pipewire")]
+            Capture::PipeWire(capture) => capture.latency(),
+            Capture::Silence { .. } => Duration::ZERO,
+        }
+    }
+
+    /// Fills `buffer` with the next samples, blocking until there are enough
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), AudioError> {
+        match self {
+            #[cfg(feature = "pulseaudio")]
+            Capture::PulseAudio { stream, .. } => stream.read(buffer).map_err(AudioError::Read),
+            #[cfg(feature = "jack")]
+            Capture::Jack(capture) => capture.read(buffer),
+            #[cfg(feature = "pipewire")]
+            Capture::PipeWire(capture) => capture.read(buffer),
+            Capture::Silence { sample_rate } => {
+                buffer.fill(0);
+                // 8 bytes per stereo frame
+                let frames = buffer.len() / 8;
+                thread::sleep(Duration::from_secs_f64(
+                    frames as f64 / (*sample_rate).max(1) as f64,
+                ));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Stereo samples pushed by a backend's realtime callback into a pair of `RingBuffer`s, and
+/// read back in order as interleaved frames by `Capture::read`
+#[cfg(any(feature = "jack", feature = "pipewire"))]
+pub(crate) struct CallbackBuffer {
+    channels: [Arc<RingBuffer>; 2],
+    // Frames read so far
+    position: Cell<usize>,
+}
+
+#[cfg(any(feature = "jack", feature = "pipewire"))]
+impl CallbackBuffer {
+    /// Keeps up to `capacity` frames that haven't been read yet
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            channels: [(); 2].map(|_| Arc::new(RingBuffer::new(capacity))),
+            position: Cell::new(0),
+        }
+    }
+
+    /// The left and right buffers for the callback to push to
+    pub fn channels(&self) -> [Arc<RingBuffer>; 2] {
+        self.channels.clone()
+    }
+
+    /// Fills `buffer` with the next interleaved stereo frames, or returns false if the
+    /// callback hasn't delivered any more for `timeout`. Frames the callback overwrote before
+    /// they were read are skipped
+    pub fn read(&self, buffer: &mut [u8], timeout: Duration) -> bool {
+        // 8 bytes per stereo frame
+        let frames = buffer.len() / 8;
+        let mut left = vec![0.0; frames];
+        let mut right = vec![0.0; frames];
+
+        let mut last_progress = (self.written(), Instant::now());
+        loop {
+            let written = self.written();
+            let capacity = self.channels[0].capacity();
+            // Skip ahead if the callback has lapped the reader
+            let lapped = written.saturating_sub(capacity.saturating_sub(frames));
+            let end = (self.position.get() + frames).max(lapped);
+
+            if self.channels[0].read_ending_at(end, &mut left)
+                && self.channels[1].read_ending_at(end, &mut right)
+            {
+                self.position.set(end);
+                break;
+            }
+
+            if written != last_progress.0 {
+                last_progress = (written, Instant::now());
+            } else if last_progress.1.elapsed() > timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        for (frame, (left, right)) in buffer.chunks_exact_mut(8).zip(left.iter().zip(&right)) {
+            frame[..4].copy_from_slice(&left.to_ne_bytes());
+            frame[4..].copy_from_slice(&right.to_ne_bytes());
+        }
+        true
+    }
+
+    /// Frames received that haven't been read yet
+    pub fn queued(&self) -> usize {
+        let queued = self.written().saturating_sub(self.position.get());
+        queued.min(self.channels[0].capacity())
+    }
+
+    // Frames both channels have received
+    fn written(&self) -> usize {
+        self.channels[0].written().min(self.channels[1].written())
+    }
+}
+
+/// Lock-free buffer of the most recent samples, written by the audio thread and read by the
+/// render thread
+///
+/// Writing never waits for the reader, it just overwrites the oldest samples. Reading copies
+/// out the latest window and retries if the writer got round to overwriting it mid-copy, so
+/// make the capacity a few times the window to keep that rare. Only one thread should write.
+///
+/// Blocks written with `push_captured` also keep when they were captured, behind a lock
+/// that's only held to add or look up one, so `captured_at` can tell when any sample still
+/// in the buffer was heard
+pub struct RingBuffer {
+    // Bits of each f32, atomic so a slot being overwritten while it's read isn't a data race
+    samples: Box<[AtomicU32]>,
+    // Total samples the writer has started writing, then finished writing. The next sample
+    // goes at `written % capacity`
+    claimed: AtomicUsize,
+    written: AtomicUsize,
+    // Total samples written at the end of each recent block, and when its last was captured
+    blocks: Mutex<VecDeque<(usize, Instant)>>,
+}
+
+impl RingBuffer {
+    /// Keeps the latest `capacity` samples
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            claimed: AtomicUsize::new(0),
+            written: AtomicUsize::new(0),
+            blocks: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Total samples written so far, including those since overwritten
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Appends `samples`, overwriting the oldest
+    pub fn push(&self, samples: &[f32]) {
+        let capacity = self.capacity();
+        let written = self.written.load(Ordering::Relaxed);
+        let total = written + samples.len();
+
+        // Anything before the last `capacity` samples would be overwritten straight away
+        let skipped = samples.len().saturating_sub(capacity);
+        let start = written + skipped;
+
+        // Readers check this after copying, so they can tell if these writes reached them
+        self.claimed.store(total, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        for (i, &sample) in samples[skipped..].iter().enumerate() {
+            self.samples[(start + i) % capacity].store(sample.to_bits(), Ordering::Relaxed);
+        }
+
+        self.written.store(total, Ordering::Release);
+    }
+
+    /// Appends `samples` as `push` does, remembering that the last of them was captured at
+    /// `captured`
+    pub fn push_captured(&self, samples: &[f32], captured: Instant) {
+        self.push(samples);
+
+        let capacity = self.capacity();
+        let written = self.written();
+        let mut blocks = self.blocks.lock().unwrap_or_else(|e| e.into_inner());
+        // A block is kept while any of its samples are, so the oldest ones can be timed too
+        while blocks
+            .get(1)
+            .is_some_and(|&(end, _)| end + capacity <= written)
+        {
+            blocks.pop_front();
+        }
+        blocks.push_back((written, captured));
+    }
+
+    /// When the sample before the `end`th written was captured, counting on from the block
+    /// it was written in at `sample_rate`. None if no block was written with `push_captured`
+    pub fn captured_at(&self, end: usize, sample_rate: usize) -> Option<Instant> {
+        let blocks = self.blocks.lock().unwrap_or_else(|e| e.into_inner());
+        // The block the sample was written in, or the latest if it hasn't been written yet
+        let &(block_end, captured) = blocks
+            .iter()
+            .find(|(block_end, _)| *block_end >= end)
+            .or(blocks.back())?;
+
+        let offset = Duration::from_secs_f64(block_end.abs_diff(end) as f64 / sample_rate as f64);
+        if block_end >= end {
+            captured.checked_sub(offset)
+        } else {
+            captured.checked_add(offset)
+        }
+    }
+
+    /// Copies the latest `window.len()` samples into `window`, oldest first, or returns false
+    /// if fewer than that have been written so far
+    pub fn read_latest(&self, window: &mut [f32]) -> bool {
+        loop {
+            let end = self.written();
+            if end < window.len() {
+                return false;
+            }
+            if self.copy_window(end, window) {
+                return true;
+            }
+        }
+    }
+
+    /// Copies the `window.len()` samples before the `end`th sample written into `window`,
+    /// oldest first. Returns false if they haven't all been written yet or have since been
+    /// overwritten
+    pub fn read_ending_at(&self, end: usize, window: &mut [f32]) -> bool {
+        end >= window.len() && end <= self.written() && self.copy_window(end, window)
+    }
+
+    // Returns false if the writer overwrote any of the window while it was being copied
+    fn copy_window(&self, end: usize, window: &mut [f32]) -> bool {
+        let capacity = self.capacity();
+        assert!(
+            window.len() <= capacity,
+            "window is larger than the ring buffer"
+        );
+
+        let start = end - window.len();
+        for (i, sample) in window.iter_mut().enumerate() {
+            *sample = f32::from_bits(self.samples[(start + i) % capacity].load(Ordering::Relaxed));
+        }
+
+        // The copy is good unless the writer has since started on the slots it read
+        fence(Ordering::Acquire);
+        self.claimed.load(Ordering::Relaxed) - start <= capacity
+    }
+}
@@ -0,0 +1,207 @@
+//! Switching scenes to suit the music, like a VJ would
+//!
+//! Each `[[scene]]` table gives a mode, colours, beat flash and sensitivity (`gain`,
+//! `normalisation` and `smoothing`, as in presets) to switch to, and ranges that
+//! features of the music, averaged over the last few seconds, have to fall in for it. The
+//! first scene whose ranges have all held for `auto.hold` seconds is switched to:
+//!
+//! ```toml
+//! [auto]
+//! window = 8.0
+//! hold = 4.0
+//!
+//! [[scene]]
+//! name = "acoustic"
+//! loudness = [-60.0, -25.0]
+//! onsets = [0.0, 2.0]
+//! mode = "waveform"
+//! colour = { mapper = "static", colour = "#ffd9a0" }
+//! flash = 0.0
+//! smoothing = { rise = 0.8, fall = 0.97 }
+//!
+//! [[scene]]
+//! name = "electronic"
+//! loudness = [-18.0, 0.0]
+//! bass = [0.6, 1.0]
+//! bpm = [118.0, 150.0]
+//! mode = "bars"
+//! flash = 0.8
+//! normalisation = { amplitude = "log2", agc = { attack = 0.01, release = 0.3 } }
+//! ```
+
+use serde::Deserialize;
+
+use crate::{
+    analysis::{Feature, Features, FrameAnalysis},
+    config::{ColourConfig, NormalisationConfig, SmoothingConfig},
+};
+
+/// How closely scene switching follows the music
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoConfig {
+    /// Seconds of music the features are averaged over
+    pub window: f32,
+    /// Seconds a scene's ranges have to keep matching before switching to it
+    pub hold: f32,
+}
+
+impl Default for AutoConfig {
+    fn default() -> Self {
+        Self {
+            window: 8.0,
+            hold: 4.0,
+        }
+    }
+}
+
+/// One `[[scene]]` table. Ranges left out match anything
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneConfig {
+    pub name: String,
+    /// Average loudness in dBFS
+    pub loudness: Option<[f32; 2]>,
+    /// Average band energies, from 0.0 to 1.0
+    pub bass: Option<[f32; 2]>,
+    pub mids: Option<[f32; 2]>,
+    pub treble: Option<[f32; 2]>,
+    /// Onsets a second
+    pub onsets: Option<[f32; 2]>,
+    /// Estimated tempo, which doesn't match while there isn't one
+    pub bpm: Option<[f32; 2]>,
+    pub mode: Option<String>,
+    pub colour: Option<ColourConfig>,
+    /// Opacity of the beat flash, 0.0 for none
+    pub flash: Option<f32>,
+    /// Decibels added to the spectrum before grouping
+    pub gain: Option<f32>,
+    pub normalisation: Option<NormalisationConfig>,
+    pub smoothing: Option<SmoothingConfig>,
+}
+
+impl SceneConfig {
+    pub fn matches(&self, features: &MusicFeatures) -> bool {
+        let within = |range: Option<[f32; 2]>, value: f32| {
+            range.is_none_or(|[min, max]| (min..=max).contains(&value))
+        };
+
+        within(self.loudness, features.loudness)
+            && within(self.bass, features.bass)
+            && within(self.mids, features.mids)
+            && within(self.treble, features.treble)
+            && within(self.onsets, features.onsets)
+            && self
+                .bpm
+                .is_none_or(|[min, max]| features.bpm.is_some_and(|bpm| (min..=max).contains(&bpm)))
+    }
+}
+
+/// Features of the music averaged over the last few seconds
+#[derive(Clone, Copy, Debug)]
+pub struct MusicFeatures {
+    pub loudness: f32,
+    pub bass: f32,
+    pub mids: f32,
+    pub treble: f32,
+    /// Onsets a second
+    pub onsets: f32,
+    pub bpm: Option<f32>,
+}
+
+/// Picks which `[[scene]]` suits the music, see the module docs
+pub struct AutoDj {
+    config: AutoConfig,
+    features: Option<MusicFeatures>,
+    last_time: f64,
+    // Scene that's matched since the given time, which is switched to once it's held
+    candidate: Option<(usize, f64)>,
+    current: Option<usize>,
+}
+
+impl AutoDj {
+    pub fn new(config: AutoConfig) -> Self {
+        Self {
+            config,
+            features: None,
+            last_time: 0.0,
+            candidate: None,
+            current: None,
+        }
+    }
+
+    /// Parts of the analysis `update` needs to choose between `scenes`, none without any
+    pub fn analysis_features(scenes: &[SceneConfig]) -> Features {
+        if scenes.is_empty() {
+            Features::NONE
+        } else {
+            Features::of(&[Feature::Bands, Feature::Onsets, Feature::Beats])
+        }
+    }
+
+    /// Feeds the analysis drawn this frame, returning the index of a scene in `scenes` to
+    /// switch to
+    pub fn update(&mut self, scenes: &[SceneConfig], analysis: &FrameAnalysis) -> Option<usize> {
+        let time = analysis.time;
+        let elapsed = (time - self.last_time) as f32;
+        // Frames without a new analysis add nothing
+        if self.features.is_some() && elapsed <= 0.0 {
+            return None;
+        }
+        self.last_time = time;
+
+        let onsets = if analysis.onset && elapsed > 0.0 {
+            1.0 / elapsed
+        } else {
+            0.0
+        };
+        let features = match &mut self.features {
+            Some(features) => {
+                // Exponential moving average with a time constant of the window
+                let weight = 1.0 - (-elapsed / self.config.window.max(f32::EPSILON)).exp();
+                let average =
+                    |average: &mut f32, value: f32| *average += (value - *average) * weight;
+                average(&mut features.loudness, analysis.loudness);
+                average(&mut features.bass, analysis.bass);
+                average(&mut features.mids, analysis.mids);
+                average(&mut features.treble, analysis.treble);
+                average(&mut features.onsets, onsets);
+                // The tempo estimate is already steady over several seconds
+                features.bpm = analysis.bpm;
+                *features
+            }
+            None => *self.features.insert(MusicFeatures {
+                loudness: analysis.loudness,
+                bass: analysis.bass,
+                mids: analysis.mids,
+                treble: analysis.treble,
+                onsets: 0.0,
+                bpm: analysis.bpm,
+            }),
+        };
+
+        let Some(matching) = scenes.iter().position(|scene| scene.matches(&features)) else {
+            self.candidate = None;
+            return None;
+        };
+        if self.current == Some(matching) {
+            self.candidate = None;
+            return None;
+        }
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == matching => since,
+            _ => {
+                self.candidate = Some((matching, time));
+                time
+            }
+        };
+        if time - since < self.config.hold as f64 {
+            return None;
+        }
+
+        self.candidate = None;
+        self.current = Some(matching);
+        Some(matching)
+    }
+}
@@ -0,0 +1,403 @@
+use std::f32::consts::TAU;
+
+use serde::Deserialize;
+
+use crate::{
+    analysis::{self, Features, FrameAnalysis},
+    expression::Binding,
+};
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+}
+
+/// Analysis values usable as modulation sources, each scaled to roughly 0.0 to 1.0
+#[derive(Clone, Copy)]
+pub enum Feature {
+    /// -60dBFS to 0dBFS
+    Loudness,
+    Bass,
+    Mids,
+    Treble,
+    /// 1.0 on frames with an onset, otherwise 0.0
+    Onset,
+    BeatPhase,
+    /// Confidence of the beat on frames with one, otherwise 0.0
+    Beat,
+}
+
+impl Feature {
+    fn features(&self) -> Features {
+        match self {
+            Feature::Loudness => Features::NONE,
+            Feature::Bass | Feature::Mids | Feature::Treble => analysis::Feature::Bands.into(),
+            Feature::Onset => analysis::Feature::Onsets.into(),
+            Feature::BeatPhase | Feature::Beat => analysis::Feature::Beats.into(),
+        }
+    }
+
+    fn value(&self, analysis: &FrameAnalysis) -> f32 {
+        match self {
+            Feature::Loudness => analysis.normalised_loudness(),
+            Feature::Bass => analysis.bass,
+            Feature::Mids => analysis.mids,
+            Feature::Treble => analysis.treble,
+            Feature::Onset => analysis.onset as u8 as f32,
+            Feature::BeatPhase => analysis.beat_phase.unwrap_or(0.0),
+            Feature::Beat => analysis.beat.map_or(0.0, |beat| beat.confidence),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Modulator {
+    /// Free-running oscillator from -1.0 to 1.0. `phase` is in cycles (0.0 to 1.0)
+    Lfo {
+        shape: LfoShape,
+        frequency: f32,
+        phase: f32,
+    },
+    /// Rises to 1.0 over `attack` seconds on every onset, then decays with time constant `decay`
+    BeatEnvelope {
+        attack: f32,
+        decay: f32,
+    },
+    Feature(Feature),
+}
+
+/// Parameters of the visual engine that modulations can target
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Parameter {
+    /// Degrees added to the hue of the bar colour
+    HueOffset,
+    /// Multiplier on bar heights
+    Zoom,
+    SmoothingRise,
+    SmoothingFall,
+    /// Crossfade from the primary colour mapper (0.0) to the secondary one (1.0)
+    ColourBlend,
+}
+
+impl Parameter {
+    pub const ALL: [Parameter; 5] = [
+        Parameter::HueOffset,
+        Parameter::Zoom,
+        Parameter::SmoothingRise,
+        Parameter::SmoothingFall,
+        Parameter::ColourBlend,
+    ];
+
+    /// Name used in expression bindings and timelines
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::HueOffset => "hue",
+            Parameter::Zoom => "zoom",
+            Parameter::SmoothingRise => "rise",
+            Parameter::SmoothingFall => "fall",
+            Parameter::ColourBlend => "blend",
+        }
+    }
+
+    /// Looks up a parameter by the name used in expression bindings
+    pub fn from_name(name: &str) -> Option<Self> {
+        Parameter::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// Current value of every modulatable parameter
+#[derive(Clone, Copy)]
+pub struct Parameters {
+    pub hue_offset: f32,
+    pub zoom: f32,
+    pub smoothing_rise: f32,
+    pub smoothing_fall: f32,
+    pub colour_blend: f32,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            hue_offset: 0.0,
+            zoom: 1.0,
+            smoothing_rise: 0.5,
+            smoothing_fall: 0.9,
+            colour_blend: 0.0,
+        }
+    }
+}
+
+impl Parameters {
+    pub fn get(&self, parameter: Parameter) -> f32 {
+        match parameter {
+            Parameter::HueOffset => self.hue_offset,
+            Parameter::Zoom => self.zoom,
+            Parameter::SmoothingRise => self.smoothing_rise,
+            Parameter::SmoothingFall => self.smoothing_fall,
+            Parameter::ColourBlend => self.colour_blend,
+        }
+    }
+
+    pub fn get_mut(&mut self, parameter: Parameter) -> &mut f32 {
+        match parameter {
+            Parameter::HueOffset => &mut self.hue_offset,
+            Parameter::Zoom => &mut self.zoom,
+            Parameter::SmoothingRise => &mut self.smoothing_rise,
+            Parameter::SmoothingFall => &mut self.smoothing_fall,
+            Parameter::ColourBlend => &mut self.colour_blend,
+        }
+    }
+
+    /// Keeps modulated values within the range each parameter can sensibly take
+    pub fn clamp(&mut self) {
+        self.hue_offset = self.hue_offset.rem_euclid(360.0);
+        self.zoom = self.zoom.max(0.0);
+        self.smoothing_rise = self.smoothing_rise.clamp(0.0, 0.99);
+        self.smoothing_fall = self.smoothing_fall.clamp(0.0, 0.99);
+        self.colour_blend = self.colour_blend.clamp(0.0, 1.0);
+    }
+}
+
+/// A single route in the modulation matrix: `source * amount` is added to `target`
+#[derive(Clone, Copy)]
+pub struct Modulation {
+    pub target: Parameter,
+    pub source: Modulator,
+    pub amount: f32,
+}
+
+struct Route {
+    modulation: Modulation,
+    // Time of the onset that last triggered this route's envelope
+    triggered_at: Option<f64>,
+}
+
+/// Where a `[[modulation]]` route's value comes from: an LFO, an envelope triggered by
+/// onsets, or one of the analysis features
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceKind {
+    Lfo,
+    Envelope,
+    Loudness,
+    Bass,
+    Mids,
+    Treble,
+    Onset,
+    BeatPhase,
+    Beat,
+}
+
+/// One `[[modulation]]` table, adding `source * amount` to `target` every frame
+///
+/// ```toml
+/// [[modulation]]
+/// target = "hue"
+/// source = "lfo"
+/// amount = 30.0
+/// shape = "triangle"
+/// frequency = 0.1
+/// ```
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ModulationConfig {
+    /// Parameter modulated, by the names used in expression bindings
+    pub target: String,
+    pub source: SourceKind,
+    pub amount: f32,
+    /// Only used by `lfo`
+    #[serde(default = "default_shape")]
+    pub shape: LfoShape,
+    /// Cycles a second, only used by `lfo`
+    #[serde(default = "default_frequency")]
+    pub frequency: f32,
+    /// Cycles the LFO starts into, from 0.0 to 1.0
+    #[serde(default)]
+    pub phase: f32,
+    /// Seconds the envelope rises for, only used by `envelope`
+    #[serde(default = "default_attack")]
+    pub attack: f32,
+    /// Seconds the envelope takes to decay to about a third, only used by `envelope`
+    #[serde(default = "default_decay")]
+    pub decay: f32,
+}
+
+fn default_shape() -> LfoShape {
+    LfoShape::Sine
+}
+
+fn default_frequency() -> f32 {
+    0.25
+}
+
+fn default_attack() -> f32 {
+    0.01
+}
+
+fn default_decay() -> f32 {
+    0.3
+}
+
+impl ModulationConfig {
+    /// The route this table describes, or an error naming what's wrong with it
+    pub fn modulation(&self) -> Result<Modulation, String> {
+        let target = Parameter::from_name(&self.target)
+            .ok_or_else(|| format!("unknown modulation target `{}`", self.target))?;
+        if !(self.frequency.is_finite() && self.frequency >= 0.0) {
+            return Err("modulation `frequency` can't be negative".to_string());
+        }
+        if !(self.attack >= 0.0 && self.decay >= 0.0) {
+            return Err("modulation `attack` and `decay` can't be negative".to_string());
+        }
+
+        let source = match self.source {
+            SourceKind::Lfo => Modulator::Lfo {
+                shape: self.shape,
+                frequency: self.frequency,
+                phase: self.phase,
+            },
+            SourceKind::Envelope => Modulator::BeatEnvelope {
+                attack: self.attack,
+                decay: self.decay,
+            },
+            SourceKind::Loudness => Modulator::Feature(Feature::Loudness),
+            SourceKind::Bass => Modulator::Feature(Feature::Bass),
+            SourceKind::Mids => Modulator::Feature(Feature::Mids),
+            SourceKind::Treble => Modulator::Feature(Feature::Treble),
+            SourceKind::Onset => Modulator::Feature(Feature::Onset),
+            SourceKind::BeatPhase => Modulator::Feature(Feature::BeatPhase),
+            SourceKind::Beat => Modulator::Feature(Feature::Beat),
+        };
+
+        Ok(Modulation {
+            target,
+            source,
+            amount: self.amount,
+        })
+    }
+}
+
+/// Modulates visual parameters every frame from LFOs, beat-triggered envelopes
+/// and audio features
+pub struct ModulationMatrix {
+    bindings: Vec<Binding>,
+    routes: Vec<Route>,
+}
+
+impl Default for ModulationMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModulationMatrix {
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Replaces the base value of the binding's target with its expression every frame,
+    /// before any modulations are added
+    pub fn with_binding(mut self, binding: Binding) -> Self {
+        self.bindings.push(binding);
+        self
+    }
+
+    pub fn with_modulation(mut self, modulation: Modulation) -> Self {
+        self.routes.push(Route {
+            modulation,
+            triggered_at: None,
+        });
+        self
+    }
+
+    /// Parts of the analysis the bindings and routes read
+    pub fn features(&self) -> Features {
+        let bindings = self
+            .bindings
+            .iter()
+            .map(|binding| binding.expression.features());
+        let routes = self
+            .routes
+            .iter()
+            .map(|route| match route.modulation.source {
+                Modulator::Lfo { .. } => Features::NONE,
+                Modulator::BeatEnvelope { .. } => analysis::Feature::Onsets.into(),
+                Modulator::Feature(feature) => feature.features(),
+            });
+        bindings
+            .chain(routes)
+            .fold(Features::NONE, |features, more| features | more)
+    }
+
+    /// Returns `base` with bindings evaluated and every route's contribution for this frame added on
+    pub fn apply(&mut self, base: Parameters, analysis: &FrameAnalysis) -> Parameters {
+        let mut parameters = base;
+
+        for binding in &self.bindings {
+            *parameters.get_mut(binding.target) = binding.expression.evaluate(analysis);
+        }
+
+        for route in &mut self.routes {
+            let Modulation {
+                target,
+                source,
+                amount,
+            } = route.modulation;
+
+            let value = match source {
+                Modulator::Lfo {
+                    shape,
+                    frequency,
+                    phase,
+                } => lfo(shape, (analysis.time as f32 * frequency + phase).fract()),
+                Modulator::BeatEnvelope { attack, decay } => {
+                    if analysis.onset {
+                        route.triggered_at = Some(analysis.time);
+                    }
+
+                    route.triggered_at.map_or(0.0, |at| {
+                        envelope((analysis.time - at) as f32, attack, decay)
+                    })
+                }
+                Modulator::Feature(feature) => feature.value(analysis),
+            };
+
+            *parameters.get_mut(target) += value * amount;
+        }
+
+        parameters.clamp();
+        parameters
+    }
+}
+
+/// Value of an LFO `cycle` of the way (0.0 to 1.0) through its period
+fn lfo(shape: LfoShape, cycle: f32) -> f32 {
+    match shape {
+        LfoShape::Sine => (cycle * TAU).sin(),
+        LfoShape::Triangle => 1.0 - 4.0 * (cycle - 0.5).abs(),
+        LfoShape::Saw => 2.0 * cycle - 1.0,
+        LfoShape::Square => {
+            if cycle < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+    }
+}
+
+/// Attack-decay envelope `elapsed` seconds after being triggered
+fn envelope(elapsed: f32, attack: f32, decay: f32) -> f32 {
+    if elapsed < attack {
+        elapsed / attack
+    } else {
+        (-(elapsed - attack) / decay.max(f32::EPSILON)).exp()
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use crate::bins;
+
+// Only the kick and bass region is considered, where beats are clearest
+const BEAT_MAX_HZ: f32 = 200.0;
+// Seconds of history the thresholds adapt to
+const HISTORY_SECONDS: f32 = 1.0;
+// Shortest gap between beats, in seconds (240bpm)
+const MIN_GAP_SECONDS: f32 = 0.25;
+// Low band energy has to exceed its recent average by this factor...
+const ENERGY_RATIO: f32 = 1.3;
+// ...and its spectral flux exceed the recent average by this many standard deviations
+const FLUX_DEVIATIONS: f32 = 1.5;
+
+/// A beat detected in the audio
+#[derive(Clone, Copy, Debug)]
+pub struct BeatEvent {
+    /// Time of the frame the beat was detected on, in seconds
+    pub time: f64,
+    /// How clearly the frame stood out from its surroundings, from 0.0 (only just) to 1.0
+    pub confidence: f32,
+    /// Low band energy relative to its recent average
+    pub strength: f32,
+}
+
+/// Detects beats from successive FFT frames by combining energy and spectral flux in the
+/// low frequencies
+///
+/// A frame is a beat when the energy below 200Hz jumps well above its average over the last
+/// second and that jump is also a sharp rise in spectral flux, so sustained bass notes don't
+/// count. Confidence grows with how far past both thresholds the frame is
+pub struct BeatDetector {
+    sampling_rate: usize,
+    previous_band: Vec<f32>,
+    energy_history: VecDeque<f32>,
+    flux_history: VecDeque<f32>,
+    history_len: usize,
+    min_gap: usize,
+    frames_since_beat: usize,
+}
+
+impl BeatDetector {
+    /// Expects one frame every `1 / frame_rate` seconds
+    pub fn new(sampling_rate: usize, frame_rate: usize) -> Self {
+        let history_len = ((HISTORY_SECONDS * frame_rate as f32) as usize).max(2);
+
+        Self {
+            sampling_rate,
+            previous_band: Vec::new(),
+            energy_history: VecDeque::with_capacity(history_len),
+            flux_history: VecDeque::with_capacity(history_len),
+            history_len,
+            min_gap: (MIN_GAP_SECONDS * frame_rate as f32) as usize,
+            frames_since_beat: usize::MAX,
+        }
+    }
+
+    /// Feeds the power spectrum of the frame at `time`, returning a beat if one falls on it
+    pub fn update(&mut self, spectrum: &[f32], time: f64) -> Option<BeatEvent> {
+        let freq_per_bin = bins::bin_width(spectrum.len(), self.sampling_rate);
+        let band_bins = ((BEAT_MAX_HZ / freq_per_bin).ceil() as usize).max(1);
+        let band = &spectrum[..band_bins.min(spectrum.len())];
+
+        let energy: f32 = band.iter().sum();
+        let flux: f32 = if self.previous_band.len() == band.len() {
+            band.iter()
+                .zip(&self.previous_band)
+                .map(|(&current, &previous)| {
+                    ((current + 1.0).ln() - (previous + 1.0).ln()).max(0.0)
+                })
+                .sum()
+        } else {
+            0.0
+        };
+        self.previous_band.clear();
+        self.previous_band.extend_from_slice(band);
+        self.frames_since_beat = self.frames_since_beat.saturating_add(1);
+
+        let full = self.energy_history.len() == self.history_len;
+        let (mean_energy, _) = mean_and_deviation(&self.energy_history);
+        let (mean_flux, flux_deviation) = mean_and_deviation(&self.flux_history);
+
+        if full {
+            self.energy_history.pop_front();
+            self.flux_history.pop_front();
+        }
+        self.energy_history.push_back(energy);
+        self.flux_history.push_back(flux);
+
+        // Wait for a full history so the thresholds are meaningful
+        if !full || self.frames_since_beat < self.min_gap || mean_energy <= f32::EPSILON {
+            return None;
+        }
+
+        let strength = energy / mean_energy;
+        let deviations = (flux - mean_flux) / flux_deviation.max(f32::EPSILON);
+        if strength < ENERGY_RATIO || deviations < FLUX_DEVIATIONS {
+            return None;
+        }
+
+        self.frames_since_beat = 0;
+        let margin = (strength - ENERGY_RATIO) / ENERGY_RATIO
+            + (deviations - FLUX_DEVIATIONS) / FLUX_DEVIATIONS;
+
+        Some(BeatEvent {
+            time,
+            confidence: 1.0 - (-margin).exp(),
+            strength,
+        })
+    }
+}
+
+fn mean_and_deviation(values: &VecDeque<f32>) -> (f32, f32) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+
+    let count = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / count;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+
+    (mean, variance.sqrt())
+}
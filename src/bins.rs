@@ -0,0 +1,104 @@
+//! How the bins of a power spectrum map to frequencies
+//!
+//! A real FFT of `fft_size` samples gives `fft_size / 2 + 1` bins, evenly spaced from 0Hz
+//! to the Nyquist frequency (half the sample rate), both included. Bin `k` is centred on
+//! `k * sample_rate / fft_size` Hz. Every spectrum in the crate is laid out like this, so
+//! anything needing frequencies works them out here from the spectrum's length rather than
+//! assuming it
+//!
+//! Part of the `no_std` analysis core, and needs nothing from `alloc` either
+
+/// Number of bins in the spectrum of an FFT of `fft_size` samples
+pub fn bin_count(fft_size: usize) -> usize {
+    fft_size / 2 + 1
+}
+
+/// Size of the FFT a spectrum of `bins` bins came from
+pub fn fft_size(bins: usize) -> usize {
+    bins.saturating_sub(1) * 2
+}
+
+/// Hz between the centres of neighbouring bins in a spectrum of `bins` bins
+pub fn bin_width(bins: usize, sample_rate: usize) -> f32 {
+    sample_rate as f32 / fft_size(bins).max(1) as f32
+}
+
+/// Frequency in Hz at the centre of `bin`, in a spectrum of `bins` bins
+pub fn bin_frequency(bin: usize, bins: usize, sample_rate: usize) -> f32 {
+    bin as f32 * bin_width(bins, sample_rate)
+}
+
+/// Position of `frequency` in a spectrum of `bins` bins, in bins from 0Hz. Round or truncate
+/// it to get the index of a bin
+pub fn frequency_bin(frequency: f32, bins: usize, sample_rate: usize) -> f32 {
+    frequency / bin_width(bins, sample_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 44_100;
+
+    #[test]
+    fn spectrum_includes_dc_and_nyquist() {
+        assert_eq!(bin_count(2048), 1025);
+        assert_eq!(fft_size(1025), 2048);
+        assert_eq!(fft_size(bin_count(512)), 512);
+    }
+
+    #[test]
+    fn first_and_last_bins_span_zero_to_nyquist() {
+        let bins = bin_count(2048);
+        assert_eq!(bin_frequency(0, bins, SAMPLE_RATE), 0.0);
+        assert_eq!(bin_frequency(bins - 1, bins, SAMPLE_RATE), 22_050.0);
+    }
+
+    #[test]
+    fn bins_are_sample_rate_over_fft_size_apart() {
+        let bins = bin_count(1024);
+        let width = SAMPLE_RATE as f32 / 1024.0;
+        assert_eq!(bin_width(bins, SAMPLE_RATE), width);
+        assert_eq!(bin_frequency(10, bins, SAMPLE_RATE), 10.0 * width);
+    }
+
+    #[test]
+    fn frequency_bin_inverts_bin_frequency() {
+        let bins = bin_count(4096);
+        for bin in [0, 1, 41, 1000, 2048] {
+            let frequency = bin_frequency(bin, bins, SAMPLE_RATE);
+            assert!((frequency_bin(frequency, bins, SAMPLE_RATE) - bin as f32).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn pitch_spectrum_uses_the_same_mapping() {
+        let bins = bin_count(8192);
+        let mut spectrum = [0.0; 4097];
+        spectrum[frequency_bin(440.0, bins, SAMPLE_RATE).round() as usize] = 1.0;
+        let pitches = crate::chroma::frequency_to_pitch_spectrum(&spectrum, SAMPLE_RATE);
+        assert_eq!(pitches[69], 1.0);
+    }
+
+    #[test]
+    fn log_ranges_start_at_dc_and_end_on_their_top_frequency() {
+        use crate::grouping::GroupingStrategy;
+
+        let grouping = GroupingStrategy::LogMax { num_groups: 32 };
+        let bins = bin_count(2048);
+        let ranges = grouping.create_ranges(SAMPLE_RATE, 2048);
+        assert_eq!(ranges[0], (0, 1));
+        let top = frequency_bin(20_000.0, bins, SAMPLE_RATE).round() as usize;
+        assert_eq!(ranges.last().unwrap().1, top);
+
+        // 20kHz is above Nyquist here, so the top range ends with the spectrum
+        let ranges = grouping.create_ranges(32_000, 2048);
+        assert_eq!(ranges.last().unwrap().1, bins);
+    }
+
+    #[test]
+    fn empty_spectrum_has_no_width() {
+        assert_eq!(fft_size(0), 0);
+        assert!(bin_width(0, SAMPLE_RATE).is_finite());
+    }
+}
@@ -0,0 +1,79 @@
+//! Keeping the history the visualiser holds on to within a fixed amount of memory
+//!
+//! ```toml
+//! memory_budget = 48
+//! ```
+//!
+//! Most of what's analysed is thrown away once it's drawn, but a few things keep history
+//! that grows with the window or the session: the spectrogram's columns, the trails'
+//! offscreen buffer, the piano roll's notes and the session transcription exported as MIDI.
+//! `memory_budget` is the most, in MiB, those are allowed between them, shared out in fixed
+//! parts. Each keeps to its part by holding less: the spectrogram shows fewer seconds, the
+//! trails are drawn at a lower resolution and the oldest notes are forgotten. Whatever's cut
+//! is shown, the spectrogram leaving the time it's lost blank and the HUD counting the notes
+//! dropped from the session, and MIDI exports warn that they start late
+//!
+//! Left out, nothing is capped, which is fine on a desktop but can run a Raspberry Pi left on
+//! overnight out of memory
+
+use crate::transcription::NoteEvent;
+
+const MEBIBYTE: f32 = 1024.0 * 1024.0;
+// Parts of the budget each history gets, adding up to 1.0
+const SPECTROGRAM_SHARE: f32 = 0.4;
+const TRAILS_SHARE: f32 = 0.4;
+const NOTES_SHARE: f32 = 0.05;
+const SESSION_SHARE: f32 = 0.15;
+// Bytes each spectrogram cell takes, its level and its pixel
+const SPECTROGRAM_CELL: usize = size_of::<f32>() + 4;
+// Bytes each pixel of the trails' buffer takes
+const TRAIL_PIXEL: usize = 4;
+
+/// How much of a memory budget each history is given
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryBudget {
+    // None for no limit
+    bytes: Option<usize>,
+}
+
+impl MemoryBudget {
+    /// A budget of `megabytes` MiB, or no limit if none is given
+    pub fn new(megabytes: Option<f32>) -> Self {
+        Self {
+            bytes: megabytes.map(|megabytes| (megabytes.max(0.0) * MEBIBYTE) as usize),
+        }
+    }
+
+    /// The whole budget in MiB, or none if there's no limit
+    pub fn megabytes(&self) -> Option<f32> {
+        self.bytes.map(|bytes| bytes as f32 / MEBIBYTE)
+    }
+
+    /// Most columns a spectrogram of `rows` rows can keep, up to `max`
+    pub fn spectrogram_columns(&self, rows: usize, max: usize) -> usize {
+        self.share(SPECTROGRAM_SHARE).map_or(max, |bytes| {
+            (bytes / (rows * SPECTROGRAM_CELL).max(1)).min(max)
+        })
+    }
+
+    /// Most pixels the trails' offscreen buffer can have, or none if it can match the screen
+    pub fn trail_pixels(&self) -> Option<usize> {
+        self.share(TRAILS_SHARE).map(|bytes| bytes / TRAIL_PIXEL)
+    }
+
+    /// Most notes the piano roll keeps
+    pub fn note_events(&self) -> Option<usize> {
+        self.share(NOTES_SHARE)
+            .map(|bytes| bytes / size_of::<NoteEvent>())
+    }
+
+    /// Most notes the session transcription keeps, across all its tracks
+    pub fn session_events(&self) -> Option<usize> {
+        self.share(SESSION_SHARE)
+            .map(|bytes| bytes / size_of::<NoteEvent>())
+    }
+
+    fn share(&self, share: f32) -> Option<usize> {
+        self.bytes.map(|bytes| (bytes as f32 * share) as usize)
+    }
+}
@@ -0,0 +1,215 @@
+//! Screenshots and short recordings of the visualiser, taken from the keyboard
+//!
+//! ```toml
+//! [capture]
+//! directory = "captures"
+//! seconds = 4.0
+//! fps = 20
+//! max_width = 800
+//! ```
+//!
+//! F12 saves the frame on screen as a PNG, and Ctrl+R records the next `seconds` as an
+//! animated PNG, which browsers and most image viewers play on a loop. Recordings are taken
+//! at `fps` and shrunk to at most `max_width` pixels wide to keep them small, while
+//! screenshots are full size. Both go in `directory`, named by when they were taken, like
+//! `screenshot-20250614-213045-120.png`
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use macroquad::texture::{Image, get_screen_data};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::schedule::local_time;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("couldn't write capture: {0}")]
+    Io(#[from] io::Error),
+    #[error("couldn't encode PNG: {0}")]
+    Png(#[from] png::EncodingError),
+}
+
+/// The `[capture]` table, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Where screenshots and recordings are saved, created if it doesn't exist
+    pub directory: PathBuf,
+    /// Length of each recording
+    pub seconds: f32,
+    /// Frames a second of recordings
+    pub fps: u16,
+    /// Widest recordings are, in pixels
+    pub max_width: u16,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("."),
+            seconds: 4.0,
+            fps: 20,
+            max_width: 800,
+        }
+    }
+}
+
+// A recording in progress, written a frame at a time so it's never all in memory
+struct Recording {
+    path: PathBuf,
+    writer: png::Writer<BufWriter<File>>,
+    frames_left: u32,
+    // Time the next frame is due to be taken
+    next_frame: f64,
+    // Screen pixels per recorded pixel in each direction
+    step: usize,
+}
+
+/// Takes screenshots and recordings of what's on screen
+pub struct Capture {
+    config: CaptureConfig,
+    recording: Option<Recording>,
+}
+
+impl Capture {
+    pub fn new(config: CaptureConfig) -> Self {
+        Self {
+            config,
+            recording: None,
+        }
+    }
+
+    /// Saves the frame on screen as a PNG, returning where it was saved
+    pub fn screenshot(&self) -> Result<PathBuf, CaptureError> {
+        let path = self.new_path("screenshot")?;
+        let (pixels, width, height) = shrink(&get_screen_data(), 1);
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(&path)?), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts recording from the frame on screen at `now` seconds, returning where the
+    /// recording will be saved
+    pub fn start_recording(&mut self, now: f64) -> Result<PathBuf, CaptureError> {
+        let path = self.new_path("recording")?;
+        let screen = get_screen_data();
+        let step = (screen.width as usize).div_ceil(self.config.max_width.max(1) as usize);
+        let (width, height) = (
+            (screen.width as usize).div_ceil(step) as u32,
+            (screen.height as usize).div_ceil(step) as u32,
+        );
+        let fps = self.config.fps.max(1);
+        let frames = ((self.config.seconds * fps as f32).round() as u32).max(1);
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(&path)?), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Played on a loop
+        encoder.set_animated(frames, 0)?;
+        encoder.set_frame_delay(1, fps)?;
+
+        self.recording = Some(Recording {
+            path: path.clone(),
+            writer: encoder.write_header()?,
+            frames_left: frames,
+            next_frame: now,
+            step,
+        });
+        Ok(path)
+    }
+
+    /// Adds the frame on screen at `now` seconds to the recording if one's due, returning
+    /// where the recording was saved once it's finished. Call after each frame is drawn
+    pub fn update(&mut self, now: f64) -> Result<Option<PathBuf>, CaptureError> {
+        let Some(recording) = &mut self.recording else {
+            return Ok(None);
+        };
+        if now < recording.next_frame {
+            return Ok(None);
+        }
+
+        let (pixels, ..) = shrink(&get_screen_data(), recording.step);
+        // Frames stop fitting if the window's resized, which abandons the recording
+        if let Err(e) = recording.writer.write_image_data(&pixels) {
+            self.cancel();
+            return Err(e.into());
+        }
+        recording.frames_left -= 1;
+        // Kept on schedule, unless drawing has fallen a whole frame behind
+        recording.next_frame =
+            (recording.next_frame + 1.0 / self.config.fps.max(1) as f64).max(now);
+        if recording.frames_left > 0 {
+            return Ok(None);
+        }
+
+        let Some(recording) = self.recording.take() else {
+            return Ok(None);
+        };
+        recording.writer.finish()?;
+        Ok(Some(recording.path))
+    }
+
+    /// Stops any recording, deleting it as it's unfinished
+    pub fn cancel(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            drop(recording.writer);
+            let _ = fs::remove_file(recording.path);
+        }
+    }
+
+    // A path in the capture directory named by `kind` and the current local time
+    fn new_path(&self, kind: &str) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.config.directory)?;
+
+        let now = SystemTime::now();
+        let tm = local_time(now);
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_millis();
+
+        Ok(self.config.directory.join(format!(
+            "{kind}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.png",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec,
+            millis,
+        )))
+    }
+}
+
+// The screen's pixels top row first, taking every `step`th pixel in each direction, and
+// the size they make up
+fn shrink(screen: &Image, step: usize) -> (Vec<u8>, u32, u32) {
+    let (width, height) = (screen.width as usize, screen.height as usize);
+    let mut pixels = Vec::with_capacity(width.div_ceil(step) * height.div_ceil(step) * 4);
+    // Read back bottom row first
+    for y in (0..height).rev().step_by(step) {
+        let row = &screen.bytes[y * width * 4..(y + 1) * width * 4];
+        for pixel in row.chunks_exact(4).step_by(step) {
+            // Drawn over an opaque background, so any transparency is left from blending
+            pixels.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+        }
+    }
+    (
+        pixels,
+        width.div_ceil(step) as u32,
+        height.div_ceil(step) as u32,
+    )
+}
@@ -0,0 +1,120 @@
+use crate::{
+    pitch::{Scale, ScaleKind},
+    spectra::chroma_index_to_note,
+};
+
+// Krumhansl-Kessler key profiles: how strongly each scale degree is associated with a key
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+}
+
+/// A triad identified from a chromagram, rooted on a chroma index (0 = C ... 11 = B)
+#[derive(Clone, Copy, PartialEq)]
+pub struct Chord {
+    pub root: usize,
+    pub quality: ChordQuality,
+}
+
+impl Chord {
+    /// Chroma indices of the root, third and fifth
+    pub fn chroma_indices(&self) -> [usize; 3] {
+        let third = match self.quality {
+            ChordQuality::Major => 4,
+            ChordQuality::Minor => 3,
+        };
+
+        [self.root, (self.root + third) % 12, (self.root + 7) % 12]
+    }
+
+    pub fn name(&self) -> String {
+        match self.quality {
+            ChordQuality::Major => chroma_index_to_note(self.root),
+            ChordQuality::Minor => format!("{}m", chroma_index_to_note(self.root)),
+        }
+    }
+}
+
+/// Matches a 12-bin chromagram against the 24 major and minor triad templates
+///
+/// Returns `None` when the chromagram is silent or no triad stands out from the
+/// rest of the energy by at least `min_score` (cosine similarity, 0.0 to 1.0)
+pub fn detect_chord(chromagram: &[f32; 12], min_score: f32) -> Option<Chord> {
+    let norm = chromagram.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if norm <= f32::EPSILON {
+        return None;
+    }
+
+    let mut best: Option<(Chord, f32)> = None;
+
+    for root in 0..12 {
+        for quality in [ChordQuality::Major, ChordQuality::Minor] {
+            let chord = Chord { root, quality };
+
+            // Triad templates have three equal entries, so their norm is sqrt(3)
+            let score = chord
+                .chroma_indices()
+                .iter()
+                .map(|&i| chromagram[i])
+                .sum::<f32>()
+                / (norm * 3.0_f32.sqrt());
+
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((chord, score));
+            }
+        }
+    }
+
+    best.filter(|&(_, score)| score >= min_score)
+        .map(|(chord, _)| chord)
+}
+
+/// Estimates the key of a piece from its chromagram accumulated over time
+///
+/// Correlates the chroma profile against the major and minor Krumhansl-Kessler
+/// profiles in all 12 rotations and returns the best match, or `None` for silence
+pub fn detect_key(chroma_profile: &[f32; 12]) -> Option<Scale> {
+    if chroma_profile.iter().sum::<f32>() <= f32::EPSILON {
+        return None;
+    }
+
+    let mut best: Option<(Scale, f32)> = None;
+
+    for root in 0..12 {
+        for (kind, profile) in [
+            (ScaleKind::Major, &MAJOR_PROFILE),
+            (ScaleKind::NaturalMinor, &MINOR_PROFILE),
+        ] {
+            let rotated: [f32; 12] = std::array::from_fn(|i| profile[(i + 12 - root) % 12]);
+            let score = pearson_correlation(chroma_profile, &rotated);
+
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((Scale::new(root, kind), score));
+            }
+        }
+    }
+
+    best.map(|(scale, _)| scale)
+}
+
+fn pearson_correlation(a: &[f32; 12], b: &[f32; 12]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / 12.0;
+    let mean_b = b.iter().sum::<f32>() / 12.0;
+
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+
+    covariance / (variance_a * variance_b).sqrt().max(f32::EPSILON)
+}
@@ -0,0 +1,81 @@
+//! Pitch and chroma math on power spectra
+//!
+//! Part of the `no_std` analysis core along with `grouping` and `smoothing`, so only needs
+//! `alloc`
+
+use alloc::vec::Vec;
+use libm::{log2f, roundf};
+
+use crate::bins::bin_width;
+
+/// Takes a frequency-domain spectrum of any length and
+///  groups it into a 128-pitch log frequency spectrogram
+///
+///  `frequencies` is laid out as described in `bins`
+pub fn frequency_to_pitch_spectrum(frequencies: &[f32], sampling_rate: usize) -> [f32; 128] {
+    let min_pitch: usize = 40; // E2
+    let max_pitch: usize = 84; // C6
+
+    // Ignore pitches outside desired range (e.g ignore signals from percussion instruments)
+    frequency_to_pitch_spectrum_in_range(frequencies, sampling_rate, min_pitch, max_pitch)
+}
+
+/// Same as `frequency_to_pitch_spectrum`, but only keeps MIDI pitches within `min_pitch..=max_pitch`
+pub fn frequency_to_pitch_spectrum_in_range(
+    frequencies: &[f32],
+    sampling_rate: usize,
+    min_pitch: usize,
+    max_pitch: usize,
+) -> [f32; 128] {
+    let mut spectrogram = [0.0; 128];
+    let freq_per_bin = bin_width(frequencies.len(), sampling_rate);
+
+    for (bin_idx, value) in frequencies.iter().enumerate() {
+        let bin_freq = bin_idx as f32 * freq_per_bin;
+        let pitch = 69.0 + 12.0 * log2f(bin_freq / 440.0); // MIDI pitch estimate
+        let pitch_idx = roundf(pitch) as usize;
+        if pitch_idx < min_pitch || pitch_idx > max_pitch {
+            continue;
+        }
+        if pitch_idx < 128 {
+            spectrogram[pitch_idx] += value;
+        }
+    }
+
+    spectrogram
+}
+
+/// Takes a MIDI standard 128-pitch spectrum and collects
+///  melodic frequencies into the twelve Western musical notes:
+///
+/// C, C#, D, D#, E, F, F#, G, G#, A, A#, B
+pub fn pitch_spectrum_to_chromagram(pitches: &[f32]) -> [f32; 12] {
+    let mut chromagram = [0.0; 12];
+
+    for (p, &val) in pitches.iter().enumerate() {
+        chromagram[p % 12] += val;
+    }
+
+    chromagram
+}
+
+/// Computes the Harmonic Product Spectrum from a uniformly-spaced frequency spectrum
+///
+/// `downsamples` dictates the number of products used to compute the final result, which
+/// will be of length `frequencies.len() / downsamples`
+pub fn frequency_to_harmonic_product_spectrum(frequencies: &[f32], downsamples: usize) -> Vec<f32> {
+    if downsamples <= 1 {
+        return frequencies.to_vec();
+    }
+
+    let output_len = frequencies.len() / downsamples;
+    let mut result: Vec<f32> = frequencies[0..output_len].to_vec();
+
+    for i in 1..output_len {
+        for j in 2..=downsamples {
+            result[i] *= frequencies[j * i];
+        }
+    }
+
+    result
+}
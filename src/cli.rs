@@ -0,0 +1,314 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use rust_audio_visualiser::{
+    audio::InputKind,
+    config::{AmplitudeKind, ColourConfig, Config, ConfigError, NoiseGateConfig},
+    spectra::Weighting,
+    tracklog::HISTORY_PATH,
+    visualiser::{DisplayMode, StereoLayout},
+};
+
+/// Real-time audio visualiser for PulseAudio sources
+///
+/// Options given here override the config file
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub task: Option<Task>,
+
+    /// WAV file to transcribe to MIDI instead of opening the visualiser
+    #[arg(conflicts_with_all = ["decks", "script", "timeline"])]
+    pub transcribe: Option<PathBuf>,
+
+    /// WAV file to render the whole spectrogram of, saved as a PNG with the same name
+    #[arg(long, value_name = "WAV", conflicts_with_all = ["transcribe", "decks", "script", "timeline"])]
+    pub spectrogram: Option<PathBuf>,
+
+    /// Rows of the exported spectrogram: fft (log-spaced frequencies) or cqt (one per
+    /// semitone)
+    #[arg(
+        long,
+        value_name = "KIND",
+        default_value = "fft",
+        value_parser = ["fft", "cqt"],
+        requires = "spectrogram"
+    )]
+    pub spectrogram_kind: String,
+
+    /// WAV file to plot the chromagram, loudness and novelty of over time, saved next to it
+    /// as e.g. song.features.png
+    #[arg(long, value_name = "WAV", conflicts_with_all = ["transcribe", "spectrogram", "decks", "script", "timeline"])]
+    pub plot: Option<PathBuf>,
+
+    /// Image format of the feature plot: png or svg
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "png",
+        value_parser = ["png", "svg"],
+        requires = "plot"
+    )]
+    pub plot_format: String,
+
+    /// WAV file to render the visualiser for, frame by frame at --fps, saved with the audio
+    /// as an MP4 with the same name. Needs ffmpeg
+    #[arg(long, value_name = "WAV", conflicts_with_all = ["transcribe", "spectrogram", "plot", "decks", "headless", "kiosk"])]
+    pub render: Option<PathBuf>,
+
+    /// Where to save the rendered video instead
+    #[arg(long, value_name = "PATH", requires = "render")]
+    pub render_output: Option<PathBuf>,
+
+    /// Colour map of the exported spectrogram or chromagram: heat, grey or viridis
+    #[arg(
+        long,
+        value_name = "MAP",
+        default_value = "heat",
+        value_parser = ["heat", "grey", "viridis"]
+    )]
+    pub colour_map: String,
+
+    /// Config file to use instead of ~/.config/rust-audio-visualiser/config.toml
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Capture source, by index or name as listed by --list-devices
+    #[arg(long)]
+    pub device: Option<String>,
+
+    /// Kind of source captured when --device isn't given: monitor (what's playing) or
+    /// microphone (the default input, with a noise gate)
+    #[arg(long, value_parser = ["monitor", "microphone"])]
+    pub input: Option<String>,
+
+    /// Silence audio quieter than this many dBFS, e.g. -50 to hide room noise
+    #[arg(long, value_name = "DBFS", allow_hyphen_values = true)]
+    pub noise_gate: Option<f32>,
+
+    /// Print the available capture sources and exit
+    #[arg(long)]
+    pub list_devices: bool,
+
+    /// Audio backend to capture with, falling back to the next working one if it isn't
+    /// available. See --list-backends
+    #[arg(long)]
+    pub backend: Option<String>,
+
+    /// Print the audio backends in this build, in the order they're tried, and exit
+    #[arg(long)]
+    pub list_backends: bool,
+
+    /// Open at login with the other options given, then exit. Use with the config file's
+    /// [schedule] to keep a dedicated display running
+    #[arg(long)]
+    pub install_autostart: bool,
+
+    /// Run fullscreen, ignore all input except Ctrl+Alt+Shift+Q to exit, and restart
+    /// automatically after any error
+    #[arg(long)]
+    pub kiosk: bool,
+
+    /// Respond faster with short windows averaged over a few hops. --fft-size and --overlap
+    /// still apply over it
+    #[arg(long)]
+    pub low_latency: bool,
+
+    /// Samples per FFT, a power of two
+    #[arg(long)]
+    pub fft_size: Option<usize>,
+
+    /// Fraction of each FFT window shared with the previous one, e.g. 0.75
+    #[arg(long)]
+    pub overlap: Option<f32>,
+
+    /// Number of bars
+    #[arg(long)]
+    pub bars: Option<usize>,
+
+    /// Scene to show, as listed by --list-modes, or a .rhai script
+    #[arg(long)]
+    pub mode: Option<String>,
+
+    /// Print the modes and exit
+    #[arg(long)]
+    pub list_modes: bool,
+
+    /// Show the left and right channels separately in the bars mode: split (side by side)
+    /// or mirrored (low frequencies meeting in the middle)
+    #[arg(long, value_name = "LAYOUT")]
+    pub stereo: Option<String>,
+
+    /// How bar heights follow level: linear or log2 (both relative to the tallest bar), or
+    /// dbfs (absolute, from the config's `bars.floor`)
+    #[arg(long, value_name = "SCALE")]
+    pub amplitude: Option<String>,
+
+    /// Weight the spectrum before grouping bars, so they follow perceived loudness: a or c
+    #[arg(long, value_name = "CURVE")]
+    pub weighting: Option<String>,
+
+    /// Frames per second
+    #[arg(long)]
+    pub fps: Option<usize>,
+
+    /// Bar colour like "#ff8800", or a colour mapper: static, chromagram, bar-chroma, palette
+    /// or gradient
+    #[arg(long)]
+    pub colour: Option<String>,
+
+    /// Two capture sources to show side by side for beat-matching
+    #[arg(long, num_args = 2, value_names = ["SOURCE_A", "SOURCE_B"])]
+    pub decks: Option<Vec<String>>,
+
+    /// Rhai script drawing a custom scene, reloaded whenever it's saved
+    #[arg(long, conflicts_with = "mode")]
+    pub script: Option<PathBuf>,
+
+    /// Choreographed show of scene and parameter changes
+    #[arg(long)]
+    pub timeline: Option<PathBuf>,
+
+    /// Don't open a window, writing the analysis of each window to stdout instead: json (a
+    /// line per frame) or msgpack
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "json",
+        value_parser = ["json", "msgpack"],
+        conflicts_with_all = ["decks", "script", "timeline", "kiosk"]
+    )]
+    pub headless: Option<String>,
+}
+
+/// Jobs run instead of opening the visualiser
+#[derive(Subcommand)]
+pub enum Task {
+    /// Analyse the BPM, key and loudness of every WAV file under a directory
+    Scan {
+        /// Directory to search, including its subdirectories
+        dir: PathBuf,
+
+        /// JSON file to write the results to
+        #[arg(long, default_value = "library.json")]
+        output: PathBuf,
+
+        /// Files analysed at once, one per CPU core if not given
+        #[arg(long)]
+        jobs: Option<usize>,
+    },
+    /// List tracks from the listening history, with their BPM, key and loudness
+    History {
+        /// History database to read
+        #[arg(long, default_value = HISTORY_PATH)]
+        database: PathBuf,
+
+        /// CSV session log to add to the history first, skipping tracks it already has
+        #[arg(long)]
+        import: Option<PathBuf>,
+
+        /// Only tracks by artists containing this
+        #[arg(long)]
+        artist: Option<String>,
+
+        /// Only tracks in this key, e.g. "A minor"
+        #[arg(long)]
+        key: Option<String>,
+
+        #[arg(long)]
+        min_bpm: Option<f32>,
+
+        #[arg(long)]
+        max_bpm: Option<f32>,
+
+        /// Only tracks played in this many days
+        #[arg(long)]
+        days: Option<u64>,
+    },
+}
+
+impl Cli {
+    /// Loads the config file and applies the options given on the command line over it
+    pub fn config(&self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::load_default()?,
+        };
+
+        if let Some(device) = &self.device {
+            config.device = Some(device.clone());
+        }
+        if let Some(backend) = &self.backend {
+            config.backend = Some(backend.clone());
+        }
+        // The command line parser only accepts valid names
+        if let Some(input) = self.input.as_deref().and_then(InputKind::from_name) {
+            config.input = input;
+        }
+        if let Some(threshold) = self.noise_gate {
+            config.noise_gate = Some(NoiseGateConfig {
+                threshold,
+                ..config.noise_gate.unwrap_or_default()
+            });
+        }
+        if self.kiosk {
+            config.kiosk = true;
+        }
+        if self.low_latency {
+            config.apply_low_latency();
+        }
+        if let Some(fft_size) = self.fft_size {
+            if !fft_size.is_power_of_two() {
+                return Err(ConfigError::Invalid(
+                    "--fft-size must be a power of two".to_string(),
+                ));
+            }
+            config.fft_size = fft_size;
+        }
+        if let Some(overlap) = self.overlap {
+            if !(0.0..1.0).contains(&overlap) {
+                return Err(ConfigError::Invalid(
+                    "--overlap must be at least 0.0 and less than 1.0".to_string(),
+                ));
+            }
+            config.overlap = overlap;
+        }
+        if let Some(bars) = self.bars {
+            config.grouping.bars = bars;
+        }
+        if let Some(mode) = &self.mode {
+            if DisplayMode::from_name(mode).is_none() {
+                return Err(ConfigError::Invalid(format!("unknown mode `{mode}`")));
+            }
+            config.mode = mode.clone();
+        }
+        if let Some(amplitude) = &self.amplitude {
+            config.bars.amplitude = AmplitudeKind::from_name(amplitude).ok_or_else(|| {
+                ConfigError::Invalid(format!("unknown amplitude scale `{amplitude}`"))
+            })?;
+        }
+        if let Some(weighting) = &self.weighting {
+            config.bars.weighting =
+                Some(Weighting::from_name(weighting).ok_or_else(|| {
+                    ConfigError::Invalid(format!("unknown weighting `{weighting}`"))
+                })?);
+        }
+        if let Some(stereo) = &self.stereo {
+            config.stereo = Some(StereoLayout::from_name(stereo).ok_or_else(|| {
+                ConfigError::Invalid(format!("unknown stereo layout `{stereo}`"))
+            })?);
+        }
+        if let Some(fps) = self.fps {
+            config.frame_rate = fps.max(1);
+        }
+        if let Some(colour) = &self.colour {
+            config.colour = ColourConfig::from_name(colour)
+                .ok_or_else(|| ConfigError::Invalid(format!("unknown colour `{colour}`")))?;
+        }
+
+        Ok(config)
+    }
+}
@@ -4,11 +4,36 @@ use std::{
 };
 
 use macroquad::color::{Color, WHITE};
+use serde::Deserialize;
 
-use crate::spectra::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram};
+use crate::{
+    analysis::{Feature, Features, FrameAnalysis},
+    beat::BeatEvent,
+    bins,
+};
+
+pub trait ColourMapper: Send {
+    fn get_colour(&mut self, analysis: &FrameAnalysis) -> Color;
+
+    /// Colours for each bar, given the range of spectrum bins each bar covers
+    ///
+    /// By default every bar shares the colour from `get_colour`
+    fn get_bar_colours(
+        &mut self,
+        analysis: &FrameAnalysis,
+        bar_ranges: &[(usize, usize)],
+    ) -> Vec<Color> {
+        vec![self.get_colour(analysis); bar_ranges.len()]
+    }
+
+    /// Called on each frame with a beat, before that frame's colours are asked for
+    fn on_beat(&mut self, _beat: &BeatEvent) {}
 
-pub trait ColourMapper {
-    fn get_colour(&mut self, spectrum: &[f32], sampling_rate: usize) -> Color;
+    /// Parts of the analysis the colours are worked out from, besides the loudness and
+    /// spectrum
+    fn features(&self) -> Features {
+        Features::NONE
+    }
 }
 
 pub struct StaticColour {
@@ -16,7 +41,7 @@ pub struct StaticColour {
 }
 
 impl ColourMapper for StaticColour {
-    fn get_colour(&mut self, spectrum: &[f32], sampling_rate: usize) -> Color {
+    fn get_colour(&mut self, analysis: &FrameAnalysis) -> Color {
         self.colour
     }
 }
@@ -27,40 +52,98 @@ impl StaticColour {
     }
 }
 
+/// A saturation or value level for a colour mapper
+#[derive(Clone, Copy)]
+pub enum ColourLevel {
+    Fixed(f32),
+    /// Scales from `min` at -60dBFS to `max` at 0dBFS
+    Loudness {
+        min: f32,
+        max: f32,
+    },
+}
+
+impl ColourLevel {
+    fn level(&self, analysis: &FrameAnalysis) -> f32 {
+        match *self {
+            ColourLevel::Fixed(level) => level,
+            ColourLevel::Loudness { min, max } => {
+                min + (max - min) * analysis.normalised_loudness()
+            }
+        }
+    }
+}
+
+/// Picks a hue from the dominant pitch classes: each of the 12 chroma bins is a hue 30
+/// degrees apart and the colour points along their weighted sum
 pub struct ChromagramColour {
     hue_vector: (f32, f32),
     smoothing_factor: f32,
     smoothed_chromagram: [f32; 12],
+    saturation: ColourLevel,
+    value: ColourLevel,
+    // Degrees added to the hue so that C need not be red
+    hue_offset: f32,
+    // Concentration of the chromagram (0.0 uniform, 1.0 a single pitch class) below which
+    // the colour fades to grey, as the resultant hue is meaningless there
+    min_concentration: f32,
+    // Hue changes smaller than this many degrees are ignored
+    hysteresis: f32,
+    // Seconds a displayed hue is held before it may change again
+    min_dwell: f64,
+    displayed_hue: f32,
+    hue_changed_at: f64,
 }
 
 impl ChromagramColour {
-    pub fn new(smoothing_factor: f32) -> Self {
+    pub fn new(
+        smoothing_factor: f32,
+        saturation: ColourLevel,
+        value: ColourLevel,
+        hue_offset: f32,
+    ) -> Self {
         Self {
             hue_vector: (0.0, 0.0),
             smoothing_factor,
             smoothed_chromagram: [0.0; 12],
+            saturation,
+            value,
+            hue_offset,
+            min_concentration: 0.15,
+            hysteresis: 15.0,
+            min_dwell: 0.2,
+            displayed_hue: 0.0,
+            hue_changed_at: f64::NEG_INFINITY,
         }
     }
+
+    /// Sets the chromagram concentration below which the colour starts to desaturate
+    pub fn with_min_concentration(mut self, min_concentration: f32) -> Self {
+        self.min_concentration = min_concentration;
+        self
+    }
+
+    /// Sets how far (in degrees) the hue must move, and how long (in seconds) the previous
+    /// hue must have been shown, before the displayed hue changes
+    pub fn with_hue_hysteresis(mut self, degrees: f32, min_dwell: f64) -> Self {
+        self.hysteresis = degrees;
+        self.min_dwell = min_dwell;
+        self
+    }
 }
 
 impl ColourMapper for ChromagramColour {
-    fn get_colour(&mut self, spectrum: &[f32], sampling_rate: usize) -> Color {
-        let chromagram =
-            pitch_spectrum_to_chromagram(&frequency_to_pitch_spectrum(spectrum, sampling_rate));
+    fn features(&self) -> Features {
+        Feature::Chromagram.into()
+    }
 
-        for (i, &value) in chromagram.iter().enumerate() {
+    fn get_colour(&mut self, analysis: &FrameAnalysis) -> Color {
+        for (i, &value) in analysis.chromagram.iter().enumerate() {
             self.smoothed_chromagram[i] = (1.0 - self.smoothing_factor) * value
                 + self.smoothing_factor * self.smoothed_chromagram[i];
         }
 
-        let mut hue_vector: (f32, f32) = (0.0_f32.cos(), 0.0_f32.sin());
-
-        for (i, &intensity) in self.smoothed_chromagram.iter().enumerate() {
-            let hue: f32 = (i as f32 * 30.0).to_radians();
-            // Add weighted hue vectors together
-            hue_vector.0 += intensity * hue.cos();
-            hue_vector.1 += intensity * hue.sin();
-        }
+        let hue_vector = chroma_vector(&self.smoothed_chromagram, 1);
 
         self.hue_vector.0 = (1.0 - self.smoothing_factor) * hue_vector.0
             + self.smoothing_factor * self.hue_vector.0;
@@ -68,8 +151,29 @@ impl ColourMapper for ChromagramColour {
             + self.smoothing_factor * self.hue_vector.1;
 
         // theta = atan2(y, x)
-        let final_hue = f32::atan2(self.hue_vector.1, self.hue_vector.0).to_degrees();
-        let final_colour = hsv_to_rgb(final_hue, 1.0, 1.0);
+        let target_hue = f32::atan2(self.hue_vector.1, self.hue_vector.0).to_degrees();
+
+        // Shortest angular distance, so 350 -> 10 degrees counts as 20 rather than 340
+        let hue_change = (target_hue - self.displayed_hue + 180.0).rem_euclid(360.0) - 180.0;
+        if hue_change.abs() > self.hysteresis
+            && analysis.time - self.hue_changed_at >= self.min_dwell
+        {
+            self.displayed_hue = target_hue;
+            self.hue_changed_at = analysis.time;
+        }
+
+        // A nearly uniform chromagram has a short resultant vector whose direction is
+        // mostly noise, so fade towards grey rather than flicker between hues
+        let total: f32 = self.smoothed_chromagram.iter().sum();
+        let magnitude = (self.hue_vector.0.powi(2) + self.hue_vector.1.powi(2)).sqrt();
+        let concentration = (magnitude / total.max(f32::EPSILON)).min(1.0);
+        let desaturation = (concentration / self.min_concentration.max(f32::EPSILON)).min(1.0);
+
+        let final_colour = hsv_to_rgb(
+            self.displayed_hue + self.hue_offset,
+            self.saturation.level(analysis) * desaturation,
+            self.value.level(analysis),
+        );
 
         Color {
             r: final_colour.0,
@@ -80,7 +184,373 @@ impl ColourMapper for ChromagramColour {
     }
 }
 
-fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
+/// Colours each bar by the pitch class that dominates its own frequency range, using the
+/// same 30 degrees per semitone hue wheel as `ChromagramColour`
+///
+/// Bars narrower than a semitone take the hue of the note they sit on; wider bars mix
+/// their bins' hues weighted by energy and fade towards grey when no pitch class dominates
+pub struct BarChromaColour {
+    sampling_rate: usize,
+    smoothing_factor: f32,
+    // Smoothed resultant hue vector of each bar
+    hue_vectors: Vec<(f32, f32)>,
+}
+
+impl BarChromaColour {
+    pub fn new(sampling_rate: usize, smoothing_factor: f32) -> Self {
+        Self {
+            sampling_rate,
+            smoothing_factor,
+            hue_vectors: Vec::new(),
+        }
+    }
+}
+
+impl ColourMapper for BarChromaColour {
+    fn get_colour(&mut self, analysis: &FrameAnalysis) -> Color {
+        let last_bin = analysis.spectrum.len();
+        self.get_bar_colours(analysis, &[(1, last_bin)])[0]
+    }
+
+    fn get_bar_colours(
+        &mut self,
+        analysis: &FrameAnalysis,
+        bar_ranges: &[(usize, usize)],
+    ) -> Vec<Color> {
+        let spectrum = &analysis.spectrum;
+        let freq_per_bin = bins::bin_width(spectrum.len(), self.sampling_rate);
+        self.hue_vectors.resize(bar_ranges.len(), (0.0, 0.0));
+
+        bar_ranges
+            .iter()
+            .zip(&mut self.hue_vectors)
+            .map(|(&(start, end), smoothed)| {
+                let mut vector = (0.0, 0.0);
+                let mut total = 0.0;
+
+                // Skip the DC bin, which has no pitch
+                for (bin, &power) in spectrum.iter().enumerate().take(end).skip(start.max(1)) {
+                    let midi = 69.0 + 12.0 * (bin as f32 * freq_per_bin / 440.0).log2();
+                    let hue = (midi.round().rem_euclid(12.0) * 30.0).to_radians();
+                    let energy = power.sqrt();
+
+                    vector.0 += energy * hue.cos();
+                    vector.1 += energy * hue.sin();
+                    total += energy;
+                }
+
+                let a = self.smoothing_factor;
+                smoothed.0 = (1.0 - a) * vector.0 + a * smoothed.0;
+                smoothed.1 = (1.0 - a) * vector.1 + a * smoothed.1;
+
+                let hue = f32::atan2(smoothed.1, smoothed.0).to_degrees();
+                let magnitude = (smoothed.0.powi(2) + smoothed.1.powi(2)).sqrt();
+                let saturation = (magnitude / total.max(f32::EPSILON)).min(1.0);
+                let (r, g, b) = hsv_to_rgb(hue, saturation, 1.0);
+
+                Color { r, g, b, a: 1.0 }
+            })
+            .collect()
+    }
+}
+
+/// What places each bar along a `GradientColour`
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum GradientAxis {
+    /// Low bars at the start of the gradient, high bars at the end
+    #[default]
+    Frequency,
+    /// Quiet bars at the start of the gradient, the loudest bar at the end
+    Amplitude,
+}
+
+/// Colours bars along a gradient through several colours, by their frequency or level
+///
+/// A single colour for the whole frame comes from the spectral centroid (on a log scale) for
+/// frequency gradients, and from the loudness for amplitude gradients
+pub struct GradientColour {
+    // Position from 0.0 to 1.0 and colour of each stop, in order of position
+    stops: Vec<(f32, Color)>,
+    axis: GradientAxis,
+}
+
+impl GradientColour {
+    /// `stops` are spread evenly along the gradient unless `positions` gives one for each
+    pub fn new(stops: &[Color], positions: Option<&[f32]>, axis: GradientAxis) -> Self {
+        let last = stops.len().saturating_sub(1).max(1) as f32;
+        let mut stops: Vec<(f32, Color)> = match positions {
+            Some(positions) if positions.len() == stops.len() => positions
+                .iter()
+                .copied()
+                .zip(stops.iter().copied())
+                .collect(),
+            _ => (stops.iter().enumerate())
+                .map(|(i, &colour)| (i as f32 / last, colour))
+                .collect(),
+        };
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if stops.is_empty() {
+            stops.push((0.0, WHITE));
+        }
+
+        Self { stops, axis }
+    }
+
+    /// Colour at `t` along the gradient, holding the end colours beyond the first and last
+    /// stops
+    pub fn colour_at(&self, t: f32) -> Color {
+        let next = self.stops.partition_point(|&(position, _)| position <= t);
+        match (self.stops.get(next.wrapping_sub(1)), self.stops.get(next)) {
+            (Some(&(start, from)), Some(&(end, to))) => {
+                blend_colours(from, to, (t - start) / (end - start).max(f32::EPSILON))
+            }
+            (Some(&(_, colour)), None) | (None, Some(&(_, colour))) => colour,
+            (None, None) => WHITE,
+        }
+    }
+}
+
+impl ColourMapper for GradientColour {
+    fn get_colour(&mut self, analysis: &FrameAnalysis) -> Color {
+        let t = match self.axis {
+            GradientAxis::Frequency => {
+                let spectrum = &analysis.spectrum;
+                let total = spectrum.iter().sum::<f32>().max(f32::EPSILON);
+                let centroid = (spectrum.iter().enumerate())
+                    .map(|(bin, power)| bin as f32 * power)
+                    .sum::<f32>()
+                    / total;
+                (1.0 + centroid).log2() / (spectrum.len().max(2) as f32).log2()
+            }
+            GradientAxis::Amplitude => analysis.normalised_loudness(),
+        };
+        self.colour_at(t)
+    }
+
+    fn get_bar_colours(
+        &mut self,
+        analysis: &FrameAnalysis,
+        bar_ranges: &[(usize, usize)],
+    ) -> Vec<Color> {
+        match self.axis {
+            GradientAxis::Frequency => {
+                let last = bar_ranges.len().saturating_sub(1).max(1) as f32;
+                (0..bar_ranges.len())
+                    .map(|i| self.colour_at(i as f32 / last))
+                    .collect()
+            }
+            GradientAxis::Amplitude => {
+                // On the same log scale as the bars, relative to the tallest
+                let levels: Vec<f32> = bar_ranges
+                    .iter()
+                    .map(|&(start, end)| {
+                        let bins = analysis.spectrum.get(start..end).unwrap_or_default();
+                        (1.0 + bins.iter().cloned().fold(0.0, f32::max)).log2()
+                    })
+                    .collect();
+                let max_level = levels.iter().cloned().fold(f32::EPSILON, f32::max);
+                levels
+                    .iter()
+                    .map(|level| self.colour_at(level / max_level))
+                    .collect()
+            }
+        }
+    }
+}
+
+// Seconds of history the palette is derived from, and how often a sample is taken from it
+const PALETTE_HISTORY_SECONDS: f64 = 30.0;
+const PALETTE_SAMPLE_SECONDS: f64 = 0.1;
+// Beats at least this confident step the palette along by one colour
+const PALETTE_BEAT_CONFIDENCE: f32 = 0.5;
+// Chroma concentration treated as fully saturated; real music rarely exceeds this
+const PALETTE_FULL_CONCENTRATION: f32 = 0.3;
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Derives a small palette from the last 30 seconds of chroma and loudness, so the colour
+/// scheme drifts to suit the song over tens of seconds
+///
+/// Every `update_interval` seconds the recent frames are clustered (k-means on each frame's
+/// resultant chroma vector and loudness) and each cluster centre becomes a palette colour:
+/// hue from the dominant pitch class, saturation from how tonal the cluster is and value
+/// from its loudness. The displayed palette eases towards the new one over the same interval
+pub struct PaletteColour {
+    palette_size: usize,
+    update_interval: f64,
+    history: VecDeque<[f32; 3]>,
+    last_sample: f64,
+    last_update: f64,
+    last_time: Option<f64>,
+    palette: Vec<Color>,
+    target: Vec<Color>,
+}
+
+impl PaletteColour {
+    pub fn new(palette_size: usize, update_interval: f64) -> Self {
+        let palette_size = palette_size.max(1);
+
+        Self {
+            palette_size,
+            update_interval,
+            history: VecDeque::new(),
+            last_sample: f64::NEG_INFINITY,
+            last_update: f64::NEG_INFINITY,
+            last_time: None,
+            palette: vec![WHITE; palette_size],
+            target: vec![WHITE; palette_size],
+        }
+    }
+
+    /// Records the frame into the history and moves the palette along
+    fn update(&mut self, analysis: &FrameAnalysis) {
+        let time = analysis.time;
+
+        if time - self.last_sample >= PALETTE_SAMPLE_SECONDS {
+            let history_len = (PALETTE_HISTORY_SECONDS / PALETTE_SAMPLE_SECONDS) as usize;
+            if self.history.len() == history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(palette_point(analysis));
+            self.last_sample = time;
+        }
+
+        if time - self.last_update >= self.update_interval
+            && self.history.len() >= self.palette_size
+        {
+            self.target = kmeans(&self.history, self.palette_size)
+                .iter()
+                .map(point_colour)
+                .collect();
+            // Order by hue so colours ease to their nearest counterpart rather than swapping
+            self.target.sort_by(|a, b| {
+                rgb_to_hsv(a.r, a.g, a.b)
+                    .0
+                    .total_cmp(&rgb_to_hsv(b.r, b.g, b.b).0)
+            });
+            self.last_update = time;
+        }
+
+        let elapsed = self.last_time.map_or(0.0, |last| time - last);
+        let t = (elapsed / self.update_interval.max(f64::EPSILON)).min(1.0) as f32;
+        for (colour, &target) in self.palette.iter_mut().zip(&self.target) {
+            *colour = blend_colours(*colour, target, t);
+        }
+        self.last_time = Some(time);
+    }
+}
+
+impl ColourMapper for PaletteColour {
+    fn features(&self) -> Features {
+        Feature::Chromagram.into()
+    }
+
+    /// The palette colour closest to the current frame's character
+    fn get_colour(&mut self, analysis: &FrameAnalysis) -> Color {
+        self.update(analysis);
+
+        let current = point_colour(&palette_point(analysis));
+        let distance = |c: &Color| {
+            (c.r - current.r).powi(2) + (c.g - current.g).powi(2) + (c.b - current.b).powi(2)
+        };
+
+        self.palette
+            .iter()
+            .copied()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .unwrap_or(WHITE)
+    }
+
+    /// Spreads the palette across the bars, low to high
+    fn get_bar_colours(
+        &mut self,
+        analysis: &FrameAnalysis,
+        bar_ranges: &[(usize, usize)],
+    ) -> Vec<Color> {
+        self.update(analysis);
+
+        let num_bars = bar_ranges.len();
+        (0..num_bars)
+            .map(|i| self.palette[i * self.palette.len() / num_bars])
+            .collect()
+    }
+
+    /// Rotates the palette across the bars on clear beats
+    fn on_beat(&mut self, beat: &BeatEvent) {
+        if beat.confidence >= PALETTE_BEAT_CONFIDENCE {
+            self.palette.rotate_left(1);
+            self.target.rotate_left(1);
+        }
+    }
+}
+
+/// Weighted sum of a unit vector for each pitch class, with pitch class `i` at `i * step`
+/// thirty degree steps round from C. A `step` of 1 goes round chromatically, giving the
+/// hues of `ChromagramColour`, and 7 goes round the circle of fifths
+pub fn chroma_vector(chromagram: &[f32], step: usize) -> (f32, f32) {
+    chromagram
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(x, y), (i, &intensity)| {
+            let angle = ((i * step % 12) as f32 * 30.0).to_radians();
+            (x + intensity * angle.cos(), y + intensity * angle.sin())
+        })
+}
+
+/// A frame's resultant chroma vector (normalised by total chroma) and normalised loudness
+fn palette_point(analysis: &FrameAnalysis) -> [f32; 3] {
+    let total: f32 = analysis.chromagram.iter().sum::<f32>().max(f32::EPSILON);
+    let (x, y) = chroma_vector(&analysis.chromagram, 1);
+
+    [x / total, y / total, analysis.normalised_loudness()]
+}
+
+fn point_colour(point: &[f32; 3]) -> Color {
+    let [x, y, loudness] = *point;
+    let hue = f32::atan2(y, x).to_degrees();
+    let saturation = ((x * x + y * y).sqrt() / PALETTE_FULL_CONCENTRATION).min(1.0);
+    let (r, g, b) = hsv_to_rgb(hue, saturation, 0.4 + 0.6 * loudness);
+
+    Color { r, g, b, a: 1.0 }
+}
+
+/// Clusters `points` into `k` groups and returns the centre of each
+///
+/// Starts from points spread evenly through the history so results are deterministic
+fn kmeans(points: &VecDeque<[f32; 3]>, k: usize) -> Vec<[f32; 3]> {
+    let mut centres: Vec<[f32; 3]> = (0..k).map(|i| points[i * points.len() / k]).collect();
+    let distance =
+        |a: &[f32; 3], b: &[f32; 3]| a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f32>();
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![[0.0; 3]; k];
+        let mut counts = vec![0; k];
+
+        for point in points {
+            let nearest = (0..k)
+                .min_by(|&a, &b| {
+                    distance(point, &centres[a]).total_cmp(&distance(point, &centres[b]))
+                })
+                .unwrap_or(0);
+
+            for (sum, value) in sums[nearest].iter_mut().zip(point) {
+                *sum += value;
+            }
+            counts[nearest] += 1;
+        }
+
+        for ((centre, sum), &count) in centres.iter_mut().zip(&sums).zip(&counts) {
+            // Empty clusters keep their previous centre
+            if count > 0 {
+                *centre = sum.map(|s| s / count as f32);
+            }
+        }
+    }
+
+    centres
+}
+
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
     let h = h.rem_euclid(360.0) / 60.0; // hue sector
     let c = v * s;
     let x = c * (1.0 - (h % 2.0 - 1.0).abs());
@@ -98,3 +568,118 @@ fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
 
     (r1 + m, g1 + m, b1 + m)
 }
+
+fn rgb_to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    (h, s, max)
+}
+
+/// Linearly interpolates from `a` (at 0.0) to `b` (at 1.0)
+pub fn blend_colours(a: Color, b: Color, t: f32) -> Color {
+    Color {
+        r: a.r + (b.r - a.r) * t,
+        g: a.g + (b.g - a.g) * t,
+        b: a.b + (b.b - a.b) * t,
+        a: a.a + (b.a - a.a) * t,
+    }
+}
+
+/// Shifts the hue of `colour` by `degrees`, keeping its saturation, value and alpha
+pub fn rotate_hue(colour: Color, degrees: f32) -> Color {
+    let (h, s, v) = rgb_to_hsv(colour.r, colour.g, colour.b);
+    let (r, g, b) = hsv_to_rgb(h + degrees, s, v);
+
+    Color {
+        r,
+        g,
+        b,
+        a: colour.a,
+    }
+}
+
+// Stops of the heat colour map from silent to loudest, roughly matplotlib's "inferno"
+const HEAT_STOPS: [(f32, f32, f32); 5] = [
+    (0.0, 0.0, 0.02),
+    (0.34, 0.06, 0.43),
+    (0.73, 0.21, 0.33),
+    (0.98, 0.55, 0.04),
+    (0.99, 1.0, 0.64),
+];
+
+// Dark blue through teal and green to yellow, roughly matplotlib's viridis
+const VIRIDIS_STOPS: [(f32, f32, f32); 5] = [
+    (0.27, 0.0, 0.33),
+    (0.23, 0.32, 0.55),
+    (0.13, 0.57, 0.55),
+    (0.37, 0.79, 0.38),
+    (0.99, 0.91, 0.14),
+];
+
+/// Lookup table from a level between 0.0 and 1.0 to a colour, for heatmaps
+pub struct ColourMap {
+    table: [[u8; 4]; 256],
+}
+
+impl ColourMap {
+    /// Dark purple through red and orange to pale yellow
+    pub fn heat() -> Self {
+        Self::from_stops(&HEAT_STOPS)
+    }
+
+    /// Black to white
+    pub fn grey() -> Self {
+        Self::from_stops(&[(0.0, 0.0, 0.0), (1.0, 1.0, 1.0)])
+    }
+
+    /// Dark blue through green to yellow, evenly bright steps that read well in print
+    pub fn viridis() -> Self {
+        Self::from_stops(&VIRIDIS_STOPS)
+    }
+
+    /// Looks up a colour map by the name used on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "heat" => Some(Self::heat()),
+            "grey" => Some(Self::grey()),
+            "viridis" => Some(Self::viridis()),
+            _ => None,
+        }
+    }
+
+    /// Blends evenly between `stops`, given as RGB from 0.0 to 1.0
+    fn from_stops(stops: &[(f32, f32, f32)]) -> Self {
+        let mut table = [[0, 0, 0, 255]; 256];
+
+        for (i, entry) in table.iter_mut().enumerate() {
+            let position = i as f32 / 255.0 * (stops.len() - 1) as f32;
+            let stop = (position as usize).min(stops.len() - 2);
+            let t = position - stop as f32;
+            let (r0, g0, b0) = stops[stop];
+            let (r1, g1, b1) = stops[stop + 1];
+
+            let channel = |a: f32, b: f32| ((a + (b - a) * t) * 255.0).round() as u8;
+            *entry = [channel(r0, r1), channel(g0, g1), channel(b0, b1), 255];
+        }
+
+        Self { table }
+    }
+
+    /// RGBA bytes for `level`, clamped to 0.0..=1.0
+    pub fn lookup(&self, level: f32) -> [u8; 4] {
+        self.table[(level.clamp(0.0, 1.0) * 255.0).round() as usize]
+    }
+}
@@ -0,0 +1,987 @@
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use macroquad::color::Color;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    atlas::{AtlasError, SkinFit},
+    audio::{Downmix, InputKind},
+    autodj::{AutoConfig, SceneConfig},
+    automation::{ModulationConfig, ModulationMatrix},
+    budget::MemoryBudget,
+    capture::CaptureConfig,
+    colour::{
+        BarChromaColour, ChromagramColour, ColourLevel, ColourMapper, GradientAxis, GradientColour,
+        PaletteColour, StaticColour,
+    },
+    distortion::DistortionConfig,
+    expression::Binding,
+    graph::{self, NodeConfig},
+    grouping::{DEFAULT_GAMMA, GroupingStrategy},
+    latency::LatencyConfig,
+    layout::BarGap,
+    listenbrainz::ListenBrainzConfig,
+    modes,
+    output::OutputAdjustments,
+    overlay::OverlayConfig,
+    particles::EmitterConfig,
+    presets::PresetConfig,
+    primitives::BarStyle,
+    rotation::RotationConfig,
+    schedule::ScheduleConfig,
+    sinks::SinkConfig,
+    smoothing::SmoothingStrategy,
+    spectra::Weighting,
+    typography::{FontError, TextRole, TextStyle, Typography},
+    visualiser::{AmplitudeScale, DisplayMode, Reflection, StereoLayout},
+    warmup::WarmUpConfig,
+    widgets::WidgetsConfig,
+};
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] toml::de::Error),
+    #[error("couldn't load font: {0}")]
+    Font(#[from] FontError),
+    #[error("couldn't load atlas: {0}")]
+    Atlas(#[from] AtlasError),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Settings loaded from a TOML file, any of which can be left out to keep its default:
+///
+/// ```toml
+/// fft_size = 4096
+/// mode = "bars"
+///
+/// [window]
+/// width = 1920
+/// height = 1080
+///
+/// [grouping]
+/// strategy = "log-mean"
+/// bars = 32
+///
+/// [smoothing]
+/// rise = 0.3
+/// fall = 0.95
+///
+/// [colour]
+/// mapper = "static"
+/// colour = "#ff8800"
+///
+/// [bars]
+/// style = "capsule"
+/// gap_pixels = 4
+/// ```
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Rate audio is analysed at. Captures at other rates are resampled to it
+    pub sample_rate: usize,
+    /// Analyse at the capture source's own rate when it can be found, rather than
+    /// `sample_rate`, so nothing needs resampling
+    pub match_device_rate: bool,
+    pub fft_size: usize,
+    /// Fraction of each FFT window shared with the previous one, from 0.0 up to but not
+    /// including 1.0. More overlap analyses the audio more often
+    pub overlap: f32,
+    /// Number of successive spectra averaged before drawing, 1 for none
+    pub spectral_averaging: usize,
+    /// Use short windows that respond quickly, replacing `fft_size`, `overlap` and
+    /// `spectral_averaging`, see `Config::apply_low_latency`
+    pub low_latency: bool,
+    pub frame_rate: usize,
+    /// Scene shown at startup, by the names used in timelines
+    pub mode: String,
+    /// Capture source, by index or name as listed by `--list-devices`
+    pub device: Option<String>,
+    /// Kind of source captured when `device` isn't given
+    pub input: InputKind,
+    /// Silences audio quieter than a threshold, on by default for microphone input
+    pub noise_gate: Option<NoiseGateConfig>,
+    /// How the left and right channels are combined before analysis
+    pub downmix: Downmix,
+    /// Audio backend tried first, as listed by `--list-backends`. The others are tried in
+    /// order if it isn't working
+    pub backend: Option<String>,
+    pub window: WindowConfig,
+    pub grouping: GroupingConfig,
+    pub smoothing: SmoothingConfig,
+    pub colour: ColourConfig,
+    /// Colour mapper faded to with the colour blend parameter
+    pub crossfade_colour: Option<ColourConfig>,
+    pub bars: BarsConfig,
+    pub waveform: WaveformConfig,
+    pub beat: BeatConfig,
+    pub output: OutputAdjustments,
+    pub text: TextConfig,
+    /// Positions of elements drawn over the scenes, see `overlay`
+    pub overlay: OverlayConfig,
+    /// Clock, system stats and weather drawn over the scenes, see `widgets`
+    pub widgets: WidgetsConfig,
+    /// Images bars and particles can be drawn with, see `atlas`
+    pub atlas: AtlasConfig,
+    /// Image warped by the music behind the scene, see `distortion`
+    pub distortion: Option<DistortionConfig>,
+    pub schedule: ScheduleConfig,
+    /// How long the analysis settles before the visualiser fades in, see `warmup`
+    pub warm_up: WarmUpConfig,
+    /// How far to hold the visuals back to line up with what's heard, see `latency`
+    pub latency: LatencyConfig,
+    /// Where screenshots and recordings taken from the keyboard go, see `capture`
+    pub capture: CaptureConfig,
+    /// Show the left and right channels separately in the bars mode
+    pub stereo: Option<StereoLayout>,
+    /// Run fullscreen with input locked and restart after any crash, for unattended displays
+    pub kiosk: bool,
+    /// Most memory, in MiB, history is kept in before the oldest is dropped, see `budget`
+    pub memory_budget: Option<f32>,
+    #[serde(rename = "profile")]
+    pub profiles: Vec<ProfileConfig>,
+    /// Settings switched between with the number keys, see `presets`
+    #[serde(rename = "preset")]
+    pub presets: Vec<PresetConfig>,
+    /// Directory of more presets, one to each `.toml` file
+    pub preset_dir: Option<PathBuf>,
+    /// Switches between presets on a schedule, see `rotation`
+    pub rotation: Option<RotationConfig>,
+    /// Processing graph run alongside the scenes, see `graph`
+    #[serde(rename = "node")]
+    pub nodes: Vec<NodeConfig>,
+    /// Outputs fed from the analysis on their own threads, see `sinks`
+    #[serde(rename = "sink")]
+    pub sinks: Vec<SinkConfig>,
+    pub auto: AutoConfig,
+    /// Scenes switched between to suit the music, see `autodj`
+    #[serde(rename = "scene")]
+    pub scenes: Vec<SceneConfig>,
+    /// Particles thrown out by frequency bands over every scene, see `particles`
+    #[serde(rename = "emitter")]
+    pub emitters: Vec<EmitterConfig>,
+    /// Visual parameters set from the analysis every frame, each like `"hue = bass * 360"`,
+    /// see `expression`
+    pub bindings: Vec<String>,
+    /// LFOs, envelopes and features added to visual parameters every frame
+    #[serde(rename = "modulation")]
+    pub modulations: Vec<ModulationConfig>,
+    /// Where finished tracks are submitted with their BPM and key, see `listenbrainz`
+    pub listenbrainz: Option<ListenBrainzConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44_100,
+            match_device_rate: true,
+            fft_size: 2048,
+            overlap: 0.75,
+            spectral_averaging: 1,
+            low_latency: false,
+            frame_rate: 60,
+            mode: "chromagram".to_string(),
+            device: None,
+            input: InputKind::Monitor,
+            noise_gate: None,
+            downmix: Downmix::Average,
+            backend: None,
+            window: WindowConfig::default(),
+            grouping: GroupingConfig::default(),
+            smoothing: SmoothingConfig::default(),
+            colour: ColourConfig::default(),
+            crossfade_colour: Some(ColourConfig::Chromagram {
+                smoothing: 0.9,
+                saturation: LevelConfig::Fixed(1.0),
+                value: LevelConfig::Loudness { min: 0.4, max: 1.0 },
+                hue_offset: 0.0,
+            }),
+            bars: BarsConfig::default(),
+            waveform: WaveformConfig::default(),
+            beat: BeatConfig::default(),
+            output: OutputAdjustments::default(),
+            text: TextConfig::default(),
+            overlay: OverlayConfig::default(),
+            widgets: WidgetsConfig::default(),
+            atlas: AtlasConfig::default(),
+            distortion: None,
+            schedule: ScheduleConfig::default(),
+            warm_up: WarmUpConfig::default(),
+            latency: LatencyConfig::default(),
+            capture: CaptureConfig::default(),
+            stereo: None,
+            kiosk: false,
+            memory_budget: None,
+            profiles: Vec::new(),
+            presets: Vec::new(),
+            preset_dir: None,
+            rotation: None,
+            nodes: Vec::new(),
+            sinks: Vec::new(),
+            auto: AutoConfig::default(),
+            scenes: Vec::new(),
+            emitters: Vec::new(),
+            bindings: Vec::new(),
+            modulations: Vec::new(),
+            listenbrainz: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::parse(&source)
+    }
+
+    /// Parses and validates the contents of a config file
+    pub fn parse(source: &str) -> Result<Self, ConfigError> {
+        let mut config: Config = toml::from_str(source).map_err(ConfigError::Parse)?;
+        if config.low_latency {
+            config.apply_low_latency();
+        }
+
+        let modes = (config.profiles.iter().filter_map(|p| p.mode.as_ref()))
+            .chain(config.scenes.iter().filter_map(|s| s.mode.as_ref()))
+            .chain(config.presets.iter().filter_map(|p| p.mode.as_ref()));
+        for mode in [&config.mode].into_iter().chain(modes) {
+            if DisplayMode::from_name(mode).is_none() {
+                return Err(ConfigError::Invalid(format!("unknown mode `{mode}`")));
+            }
+        }
+        if !config.fft_size.is_power_of_two() {
+            return Err(ConfigError::Invalid(
+                "`fft_size` must be a power of two".to_string(),
+            ));
+        }
+        graph::plan(&config.nodes).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        if !(0.0..1.0).contains(&config.overlap) {
+            return Err(ConfigError::Invalid(
+                "`overlap` must be at least 0.0 and less than 1.0".to_string(),
+            ));
+        }
+        if config.bars.floor >= 0.0 {
+            return Err(ConfigError::Invalid(
+                "`bars.floor` must be below 0 dBFS".to_string(),
+            ));
+        }
+        let colours = [Some(&config.colour), config.crossfade_colour.as_ref()]
+            .into_iter()
+            .chain(config.profiles.iter().map(|p| p.colour.as_ref()))
+            .chain(config.scenes.iter().map(|s| s.colour.as_ref()))
+            .chain(config.presets.iter().map(|p| p.colour.as_ref()))
+            .flatten();
+        for colour in colours {
+            colour.validate().map_err(ConfigError::Invalid)?;
+        }
+        // Without an image there's nothing to cut sprites from
+        let sprites: Vec<&str> = if config.atlas.image.is_some() {
+            config.atlas.sprites.keys().map(String::as_str).collect()
+        } else {
+            Vec::new()
+        };
+        if let Some(skin) = &config.bars.skin
+            && !sprites.contains(&skin.sprite.as_str())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "bar skin `{}` isn't in the atlas",
+                skin.sprite
+            )));
+        }
+        for emitter in &config.emitters {
+            emitter.validate(&sprites).map_err(ConfigError::Invalid)?;
+        }
+        config.modulation_matrix()?;
+
+        Ok(config)
+    }
+
+    /// `config.toml` in the user's config directory, e.g.
+    /// `~/.config/rust-audio-visualiser/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        Some(
+            config_dir()?
+                .join("rust-audio-visualiser")
+                .join("config.toml"),
+        )
+    }
+
+    /// Loads the config at the default path, or the defaults if there's no file there
+    pub fn load_default() -> Result<Self, ConfigError> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        DisplayMode::from_name(&self.mode).unwrap_or(DisplayMode::BuiltIn(modes::BARS))
+    }
+
+    /// Switches to 512 sample windows four times a window, for music where the usual 2048
+    /// sample windows visibly lag behind the drums. Each spectrum is averaged with the three
+    /// before it to make up for the coarser frequency resolution, while onsets and beats are
+    /// still found from each window as it comes in
+    pub fn apply_low_latency(&mut self) {
+        self.low_latency = true;
+        self.fft_size = 512;
+        self.overlap = 0.75;
+        self.spectral_averaging = 4;
+    }
+
+    /// Samples between the starts of successive FFT windows
+    pub fn hop_size(&self) -> usize {
+        ((self.fft_size as f32 * (1.0 - self.overlap)).round() as usize).clamp(1, self.fft_size)
+    }
+
+    /// The `bindings` and `[[modulation]]` routes, to apply to the visual parameters every
+    /// frame
+    pub fn modulation_matrix(&self) -> Result<ModulationMatrix, ConfigError> {
+        let mut matrix = ModulationMatrix::new();
+        for binding in &self.bindings {
+            let parsed = Binding::parse(binding)
+                .map_err(|e| ConfigError::Invalid(format!("binding `{binding}`: {e}")))?;
+            matrix = matrix.with_binding(parsed);
+        }
+        for modulation in &self.modulations {
+            matrix = matrix.with_modulation(modulation.modulation().map_err(ConfigError::Invalid)?);
+        }
+        Ok(matrix)
+    }
+
+    /// How much memory each history is kept to
+    pub fn memory_budget(&self) -> MemoryBudget {
+        MemoryBudget::new(self.memory_budget)
+    }
+
+    /// The configured noise gate, or the default one for microphone input
+    pub fn noise_gate(&self) -> Option<NoiseGateConfig> {
+        match self.input {
+            InputKind::Microphone => Some(self.noise_gate.unwrap_or_default()),
+            InputKind::Monitor => self.noise_gate,
+        }
+    }
+}
+
+/// The user's config directory, usually `~/.config`
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupingKind {
+    None,
+    LogMax,
+    LogMean,
+    GammaCorrected,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupingConfig {
+    pub strategy: GroupingKind,
+    pub bars: usize,
+    /// Only used by `gamma-corrected`
+    pub gamma: f32,
+}
+
+impl Default for GroupingConfig {
+    fn default() -> Self {
+        Self {
+            strategy: GroupingKind::LogMax,
+            bars: 12,
+            gamma: DEFAULT_GAMMA,
+        }
+    }
+}
+
+impl GroupingConfig {
+    pub fn strategy(&self) -> GroupingStrategy {
+        let num_groups = self.bars.max(1);
+
+        match self.strategy {
+            GroupingKind::None => GroupingStrategy::NoGrouping { num_groups },
+            GroupingKind::LogMax => GroupingStrategy::LogMax { num_groups },
+            GroupingKind::LogMean => GroupingStrategy::LogMean { num_groups },
+            GroupingKind::GammaCorrected => GroupingStrategy::GammaCorrected {
+                num_groups,
+                gamma: self.gamma,
+            },
+        }
+    }
+}
+
+/// Rise and fall smoothing of bar heights, 0.0 for none
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmoothingConfig {
+    pub rise: f32,
+    pub fall: f32,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            rise: 0.5,
+            fall: 0.9,
+        }
+    }
+}
+
+impl SmoothingConfig {
+    pub fn strategy(&self) -> SmoothingStrategy {
+        SmoothingStrategy::RiseFall {
+            rise: self.rise,
+            fall: self.fall,
+        }
+    }
+}
+
+/// Settings for the waveform mode
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaveformConfig {
+    /// Start each frame at a rising zero crossing so periodic sounds stand still
+    pub trigger: bool,
+}
+
+impl Default for WaveformConfig {
+    fn default() -> Self {
+        Self { trigger: true }
+    }
+}
+
+/// How the visuals react to detected beats
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BeatConfig {
+    /// Opacity of the white flash on a confident beat, 0.0 for none
+    pub flash: f32,
+}
+
+/// A colour written as `"#rrggbb"` or `"#rrggbbaa"`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct ConfigColour(pub Color);
+
+impl TryFrom<String> for ConfigColour {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("`{value}` isn't a colour like \"#ff8800\"");
+        let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+        if !matches!(hex.len(), 6 | 8) {
+            return Err(invalid());
+        }
+
+        let mut channels = [1.0; 4];
+        for (i, channel) in channels.iter_mut().enumerate().take(hex.len() / 2) {
+            let byte = hex
+                .get(i * 2..i * 2 + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid)?;
+            *channel = byte as f32 / 255.0;
+        }
+
+        let [r, g, b, a] = channels;
+        Ok(ConfigColour(Color::new(r, g, b, a)))
+    }
+}
+
+/// A `ColourLevel`, either a number or `{ min = 0.4, max = 1.0 }` to follow loudness
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum LevelConfig {
+    Fixed(f32),
+    Loudness { min: f32, max: f32 },
+}
+
+impl LevelConfig {
+    fn level(&self) -> ColourLevel {
+        match *self {
+            LevelConfig::Fixed(level) => ColourLevel::Fixed(level),
+            LevelConfig::Loudness { min, max } => ColourLevel::Loudness { min, max },
+        }
+    }
+}
+
+/// Which `ColourMapper` to use, chosen by `mapper` along with its own settings
+#[derive(Deserialize, Clone)]
+#[serde(tag = "mapper", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ColourConfig {
+    Static {
+        colour: ConfigColour,
+    },
+    Chromagram {
+        #[serde(default = "default_colour_smoothing")]
+        smoothing: f32,
+        #[serde(default = "default_level")]
+        saturation: LevelConfig,
+        #[serde(default = "default_level")]
+        value: LevelConfig,
+        #[serde(default)]
+        hue_offset: f32,
+    },
+    BarChroma {
+        #[serde(default = "default_colour_smoothing")]
+        smoothing: f32,
+    },
+    Palette {
+        #[serde(default = "default_palette_size")]
+        size: usize,
+        /// Seconds between palette updates
+        #[serde(default = "default_palette_interval")]
+        update_interval: f64,
+    },
+    Gradient {
+        #[serde(default = "default_gradient_stops")]
+        stops: Vec<ConfigColour>,
+        /// Where each stop sits from 0.0 to 1.0, spread evenly if left out
+        positions: Option<Vec<f32>>,
+        #[serde(default)]
+        by: GradientAxis,
+    },
+}
+
+fn default_colour_smoothing() -> f32 {
+    0.9
+}
+
+fn default_level() -> LevelConfig {
+    LevelConfig::Fixed(1.0)
+}
+
+fn default_palette_size() -> usize {
+    5
+}
+
+fn default_palette_interval() -> f64 {
+    2.0
+}
+
+fn default_gradient_stops() -> Vec<ConfigColour> {
+    ["#2040ff", "#c020ff", "#ff4040"]
+        .map(|hex| ConfigColour::try_from(hex.to_string()).expect("valid colour"))
+        .to_vec()
+}
+
+impl Default for ColourConfig {
+    fn default() -> Self {
+        ColourConfig::Static {
+            colour: ConfigColour(Color::new(1.0, 1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+impl ColourConfig {
+    /// Parses a colour like `"#ff8800"` as a static colour, or a mapper name for that mapper
+    /// with its default settings
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.starts_with('#') {
+            let colour = ConfigColour::try_from(name.to_string()).ok()?;
+            return Some(ColourConfig::Static { colour });
+        }
+
+        Some(match name {
+            "static" => ColourConfig::default(),
+            "chromagram" => ColourConfig::Chromagram {
+                smoothing: default_colour_smoothing(),
+                saturation: default_level(),
+                value: default_level(),
+                hue_offset: 0.0,
+            },
+            "bar-chroma" => ColourConfig::BarChroma {
+                smoothing: default_colour_smoothing(),
+            },
+            "palette" => ColourConfig::Palette {
+                size: default_palette_size(),
+                update_interval: default_palette_interval(),
+            },
+            "gradient" => ColourConfig::Gradient {
+                stops: default_gradient_stops(),
+                positions: None,
+                by: GradientAxis::default(),
+            },
+            _ => return None,
+        })
+    }
+
+    /// Describes the first setting that can't work, if any
+    pub fn validate(&self) -> Result<(), String> {
+        if let ColourConfig::Gradient {
+            stops, positions, ..
+        } = self
+        {
+            if stops.is_empty() {
+                return Err("gradients need at least one stop".to_string());
+            }
+            if positions.as_ref().is_some_and(|p| p.len() != stops.len()) {
+                return Err("gradients need a position for each stop".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn mapper(&self, sampling_rate: usize) -> Box<dyn ColourMapper> {
+        match *self {
+            ColourConfig::Static { colour } => Box::new(StaticColour::new(colour.0)),
+            ColourConfig::Chromagram {
+                smoothing,
+                saturation,
+                value,
+                hue_offset,
+            } => Box::new(ChromagramColour::new(
+                smoothing,
+                saturation.level(),
+                value.level(),
+                hue_offset,
+            )),
+            ColourConfig::BarChroma { smoothing } => {
+                Box::new(BarChromaColour::new(sampling_rate, smoothing))
+            }
+            ColourConfig::Palette {
+                size,
+                update_interval,
+            } => Box::new(PaletteColour::new(size, update_interval)),
+            ColourConfig::Gradient {
+                ref stops,
+                ref positions,
+                by,
+            } => {
+                let stops: Vec<Color> = stops.iter().map(|stop| stop.0).collect();
+                Box::new(GradientColour::new(&stops, positions.as_deref(), by))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum BarShape {
+    Square,
+    Rounded,
+    Capsule,
+}
+
+/// Automatic gain control of bar heights, see `AutoGain`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgcConfig {
+    /// Seconds for the reference level to rise to a louder peak
+    pub attack: f32,
+    /// Seconds for it to fall back when the music gets quieter. Zero for both scales to
+    /// the tallest bar every frame
+    pub release: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        Self {
+            attack: 0.05,
+            release: 2.0,
+        }
+    }
+}
+
+/// How bar heights are scaled, for presets and scenes that set it apart from `[bars]`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct NormalisationConfig {
+    pub amplitude: AmplitudeKind,
+    /// Level in dBFS shown as an empty bar, only used by `dbfs`
+    pub floor: f32,
+    pub agc: AgcConfig,
+}
+
+impl Default for NormalisationConfig {
+    fn default() -> Self {
+        Self {
+            amplitude: AmplitudeKind::Log2,
+            floor: -70.0,
+            agc: AgcConfig::default(),
+        }
+    }
+}
+
+impl NormalisationConfig {
+    pub fn amplitude_scale(&self) -> AmplitudeScale {
+        match self.amplitude {
+            AmplitudeKind::Linear => AmplitudeScale::Linear,
+            AmplitudeKind::Log2 => AmplitudeScale::Log2,
+            AmplitudeKind::Dbfs => AmplitudeScale::Dbfs { floor: self.floor },
+        }
+    }
+}
+
+/// Noise gate on captured audio, see `NoiseGate`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct NoiseGateConfig {
+    /// Level in dBFS the gate opens above
+    pub threshold: f32,
+    /// Seconds the gate stays open after the level drops
+    pub hold: f32,
+    /// Seconds the gate then takes to fade out
+    pub release: f32,
+}
+
+impl Default for NoiseGateConfig {
+    fn default() -> Self {
+        Self {
+            threshold: -50.0,
+            hold: 0.2,
+            release: 0.15,
+        }
+    }
+}
+
+/// How bar heights follow the level of their frequencies
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum AmplitudeKind {
+    /// Relative to the tallest bar, in proportion to amplitude
+    Linear,
+    /// Relative to the tallest bar, in proportion to log2 of power
+    Log2,
+    /// Absolute level in dBFS, from `floor` up to full scale
+    Dbfs,
+}
+
+impl AmplitudeKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(AmplitudeKind::Linear),
+            "log2" => Some(AmplitudeKind::Log2),
+            "dbfs" => Some(AmplitudeKind::Dbfs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct TrailsConfig {
+    /// Roughly how many seconds a trail lasts
+    pub length: f32,
+    pub opacity: f32,
+}
+
+/// The image and the named regions of it in `[atlas]`, see `TextureAtlas`
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AtlasConfig {
+    pub image: Option<PathBuf>,
+    /// `[x, y, width, height]` in pixels of each sprite, by name
+    pub sprites: HashMap<String, [f32; 4]>,
+}
+
+/// An atlas sprite bars are drawn with instead of solid colour
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SkinConfig {
+    pub sprite: String,
+    #[serde(default)]
+    pub fit: SkinFit,
+}
+
+/// Peak-hold caps above the bars, see `PeakHold`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct PeaksConfig {
+    /// Seconds each peak stays put before falling
+    pub hold: f32,
+    /// Bar heights a second the peaks fall at
+    pub fall: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BarsConfig {
+    pub style: BarShape,
+    /// Corner radius in pixels for `rounded`
+    pub radius: f32,
+    /// Gap between bars as a fraction of each bar's space
+    pub gap: Option<f32>,
+    /// Gap between bars in pixels, used instead of `gap` if set
+    pub gap_pixels: Option<f32>,
+    pub trails: Option<TrailsConfig>,
+    pub reflection: Option<Reflection>,
+    pub peaks: Option<PeaksConfig>,
+    pub amplitude: AmplitudeKind,
+    /// Level in dBFS shown as an empty bar, only used by `dbfs`
+    pub floor: f32,
+    /// Decibels added to every frequency before grouping, so a quiet source fills more of
+    /// the `dbfs` scale
+    pub gain: f32,
+    /// Frequency weighting applied before grouping, none if left out
+    pub weighting: Option<Weighting>,
+    pub agc: AgcConfig,
+    /// Atlas sprite the bars are drawn with, tinted by their colours
+    pub skin: Option<SkinConfig>,
+}
+
+impl Default for BarsConfig {
+    fn default() -> Self {
+        Self {
+            style: BarShape::Square,
+            radius: 4.0,
+            gap: None,
+            gap_pixels: None,
+            trails: None,
+            reflection: None,
+            peaks: None,
+            amplitude: AmplitudeKind::Log2,
+            floor: -70.0,
+            gain: 0.0,
+            weighting: None,
+            agc: AgcConfig::default(),
+            skin: None,
+        }
+    }
+}
+
+impl BarsConfig {
+    pub fn style(&self) -> BarStyle {
+        match self.style {
+            BarShape::Square => BarStyle::Square,
+            BarShape::Rounded => BarStyle::Rounded {
+                radius: self.radius,
+            },
+            BarShape::Capsule => BarStyle::Capsule,
+        }
+    }
+
+    pub fn gap(&self) -> BarGap {
+        match (self.gap_pixels, self.gap) {
+            (Some(pixels), _) => BarGap::Pixels(pixels),
+            (None, Some(fraction)) => BarGap::Proportional(fraction),
+            (None, None) => BarGap::default(),
+        }
+    }
+
+    pub fn amplitude_scale(&self) -> AmplitudeScale {
+        self.normalisation().amplitude_scale()
+    }
+
+    /// The amplitude scale, floor and AGC, as presets and scenes give them
+    pub fn normalisation(&self) -> NormalisationConfig {
+        NormalisationConfig {
+            amplitude: self.amplitude,
+            floor: self.floor,
+            agc: self.agc,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextStyleConfig {
+    /// Multiplier on the element's usual size
+    pub size: Option<f32>,
+    pub colour: Option<ConfigColour>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextConfig {
+    /// TTF file used for all text instead of the built-in font
+    pub font: Option<PathBuf>,
+    pub notes: TextStyleConfig,
+    pub metadata: TextStyleConfig,
+    pub labels: TextStyleConfig,
+}
+
+impl TextConfig {
+    pub fn typography(&self) -> Result<Typography, FontError> {
+        let mut typography = Typography::new();
+        if let Some(font) = &self.font {
+            typography = typography.with_font(font)?;
+        }
+
+        for (role, style) in [
+            (TextRole::Notes, self.notes),
+            (TextRole::Metadata, self.metadata),
+            (TextRole::Labels, self.labels),
+        ] {
+            typography = typography.with_style(
+                role,
+                TextStyle {
+                    size: style.size.unwrap_or(1.0),
+                    colour: style.colour.map(|c| c.0),
+                },
+            );
+        }
+
+        Ok(typography)
+    }
+}
+
+/// Settings used instead of the main ones while a matching source is active:
+///
+/// ```toml
+/// [[profile]]
+/// name = "mic"
+/// device = "alsa_input"
+/// smoothing = { rise = 0.8, fall = 0.97 }
+///
+/// [[profile]]
+/// name = "music"
+/// player = "spotify"
+/// colour = { mapper = "palette" }
+/// ```
+///
+/// `device` matches part of the capture source's name and `player` part of the name of the
+/// media player that's playing. A profile with both needs both to match
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub device: Option<String>,
+    pub player: Option<String>,
+    pub mode: Option<String>,
+    pub smoothing: Option<SmoothingConfig>,
+    pub colour: Option<ColourConfig>,
+}
+
+impl ProfileConfig {
+    pub fn matches(&self, source: &str, player: Option<&str>) -> bool {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+
+        let device_matches = self.device.as_ref().is_none_or(|d| contains(source, d));
+        let player_matches = self
+            .player
+            .as_ref()
+            .is_none_or(|p| player.is_some_and(|player| contains(player, p)));
+
+        device_matches && player_matches
+    }
+}
@@ -0,0 +1,222 @@
+//! A user image warped by the music, drawn behind the scene
+//!
+//! The spectrum is grouped into a few log-spaced bands and uploaded each frame as a one
+//! pixel high texture. A shader displaces the image by a sum of waves, one per band, where
+//! the bass bands give wide, slow warps and the treble bands fine, fast ripples:
+//!
+//! ```toml
+//! [distortion]
+//! image = "logo.png"
+//! strength = 0.05
+//! ```
+
+use std::{fs, io, path::PathBuf};
+
+use macroquad::{
+    color::Color,
+    material::{Material, MaterialParams, gl_use_default_material, gl_use_material, load_material},
+    math::{Rect, vec2},
+    miniquad::{ShaderSource, UniformDesc, UniformType},
+    texture::{DrawTextureParams, FilterMode, Image, Texture2D, draw_texture_ex},
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{analysis::FrameAnalysis, bins, output::VERTEX_SHADER};
+
+// Bands the spectrum is grouped into, which the fragment shader loops over
+const BANDS: usize = 16;
+// Lowest frequency of the first band in Hz
+const LOWEST_HZ: f32 = 30.0;
+// Per-frame decay of each band's running peak, which its level is relative to
+const PEAK_DECAY: f32 = 0.998;
+// Fraction of a band's level kept each frame when it falls, so warps settle rather than snap
+const LEVEL_FALL: f32 = 0.9;
+// Height of the image as a fraction of the window when no area is given
+const DEFAULT_HEIGHT: f32 = 0.6;
+
+#[derive(Debug, Error)]
+pub enum DistortionError {
+    #[error("couldn't read image: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Image(#[from] macroquad::Error),
+}
+
+/// The `[distortion]` table, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DistortionConfig {
+    pub image: PathBuf,
+    /// Furthest the image is displaced, as a fraction of its size
+    #[serde(default = "default_strength")]
+    pub strength: f32,
+    /// Left, top, width and height as fractions of the window, or centred at the image's
+    /// own aspect ratio if left out
+    pub area: Option<[f32; 4]>,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+}
+
+fn default_strength() -> f32 {
+    0.05
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+/// Draws the image in `DistortionConfig` warped by the latest spectrum
+pub struct DistortionLayer {
+    config: DistortionConfig,
+    image: Texture2D,
+    // One pixel per band, with its level in the red channel
+    bands: Image,
+    bands_texture: Texture2D,
+    levels: [f32; BANDS],
+    peaks: [f32; BANDS],
+    material: Material,
+}
+
+impl DistortionLayer {
+    /// Loads the image and compiles the shader, so needs the window to exist
+    pub fn new(config: DistortionConfig) -> Result<Self, DistortionError> {
+        let bytes = fs::read(&config.image)?;
+        let image = Texture2D::from_image(&Image::from_file_with_format(&bytes, None)?);
+
+        let bands = Image::gen_image_color(BANDS as u16, 1, Color::new(0.0, 0.0, 0.0, 1.0));
+        let bands_texture = Texture2D::from_image(&bands);
+        bands_texture.set_filter(FilterMode::Nearest);
+
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: VERTEX_SHADER,
+                fragment: FRAGMENT_SHADER,
+            },
+            MaterialParams {
+                uniforms: vec![
+                    UniformDesc::new("time", UniformType::Float1),
+                    UniformDesc::new("strength", UniformType::Float1),
+                ],
+                textures: vec!["Bands".to_string()],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            config,
+            image,
+            bands,
+            bands_texture,
+            levels: [0.0; BANDS],
+            peaks: [0.0; BANDS],
+            material,
+        })
+    }
+
+    /// Groups the spectrum in `analysis` into the bands the shader warps by
+    pub fn update(&mut self, analysis: &FrameAnalysis, sampling_rate: usize) {
+        let spectrum = &analysis.spectrum;
+        let nyquist = sampling_rate as f32 / 2.0;
+        let freq_per_bin = bins::bin_width(spectrum.len(), sampling_rate);
+        let edge = |band: usize| {
+            let freq = LOWEST_HZ * (nyquist / LOWEST_HZ).powf(band as f32 / BANDS as f32);
+            ((freq / freq_per_bin) as usize).min(spectrum.len())
+        };
+
+        let mut colours = [Color::new(0.0, 0.0, 0.0, 1.0); BANDS];
+        for (band, colour) in colours.iter_mut().enumerate() {
+            // Every band covers at least one bin, even where bins are wider than bands
+            let start = edge(band);
+            let end = edge(band + 1).max(start + 1).min(spectrum.len());
+            let energy = spectrum
+                .get(start..end)
+                .unwrap_or_default()
+                .iter()
+                .sum::<f32>()
+                .sqrt();
+
+            let peak = &mut self.peaks[band];
+            *peak = energy.max(*peak * PEAK_DECAY);
+            let level = if *peak > 0.0 { energy / *peak } else { 0.0 };
+            self.levels[band] = level.max(self.levels[band] * LEVEL_FALL);
+            colour.r = self.levels[band];
+        }
+
+        self.bands.update(&colours);
+        self.bands_texture.update(&self.bands);
+    }
+
+    /// Draws the image distorted at `time` seconds in, which animates the distortion
+    pub fn draw(&self, time: f64) {
+        let area = match self.config.area {
+            Some([left, top, width, height]) => Rect::new(
+                left * screen_width(),
+                top * screen_height(),
+                width * screen_width(),
+                height * screen_height(),
+            ),
+            None => {
+                let height = DEFAULT_HEIGHT * screen_height();
+                let width = height * self.image.width() / self.image.height().max(1.0);
+                Rect::new(
+                    (screen_width() - width) / 2.0,
+                    (screen_height() - height) / 2.0,
+                    width,
+                    height,
+                )
+            }
+        };
+
+        self.material.set_uniform("time", time as f32);
+        self.material.set_uniform("strength", self.config.strength);
+        self.material
+            .set_texture("Bands", self.bands_texture.clone());
+        gl_use_material(&self.material);
+        draw_texture_ex(
+            &self.image,
+            area.x,
+            area.y,
+            Color::new(1.0, 1.0, 1.0, self.config.opacity),
+            DrawTextureParams {
+                dest_size: Some(vec2(area.w, area.h)),
+                ..Default::default()
+            },
+        );
+        gl_use_default_material();
+    }
+}
+
+// Each band adds a travelling wave to the displacement. Lower bands have longer wavelengths,
+// move slower and are given more of the displacement, so bass bends the whole image while
+// treble only ripples it
+const FRAGMENT_SHADER: &str = "#version 100
+precision mediump float;
+
+varying vec4 color;
+varying vec2 uv;
+
+uniform sampler2D Texture;
+uniform sampler2D Bands;
+uniform float time;
+uniform float strength;
+
+const int BANDS = 16;
+
+void main() {
+    vec2 offset = vec2(0.0);
+    for (int i = 0; i < BANDS; i++) {
+        float t = (float(i) + 0.5) / float(BANDS);
+        float level = texture2D(Bands, vec2(t, 0.5)).r;
+        float waves = 1.0 + t * 40.0;
+        float speed = 0.5 + t * 6.0;
+        float phase = float(i) * 1.7;
+        offset += level / waves * vec2(
+            sin(uv.y * waves * 6.2832 + time * speed + phase),
+            cos(uv.x * waves * 6.2832 - time * speed * 1.3 + phase)
+        );
+    }
+    gl_FragColor = texture2D(Texture, clamp(uv + offset * strength, 0.0, 1.0)) * color;
+}
+";
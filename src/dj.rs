@@ -0,0 +1,255 @@
+use macroquad::{
+    color::{Color, GREEN, WHITE, YELLOW},
+    shapes::{draw_circle, draw_line, draw_rectangle},
+    window::{screen_height, screen_width},
+};
+
+use crate::{
+    grouping::GroupingStrategy,
+    layout::{BarGap, bar_columns},
+    smoothing::SmoothingStrategy,
+    tempo::TempoEstimator,
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+};
+
+// Phase difference (as a fraction of a beat) within which the decks count as aligned
+const ALIGNED_PHASE: f32 = 0.05;
+
+/// One deck's analysis state
+struct Deck {
+    label: &'static str,
+    colour: Color,
+    bars: Vec<f32>,
+    // Slowly decaying peak used to normalise this deck independently of the crossfader
+    reference_level: f32,
+    level: f32,
+    tempo: TempoEstimator,
+}
+
+impl Deck {
+    fn new(label: &'static str, colour: Color, num_bars: usize, frame_rate: usize) -> Self {
+        Self {
+            label,
+            colour,
+            bars: vec![0.0; num_bars],
+            reference_level: 1e-6,
+            level: 0.0,
+            tempo: TempoEstimator::new(frame_rate),
+        }
+    }
+}
+
+/// Beat-matching aid showing two inputs at once: deck A's spectrum rises from the centre
+/// line and deck B's hangs below it, each with its own BPM readout
+///
+/// Each deck is normalised against its own recent peak rather than a shared one, so a deck
+/// pulled down on the crossfader still shows its full spectrum and keeps its tempo lock.
+/// The decks' relative levels are shown as an estimate of the crossfader position instead
+pub struct DualDeckVisualiser {
+    grouping: GroupingStrategy,
+    grouping_ranges: Vec<(usize, usize)>,
+    smoothing: SmoothingStrategy,
+    decks: [Deck; 2],
+    typography: Typography,
+}
+
+impl DualDeckVisualiser {
+    pub fn new(sampling_rate: usize, fft_size: usize, frame_rate: usize) -> Self {
+        let grouping = GroupingStrategy::LogMax { num_groups: 32 };
+        let grouping_ranges = grouping.create_ranges(sampling_rate, fft_size);
+        let num_bars = grouping.num_bars();
+
+        Self {
+            grouping,
+            grouping_ranges,
+            smoothing: SmoothingStrategy::RiseFall {
+                rise: 0.5,
+                fall: 0.9,
+            },
+            decks: [
+                Deck::new("A", Color::new(0.2, 0.7, 1.0, 1.0), num_bars, frame_rate),
+                Deck::new("B", Color::new(1.0, 0.4, 0.2, 1.0), num_bars, frame_rate),
+            ],
+            typography: Typography::new(),
+        }
+    }
+
+    /// Sets the font and styling of the readouts and labels
+    pub fn with_typography(mut self, typography: Typography) -> Self {
+        self.typography = typography;
+        self
+    }
+
+    /// Analyses and draws one frame from each deck's FFT spectrum
+    pub fn draw(&mut self, spectrum_a: &[f32], spectrum_b: &[f32]) {
+        for (deck, spectrum) in self.decks.iter_mut().zip([spectrum_a, spectrum_b]) {
+            let grouped = self
+                .grouping
+                .group_spectrum(spectrum, &self.grouping_ranges);
+            self.smoothing.smooth(&mut deck.bars, &grouped);
+
+            deck.level = spectrum.iter().sum::<f32>().sqrt();
+            let frame_max = deck.bars.iter().cloned().fold(1e-6, f32::max);
+            deck.reference_level = frame_max.max(deck.reference_level * 0.995);
+
+            deck.tempo.update(spectrum);
+        }
+
+        let centre = screen_height() / 2.0;
+
+        self.draw_deck(0, centre, -1.0);
+        self.draw_deck(1, centre, 1.0);
+        draw_line(0.0, centre, screen_width(), centre, ui_scale(), WHITE);
+
+        self.draw_phase_alignment(centre);
+        self.draw_crossfade_estimate();
+    }
+
+    /// Draws a deck's bars growing from `baseline` in `direction` (-1.0 up, 1.0 down)
+    fn draw_deck(&self, index: usize, baseline: f32, direction: f32) {
+        let deck = &self.decks[index];
+        let scale = ui_scale();
+        let columns = bar_columns(deck.bars.len(), screen_width(), BarGap::default(), true);
+        let max_height = baseline - 40.0 * scale;
+
+        for (&value, &(x, bar_width)) in deck.bars.iter().zip(&columns) {
+            let height = (value / deck.reference_level).min(1.0) * max_height;
+            let y = if direction < 0.0 {
+                baseline - height
+            } else {
+                baseline
+            };
+
+            draw_rectangle(x, y, bar_width, height, deck.colour);
+        }
+
+        let readout = match deck.tempo.bpm() {
+            Some(bpm) => format!("{} {:.1} BPM", deck.label, bpm),
+            None => format!("{} --- BPM", deck.label),
+        };
+        let text_y = if direction < 0.0 {
+            30.0 * scale
+        } else {
+            screen_height() - 12.0 * scale
+        };
+        self.typography.draw(
+            TextRole::Metadata,
+            &readout,
+            12.0 * scale,
+            text_y,
+            30.0 * scale,
+            deck.colour,
+        );
+
+        // Pulse on each beat so the decks' beats can be compared by eye
+        if let Some(phase) = deck.tempo.beat_phase() {
+            let radius = 14.0 * (1.0 - phase).powi(3);
+            draw_circle(
+                screen_width() - 30.0 * scale,
+                text_y - 10.0 * scale,
+                (radius + 2.0) * scale,
+                deck.colour,
+            );
+        }
+    }
+
+    /// Shows how far deck B's beat lags or leads deck A's, with the tempo difference
+    fn draw_phase_alignment(&self, centre: f32) {
+        let [a, b] = &self.decks;
+        let (Some(phase_a), Some(phase_b)) = (a.tempo.beat_phase(), b.tempo.beat_phase()) else {
+            return;
+        };
+
+        // Wrap to -0.5..0.5 of a beat: positive means B is behind A
+        let difference = (phase_a - phase_b + 0.5).rem_euclid(1.0) - 0.5;
+
+        let scale = ui_scale();
+        let width = screen_width() / 3.0;
+        let x_centre = screen_width() / 2.0;
+        let colour = if difference.abs() < ALIGNED_PHASE {
+            GREEN
+        } else {
+            YELLOW
+        };
+
+        draw_rectangle(
+            x_centre - width / 2.0,
+            centre - 3.0 * scale,
+            width,
+            6.0 * scale,
+            Color::new(0.3, 0.3, 0.3, 1.0),
+        );
+        draw_line(
+            x_centre,
+            centre - 12.0 * scale,
+            x_centre,
+            centre + 12.0 * scale,
+            2.0 * scale,
+            WHITE,
+        );
+        draw_rectangle(
+            x_centre + difference * width - 3.0 * scale,
+            centre - 10.0 * scale,
+            6.0 * scale,
+            20.0 * scale,
+            colour,
+        );
+
+        if let (Some(bpm_a), Some(bpm_b)) = (a.tempo.bpm(), b.tempo.bpm()) {
+            let text = format!(
+                "{:+.1} BPM  {:+.0}% beat",
+                bpm_b - bpm_a,
+                difference * 100.0
+            );
+            self.typography.draw(
+                TextRole::Metadata,
+                &text,
+                x_centre + width / 2.0 + 12.0 * scale,
+                centre + 8.0 * scale,
+                24.0 * scale,
+                colour,
+            );
+        }
+    }
+
+    /// Estimates the crossfader position from the decks' relative loudness
+    fn draw_crossfade_estimate(&self) {
+        let [a, b] = &self.decks;
+        let total = a.level + b.level;
+        if total <= f32::EPSILON {
+            return;
+        }
+
+        let scale = ui_scale();
+        let position = b.level / total;
+        let width = screen_width() / 4.0;
+        let x = (screen_width() - width) / 2.0;
+        let y = screen_height() - 20.0 * scale;
+
+        draw_rectangle(x, y, width, 4.0 * scale, Color::new(0.3, 0.3, 0.3, 1.0));
+        draw_rectangle(
+            x + (position * width - 4.0 * scale),
+            y - 6.0 * scale,
+            8.0 * scale,
+            16.0 * scale,
+            WHITE,
+        );
+        self.typography.draw(
+            TextRole::Labels,
+            "A",
+            x - 16.0 * scale,
+            y + 8.0 * scale,
+            20.0 * scale,
+            a.colour,
+        );
+        self.typography.draw(
+            TextRole::Labels,
+            "B",
+            x + width + 6.0 * scale,
+            y + 8.0 * scale,
+            20.0 * scale,
+            b.colour,
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+
+use crate::{bins, onset::OnsetDetector, tempo::TempoEstimator};
+
+// Frequencies below this count towards the bass energy that disappears in a breakdown
+const BASS_CUTOFF_HZ: f32 = 150.0;
+// A build must last this long (in seconds) before a drop can be predicted
+const MIN_BUILD_SECONDS: f64 = 2.0;
+// Beats per phrase; drops almost always land on a phrase boundary
+const PHRASE_BEATS: f32 = 16.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DropState {
+    Idle,
+    /// Bass has dropped out while novelty rises. `tension` ramps from 0.0 to 1.0 as the
+    /// predicted drop approaches
+    Building {
+        since: f64,
+        tension: f32,
+    },
+    /// The drop landed at `at` seconds
+    Dropped {
+        at: f64,
+    },
+}
+
+/// Predicts "drops": a breakdown or riser (little bass, rising novelty) that resolves
+/// into a sudden return of bass energy
+///
+/// Keeps the detector armed during a build so the drop can be reported on the very frame
+/// the bass returns, and estimates when it will land from the tempo and phrase length
+pub struct DropPredictor {
+    sampling_rate: usize,
+    frame_rate: f32,
+    onsets: OnsetDetector,
+    tempo: TempoEstimator,
+    // Slow moving averages of bass and total energy, the "normal" level of the track
+    bass_average: f32,
+    energy_average: f32,
+    novelty: VecDeque<f32>,
+    novelty_len: usize,
+    state: DropState,
+    // Bass level during the current build, for judging whether the return is a real drop
+    build_bass: f32,
+}
+
+impl DropPredictor {
+    pub fn new(sampling_rate: usize, frame_rate: usize) -> Self {
+        Self {
+            sampling_rate,
+            frame_rate: frame_rate as f32,
+            onsets: OnsetDetector::new(),
+            tempo: TempoEstimator::new(frame_rate),
+            bass_average: 0.0,
+            energy_average: 0.0,
+            novelty: VecDeque::with_capacity(frame_rate * 2),
+            novelty_len: frame_rate * 2,
+            state: DropState::Idle,
+            build_bass: 0.0,
+        }
+    }
+
+    /// Feeds a new FFT frame captured at `time` seconds and returns the updated state
+    pub fn update(&mut self, spectrum: &[f32], time: f64) -> DropState {
+        let freq_per_bin = bins::bin_width(spectrum.len(), self.sampling_rate);
+        let bass_bins = ((BASS_CUTOFF_HZ / freq_per_bin).ceil() as usize).min(spectrum.len());
+
+        let bass = spectrum[..bass_bins].iter().sum::<f32>().sqrt();
+        let energy = spectrum.iter().sum::<f32>().sqrt();
+
+        let flux = self.onsets.flux(spectrum);
+        self.onsets.update(spectrum);
+        self.tempo.update(spectrum);
+
+        if self.novelty.len() == self.novelty_len {
+            self.novelty.pop_front();
+        }
+        self.novelty.push_back(flux);
+
+        // ~10 second time constant, so a breakdown is judged against the track's usual level
+        let alpha = 1.0 / (10.0 * self.frame_rate);
+        self.bass_average += alpha * (bass - self.bass_average);
+        self.energy_average += alpha * (energy - self.energy_average);
+
+        self.state = match self.state {
+            DropState::Building { since, .. } => {
+                let bass_returned = bass > 0.8 * self.bass_average
+                    && bass > 2.0 * self.build_bass.max(f32::EPSILON);
+                self.build_bass = 0.9 * self.build_bass + 0.1 * bass;
+
+                if bass_returned && time - since >= MIN_BUILD_SECONDS {
+                    DropState::Dropped { at: time }
+                } else if bass < 0.5 * self.bass_average {
+                    // Stay armed for the whole breakdown, even if novelty dips for a moment
+                    DropState::Building {
+                        since,
+                        tension: self.tension(since, time),
+                    }
+                } else {
+                    DropState::Idle
+                }
+            }
+            DropState::Idle | DropState::Dropped { .. } => {
+                let settled = match self.state {
+                    DropState::Dropped { at } => time - at > MIN_BUILD_SECONDS,
+                    _ => true,
+                };
+
+                if settled && self.is_building(bass, energy) {
+                    self.build_bass = bass;
+                    DropState::Building {
+                        since: time,
+                        tension: 0.0,
+                    }
+                } else if settled {
+                    DropState::Idle
+                } else {
+                    self.state
+                }
+            }
+        };
+
+        self.state
+    }
+
+    /// Whether the track currently sounds like a build: much less bass than usual while
+    /// there's still energy, and novelty trending upwards
+    fn is_building(&self, bass: f32, energy: f32) -> bool {
+        let breakdown = bass < 0.5 * self.bass_average && energy > 0.3 * self.energy_average;
+
+        breakdown && self.novelty_slope() >= 0.0
+    }
+
+    /// Difference between the mean novelty of the newer and older halves of the window
+    fn novelty_slope(&self) -> f32 {
+        let half = self.novelty.len() / 2;
+        if half == 0 {
+            return 0.0;
+        }
+
+        let older = self.novelty.iter().take(half).sum::<f32>() / half as f32;
+        let newer =
+            self.novelty.iter().skip(half).sum::<f32>() / (self.novelty.len() - half) as f32;
+
+        newer - older
+    }
+
+    /// Progress towards the predicted drop, 0.0 at the start of the build and 1.0 on it
+    fn tension(&self, since: f64, time: f64) -> f32 {
+        let elapsed = (time - since) as f32;
+
+        match self.predicted_drop_in(since, time) {
+            Some(remaining) => (elapsed / (elapsed + remaining)).clamp(0.0, 1.0),
+            // Without a tempo, assume builds last around 8 seconds
+            None => (elapsed / 8.0).min(1.0),
+        }
+    }
+
+    /// Seconds until the next phrase boundary counted from the start of the build
+    fn predicted_drop_in(&self, since: f64, time: f64) -> Option<f32> {
+        let beat_seconds = 60.0 / self.tempo.bpm()?;
+        let phrase_seconds = beat_seconds * PHRASE_BEATS;
+        let elapsed = (time - since) as f32;
+
+        let phrases = (elapsed / phrase_seconds).floor() + 1.0;
+        Some(phrases * phrase_seconds - elapsed)
+    }
+}
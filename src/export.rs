@@ -0,0 +1,651 @@
+//! Rendering audio files to images offline, without opening the visualiser
+//!
+//! The whole file is analysed up front and drawn with labelled axes, either as a
+//! spectrogram with one image column per hop, or as a summary of its chromagram, loudness
+//! and novelty over time:
+//!
+//! ```text
+//! rust-audio-visualiser --spectrogram song.wav --spectrogram-kind cqt --colour-map viridis
+//! rust-audio-visualiser --plot song.wav --plot-format svg
+//! ```
+
+use std::{fmt::Write as _, fs, fs::File, io, io::BufWriter, path::Path};
+
+use cqt_rs::CQTParamsError;
+use thiserror::Error;
+
+use crate::{
+    analysis::{Analyser, FrameAnalysis},
+    colour::ColourMap,
+    spectra::{CqtAnalyzer, FourierTransform, chroma_index_to_note},
+    spectrogram::{MIN_FREQUENCY, log_rows},
+};
+
+// Frequency rows in an FFT spectrogram, spaced logarithmically
+const FFT_ROWS: usize = 512;
+// Pixels tall each semitone is drawn in a constant-Q spectrogram
+const CQT_ROW_HEIGHT: usize = 5;
+// Most columns drawn, beyond which the hop is lengthened so long files stay a usable size
+const MAX_COLUMNS: usize = 16384;
+// Range of levels shown below the loudest point in the file, in dB
+const DYNAMIC_RANGE: f32 = 80.0;
+// Space around the plot for the axis labels, in pixels
+const MARGIN_LEFT: usize = 64;
+const MARGIN_BOTTOM: usize = 32;
+const MARGIN_TOP: usize = 10;
+const MARGIN_RIGHT: usize = 10;
+// Length of the tick marks beside each label, in pixels
+const TICK_LENGTH: usize = 4;
+// Fewest pixels between labels on the time axis
+const MIN_TIME_LABEL_SPACING: usize = 80;
+// Intervals in seconds the time axis can be labelled at
+const TIME_LABEL_INTERVALS: [f32; 10] = [0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0, 300.0];
+// Frequencies labelled on an FFT spectrogram's axis, in Hz
+const FREQUENCY_LABELS: [f32; 9] = [
+    50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10_000.0, 20_000.0,
+];
+
+// Width of each panel of a feature plot, whatever the length of the file
+const PLOT_WIDTH: usize = 1600;
+// Heights of the feature plot's panels and the gap between them, in pixels
+const CHROMA_ROW_HEIGHT: usize = 16;
+const LOUDNESS_HEIGHT: usize = 160;
+const NOVELTY_HEIGHT: usize = 120;
+const PANEL_GAP: usize = 16;
+const LOUDNESS_TOP: usize = MARGIN_TOP + 12 * CHROMA_ROW_HEIGHT + PANEL_GAP;
+const NOVELTY_TOP: usize = LOUDNESS_TOP + LOUDNESS_HEIGHT + PANEL_GAP;
+// Quietest loudness plotted, in dBFS
+const LOUDNESS_FLOOR: f32 = -60.0;
+
+const BACKGROUND: [u8; 4] = [16, 16, 16, 255];
+const PANEL: [u8; 4] = [32, 32, 32, 255];
+const GRID: [u8; 4] = [64, 64, 64, 255];
+const FOREGROUND: [u8; 4] = [220, 220, 220, 255];
+const LOUDNESS_LINE: [u8; 4] = [255, 170, 60, 255];
+const NOVELTY_LINE: [u8; 4] = [90, 200, 250, 255];
+
+// Pixels each font pixel is drawn as
+const FONT_SCALE: usize = 2;
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("couldn't read WAV file: {0}")]
+    Wav(#[from] hound::Error),
+    #[error("couldn't write image: {0}")]
+    Io(#[from] io::Error),
+    #[error("couldn't encode PNG: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error(transparent)]
+    Cqt(#[from] CQTParamsError),
+    #[error("file is shorter than one analysis window")]
+    TooShort,
+}
+
+/// How a spectrogram's rows are spaced
+#[derive(Clone, Copy)]
+pub enum SpectrogramKind {
+    /// FFT bins resampled onto log-spaced rows, labelled in Hz
+    Fft,
+    /// One row per semitone from a constant-Q transform, labelled at each C
+    Cqt,
+}
+
+impl SpectrogramKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fft" => Some(SpectrogramKind::Fft),
+            "cqt" => Some(SpectrogramKind::Cqt),
+            _ => None,
+        }
+    }
+}
+
+/// File format of a feature plot
+#[derive(Clone, Copy)]
+pub enum PlotFormat {
+    Png,
+    /// Scalable, with real text, for papers and further editing
+    Svg,
+}
+
+impl PlotFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "png" => Some(PlotFormat::Png),
+            "svg" => Some(PlotFormat::Svg),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlotFormat::Png => "png",
+            PlotFormat::Svg => "svg",
+        }
+    }
+}
+
+/// Where text is drawn relative to the point it's given, horizontally
+#[derive(Clone, Copy)]
+pub enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+/// Something plots are drawn on, in pixels from the top left corner
+pub trait Surface {
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, colour: [u8; 4]);
+
+    /// Draws straight lines between successive points
+    fn polyline(&mut self, points: &[(f32, f32)], colour: [u8; 4]);
+
+    /// Draws one line of text, vertically centred on `y`
+    fn text(&mut self, x: usize, y: usize, text: &str, align: Align, colour: [u8; 4]);
+}
+
+/// RGBA image with a small built-in font for axis labels
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, background: [u8; 4]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: background.repeat(width * height),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Sets one pixel, ignoring any outside the canvas
+    pub fn set_pixel(&mut self, x: usize, y: usize, colour: [u8; 4]) {
+        if x < self.width && y < self.height {
+            let start = (y * self.width + x) * 4;
+            self.pixels[start..start + 4].copy_from_slice(&colour);
+        }
+    }
+
+    /// Width in pixels `text` is drawn at
+    pub fn text_width(text: &str) -> usize {
+        text.chars().count() * (GLYPH_WIDTH + 1) * FONT_SCALE
+    }
+
+    /// Height in pixels of a line of text
+    pub fn text_height() -> usize {
+        GLYPH_HEIGHT * FONT_SCALE
+    }
+
+    /// Draws `text` with its top left corner at `x`, `y`. Only digits, note names and the
+    /// letters in units are drawn, anything else is left as a gap
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, colour: [u8; 4]) {
+        for (index, c) in text.chars().enumerate() {
+            let left = x + index * (GLYPH_WIDTH + 1) * FONT_SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        self.fill_rect(
+                            left + column * FONT_SCALE,
+                            y + row * FONT_SCALE,
+                            FONT_SCALE,
+                            FONT_SCALE,
+                            colour,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), ExportError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+impl Surface for Canvas {
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, colour: [u8; 4]) {
+        for y in y..(y + height).min(self.height) {
+            for x in x..(x + width).min(self.width) {
+                self.set_pixel(x, y, colour);
+            }
+        }
+    }
+
+    fn polyline(&mut self, points: &[(f32, f32)], colour: [u8; 4]) {
+        for pair in points.windows(2) {
+            let [(x0, y0), (x1, y1)] = [pair[0], pair[1]];
+            let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let t = step as f32 / steps as f32;
+                let x = (x0 + (x1 - x0) * t).round() as usize;
+                let y = (y0 + (y1 - y0) * t).round() as usize;
+                self.set_pixel(x, y, colour);
+            }
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str, align: Align, colour: [u8; 4]) {
+        let width = Canvas::text_width(text);
+        let left = match align {
+            Align::Left => x,
+            Align::Centre => x.saturating_sub(width / 2),
+            Align::Right => x.saturating_sub(width),
+        };
+        let top = y.saturating_sub(Canvas::text_height() / 2);
+        self.draw_text(left, top, text, colour);
+    }
+}
+
+/// SVG document built up as text
+pub struct Svg {
+    width: usize,
+    height: usize,
+    body: String,
+}
+
+impl Svg {
+    pub fn new(width: usize, height: usize, background: [u8; 4]) -> Self {
+        let mut svg = Self {
+            width,
+            height,
+            body: String::new(),
+        };
+        svg.fill_rect(0, 0, width, height, background);
+        svg
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ExportError> {
+        let document = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+             viewBox=\"0 0 {0} {1}\" font-family=\"monospace\" font-size=\"{2}\">\n{3}</svg>\n",
+            self.width,
+            self.height,
+            Canvas::text_height(),
+            self.body,
+        );
+        fs::write(path, document)?;
+        Ok(())
+    }
+}
+
+impl Surface for Svg {
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, colour: [u8; 4]) {
+        // Writing to a String can't fail
+        let _ = writeln!(
+            self.body,
+            "<rect x=\"{x}\" y=\"{y}\" width=\"{width}\" height=\"{height}\" fill=\"{}\"/>",
+            svg_colour(colour),
+        );
+    }
+
+    fn polyline(&mut self, points: &[(f32, f32)], colour: [u8; 4]) {
+        let points: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{x:.1},{y:.1}"))
+            .collect();
+        let _ = writeln!(
+            self.body,
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\"/>",
+            points.join(" "),
+            svg_colour(colour),
+        );
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str, align: Align, colour: [u8; 4]) {
+        let anchor = match align {
+            Align::Left => "start",
+            Align::Centre => "middle",
+            Align::Right => "end",
+        };
+        let text = text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        let _ = writeln!(
+            self.body,
+            "<text x=\"{x}\" y=\"{y}\" text-anchor=\"{anchor}\" dominant-baseline=\"middle\" \
+             fill=\"{}\">{text}</text>",
+            svg_colour(colour),
+        );
+    }
+}
+
+fn svg_colour([r, g, b, a]: [u8; 4]) -> String {
+    if a == 255 {
+        format!("#{r:02x}{g:02x}{b:02x}")
+    } else {
+        format!("rgba({r},{g},{b},{:.3})", a as f32 / 255.0)
+    }
+}
+
+/// Rows of a 3x5 pixel glyph, with the leftmost pixel in the highest of the three bits
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b100, 0b100, 0b110, 0b101, 0b110],
+        'd' => [0b001, 0b001, 0b011, 0b101, 0b011],
+        'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'z' => [0b000, 0b111, 0b001, 0b010, 0b111],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Renders the spectrogram of `samples` to a PNG at `path`, with frequency up the side and
+/// time along the bottom. Levels are coloured by `colour_map` relative to the loudest point
+/// in the file
+pub fn spectrogram_png(
+    samples: &[f32],
+    sample_rate: usize,
+    kind: SpectrogramKind,
+    fft_size: usize,
+    hop_size: usize,
+    colour_map: &ColourMap,
+    path: &Path,
+) -> Result<(), ExportError> {
+    let hop_size = hop_size.max(samples.len().div_ceil(MAX_COLUMNS)).max(1);
+
+    // Levels in dB for each column, lowest frequency first, and the height of each row
+    let (columns, row_height): (Vec<Vec<f32>>, usize) = match kind {
+        SpectrogramKind::Fft => {
+            let fft = FourierTransform::new(fft_size).with_hop_size(hop_size);
+            let columns = fft
+                .compute_hops(samples)
+                .map(|spectrum| {
+                    log_rows(&spectrum, sample_rate, FFT_ROWS)
+                        .iter()
+                        .map(|&power| 10.0 * power.max(1e-12).log10())
+                        .collect()
+                })
+                .collect();
+            (columns, 1)
+        }
+        SpectrogramKind::Cqt => {
+            let cqt = CqtAnalyzer::new(sample_rate, fft_size)?;
+            let window_length = cqt.window_length();
+            let columns = (window_length..=samples.len())
+                .step_by(hop_size)
+                .map(|end| {
+                    cqt.compute(&samples[end - window_length..end])
+                        .iter()
+                        .map(|&magnitude| 20.0 * magnitude.max(1e-6).log10())
+                        .collect()
+                })
+                .collect();
+            (columns, CQT_ROW_HEIGHT)
+        }
+    };
+
+    let Some(rows) = columns.first().map(Vec::len) else {
+        return Err(ExportError::TooShort);
+    };
+    let loudest = columns.iter().flatten().cloned().fold(f32::MIN, f32::max);
+
+    let (plot_width, plot_height) = (columns.len(), rows * row_height);
+    let mut canvas = Canvas::new(
+        MARGIN_LEFT + plot_width + MARGIN_RIGHT,
+        MARGIN_TOP + plot_height + MARGIN_BOTTOM,
+        BACKGROUND,
+    );
+
+    for (x, column) in columns.iter().enumerate() {
+        for (row, &level) in column.iter().enumerate() {
+            let normalised = (1.0 - (loudest - level) / DYNAMIC_RANGE).clamp(0.0, 1.0);
+            let y = MARGIN_TOP + plot_height - (row + 1) * row_height;
+            canvas.fill_rect(
+                MARGIN_LEFT + x,
+                y,
+                1,
+                row_height,
+                colour_map.lookup(normalised),
+            );
+        }
+    }
+
+    // Frequency labels, centred on the row they name
+    let label_row = |canvas: &mut Canvas, row_centre: f32, label: &str| {
+        let y = MARGIN_TOP + plot_height - (row_centre.round() as usize).min(plot_height);
+        draw_label(canvas, y, label);
+    };
+    match kind {
+        SpectrogramKind::Fft => {
+            let nyquist = sample_rate as f32 / 2.0;
+            for &frequency in FREQUENCY_LABELS.iter().filter(|&&f| f < nyquist) {
+                let row = (frequency / MIN_FREQUENCY).ln() / (nyquist / MIN_FREQUENCY).ln();
+                let label = if frequency >= 1000.0 {
+                    format!("{}kHz", frequency / 1000.0)
+                } else {
+                    format!("{frequency}Hz")
+                };
+                label_row(&mut canvas, row * plot_height as f32, &label);
+            }
+        }
+        SpectrogramKind::Cqt => {
+            let cqt = CqtAnalyzer::new(sample_rate, fft_size)?;
+            for bin in (0..rows).filter(|&bin| cqt.bin_pitch(bin) % 12 == 0) {
+                let octave = cqt.bin_pitch(bin) / 12 - 1;
+                let centre = (bin as f32 + 0.5) * row_height as f32;
+                label_row(&mut canvas, centre, &format!("C{octave}"));
+            }
+        }
+    }
+
+    draw_time_axis(
+        &mut canvas,
+        MARGIN_TOP + plot_height,
+        plot_width,
+        hop_size as f32 / sample_rate as f32,
+    );
+
+    canvas.save_png(path)
+}
+
+/// Renders a summary of `samples` to `path`: its chromagram, loudness in dBFS and novelty
+/// (spectral flux relative to its peak in the file) stacked over a shared time axis, each
+/// analysed as the visualiser does live
+pub fn feature_plot(
+    samples: &[f32],
+    sample_rate: usize,
+    fft_size: usize,
+    hop_size: usize,
+    colour_map: &ColourMap,
+    format: PlotFormat,
+    path: &Path,
+) -> Result<(), ExportError> {
+    let fft = FourierTransform::new(fft_size).with_hop_size(hop_size);
+    let mut analyser = Analyser::new(sample_rate, (sample_rate / hop_size).max(1));
+    let frames: Vec<_> = (fft_size..=samples.len())
+        .step_by(hop_size)
+        .map(|end| {
+            let window = &samples[end - fft_size..end];
+            let time = end as f64 / sample_rate as f64;
+            analyser.analyse(window, fft.compute(window), time)
+        })
+        .collect();
+    if frames.is_empty() {
+        return Err(ExportError::TooShort);
+    }
+
+    let width = MARGIN_LEFT + PLOT_WIDTH + MARGIN_RIGHT;
+    let height = NOVELTY_TOP + NOVELTY_HEIGHT + MARGIN_BOTTOM;
+    let duration = samples.len() as f32 / sample_rate as f32;
+    match format {
+        PlotFormat::Png => {
+            let mut canvas = Canvas::new(width, height, BACKGROUND);
+            draw_features(&mut canvas, &frames, duration, colour_map);
+            canvas.save_png(path)
+        }
+        PlotFormat::Svg => {
+            let mut svg = Svg::new(width, height, BACKGROUND);
+            draw_features(&mut svg, &frames, duration, colour_map);
+            svg.save(path)
+        }
+    }
+}
+
+/// Draws the panels of a feature plot for `frames`, which cover `duration` seconds
+fn draw_features(
+    surface: &mut impl Surface,
+    frames: &[FrameAnalysis],
+    duration: f32,
+    colour_map: &ColourMap,
+) {
+    // Frames averaged into each column, or repeated over several for short files
+    let column_frames = |x: usize| {
+        let start = x * frames.len() / PLOT_WIDTH;
+        let end = ((x + 1) * frames.len() / PLOT_WIDTH).max(start + 1);
+        &frames[start..end]
+    };
+    let loudest_flux = frames
+        .iter()
+        .map(|frame| frame.flux)
+        .fold(f32::MIN_POSITIVE, f32::max);
+
+    let bottom = NOVELTY_TOP + NOVELTY_HEIGHT;
+
+    // Chromagram, with each column relative to its strongest note so quiet passages still
+    // show their harmony, and C at the bottom
+    for x in 0..PLOT_WIDTH {
+        let mut chromagram = [0.0; 12];
+        for frame in column_frames(x) {
+            for (sum, value) in chromagram.iter_mut().zip(frame.chromagram) {
+                *sum += value;
+            }
+        }
+        let strongest = chromagram.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+        for (note, value) in chromagram.iter().enumerate() {
+            let y = MARGIN_TOP + (11 - note) * CHROMA_ROW_HEIGHT;
+            let colour = colour_map.lookup(value / strongest);
+            surface.fill_rect(MARGIN_LEFT + x, y, 1, CHROMA_ROW_HEIGHT, colour);
+        }
+    }
+    for note in 0..12 {
+        let y = MARGIN_TOP + (11 - note) * CHROMA_ROW_HEIGHT + CHROMA_ROW_HEIGHT / 2;
+        draw_label(surface, y, &chroma_index_to_note(note));
+    }
+
+    // Loudness, with grid lines every 20dB
+    surface.fill_rect(
+        MARGIN_LEFT,
+        LOUDNESS_TOP,
+        PLOT_WIDTH,
+        LOUDNESS_HEIGHT,
+        PANEL,
+    );
+    let loudness_y = |loudness: f32| {
+        let fraction = (loudness / LOUDNESS_FLOOR).clamp(0.0, 1.0);
+        LOUDNESS_TOP as f32 + fraction * (LOUDNESS_HEIGHT - 1) as f32
+    };
+    for db in [0, -20, -40, -60] {
+        let y = loudness_y(db as f32).round() as usize;
+        surface.fill_rect(MARGIN_LEFT, y, PLOT_WIDTH, 1, GRID);
+        draw_label(surface, y, &format!("{db}dB"));
+    }
+    let points: Vec<_> = (0..PLOT_WIDTH)
+        .map(|x| {
+            let frames = column_frames(x);
+            let loudness =
+                frames.iter().map(|frame| frame.loudness).sum::<f32>() / frames.len() as f32;
+            ((MARGIN_LEFT + x) as f32, loudness_y(loudness))
+        })
+        .collect();
+    surface.polyline(&points, LOUDNESS_LINE);
+
+    // Novelty, keeping the sharpest change in each column so onsets aren't averaged away
+    surface.fill_rect(MARGIN_LEFT, NOVELTY_TOP, PLOT_WIDTH, NOVELTY_HEIGHT, PANEL);
+    let points: Vec<_> = (0..PLOT_WIDTH)
+        .map(|x| {
+            let flux = column_frames(x)
+                .iter()
+                .map(|frame| frame.flux)
+                .fold(0.0, f32::max);
+            let y = bottom as f32 - 1.0 - flux / loudest_flux * (NOVELTY_HEIGHT - 1) as f32;
+            ((MARGIN_LEFT + x) as f32, y)
+        })
+        .collect();
+    surface.polyline(&points, NOVELTY_LINE);
+
+    draw_time_axis(surface, bottom, PLOT_WIDTH, duration / PLOT_WIDTH as f32);
+}
+
+/// Draws a tick and label left of the plot area at `y`
+fn draw_label(surface: &mut impl Surface, y: usize, label: &str) {
+    surface.fill_rect(MARGIN_LEFT - TICK_LENGTH, y, TICK_LENGTH, 1, FOREGROUND);
+    surface.text(
+        MARGIN_LEFT - TICK_LENGTH * 2,
+        y,
+        label,
+        Align::Right,
+        FOREGROUND,
+    );
+}
+
+/// Draws ticks and labels in seconds below a plot whose bottom edge is at `bottom`, where
+/// each column is `seconds_per_column` long
+fn draw_time_axis(
+    surface: &mut impl Surface,
+    bottom: usize,
+    width: usize,
+    seconds_per_column: f32,
+) {
+    let interval = TIME_LABEL_INTERVALS
+        .iter()
+        .copied()
+        .find(|&interval| interval / seconds_per_column >= MIN_TIME_LABEL_SPACING as f32)
+        .unwrap_or(TIME_LABEL_INTERVALS[TIME_LABEL_INTERVALS.len() - 1]);
+
+    let mut seconds = 0.0;
+    loop {
+        let x = (seconds / seconds_per_column).round() as usize;
+        if x >= width {
+            break;
+        }
+        surface.fill_rect(MARGIN_LEFT + x, bottom, 1, TICK_LENGTH, FOREGROUND);
+        surface.text(
+            MARGIN_LEFT + x,
+            bottom + TICK_LENGTH * 2 + Canvas::text_height() / 2,
+            &format!("{seconds}s"),
+            Align::Centre,
+            FOREGROUND,
+        );
+        seconds += interval;
+    }
+}
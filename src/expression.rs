@@ -0,0 +1,457 @@
+use thiserror::Error;
+
+use crate::{
+    analysis::{Feature, Features, FrameAnalysis},
+    automation::Parameter,
+};
+
+// Deepest operators and parentheses can nest, so parsing, evaluating or dropping an
+// expression can't overflow the stack
+const MAX_DEPTH: usize = 256;
+
+/// Error from parsing an expression, with the byte offset it was found at
+#[derive(Debug, Error)]
+#[error("{message} at position {position}")]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Token {
+    Number(f32),
+    Identifier(usize, usize),
+    Operator(char),
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+/// Analysis values an expression can refer to by name
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+    Time,
+    Loudness,
+    Bass,
+    Mids,
+    Treble,
+    Flux,
+    Onset,
+    Bpm,
+    BeatPhase,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "time" => Variable::Time,
+            "loudness" => Variable::Loudness,
+            "bass" => Variable::Bass,
+            "mids" => Variable::Mids,
+            "treble" => Variable::Treble,
+            "flux" => Variable::Flux,
+            "onset" => Variable::Onset,
+            "bpm" => Variable::Bpm,
+            "beat" => Variable::BeatPhase,
+            _ => return None,
+        })
+    }
+
+    fn features(&self) -> Features {
+        match self {
+            Variable::Time | Variable::Loudness => Features::NONE,
+            Variable::Bass | Variable::Mids | Variable::Treble => Feature::Bands.into(),
+            Variable::Flux | Variable::Onset => Feature::Onsets.into(),
+            Variable::Bpm | Variable::BeatPhase => Feature::Beats.into(),
+        }
+    }
+
+    fn value(&self, analysis: &FrameAnalysis) -> f32 {
+        match self {
+            Variable::Time => analysis.time as f32,
+            Variable::Loudness => analysis.loudness,
+            Variable::Bass => analysis.bass,
+            Variable::Mids => analysis.mids,
+            Variable::Treble => analysis.treble,
+            Variable::Flux => analysis.flux,
+            Variable::Onset => analysis.onset as u8 as f32,
+            Variable::Bpm => analysis.bpm.unwrap_or(0.0),
+            Variable::BeatPhase => analysis.beat_phase.unwrap_or(0.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Sin,
+    Cos,
+    Abs,
+    Sqrt,
+    Floor,
+    Fract,
+    Min,
+    Max,
+    Clamp,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "floor" => Function::Floor,
+            "fract" => Function::Fract,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "clamp" => Function::Clamp,
+            _ => return None,
+        })
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Function::Min | Function::Max => 2,
+            Function::Clamp => 3,
+            _ => 1,
+        }
+    }
+
+    fn call(&self, args: &[f32]) -> f32 {
+        match self {
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Abs => args[0].abs(),
+            Function::Sqrt => args[0].max(0.0).sqrt(),
+            Function::Floor => args[0].floor(),
+            Function::Fract => args[0].rem_euclid(1.0),
+            Function::Min => args[0].min(args[1]),
+            Function::Max => args[0].max(args[1]),
+            Function::Clamp => args[0].max(args[1]).min(args[2]),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Constant(f32),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn features(&self) -> Features {
+        match self {
+            Node::Constant(_) => Features::NONE,
+            Node::Variable(variable) => variable.features(),
+            Node::Negate(node) => node.features(),
+            Node::Binary(_, left, right) => left.features() | right.features(),
+            Node::Call(_, args) => args
+                .iter()
+                .fold(Features::NONE, |features, arg| features | arg.features()),
+        }
+    }
+
+    fn evaluate(&self, analysis: &FrameAnalysis) -> f32 {
+        match self {
+            Node::Constant(value) => *value,
+            Node::Variable(variable) => variable.value(analysis),
+            Node::Negate(node) => -node.evaluate(analysis),
+            Node::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(analysis), right.evaluate(analysis));
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    '/' => left / right,
+                    '%' => left.rem_euclid(right),
+                    '^' => left.powf(right),
+                    _ => unreachable!(),
+                }
+            }
+            Node::Call(function, args) => {
+                let args: Vec<f32> = args.iter().map(|arg| arg.evaluate(analysis)).collect();
+                function.call(&args)
+            }
+        }
+    }
+}
+
+/// Arithmetic expression over analysis features, such as `bass*0.5 + time*10`
+///
+/// Supports `+ - * / % ^`, parentheses, the variables `time`, `loudness`, `bass`, `mids`,
+/// `treble`, `flux`, `onset`, `bpm` and `beat`, and the functions `sin`, `cos`, `abs`,
+/// `sqrt`, `floor`, `fract`, `min`, `max` and `clamp`. Parsed once, then evaluated every frame
+#[derive(Clone, Debug)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenise(source)?;
+        let mut parser = Parser {
+            source,
+            tokens: &tokens,
+            position: 0,
+            depth: 0,
+        };
+
+        let root = parser.expression()?;
+        if parser.position < tokens.len() {
+            return Err(parser.error("unexpected token"));
+        }
+
+        Ok(Self { root })
+    }
+
+    /// Parts of the analysis the expression's variables come from
+    pub fn features(&self) -> Features {
+        self.root.features()
+    }
+
+    /// Value of the expression for this frame. Non-finite results (e.g. from dividing by
+    /// zero) evaluate to 0.0 so one bad frame can't poison a parameter
+    pub fn evaluate(&self, analysis: &FrameAnalysis) -> f32 {
+        let value = self.root.evaluate(analysis);
+        if value.is_finite() { value } else { 0.0 }
+    }
+}
+
+/// A parameter driven directly by an expression, written as `parameter = expression`
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub target: Parameter,
+    pub expression: Expression,
+}
+
+impl Binding {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let Some((name, expression)) = source.split_once('=') else {
+            return Err(ParseError {
+                position: 0,
+                message: "expected `parameter = expression`".to_string(),
+            });
+        };
+
+        let target = Parameter::from_name(name.trim()).ok_or_else(|| ParseError {
+            position: 0,
+            message: format!("unknown parameter `{}`", name.trim()),
+        })?;
+
+        let offset = name.len() + 1;
+        let expression = Expression::parse(expression).map_err(|error| ParseError {
+            position: error.position + offset,
+            ..error
+        })?;
+
+        Ok(Self { target, expression })
+    }
+}
+
+/// Splits `source` into tokens, storing identifiers as byte ranges into it
+fn tokenise(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        let token = if c.is_whitespace() {
+            chars.next();
+            continue;
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+
+            let number = source[start..end].parse().map_err(|_| ParseError {
+                position: start,
+                message: format!("invalid number `{}`", &source[start..end]),
+            })?;
+            Token::Number(number)
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            Token::Identifier(start, end)
+        } else {
+            chars.next();
+            match c {
+                '+' | '-' | '*' | '/' | '%' | '^' => Token::Operator(c),
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                ',' => Token::Comma,
+                _ => {
+                    return Err(ParseError {
+                        position: start,
+                        message: format!("unexpected character `{}`", c),
+                    });
+                }
+            }
+        };
+
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser, one method per precedence level
+struct Parser<'a> {
+    source: &'a str,
+    tokens: &'a [(usize, Token)],
+    position: usize,
+    // Nodes the one being parsed is nested under, at most `MAX_DEPTH`
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).map(|&(_, token)| token)
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            position: self
+                .tokens
+                .get(self.position)
+                .map_or(self.source.len(), |&(offset, _)| offset),
+            message: message.to_string(),
+        }
+    }
+
+    // Goes a level deeper, failing past `MAX_DEPTH`. Callers put `depth` back once done
+    fn nest(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("expression nested too deeply"));
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: Token, message: &str) -> Result<(), ParseError> {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Node, ParseError> {
+        let depth = self.depth;
+        let mut node = self.term()?;
+        // Each operator puts the terms before it a level deeper
+        while let Some(Token::Operator(operator @ ('+' | '-'))) = self.peek() {
+            self.nest()?;
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.term()?));
+        }
+        self.depth = depth;
+        Ok(node)
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Node, ParseError> {
+        let depth = self.depth;
+        let mut node = self.unary()?;
+        while let Some(Token::Operator(operator @ ('*' | '/' | '%'))) = self.peek() {
+            self.nest()?;
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(node)
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        // Every way back into the grammar, through `-`, `^`, parentheses and arguments,
+        // goes through here
+        let depth = self.depth;
+        self.nest()?;
+        let node = if self.peek() == Some(Token::Operator('-')) {
+            self.position += 1;
+            Node::Negate(Box::new(self.unary()?))
+        } else {
+            self.power()?
+        };
+        self.depth = depth;
+        Ok(node)
+    }
+
+    // power := primary ('^' unary)?, right associative
+    fn power(&mut self) -> Result<Node, ParseError> {
+        let base = self.primary()?;
+        if self.peek() == Some(Token::Operator('^')) {
+            self.position += 1;
+            return Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    // primary := number | variable | function '(' arguments ')' | '(' expression ')'
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        match self.peek() {
+            Some(Token::Number(value)) => {
+                self.position += 1;
+                Ok(Node::Constant(value))
+            }
+            Some(Token::OpenParen) => {
+                self.position += 1;
+                let node = self.expression()?;
+                self.expect(Token::CloseParen, "expected `)`")?;
+                Ok(node)
+            }
+            Some(Token::Identifier(start, end)) => {
+                let name = &self.source[start..end];
+
+                if let Some(variable) = Variable::from_name(name) {
+                    self.position += 1;
+                    return Ok(Node::Variable(variable));
+                }
+
+                let Some(function) = Function::from_name(name) else {
+                    return Err(self.error(&format!("unknown name `{}`", name)));
+                };
+                self.position += 1;
+                self.expect(Token::OpenParen, "expected `(` after function name")?;
+
+                let mut args = vec![self.expression()?];
+                while self.peek() == Some(Token::Comma) {
+                    self.position += 1;
+                    args.push(self.expression()?);
+                }
+                self.expect(Token::CloseParen, "expected `)`")?;
+
+                if args.len() != function.arity() {
+                    return Err(ParseError {
+                        position: start,
+                        message: format!(
+                            "`{}` takes {} argument(s), got {}",
+                            name,
+                            function.arity(),
+                            args.len()
+                        ),
+                    });
+                }
+
+                Ok(Node::Call(function, args))
+            }
+            _ => Err(self.error("expected a number, name or `(`")),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use alloc::vec::Vec;
+
+use libm::expf;
+
+// Reference level never falls below this, so silence doesn't divide by zero
+const MIN_REFERENCE: f32 = 1e-6;
+
+/// Automatic gain control for bar heights: a reference level that follows the tallest bar
+/// slowly, so heights stay steady through quiet and loud sections rather than the tallest
+/// bar always filling the screen
+pub struct AutoGain {
+    // Fraction of the gap to the peak left after each update when rising and falling
+    attack: f32,
+    release: f32,
+    reference: Option<f32>,
+}
+
+impl AutoGain {
+    /// Rises towards louder peaks with a time constant of `attack` seconds and falls towards
+    /// quieter ones over `release` seconds, updated `frame_rate` times a second. Zero for
+    /// both follows every peak immediately, like normalising to the tallest bar
+    pub fn new(attack: f32, release: f32, frame_rate: usize) -> Self {
+        let coefficient = |seconds: f32| {
+            if seconds > 0.0 {
+                expf(-1.0 / (seconds * frame_rate.max(1) as f32))
+            } else {
+                0.0
+            }
+        };
+
+        Self {
+            attack: coefficient(attack),
+            release: coefficient(release),
+            reference: None,
+        }
+    }
+
+    /// Moves the reference towards `peak`, returning the new reference to divide levels by
+    pub fn update(&mut self, peak: f32) -> f32 {
+        // Starts at the first peak rather than rising from nothing
+        let reference = self.reference.unwrap_or(peak);
+        let coefficient = if peak > reference {
+            self.attack
+        } else {
+            self.release
+        };
+        let reference = peak + (reference - peak) * coefficient;
+        self.reference = Some(reference);
+
+        reference.max(MIN_REFERENCE)
+    }
+
+    /// Levels from 0.0 to 1.0 for `values`, relative to the reference after updating it with
+    /// their peak. Anything above the reference while it catches up is clipped
+    pub fn normalise(&mut self, values: &[f32]) -> Vec<f32> {
+        let peak = values.iter().cloned().fold(0.0, f32::max);
+        let reference = self.update(peak);
+        values
+            .iter()
+            .map(|value| (value / reference).min(1.0))
+            .collect()
+    }
+}
@@ -0,0 +1,80 @@
+use libm::{expf, powf, sqrtf};
+
+// The gate closes once the level drops this far below the threshold, so a level hovering
+// around the threshold doesn't flutter it open and shut
+const HYSTERESIS_DB: f32 = 6.0;
+
+/// Noise gate for microphone input, silencing blocks of samples quieter than a threshold
+/// so room noise doesn't keep low-level bars up between sounds
+///
+/// The level is measured over each block given to `detect`, and the gain then moves
+/// sample by sample, opening quickly and closing smoothly after a hold so the gate
+/// doesn't click or cut off the tails of notes
+pub struct NoiseGate {
+    // RMS levels the gate opens above and closes below
+    open_level: f32,
+    close_level: f32,
+    // Per-sample fraction of the gap to the target gain left when opening and closing
+    attack: f32,
+    release: f32,
+    hold_samples: usize,
+    // Samples left before a closing gate starts to fade
+    held: usize,
+    open: bool,
+    gain: f32,
+}
+
+impl NoiseGate {
+    /// Opens above `threshold` dBFS and fades out over `release` seconds once the level has
+    /// stayed below it for `hold` seconds
+    pub fn new(threshold: f32, hold: f32, release: f32, sampling_rate: usize) -> Self {
+        let sampling_rate = sampling_rate.max(1) as f32;
+        let coefficient = |seconds: f32| {
+            if seconds > 0.0 {
+                expf(-1.0 / (seconds * sampling_rate))
+            } else {
+                0.0
+            }
+        };
+        let level = |db: f32| powf(10.0, db / 20.0);
+
+        Self {
+            open_level: level(threshold),
+            close_level: level(threshold - HYSTERESIS_DB),
+            // Fast enough not to blunt the attack of a note
+            attack: coefficient(0.002),
+            release: coefficient(release),
+            hold_samples: (hold.max(0.0) * sampling_rate) as usize,
+            held: 0,
+            open: false,
+            gain: 0.0,
+        }
+    }
+
+    /// Opens or starts closing the gate on the level of the next block of `samples`
+    pub fn detect(&mut self, samples: &[f32]) {
+        let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+        let level = sqrtf(mean_square);
+
+        if level >= self.open_level {
+            self.open = true;
+            self.held = self.hold_samples;
+        } else if level < self.close_level {
+            self.held = self.held.saturating_sub(samples.len());
+            if self.held == 0 {
+                self.open = false;
+            }
+        }
+    }
+
+    /// Gain to multiply the next sample by, from 0.0 when closed to 1.0 when open
+    pub fn next_gain(&mut self) -> f32 {
+        let (target, coefficient) = if self.open {
+            (1.0, self.attack)
+        } else {
+            (0.0, self.release)
+        };
+        self.gain = target + (self.gain - target) * coefficient;
+        self.gain
+    }
+}
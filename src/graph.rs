@@ -0,0 +1,561 @@
+//! Processing graphs defined in the config file, running alongside the scenes
+//!
+//! Each `[[node]]` table is one step: a source of captured samples, a transform, a feature
+//! or a sink. Nodes name the node they take their input from, so several chains can share a
+//! capture or a spectrum:
+//!
+//! ```toml
+//! [[node]]
+//! kind = "capture"
+//! name = "mono"
+//!
+//! [[node]]
+//! kind = "fft"
+//! name = "spectrum"
+//! input = "mono"
+//!
+//! [[node]]
+//! kind = "group"
+//! name = "bands"
+//! input = "spectrum"
+//! bars = 32
+//!
+//! [[node]]
+//! kind = "normalise"
+//! name = "levels"
+//! input = "bands"
+//!
+//! [[node]]
+//! kind = "screen"
+//! name = "overlay"
+//! input = "levels"
+//! area = [0.0, 0.8, 1.0, 0.2]
+//! ```
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use macroquad::{
+    color::{Color, WHITE},
+    math::Rect,
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    chroma::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram},
+    config::{ConfigColour, GroupingKind},
+    grouping::GroupingStrategy,
+    primitives::{BarRenderer, BarStyle},
+    smoothing::SmoothingStrategy,
+    spectra::FourierTransform,
+};
+
+#[derive(Debug, Error)]
+pub enum GraphError {
+    #[error("more than one node is called `{0}`")]
+    DuplicateName(String),
+    #[error("node `{node}` takes its input from `{input}`, which doesn't exist")]
+    UnknownInput { node: String, input: String },
+    #[error("node `{node}` needs {expected} but `{input}` gives {found}")]
+    Mismatch {
+        node: String,
+        input: String,
+        expected: Signal,
+        found: Signal,
+    },
+    #[error("node `{node}` can't take its input from `{input}`, which is a sink")]
+    FromSink { node: String, input: String },
+    #[error("node `{0}` is part of a loop")]
+    Cycle(String),
+    #[error("couldn't open {0}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// What a node passes on to the nodes reading from it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// A window of audio samples, oldest first
+    Samples,
+    /// A power spectrum from 0Hz to the Nyquist frequency
+    Spectrum,
+    /// Any list of values, such as bar heights or a chromagram
+    Levels,
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Signal::Samples => "samples",
+            Signal::Spectrum => "a spectrum",
+            Signal::Levels => "levels",
+        })
+    }
+}
+
+/// Which captured samples a capture node passes on
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Channel {
+    #[default]
+    Mono,
+    Left,
+    Right,
+}
+
+/// One `[[node]]` table, told apart by its `kind`
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum NodeConfig {
+    /// Source of each window of captured samples
+    Capture {
+        name: String,
+        #[serde(default)]
+        channel: Channel,
+    },
+    /// Power spectrum of a window of samples
+    Fft { name: String, input: String },
+    /// Groups a spectrum into bars
+    Group {
+        name: String,
+        input: String,
+        #[serde(default = "default_grouping")]
+        strategy: GroupingKind,
+        #[serde(default = "default_bars")]
+        bars: usize,
+        /// Only used by `gamma-corrected`
+        #[serde(default = "default_gamma")]
+        gamma: f32,
+    },
+    /// Rise and fall smoothing of levels between windows
+    Smooth {
+        name: String,
+        input: String,
+        rise: f32,
+        fall: f32,
+    },
+    /// Scales levels so the largest is 1.0
+    Normalise { name: String, input: String },
+    /// Energy in each pitch class from C to B, from a spectrum
+    Chromagram { name: String, input: String },
+    /// Loudness of a window of samples, mapped from -60dBFS..0dBFS to 0.0..1.0
+    Loudness { name: String, input: String },
+    /// Draws levels from 0.0 to 1.0 as bars over the scene
+    Screen {
+        name: String,
+        input: String,
+        colour: Option<ConfigColour>,
+        /// Left, top, width and height as fractions of the window, the whole window if
+        /// left out
+        area: Option<[f32; 4]>,
+    },
+    /// Appends a line to a CSV file for every window, the time followed by the input
+    Csv {
+        name: String,
+        input: String,
+        path: PathBuf,
+    },
+}
+
+fn default_grouping() -> GroupingKind {
+    GroupingKind::LogMax
+}
+
+fn default_bars() -> usize {
+    12
+}
+
+fn default_gamma() -> f32 {
+    2.0
+}
+
+impl NodeConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            NodeConfig::Capture { name, .. }
+            | NodeConfig::Fft { name, .. }
+            | NodeConfig::Group { name, .. }
+            | NodeConfig::Smooth { name, .. }
+            | NodeConfig::Normalise { name, .. }
+            | NodeConfig::Chromagram { name, .. }
+            | NodeConfig::Loudness { name, .. }
+            | NodeConfig::Screen { name, .. }
+            | NodeConfig::Csv { name, .. } => name,
+        }
+    }
+
+    /// Name of the node this one reads from, or none for sources
+    pub fn input(&self) -> Option<&str> {
+        match self {
+            NodeConfig::Capture { .. } => None,
+            NodeConfig::Fft { input, .. }
+            | NodeConfig::Group { input, .. }
+            | NodeConfig::Smooth { input, .. }
+            | NodeConfig::Normalise { input, .. }
+            | NodeConfig::Chromagram { input, .. }
+            | NodeConfig::Loudness { input, .. }
+            | NodeConfig::Screen { input, .. }
+            | NodeConfig::Csv { input, .. } => Some(input),
+        }
+    }
+
+    /// What the node needs from its input, or none if it takes anything
+    fn accepts(&self) -> Option<Signal> {
+        match self {
+            NodeConfig::Capture { .. } | NodeConfig::Csv { .. } => None,
+            NodeConfig::Fft { .. } | NodeConfig::Loudness { .. } => Some(Signal::Samples),
+            NodeConfig::Group { .. } | NodeConfig::Chromagram { .. } => Some(Signal::Spectrum),
+            NodeConfig::Smooth { .. }
+            | NodeConfig::Normalise { .. }
+            | NodeConfig::Screen { .. } => Some(Signal::Levels),
+        }
+    }
+
+    /// What the node passes on, or none for sinks
+    fn produces(&self) -> Option<Signal> {
+        match self {
+            NodeConfig::Capture { .. } => Some(Signal::Samples),
+            NodeConfig::Fft { .. } => Some(Signal::Spectrum),
+            NodeConfig::Group { .. }
+            | NodeConfig::Smooth { .. }
+            | NodeConfig::Normalise { .. }
+            | NodeConfig::Chromagram { .. }
+            | NodeConfig::Loudness { .. } => Some(Signal::Levels),
+            NodeConfig::Screen { .. } | NodeConfig::Csv { .. } => None,
+        }
+    }
+}
+
+/// Checks every input exists and gives what its reader needs, returning the order to run
+/// the nodes in so each runs after its input
+pub fn plan(nodes: &[NodeConfig]) -> Result<Vec<usize>, GraphError> {
+    let mut inputs = Vec::with_capacity(nodes.len());
+    for (index, node) in nodes.iter().enumerate() {
+        if nodes[..index]
+            .iter()
+            .any(|other| other.name() == node.name())
+        {
+            return Err(GraphError::DuplicateName(node.name().to_string()));
+        }
+
+        let Some(input_name) = node.input() else {
+            inputs.push(None);
+            continue;
+        };
+        let input = nodes
+            .iter()
+            .position(|other| other.name() == input_name)
+            .ok_or_else(|| GraphError::UnknownInput {
+                node: node.name().to_string(),
+                input: input_name.to_string(),
+            })?;
+
+        let found = nodes[input].produces();
+        if let (Some(expected), Some(found)) = (node.accepts(), found)
+            && expected != found
+        {
+            return Err(GraphError::Mismatch {
+                node: node.name().to_string(),
+                input: input_name.to_string(),
+                expected,
+                found,
+            });
+        }
+        if found.is_none() {
+            return Err(GraphError::FromSink {
+                node: node.name().to_string(),
+                input: input_name.to_string(),
+            });
+        }
+
+        inputs.push(Some(input));
+    }
+
+    // Each node has at most one input, so following inputs back from any node either
+    // reaches a source or goes round a loop within `nodes.len()` steps
+    let depth = |mut index: usize| {
+        let mut depth = 0;
+        while let Some(input) = inputs[index] {
+            index = input;
+            depth += 1;
+            if depth > nodes.len() {
+                return Err(GraphError::Cycle(nodes[index].name().to_string()));
+            }
+        }
+        Ok(depth)
+    };
+
+    let mut order = (0..nodes.len())
+        .map(|index| depth(index).map(|depth| (depth, index)))
+        .collect::<Result<Vec<_>, _>>()?;
+    order.sort();
+
+    Ok(order.into_iter().map(|(_, index)| index).collect())
+}
+
+/// Captured samples for one window, fed to every capture node
+pub struct GraphInput<'a> {
+    pub mono: &'a [f32],
+    /// Left and right samples, if they're being captured separately
+    pub channels: Option<[&'a [f32]; 2]>,
+    /// Seconds since the analysis started
+    pub time: f64,
+}
+
+enum Operation {
+    Capture(Channel),
+    Fft(FourierTransform),
+    Group {
+        strategy: GroupingStrategy,
+        ranges: Vec<(usize, usize)>,
+    },
+    Smooth(SmoothingStrategy),
+    Normalise,
+    Chromagram,
+    Loudness,
+    Screen {
+        colour: Color,
+        area: [f32; 4],
+    },
+    Csv(BufWriter<File>),
+}
+
+struct Node {
+    input: Option<usize>,
+    operation: Operation,
+    output: Vec<f32>,
+}
+
+/// A processing graph built from `[[node]]` tables, run once per analysed window
+pub struct Graph {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+    sample_rate: usize,
+    bar_renderer: BarRenderer,
+}
+
+impl Graph {
+    /// Builds the graph, opening any files its sinks write to
+    pub fn new(
+        configs: &[NodeConfig],
+        sample_rate: usize,
+        fft_size: usize,
+    ) -> Result<Self, GraphError> {
+        let order = plan(configs)?;
+
+        let nodes = configs
+            .iter()
+            .map(|config| {
+                let input = config
+                    .input()
+                    .and_then(|input| configs.iter().position(|other| other.name() == input));
+
+                let operation = match config {
+                    NodeConfig::Capture { channel, .. } => Operation::Capture(*channel),
+                    NodeConfig::Fft { .. } => Operation::Fft(FourierTransform::new(fft_size)),
+                    NodeConfig::Group {
+                        strategy,
+                        bars,
+                        gamma,
+                        ..
+                    } => {
+                        let num_groups = (*bars).max(1);
+                        let strategy = match strategy {
+                            GroupingKind::None => GroupingStrategy::NoGrouping { num_groups },
+                            GroupingKind::LogMax => GroupingStrategy::LogMax { num_groups },
+                            GroupingKind::LogMean => GroupingStrategy::LogMean { num_groups },
+                            GroupingKind::GammaCorrected => GroupingStrategy::GammaCorrected {
+                                num_groups,
+                                gamma: *gamma,
+                            },
+                        };
+                        Operation::Group {
+                            ranges: strategy.create_ranges(sample_rate, fft_size),
+                            strategy,
+                        }
+                    }
+                    NodeConfig::Smooth { rise, fall, .. } => {
+                        Operation::Smooth(SmoothingStrategy::RiseFall {
+                            rise: *rise,
+                            fall: *fall,
+                        })
+                    }
+                    NodeConfig::Normalise { .. } => Operation::Normalise,
+                    NodeConfig::Chromagram { .. } => Operation::Chromagram,
+                    NodeConfig::Loudness { .. } => Operation::Loudness,
+                    NodeConfig::Screen { colour, area, .. } => Operation::Screen {
+                        colour: colour.map_or(WHITE, |colour| colour.0),
+                        area: area.unwrap_or([0.0, 0.0, 1.0, 1.0]),
+                    },
+                    NodeConfig::Csv { path, .. } => Operation::Csv(BufWriter::new(
+                        File::create(path).map_err(|e| GraphError::Io(path.clone(), e))?,
+                    )),
+                };
+
+                Ok(Node {
+                    input,
+                    operation,
+                    output: Vec::new(),
+                })
+            })
+            .collect::<Result<_, GraphError>>()?;
+
+        Ok(Self {
+            nodes,
+            order,
+            sample_rate,
+            bar_renderer: BarRenderer::new(BarStyle::Square)
+                .expect("square bars don't need a shader"),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Rebuilds the FFT and grouping nodes for `fft_size` sample windows
+    pub fn set_fft_size(&mut self, fft_size: usize) {
+        for node in &mut self.nodes {
+            match &mut node.operation {
+                Operation::Fft(fft) => *fft = FourierTransform::new(fft_size),
+                Operation::Group { strategy, ranges } => {
+                    *ranges = strategy.create_ranges(self.sample_rate, fft_size)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Whether any capture node reads the left or right channel, which need capturing
+    /// separately
+    pub fn needs_channels(&self) -> bool {
+        self.nodes.iter().any(|node| {
+            matches!(
+                node.operation,
+                Operation::Capture(Channel::Left | Channel::Right)
+            )
+        })
+    }
+
+    /// Runs every node on a new window
+    pub fn process(&mut self, input: &GraphInput) -> io::Result<()> {
+        for &index in &self.order {
+            // Inputs always run first, so taking the input's output out of the way lets this
+            // node borrow it while writing its own
+            let source = match self.nodes[index].input {
+                Some(input) => std::mem::take(&mut self.nodes[input].output),
+                None => Vec::new(),
+            };
+
+            let node = &mut self.nodes[index];
+            let result = match &mut node.operation {
+                Operation::Capture(channel) => {
+                    let samples = match (channel, input.channels) {
+                        (Channel::Left, Some([left, _])) => left,
+                        (Channel::Right, Some([_, right])) => right,
+                        _ => input.mono,
+                    };
+                    node.output.clear();
+                    node.output.extend_from_slice(samples);
+                    Ok(())
+                }
+                Operation::Fft(fft) => {
+                    node.output = fft.compute(&source);
+                    Ok(())
+                }
+                Operation::Group { strategy, ranges } => {
+                    node.output = strategy.group_spectrum(&source, ranges);
+                    Ok(())
+                }
+                Operation::Smooth(strategy) => {
+                    node.output.resize(source.len(), 0.0);
+                    strategy.smooth(&mut node.output, &source);
+                    Ok(())
+                }
+                Operation::Normalise => {
+                    let max = source.iter().cloned().fold(1e-6, f32::max);
+                    node.output = source.iter().map(|value| value / max).collect();
+                    Ok(())
+                }
+                Operation::Chromagram => {
+                    let pitches = frequency_to_pitch_spectrum(&source, self.sample_rate);
+                    node.output = pitch_spectrum_to_chromagram(&pitches).to_vec();
+                    Ok(())
+                }
+                Operation::Loudness => {
+                    let mean_square =
+                        source.iter().map(|s| s * s).sum::<f32>() / source.len().max(1) as f32;
+                    let loudness = 10.0 * mean_square.max(1e-10).log10();
+                    node.output = vec![((loudness + 60.0) / 60.0).clamp(0.0, 1.0)];
+                    Ok(())
+                }
+                // Screens keep their input to draw with the next frame
+                Operation::Screen { .. } => {
+                    node.output.clone_from(&source);
+                    Ok(())
+                }
+                Operation::Csv(writer) => write!(writer, "{:.3}", input.time)
+                    .and_then(|()| {
+                        source
+                            .iter()
+                            .try_for_each(|value| write!(writer, ",{value}"))
+                    })
+                    .and_then(|()| writeln!(writer)),
+            };
+
+            if let Some(input) = self.nodes[index].input {
+                self.nodes[input].output = source;
+            }
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the latest levels of every screen node
+    pub fn draw(&self) {
+        let mut mesh = BarRenderer::new_mesh();
+
+        for node in &self.nodes {
+            let Operation::Screen { colour, area } = node.operation else {
+                continue;
+            };
+            if node.output.is_empty() {
+                continue;
+            }
+
+            let [left, top, width, height] = area;
+            let (left, top) = (left * screen_width(), top * screen_height());
+            let (width, height) = (width * screen_width(), height * screen_height());
+            let bar_width = width / node.output.len() as f32;
+
+            for (i, level) in node.output.iter().enumerate() {
+                let bar_height = level.clamp(0.0, 1.0) * height;
+                let rect = Rect::new(
+                    left + i as f32 * bar_width,
+                    top + height - bar_height,
+                    bar_width,
+                    bar_height,
+                );
+                self.bar_renderer.push(&mut mesh, rect, colour, colour);
+            }
+        }
+
+        self.bar_renderer.draw(&mesh);
+    }
+
+    /// Writes out anything the CSV nodes still have buffered, before exiting
+    pub fn flush(&mut self) -> io::Result<()> {
+        for node in &mut self.nodes {
+            if let Operation::Csv(writer) = &mut node.operation {
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-use std::cmp::max;
+use alloc::{vec, vec::Vec};
+use core::cmp::max;
+
+use libm::{floorf, log2f, log10f, powf, roundf};
+
+use crate::bins;
+
+/// Gamma of `GroupingStrategy::GammaCorrected` when none is given
+pub const DEFAULT_GAMMA: f32 = 2.0;
 
 /// Compute how to split an FFT of length `fft_size` into `num_bins` using common music frequency ranges
 ///
@@ -22,9 +30,13 @@ fn log_ranges(num_bars: usize, sample_rate: usize, fft_size: usize) -> Vec<(usiz
         (6000.0, 20000.0),
     ];
 
-    let freq_per_bin = sample_rate as f32 / fft_size as f32;
+    let spectrum_len = bins::bin_count(fft_size);
+    let bin_of = |frequency: f32| {
+        (roundf(bins::frequency_bin(frequency, spectrum_len, sample_rate)) as usize)
+            .min(spectrum_len)
+    };
 
-    let mut bins_per_range = weights.map(|(_, v)| (num_bars as f32 * v).floor() as usize);
+    let mut bins_per_range = weights.map(|(_, v)| floorf(num_bars as f32 * v) as usize);
 
     let mut bin_sum: usize = bins_per_range.iter().sum();
     let mut index = 0;
@@ -42,20 +54,19 @@ fn log_ranges(num_bars: usize, sample_rate: usize, fft_size: usize) -> Vec<(usiz
     for (i, &bin_count) in bins_per_range.iter().enumerate() {
         let (start, end) = freq_ranges[i];
 
-        let log_start = start.log10();
-        let log_end = end.log10();
+        let log_start = log10f(start);
+        let log_end = log10f(end);
 
         let step = (log_end - log_start) / bin_count as f32;
 
         for j in 0..bin_count {
-            let f_low = 10.0_f32.powf(log_start + j as f32 * step);
-            let f_high = 10.0_f32.powf(log_start + (j as f32 + 1.0) * step);
+            let f_low = powf(10.0, log_start + j as f32 * step);
+            let f_high = powf(10.0, log_start + (j as f32 + 1.0) * step);
 
-            let computed_bin_start = ((f_low / freq_per_bin) - 1.0).round() as usize;
-            let computed_bin_end = ((f_high / freq_per_bin) - 1.0).round() as usize;
-
-            let bin_start = max(computed_bin_start, last_bin_end);
-            let bin_end = max(bin_start + 1, computed_bin_end); // Ensure at least 1 bin
+            // Ranges above the Nyquist frequency, at low sample rates, are squeezed into the
+            // top bin rather than running off the end of the spectrum
+            let bin_start = max(bin_of(f_low), last_bin_end).min(spectrum_len - 1);
+            let bin_end = max(bin_start + 1, bin_of(f_high)); // Ensure at least 1 bin
 
             ranges.push((bin_start, bin_end));
             last_bin_end = bin_end;
@@ -69,7 +80,11 @@ fn log_ranges(num_bars: usize, sample_rate: usize, fft_size: usize) -> Vec<(usiz
     ranges
 }
 
-/// Computes `num_bins` ranges for an FFT of size `fft_size` using gamma correction
+/// Computes up to `num_bins` ranges for an FFT of size `fft_size` using gamma correction
+///
+/// Each bin goes in the bar its frequency lands in once scaled from 0.0 to 1.0 between 0Hz
+/// and Nyquist and raised to `1 / gamma`. Bars too narrow to get a bin of their own are left
+/// out
 fn gamma_corrected_ranges(
     num_bins: usize,
     sample_rate: usize,
@@ -77,24 +92,22 @@ fn gamma_corrected_ranges(
     gamma: f32,
 ) -> Vec<(usize, usize)> {
     let nyquist = sample_rate as f32 / 2.0;
-    let freq_per_bin = sample_rate as f32 / fft_size as f32;
+    let spectrum_len = bins::bin_count(fft_size);
+    let bar_of = |bin: usize| {
+        let norm_freq = bins::bin_frequency(bin, spectrum_len, sample_rate) / nyquist;
+        ((powf(norm_freq, 1.0 / gamma) * num_bins as f32) as usize).min(num_bins.saturating_sub(1))
+    };
 
     let mut ranges = Vec::new();
+    let mut start = 0;
 
-    let mut start: usize = 0;
-
-    for i in 0..fft_size {
-        let freq = i as f32 * freq_per_bin;
-        let norm_freq = freq / nyquist;
-
-        let b_i = (norm_freq.powf(1.0 / gamma) * ((num_bins) as f32).floor()) as usize;
-
-        if b_i != start {
-            // println!("Frequency {} is going in bar {}", freq, b_i);
-            ranges.push((start, b_i));
-            start = b_i;
+    for bin in 1..spectrum_len {
+        if bar_of(bin) != bar_of(start) {
+            ranges.push((start, bin));
+            start = bin;
         }
     }
+    ranges.push((start, spectrum_len));
 
     ranges
 }
@@ -108,7 +121,7 @@ fn take_log_mean_ranges(spectrum: &[f32], bar_ranges: &[(usize, usize)]) -> Vec<
     for (i, &(start, end)) in bar_ranges.iter().enumerate() {
         let slice: &[f32] = &spectrum[start..end];
         let sum: f32 = slice.iter().sum();
-        log_bars[i] = ((sum / slice.len() as f32) + 1.0).log2();
+        log_bars[i] = log2f((sum / slice.len() as f32) + 1.0);
     }
 
     log_bars
@@ -123,7 +136,7 @@ fn take_log_max_ranges(spectrum: &[f32], bar_ranges: &[(usize, usize)]) -> Vec<f
     for (i, &(start, end)) in bar_ranges.iter().enumerate() {
         let slice: &[f32] = &spectrum[start..end];
         let max_value: f32 = slice.iter().copied().fold(0.0, f32::max);
-        log_bars[i] = (max_value + 1.0).log2();
+        log_bars[i] = log2f(max_value + 1.0);
     }
 
     log_bars
@@ -139,7 +152,10 @@ pub enum GroupingStrategy {
 impl GroupingStrategy {
     pub fn create_ranges(&self, sample_rate: usize, fft_size: usize) -> Vec<(usize, usize)> {
         match self {
-            GroupingStrategy::NoGrouping { num_groups: _ } => Vec::new(),
+            // One range per bin so per-bar consumers still know what each bar covers
+            GroupingStrategy::NoGrouping { num_groups: _ } => (0..bins::bin_count(fft_size))
+                .map(|bin| (bin, bin + 1))
+                .collect(),
             GroupingStrategy::LogMax { num_groups } => {
                 log_ranges(*num_groups, sample_rate, fft_size)
             }
@@ -166,6 +182,48 @@ impl GroupingStrategy {
         }
     }
 
+    /// The same strategy with `num_groups` bars
+    pub fn with_num_bars(&self, num_groups: usize) -> Self {
+        match *self {
+            GroupingStrategy::NoGrouping { num_groups: _ } => {
+                GroupingStrategy::NoGrouping { num_groups }
+            }
+            GroupingStrategy::LogMax { num_groups: _ } => GroupingStrategy::LogMax { num_groups },
+            GroupingStrategy::LogMean { num_groups: _ } => GroupingStrategy::LogMean { num_groups },
+            GroupingStrategy::GammaCorrected {
+                num_groups: _,
+                gamma,
+            } => GroupingStrategy::GammaCorrected { num_groups, gamma },
+        }
+    }
+
+    /// The strategy after this one in the order they're declared, wrapping around, with the
+    /// same number of bars
+    pub fn next(&self) -> Self {
+        let num_groups = self.num_bars();
+        match *self {
+            GroupingStrategy::NoGrouping { num_groups: _ } => {
+                GroupingStrategy::LogMax { num_groups }
+            }
+            GroupingStrategy::LogMax { num_groups: _ } => GroupingStrategy::LogMean { num_groups },
+            GroupingStrategy::LogMean { num_groups: _ } => GroupingStrategy::GammaCorrected {
+                num_groups,
+                gamma: DEFAULT_GAMMA,
+            },
+            GroupingStrategy::GammaCorrected { .. } => GroupingStrategy::NoGrouping { num_groups },
+        }
+    }
+
+    /// Name as used for `grouping.strategy` in the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupingStrategy::NoGrouping { .. } => "none",
+            GroupingStrategy::LogMax { .. } => "log-max",
+            GroupingStrategy::LogMean { .. } => "log-mean",
+            GroupingStrategy::GammaCorrected { .. } => "gamma-corrected",
+        }
+    }
+
     pub fn num_bars(&self) -> usize {
         match *self {
             GroupingStrategy::NoGrouping {
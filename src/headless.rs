@@ -0,0 +1,240 @@
+//! Running without a window, writing each analysed window to stdout for something else to
+//! draw, like an LED controller or a web page
+//!
+//! ```text
+//! rust-audio-visualiser --headless json | my-led-driver
+//! ```
+//!
+//! Every window becomes one frame: its time and loudness, the bars grouped and smoothed as
+//! in `[grouping]` and `[smoothing]` (from 0.0 to 1.0), the chromagram, the tempo, whether
+//! there was an onset, any beat, and the notes sounding. As JSON each frame is an object on
+//! its own line:
+//!
+//! ```json
+//! {"time":12.345,"loudness":-18.2,"bars":[0.8,1,0.6],"chromagram":[...],"bpm":124.1,"onset":true,"beat":{"time":12.345,"confidence":0.7,"strength":1.9},"notes":[{"pitch":57,"velocity":90,"onset":12.2}]}
+//! ```
+//!
+//! As MessagePack the same maps follow one another with nothing between them
+
+use std::io::{self, Write};
+
+use crate::{
+    analysis::FrameAnalysis,
+    config::Config,
+    gain::AutoGain,
+    pipeline::{Frame, Pipeline},
+    transcription::NoteTracker,
+};
+
+/// How frames are written out
+#[derive(Clone, Copy, PartialEq)]
+pub enum HeadlessFormat {
+    Json,
+    MessagePack,
+}
+
+impl HeadlessFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(HeadlessFormat::Json),
+            "msgpack" => Some(HeadlessFormat::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+// A frame before it's encoded, so both formats are written from the same fields
+enum Value {
+    Null,
+    Bool(bool),
+    UInt(u64),
+    Float(f32),
+    Double(f64),
+    Array(Vec<Value>),
+    Map(Vec<(&'static str, Value)>),
+}
+
+impl Value {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Value::UInt(value) => out.push_str(&value.to_string()),
+            // JSON has no infinities, which silence gives loudness
+            Value::Float(value) if !value.is_finite() => out.push_str("null"),
+            Value::Float(value) => out.push_str(&value.to_string()),
+            Value::Double(value) if !value.is_finite() => out.push_str("null"),
+            Value::Double(value) => out.push_str(&format!("{value:.4}")),
+            Value::Array(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    item.write_json(out);
+                }
+                out.push(']');
+            }
+            Value::Map(fields) => {
+                out.push('{');
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    // Keys are all plain names, so need no escaping
+                    out.push('"');
+                    out.push_str(key);
+                    out.push_str("\":");
+                    value.write_json(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn write_msgpack(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Null => out.push(0xc0),
+            Value::Bool(value) => out.push(if *value { 0xc3 } else { 0xc2 }),
+            Value::UInt(value) => match *value {
+                0..=0x7f => out.push(*value as u8),
+                0x80..=0xff => out.extend_from_slice(&[0xcc, *value as u8]),
+                0x100..=0xffff => {
+                    out.push(0xcd);
+                    out.extend_from_slice(&(*value as u16).to_be_bytes());
+                }
+                _ => {
+                    out.push(0xcf);
+                    out.extend_from_slice(&value.to_be_bytes());
+                }
+            },
+            Value::Float(value) => {
+                out.push(0xca);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Double(value) => {
+                out.push(0xcb);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Array(items) => {
+                write_msgpack_len(out, items.len(), 0x90, 0xdc);
+                for item in items {
+                    item.write_msgpack(out);
+                }
+            }
+            Value::Map(fields) => {
+                write_msgpack_len(out, fields.len(), 0x80, 0xde);
+                for (key, value) in fields {
+                    // Keys are all short enough for a fixstr
+                    out.push(0xa0 | key.len() as u8);
+                    out.extend_from_slice(key.as_bytes());
+                    value.write_msgpack(out);
+                }
+            }
+        }
+    }
+}
+
+// Writes an array or map header: the `fixed` form for up to 15 entries, then the 16 bit one
+fn write_msgpack_len(out: &mut Vec<u8>, len: usize, fixed: u8, long: u8) {
+    if len < 16 {
+        out.push(fixed | len as u8);
+    } else {
+        out.push(long);
+        out.extend_from_slice(&(len.min(u16::MAX as usize) as u16).to_be_bytes());
+    }
+}
+
+/// Turns each analysed window into a frame and writes it out
+pub struct Headless {
+    format: HeadlessFormat,
+    bars: Pipeline,
+    notes: NoteTracker,
+}
+
+impl Headless {
+    pub fn new(config: &Config, format: HeadlessFormat) -> Self {
+        Self {
+            format,
+            // Follows every peak, so the tallest bar is always 1.0
+            bars: Pipeline::bars(
+                config.sample_rate,
+                config.grouping.strategy(),
+                config.smoothing.strategy(),
+                None,
+                AutoGain::new(0.0, 0.0, 1),
+            ),
+            notes: NoteTracker::new(config.sample_rate),
+        }
+    }
+
+    /// Writes the frame for `analysis` to `out`, flushing it so it's seen straight away
+    pub fn write(&mut self, out: &mut impl Write, analysis: &FrameAnalysis) -> io::Result<()> {
+        let frame = self.frame(analysis);
+        match self.format {
+            HeadlessFormat::Json => {
+                let mut line = String::new();
+                frame.write_json(&mut line);
+                line.push('\n');
+                out.write_all(line.as_bytes())?;
+            }
+            HeadlessFormat::MessagePack => {
+                let mut bytes = Vec::new();
+                frame.write_msgpack(&mut bytes);
+                out.write_all(&bytes)?;
+            }
+        }
+        out.flush()
+    }
+
+    fn frame(&mut self, analysis: &FrameAnalysis) -> Value {
+        let bars = self
+            .bars
+            .process(Frame::from_spectrum(analysis.spectrum.clone()))
+            .bars;
+        self.notes.update(&analysis.spectrum, analysis.time);
+
+        let beat = analysis.beat.map_or(Value::Null, |beat| {
+            Value::Map(vec![
+                ("time", Value::Double(beat.time)),
+                ("confidence", Value::Float(beat.confidence)),
+                ("strength", Value::Float(beat.strength)),
+            ])
+        });
+        let notes = self
+            .notes
+            .events_since(analysis.time)
+            .filter(|note| note.offset.is_none())
+            .map(|note| {
+                Value::Map(vec![
+                    ("pitch", Value::UInt(note.pitch.into())),
+                    ("velocity", Value::UInt(note.velocity.into())),
+                    ("onset", Value::Double(note.onset)),
+                ])
+            })
+            .collect();
+
+        Value::Map(vec![
+            ("time", Value::Double(analysis.time)),
+            ("loudness", Value::Float(analysis.loudness)),
+            (
+                "bars",
+                Value::Array(bars.into_iter().map(Value::Float).collect()),
+            ),
+            (
+                "chromagram",
+                Value::Array(
+                    analysis
+                        .chromagram
+                        .iter()
+                        .map(|&v| Value::Float(v))
+                        .collect(),
+                ),
+            ),
+            ("bpm", analysis.bpm.map_or(Value::Null, Value::Float)),
+            ("onset", Value::Bool(analysis.onset)),
+            ("beat", beat),
+            ("notes", Value::Array(notes)),
+        ])
+    }
+}
@@ -0,0 +1,310 @@
+//! Listening history kept in a SQLite database, for looking up what was played and its
+//! tempo, key and loudness
+//!
+//! Every track the session log records is also added to the database, one row each, and
+//! looking tracks up filters them there rather than reading back the whole log:
+//!
+//! ```text
+//! rust-audio-visualiser history --artist "Daft Punk" --min-bpm 110 --days 30
+//! ```
+//!
+//! CSV session logs written before the database existed can be added to it with `--import`,
+//! skipping any tracks it already has
+
+use std::{fs, io, path::Path};
+
+use rusqlite::{Connection, Row, params};
+use thiserror::Error;
+
+use crate::tracklog::{CSV_HEADER, TrackSummary};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// A track is told apart by when it started and what it was, so importing a log twice
+// doesn't add its tracks twice
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tracks (
+        started INTEGER NOT NULL,
+        artist TEXT NOT NULL,
+        title TEXT NOT NULL,
+        album TEXT NOT NULL,
+        duration REAL NOT NULL,
+        bpm REAL,
+        key TEXT,
+        mean_loudness REAL NOT NULL,
+        peak_loudness REAL NOT NULL,
+        UNIQUE (started, artist, title)
+    );
+";
+
+const INSERT: &str = "
+    INSERT OR IGNORE INTO tracks
+        (started, artist, title, album, duration, bpm, key, mean_loudness, peak_loudness)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+";
+
+// Each condition is skipped when its parameter is null, for parts of the query left out
+const SELECT: &str = "
+    SELECT started, artist, title, album, duration, bpm, key, mean_loudness, peak_loudness
+    FROM tracks
+    WHERE (?1 IS NULL OR instr(lower(artist), lower(?1)) > 0)
+        AND (?2 IS NULL OR key = ?2 COLLATE NOCASE)
+        AND (?3 IS NULL OR bpm >= ?3)
+        AND (?4 IS NULL OR bpm <= ?4)
+        AND (?5 IS NULL OR started >= ?5)
+    ORDER BY started
+";
+
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("history database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("couldn't read session log: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {0} of the session log is malformed")]
+    Malformed(usize),
+    #[error("not a CSV session log, the header is `{0}`")]
+    Header(String),
+}
+
+/// One track from the history
+pub struct HistoryEntry {
+    /// Unix timestamp of when the track started
+    pub started: u64,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    /// In seconds
+    pub duration: f32,
+    pub bpm: Option<f32>,
+    /// e.g. "A minor"
+    pub key: Option<String>,
+    /// In dBFS
+    pub mean_loudness: f32,
+    pub peak_loudness: f32,
+}
+
+impl HistoryEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            started: row.get(0)?,
+            artist: row.get(1)?,
+            title: row.get(2)?,
+            album: row.get(3)?,
+            duration: row.get(4)?,
+            bpm: row.get(5)?,
+            key: row.get(6)?,
+            mean_loudness: row.get(7)?,
+            peak_loudness: row.get(8)?,
+        })
+    }
+}
+
+impl From<&TrackSummary> for HistoryEntry {
+    fn from(summary: &TrackSummary) -> Self {
+        let stats = &summary.stats;
+        Self {
+            started: summary.started,
+            artist: summary.track.artist.clone(),
+            title: summary.track.title.clone(),
+            album: summary.track.album.clone(),
+            duration: stats.duration,
+            bpm: stats.bpm,
+            key: stats.key.map(|key| key.name()),
+            mean_loudness: stats.mean_loudness,
+            peak_loudness: stats.peak_loudness,
+        }
+    }
+}
+
+/// The tracks played, in a SQLite database
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    /// Opens the database at `path`, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self, HistoryError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+
+    /// Adds a track the session log has just finished
+    pub fn record(&self, summary: &TrackSummary) -> Result<(), HistoryError> {
+        insert(&self.connection, &HistoryEntry::from(summary))
+    }
+
+    /// Adds `entries` all at once, returning how many weren't already in the history
+    pub fn import(&mut self, entries: &[HistoryEntry]) -> Result<usize, HistoryError> {
+        let transaction = self.connection.transaction()?;
+        let before = transaction.total_changes();
+        for entry in entries {
+            insert(&transaction, entry)?;
+        }
+        let added = transaction.total_changes() - before;
+        transaction.commit()?;
+        Ok(added as usize)
+    }
+
+    /// Tracks matching `query`, oldest first
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>, HistoryError> {
+        let mut statement = self.connection.prepare_cached(SELECT)?;
+        let entries = statement
+            .query_map(
+                params![
+                    query.artist,
+                    query.key,
+                    query.min_bpm,
+                    query.max_bpm,
+                    query.since
+                ],
+                HistoryEntry::from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok(entries)
+    }
+}
+
+fn insert(connection: &Connection, entry: &HistoryEntry) -> Result<(), HistoryError> {
+    connection.prepare_cached(INSERT)?.execute(params![
+        entry.started,
+        entry.artist,
+        entry.title,
+        entry.album,
+        entry.duration,
+        entry.bpm,
+        entry.key,
+        entry.mean_loudness,
+        entry.peak_loudness,
+    ])?;
+    Ok(())
+}
+
+/// Every track in the CSV session log at `path`, oldest first, for `History::import`
+pub fn load(path: &Path) -> Result<Vec<HistoryEntry>, HistoryError> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Parses the contents of a CSV session log
+pub fn parse(source: &str) -> Result<Vec<HistoryEntry>, HistoryError> {
+    let mut lines = source.lines();
+    match lines.next() {
+        None => return Ok(Vec::new()),
+        Some(header) if header != CSV_HEADER => return Err(HistoryError::Header(header.into())),
+        Some(_) => (),
+    }
+
+    lines
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| parse_row(line).ok_or(HistoryError::Malformed(index + 2)))
+        .collect()
+}
+
+fn parse_row(line: &str) -> Option<HistoryEntry> {
+    let fields = split_csv(line)?;
+    if fields.len() != CSV_HEADER.split(',').count() {
+        return None;
+    }
+    let mut fields = fields.into_iter();
+    let mut next = || fields.next().unwrap_or_default();
+
+    Some(HistoryEntry {
+        started: next().parse().ok()?,
+        artist: next(),
+        title: next(),
+        album: next(),
+        duration: next().parse().ok()?,
+        bpm: match next().as_str() {
+            "" => None,
+            bpm => Some(bpm.parse().ok()?),
+        },
+        key: Some(next()).filter(|key| !key.is_empty()),
+        mean_loudness: next().parse().ok()?,
+        peak_loudness: next().parse().ok()?,
+    })
+}
+
+/// Splits a CSV row written by the session log, undoing its quoting
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+
+    // An unclosed quote means the field ran onto another line, which the log never writes
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+/// Which tracks to show, where anything left out matches every track
+#[derive(Default)]
+pub struct HistoryQuery {
+    /// Matched case-insensitively anywhere in the artist
+    pub artist: Option<String>,
+    /// Matched case-insensitively against the whole key, e.g. "a minor"
+    pub key: Option<String>,
+    pub min_bpm: Option<f32>,
+    pub max_bpm: Option<f32>,
+    /// Unix timestamp tracks must have started at or after
+    pub since: Option<u64>,
+}
+
+impl HistoryQuery {
+    /// Only tracks started in the `days` days before `now`
+    pub fn with_days(mut self, days: u64, now: u64) -> Self {
+        self.since = Some(now.saturating_sub(days * SECONDS_PER_DAY));
+        self
+    }
+}
+
+/// Totals over the tracks matching a query
+pub struct HistorySummary {
+    pub tracks: usize,
+    /// In seconds
+    pub duration: f32,
+    pub mean_bpm: Option<f32>,
+    pub most_common_key: Option<String>,
+}
+
+impl HistorySummary {
+    pub fn new(entries: &[HistoryEntry]) -> Self {
+        let bpms: Vec<f32> = entries.iter().filter_map(|entry| entry.bpm).collect();
+
+        let mut keys: Vec<&str> = entries
+            .iter()
+            .filter_map(|entry| entry.key.as_deref())
+            .collect();
+        keys.sort_unstable();
+        // Ties go to the alphabetically first key, so the summary doesn't change between runs
+        let most_common_key = keys
+            .chunk_by(|a, b| a == b)
+            .rev()
+            .max_by_key(|run| run.len())
+            .map(|run| run[0].to_string());
+
+        Self {
+            tracks: entries.len(),
+            duration: entries.iter().map(|entry| entry.duration).sum(),
+            mean_bpm: (!bpms.is_empty()).then(|| bpms.iter().sum::<f32>() / bpms.len() as f32),
+            most_common_key,
+        }
+    }
+}
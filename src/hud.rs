@@ -0,0 +1,137 @@
+//! Readouts for tuning and tracking down dropped frames, drawn in the top right corner
+//! over the visualiser when toggled with F1
+
+use std::collections::VecDeque;
+
+use macroquad::{
+    color::{Color, GRAY, WHITE},
+    shapes::draw_rectangle,
+    time::{get_fps, get_frame_time, get_time},
+    window::screen_width,
+};
+
+use crate::{
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+};
+
+// Seconds of frame times the slowest frame is picked from
+const FRAME_HISTORY: f64 = 1.0;
+// Quietest peak level shown, rather than -inf for silence
+const PEAK_FLOOR: f32 = -96.0;
+
+/// What the HUD shows, gathered by the caller each frame
+pub struct HudStats<'a> {
+    pub fft_size: usize,
+    /// Seconds between audio reaching the source and its window being drawn
+    pub latency: f32,
+    /// Part of `latency` spent in the capture backend's buffers
+    pub capture_latency: f32,
+    /// Part of `latency` the visuals were held back by to line up with what's heard
+    pub held: f32,
+    pub preset: Option<&'a str>,
+    pub device: &'a str,
+    /// Loudest sample in the latest window, in dBFS
+    pub peak: f32,
+    /// MiB history is kept to, if limited
+    pub memory_budget: Option<f32>,
+    /// Notes dropped from the session transcription to keep to the memory budget
+    pub dropped_notes: usize,
+}
+
+/// Peak level of `samples` in dBFS
+pub fn peak_level(samples: &[f32]) -> f32 {
+    let peak = samples
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    (20.0 * peak.log10()).max(PEAK_FLOOR)
+}
+
+/// Keeps the recent frame times and draws the readouts
+#[derive(Default)]
+pub struct Hud {
+    // Start time and duration of each frame in the last `FRAME_HISTORY` seconds
+    frames: VecDeque<(f64, f32)>,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the last frame's duration. Called every frame, even while hidden, so the
+    /// slowest frame is already known when it's shown
+    pub fn update(&mut self) {
+        let now = get_time();
+        self.frames.push_back((now, get_frame_time()));
+        while self
+            .frames
+            .front()
+            .is_some_and(|&(time, _)| now - time > FRAME_HISTORY)
+        {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Longest frame in the last second, in seconds
+    pub fn slowest_frame(&self) -> f32 {
+        self.frames
+            .iter()
+            .map(|&(_, duration)| duration)
+            .fold(0.0, f32::max)
+    }
+
+    pub fn draw(&self, stats: &HudStats, typography: &Typography) {
+        let scale = ui_scale();
+        let size = 18.0 * scale;
+        let line_height = size * 1.4;
+        let margin = 24.0 * scale;
+        let column = 110.0 * scale;
+        let width = 420.0 * scale;
+
+        let rows = [
+            ("FPS", format!("{}", get_fps())),
+            (
+                "Slowest",
+                format!("{:.1} ms", self.slowest_frame() * 1000.0),
+            ),
+            ("FFT size", stats.fft_size.to_string()),
+            ("Latency", format!("~{:.0} ms", stats.latency * 1000.0)),
+            (
+                "Capture",
+                format!("{:.0} ms", stats.capture_latency * 1000.0),
+            ),
+            ("Held back", format!("{:.0} ms", stats.held * 1000.0)),
+            ("Preset", stats.preset.unwrap_or("none").to_string()),
+            ("Device", stats.device.to_string()),
+            ("Peak", format!("{:.1} dBFS", stats.peak)),
+            (
+                "Memory",
+                match stats.memory_budget {
+                    None => "unlimited".to_string(),
+                    Some(budget) if stats.dropped_notes == 0 => format!("{budget:.0} MiB"),
+                    Some(budget) => {
+                        format!("{budget:.0} MiB, {} notes dropped", stats.dropped_notes)
+                    }
+                },
+            ),
+        ];
+
+        let left = screen_width() - margin - width;
+        draw_rectangle(
+            left,
+            margin,
+            width,
+            rows.len() as f32 * line_height + margin,
+            Color::new(0.0, 0.0, 0.0, 0.7),
+        );
+
+        let x = left + margin;
+        let mut y = margin * 1.5 + size;
+        for (name, value) in rows {
+            typography.draw(TextRole::Labels, name, x, y, size, GRAY);
+            typography.draw(TextRole::Metadata, &value, x + column, y, size, WHITE);
+            y += line_height;
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Slow generative animations that take over after a long silence, so an always-on display
+//! never looks frozen, and fade back to the visualiser as soon as sound returns
+//!
+//! ```toml
+//! [schedule]
+//! idle = { after = 2.0, fade = 3.0, scenes = ["flow-field", "gradient"], cycle = 120.0 }
+//! ```
+//!
+//! `after` is in minutes of silence like `blank_after_silence`, which still takes over from
+//! the idle scenes if it's reached
+
+use std::f32::consts::TAU;
+
+use macroquad::{
+    color::Color,
+    math::{Vec2, vec2},
+    rand::gen_range,
+    shapes::{draw_circle_lines, draw_line, draw_rectangle},
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+
+use crate::{colour::hsv_to_rgb, ui::ui_scale};
+
+// Particles drifting through the flow field at once
+const FLOW_PARTICLES: usize = 600;
+// Seconds each flow field particle lives for before starting again somewhere else
+const FLOW_LIFETIME: [f32; 2] = [4.0, 12.0];
+// Horizontal strips the gradient is drawn with
+const GRADIENT_STRIPS: usize = 64;
+// Seconds between new ripples, and seconds each takes to fade out
+const RIPPLE_INTERVAL: f32 = 1.5;
+const RIPPLE_LIFETIME: f32 = 12.0;
+// Colour the idle scenes are drawn over
+const BACKDROP: Color = Color::new(0.04, 0.04, 0.06, 1.0);
+
+/// An idle animation
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleScene {
+    /// Particles trailing through a slowly turning field of currents
+    FlowField,
+    /// Bands of colour drifting through the hues
+    Gradient,
+    /// Rings spreading out from random points
+    Ripples,
+}
+
+/// How the schedule hands over to the idle scenes, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// Minutes of silence before the idle scenes fade in
+    pub after: f64,
+    /// Seconds taken to fade between the idle scenes and the visualiser, either way
+    pub fade: f64,
+    /// Scenes shown in turn
+    pub scenes: Vec<IdleScene>,
+    /// Seconds each scene is shown for before moving on to the next
+    pub cycle: f64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            after: 2.0,
+            fade: 3.0,
+            scenes: vec![
+                IdleScene::FlowField,
+                IdleScene::Gradient,
+                IdleScene::Ripples,
+            ],
+            cycle: 120.0,
+        }
+    }
+}
+
+struct FlowParticle {
+    // In fractions of the window, so resizing doesn't bunch them up
+    position: Vec2,
+    age: f32,
+    lifetime: f32,
+}
+
+impl FlowParticle {
+    fn spawn() -> Self {
+        Self {
+            position: vec2(gen_range(0.0, 1.0), gen_range(0.0, 1.0)),
+            age: 0.0,
+            lifetime: gen_range(FLOW_LIFETIME[0], FLOW_LIFETIME[1]),
+        }
+    }
+}
+
+struct Ripple {
+    centre: Vec2,
+    age: f32,
+    hue: f32,
+}
+
+/// Draws the idle scenes over the visualiser at the opacity the schedule gives
+pub struct IdleArt {
+    config: IdleConfig,
+    // Seconds the idle scenes have been running, which every animation follows
+    time: f32,
+    particles: Vec<FlowParticle>,
+    ripples: Vec<Ripple>,
+    next_ripple: f32,
+}
+
+impl IdleArt {
+    pub fn new(config: IdleConfig) -> Self {
+        Self {
+            config,
+            time: 0.0,
+            particles: (0..FLOW_PARTICLES).map(|_| FlowParticle::spawn()).collect(),
+            ripples: Vec::new(),
+            next_ripple: 0.0,
+        }
+    }
+
+    /// The scene showing now, if any are configured
+    pub fn scene(&self) -> Option<IdleScene> {
+        let scenes = &self.config.scenes;
+        if scenes.is_empty() {
+            return None;
+        }
+        let index = (self.time as f64 / self.config.cycle.max(1.0)) as usize;
+        Some(scenes[index % scenes.len()])
+    }
+
+    /// Advances the animation by `dt` seconds and draws it over the frame, fully covering it
+    /// at an `opacity` of 1.0
+    pub fn draw(&mut self, opacity: f32, dt: f32) {
+        if opacity <= 0.0 {
+            return;
+        }
+        self.time += dt;
+
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            screen_height(),
+            Color {
+                a: opacity,
+                ..BACKDROP
+            },
+        );
+
+        match self.scene() {
+            Some(IdleScene::FlowField) => self.draw_flow_field(opacity, dt),
+            Some(IdleScene::Gradient) => self.draw_gradient(opacity),
+            Some(IdleScene::Ripples) => self.draw_ripples(opacity, dt),
+            None => (),
+        }
+    }
+
+    fn draw_flow_field(&mut self, opacity: f32, dt: f32) {
+        let (width, height) = (screen_width(), screen_height());
+        let time = self.time;
+        // Direction of the current at a point, turning slowly over time
+        let angle = |point: Vec2| {
+            let x = point.x * 3.0 + time * 0.05;
+            let y = point.y * 3.0 - time * 0.03;
+            (x.sin() * y.cos() + (x * 0.5 + y).sin() * 0.5) * TAU
+        };
+
+        for particle in &mut self.particles {
+            let direction = Vec2::from_angle(angle(particle.position));
+            let next = particle.position + direction * 0.03 * dt;
+            particle.age += dt;
+
+            if particle.age > particle.lifetime
+                || !(0.0..=1.0).contains(&next.x)
+                || !(0.0..=1.0).contains(&next.y)
+            {
+                *particle = FlowParticle::spawn();
+                continue;
+            }
+
+            // Fades in and out over its life so particles don't pop in and out of view
+            let life = particle.age / particle.lifetime;
+            let alpha = (life * TAU / 2.0).sin() * 0.6 * opacity;
+            let hue = (time * 2.0 + particle.position.x * 120.0) % 360.0;
+            let (r, g, b) = hsv_to_rgb(hue, 0.5, 1.0);
+
+            // A short streak along the current, longer than one frame's movement
+            let tail = particle.position - direction * 0.006;
+            draw_line(
+                tail.x * width,
+                tail.y * height,
+                next.x * width,
+                next.y * height,
+                1.5 * ui_scale(),
+                Color::new(r, g, b, alpha),
+            );
+            particle.position = next;
+        }
+    }
+
+    fn draw_gradient(&self, opacity: f32) {
+        let (width, height) = (screen_width(), screen_height());
+        let strip = height / GRADIENT_STRIPS as f32;
+
+        for i in 0..GRADIENT_STRIPS {
+            let y = i as f32 / GRADIENT_STRIPS as f32;
+            // Two slow waves of hue, so the bands swell and drift rather than just scroll
+            let hue = self.time * 3.0
+                + y * 90.0
+                + (y * TAU + self.time * 0.07).sin() * 40.0
+                + (y * 3.0 * TAU - self.time * 0.05).sin() * 15.0;
+            let value = 0.35 + 0.15 * (y * TAU * 2.0 + self.time * 0.1).sin();
+            let (r, g, b) = hsv_to_rgb(hue.rem_euclid(360.0), 0.6, value);
+            // Overlap by a pixel so no seams show between strips
+            draw_rectangle(
+                0.0,
+                i as f32 * strip,
+                width,
+                strip + 1.0,
+                Color::new(r, g, b, opacity),
+            );
+        }
+    }
+
+    fn draw_ripples(&mut self, opacity: f32, dt: f32) {
+        self.next_ripple -= dt;
+        if self.next_ripple <= 0.0 {
+            self.next_ripple = RIPPLE_INTERVAL;
+            self.ripples.push(Ripple {
+                centre: vec2(gen_range(0.1, 0.9), gen_range(0.1, 0.9)),
+                age: 0.0,
+                hue: (self.time * 5.0 + gen_range(0.0, 60.0)) % 360.0,
+            });
+        }
+
+        let (width, height) = (screen_width(), screen_height());
+        self.ripples.retain_mut(|ripple| {
+            ripple.age += dt;
+            ripple.age < RIPPLE_LIFETIME
+        });
+        for ripple in &self.ripples {
+            let life = ripple.age / RIPPLE_LIFETIME;
+            let radius = life * 0.4 * height;
+            let (r, g, b) = hsv_to_rgb(ripple.hue, 0.4, 1.0);
+            draw_circle_lines(
+                ripple.centre.x * width,
+                ripple.centre.y * height,
+                radius,
+                2.0 * ui_scale(),
+                Color::new(r, g, b, (1.0 - life) * 0.5 * opacity),
+            );
+        }
+    }
+}
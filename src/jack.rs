@@ -0,0 +1,206 @@
+//! JACK capture backend, built with the `jack` feature
+//!
+//! Registers a client with a left and right input port, so the visualiser can be patched to
+//! any application in the JACK graph, and optionally connects them to a source's outputs
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, LatencyType, Port, PortFlags,
+    PortSpec, ProcessHandler, ProcessScope,
+};
+
+use crate::audio::{AudioError, CallbackBuffer, Device, RingBuffer};
+
+/// Source that leaves the input ports unconnected, to be patched by hand
+pub const UNCONNECTED: &str = "none";
+/// Source connected to the system's physical capture ports, like a microphone
+pub const SYSTEM_CAPTURE: &str = "system";
+
+const CLIENT_NAME: &str = "AudioVisualiser";
+// How long reads wait for the server before treating it as gone
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An active JACK client, copying what reaches its input ports into a buffer that reads are
+/// served from
+pub struct JackCapture {
+    // Kept alive so the ports stay registered and the process callback keeps running
+    client: AsyncClient<(), Process>,
+    // Full names of the input ports
+    inputs: Vec<String>,
+    buffer: CallbackBuffer,
+    sample_rate: usize,
+}
+
+struct Process {
+    ports: [Port<AudioIn>; 2],
+    channels: [Arc<RingBuffer>; 2],
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, _: &Client, scope: &ProcessScope) -> Control {
+        for (port, channel) in self.ports.iter().zip(&self.channels) {
+            channel.push(port.as_slice(scope));
+        }
+        Control::Continue
+    }
+}
+
+fn new_client() -> Result<Client, AudioError> {
+    Client::new(CLIENT_NAME, ClientOptions::NO_START_SERVER)
+        .map(|(client, _status)| client)
+        .map_err(AudioError::Jack)
+}
+
+/// Checks a JACK server is running and accepting clients
+pub fn probe() -> Result<(), AudioError> {
+    new_client().map(drop)
+}
+
+/// Sample rate of the JACK server, which every source runs at
+pub fn server_rate(_source: &str) -> Result<usize, AudioError> {
+    new_client().map(|client| client.sample_rate())
+}
+
+/// Registers the input ports and connects them to the outputs of `source`, which is a
+/// client name like `system`, a single port like `mpv:out_0`, or `UNCONNECTED`
+///
+/// JACK can't resample, so the capture runs at the server's sample rate whatever rate is
+/// asked for
+pub fn open_capture(source: &str, _sample_rate: usize) -> Result<JackCapture, AudioError> {
+    let client = new_client()?;
+    let sample_rate = client.sample_rate();
+
+    let ports = [
+        client
+            .register_port("in_left", AudioIn::default())
+            .map_err(AudioError::Jack)?,
+        client
+            .register_port("in_right", AudioIn::default())
+            .map_err(AudioError::Jack)?,
+    ];
+    let inputs = ports
+        .iter()
+        .map(|port| port.name().map_err(AudioError::Jack))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // A second of audio, so reads can fall well behind before losing any
+    let buffer = CallbackBuffer::new(sample_rate);
+    let outputs = if source == UNCONNECTED {
+        Vec::new()
+    } else {
+        let outputs = output_ports(&client, source);
+        if outputs.is_empty() {
+            return Err(AudioError::NotFound(source.to_string()));
+        }
+        outputs
+    };
+
+    let client = client
+        .activate_async(
+            (),
+            Process {
+                ports,
+                channels: buffer.channels(),
+            },
+        )
+        .map_err(AudioError::Jack)?;
+
+    // A mono source feeds both sides, and any beyond the first two are left for the user
+    for (output, input) in outputs.iter().cycle().zip(&inputs) {
+        client
+            .as_client()
+            .connect_ports_by_name(output, input)
+            .map_err(AudioError::Jack)?;
+    }
+
+    Ok(JackCapture {
+        client,
+        inputs,
+        buffer,
+        sample_rate,
+    })
+}
+
+impl JackCapture {
+    /// Fills `buffer` with the next interleaved stereo frames, blocking until they arrive
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), AudioError> {
+        if self.buffer.read(buffer, STALL_TIMEOUT) {
+            Ok(())
+        } else {
+            Err(AudioError::JackStalled)
+        }
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// The capture latency JACK reports for whatever's connected to the input ports, plus a
+    /// period and the frames waiting to be read
+    pub fn latency(&self) -> Duration {
+        let client = self.client.as_client();
+        let connected = self
+            .inputs
+            .iter()
+            .filter_map(|name| client.port_by_name(name))
+            .map(|port| port.get_latency_range(LatencyType::Capture).1 as usize)
+            .max()
+            .unwrap_or(0);
+        let frames = connected + client.buffer_size() as usize + self.buffer.queued();
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// Lists every JACK client with audio outputs to capture, physical ones (like the
+/// system's capture ports) as inputs and the rest, like players, as monitors
+pub fn devices() -> Result<Vec<Device>, AudioError> {
+    let client = new_client()?;
+    let audio = AudioOut::default().jack_port_type();
+    let physical = client.ports(
+        None,
+        Some(audio),
+        PortFlags::IS_OUTPUT | PortFlags::IS_PHYSICAL,
+    );
+
+    // Output port counts by client, keeping them in a stable order
+    let mut clients = BTreeMap::<String, (usize, bool)>::new();
+    for port in client.ports(None, Some(audio), PortFlags::IS_OUTPUT) {
+        let Some((name, _)) = port.split_once(':') else {
+            continue;
+        };
+        let entry = clients.entry(name.to_string()).or_default();
+        entry.0 += 1;
+        entry.1 |= physical.contains(&port);
+    }
+
+    let mut devices = vec![Device {
+        name: UNCONNECTED.to_string(),
+        description: "Unconnected, for patching by hand".to_string(),
+        is_monitor: true,
+    }];
+    devices.extend(
+        clients
+            .into_iter()
+            .map(|(name, (ports, is_physical))| Device {
+                description: format!("{name} ({ports} audio outputs)"),
+                name,
+                is_monitor: !is_physical,
+            }),
+    );
+    Ok(devices)
+}
+
+// Audio outputs of `source`, either all those of a client or one by its full name
+fn output_ports(client: &Client, source: &str) -> Vec<String> {
+    let prefix = format!("{source}:");
+    client
+        .ports(
+            None,
+            Some(AudioOut::default().jack_port_type()),
+            PortFlags::IS_OUTPUT,
+        )
+        .into_iter()
+        .filter(|port| port == source || port.starts_with(&prefix))
+        .collect()
+}
@@ -0,0 +1,331 @@
+use macroquad::{
+    color::{Color, GRAY, WHITE},
+    input::{KeyCode, is_key_down, is_key_pressed},
+    shapes::draw_rectangle,
+    window::{screen_height, screen_width},
+};
+
+use crate::{
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+};
+
+/// Something the user can do from the keyboard
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    BlendTowardsPrimary,
+    BlendTowardsSecondary,
+    ExportMidi,
+    Screenshot,
+    /// Records the next few seconds, see `capture`
+    RecordClip,
+    ToggleHelp,
+    ToggleHud,
+    OpenPalette,
+    HalveFftSize,
+    DoubleFftSize,
+    /// Switches to the preset at this index
+    SelectPreset(usize),
+    /// Adds this many bars, or removes them if negative
+    AdjustBars(isize),
+    CycleGrouping,
+    /// Nudges the smoothing of rising bars by this much
+    AdjustRise(f32),
+    /// Nudges the smoothing of falling bars by this much
+    AdjustFall(f32),
+    NextMode,
+    ToggleFullscreen,
+    TogglePause,
+    /// Closes the visualiser, finishing off anything it's writing first
+    Quit,
+}
+
+// How much each press of the smoothing keys changes rise or fall
+const SMOOTHING_STEP: f32 = 0.05;
+
+// Keys for the first nine presets, in order
+const PRESET_KEYS: [(KeyCode, &str); 9] = [
+    (KeyCode::Key1, "Switch to preset 1"),
+    (KeyCode::Key2, "Switch to preset 2"),
+    (KeyCode::Key3, "Switch to preset 3"),
+    (KeyCode::Key4, "Switch to preset 4"),
+    (KeyCode::Key5, "Switch to preset 5"),
+    (KeyCode::Key6, "Switch to preset 6"),
+    (KeyCode::Key7, "Switch to preset 7"),
+    (KeyCode::Key8, "Switch to preset 8"),
+    (KeyCode::Key9, "Switch to preset 9"),
+];
+
+/// When a binding fires
+#[derive(Clone, Copy)]
+pub enum Trigger {
+    /// Once, on the frame the key goes down
+    Pressed,
+    /// Every frame the key is held
+    Held,
+}
+
+pub struct KeyBinding {
+    pub key: KeyCode,
+    /// Whether Ctrl has to be held along with `key`
+    pub ctrl: bool,
+    pub trigger: Trigger,
+    pub action: Action,
+    pub description: &'static str,
+}
+
+/// Every key the visualiser responds to
+///
+/// Key handling and the help overlay both read from here, so a binding only has to be added
+/// in one place to work and show up in the help
+pub struct KeyBindings {
+    bindings: Vec<KeyBinding>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        let binding = |key, trigger, action, description| KeyBinding {
+            key,
+            ctrl: false,
+            trigger,
+            action,
+            description,
+        };
+
+        let mut bindings = vec![
+            binding(
+                KeyCode::Left,
+                Trigger::Held,
+                Action::BlendTowardsPrimary,
+                "Crossfade towards the primary colours",
+            ),
+            binding(
+                KeyCode::Right,
+                Trigger::Held,
+                Action::BlendTowardsSecondary,
+                "Crossfade towards the secondary colours",
+            ),
+            binding(
+                KeyCode::M,
+                Trigger::Pressed,
+                Action::ExportMidi,
+                "Save the session's transcription as MIDI",
+            ),
+            binding(
+                KeyCode::LeftBracket,
+                Trigger::Pressed,
+                Action::HalveFftSize,
+                "Halve the FFT size, for faster response",
+            ),
+            binding(
+                KeyCode::RightBracket,
+                Trigger::Pressed,
+                Action::DoubleFftSize,
+                "Double the FFT size, for finer frequencies",
+            ),
+            binding(
+                KeyCode::Minus,
+                Trigger::Pressed,
+                Action::AdjustBars(-1),
+                "Remove a bar",
+            ),
+            binding(
+                KeyCode::Equal,
+                Trigger::Pressed,
+                Action::AdjustBars(1),
+                "Add a bar",
+            ),
+            binding(
+                KeyCode::G,
+                Trigger::Pressed,
+                Action::CycleGrouping,
+                "Switch to the next grouping strategy",
+            ),
+            binding(
+                KeyCode::Comma,
+                Trigger::Pressed,
+                Action::AdjustRise(-SMOOTHING_STEP),
+                "Smooth rising bars less, for a snappier attack",
+            ),
+            binding(
+                KeyCode::Period,
+                Trigger::Pressed,
+                Action::AdjustRise(SMOOTHING_STEP),
+                "Smooth rising bars more",
+            ),
+            binding(
+                KeyCode::Semicolon,
+                Trigger::Pressed,
+                Action::AdjustFall(-SMOOTHING_STEP),
+                "Smooth falling bars less, so they drop faster",
+            ),
+            binding(
+                KeyCode::Apostrophe,
+                Trigger::Pressed,
+                Action::AdjustFall(SMOOTHING_STEP),
+                "Smooth falling bars more",
+            ),
+            binding(
+                KeyCode::Tab,
+                Trigger::Pressed,
+                Action::NextMode,
+                "Switch to the next mode",
+            ),
+            binding(
+                KeyCode::F,
+                Trigger::Pressed,
+                Action::ToggleFullscreen,
+                "Enter or leave fullscreen",
+            ),
+            binding(
+                KeyCode::Space,
+                Trigger::Pressed,
+                Action::TogglePause,
+                "Pause or resume, holding the current frame",
+            ),
+            binding(
+                KeyCode::H,
+                Trigger::Pressed,
+                Action::ToggleHelp,
+                "Show or hide this help",
+            ),
+            binding(
+                KeyCode::F1,
+                Trigger::Pressed,
+                Action::ToggleHud,
+                "Show or hide frame timing, latency and levels",
+            ),
+            binding(
+                KeyCode::F12,
+                Trigger::Pressed,
+                Action::Screenshot,
+                "Save a screenshot of the frame",
+            ),
+            KeyBinding {
+                ctrl: true,
+                ..binding(
+                    KeyCode::R,
+                    Trigger::Pressed,
+                    Action::RecordClip,
+                    "Record the next few seconds as an animated PNG",
+                )
+            },
+            KeyBinding {
+                ctrl: true,
+                ..binding(
+                    KeyCode::P,
+                    Trigger::Pressed,
+                    Action::OpenPalette,
+                    "Search settings and actions",
+                )
+            },
+            KeyBinding {
+                ctrl: true,
+                ..binding(
+                    KeyCode::Q,
+                    Trigger::Pressed,
+                    Action::Quit,
+                    "Quit, saving anything being written first",
+                )
+            },
+        ];
+        bindings.extend(
+            PRESET_KEYS
+                .iter()
+                .enumerate()
+                .map(|(index, &(key, description))| {
+                    binding(
+                        key,
+                        Trigger::Pressed,
+                        Action::SelectPreset(index),
+                        description,
+                    )
+                }),
+        );
+
+        Self { bindings }
+    }
+
+    /// Actions whose keys fired this frame
+    pub fn triggered(&self) -> Vec<Action> {
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+
+        self.bindings
+            .iter()
+            .filter(|binding| binding.ctrl == ctrl)
+            .filter(|binding| match binding.trigger {
+                Trigger::Pressed => is_key_pressed(binding.key),
+                Trigger::Held => is_key_down(binding.key),
+            })
+            .map(|binding| binding.action)
+            .collect()
+    }
+
+    /// Name of the key combination for `binding`, e.g. "Ctrl+P"
+    pub fn key_name(binding: &KeyBinding) -> String {
+        let key = format!("{:?}", binding.key);
+        if binding.ctrl {
+            format!("Ctrl+{key}")
+        } else {
+            key
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &KeyBinding> {
+        self.bindings.iter()
+    }
+}
+
+/// Draws a panel listing every key binding followed by the current `settings`
+pub fn draw_help(bindings: &KeyBindings, settings: &[(String, String)], typography: &Typography) {
+    let scale = ui_scale();
+    let size = 20.0 * scale;
+    let line_height = size * 1.4;
+    let margin = 24.0 * scale;
+    let column = 140.0 * scale;
+
+    let rows = bindings.iter().count() + settings.len() + 3;
+    draw_rectangle(
+        margin,
+        margin,
+        screen_width() - margin * 2.0,
+        (rows as f32 * line_height + margin).min(screen_height() - margin * 2.0),
+        Color::new(0.0, 0.0, 0.0, 0.8),
+    );
+
+    let x = margin * 2.0;
+    let mut y = margin * 1.5 + size;
+    let heading = |text: &str, y: &mut f32| {
+        typography.draw(TextRole::Labels, text, x, *y, size, GRAY);
+        *y += line_height;
+    };
+
+    heading("Keys", &mut y);
+    for binding in bindings.iter() {
+        let key = KeyBindings::key_name(binding);
+        typography.draw(TextRole::Labels, &key, x, y, size, WHITE);
+        typography.draw(
+            TextRole::Labels,
+            binding.description,
+            x + column,
+            y,
+            size,
+            WHITE,
+        );
+        y += line_height;
+    }
+
+    y += line_height;
+    heading("Settings", &mut y);
+    for (name, value) in settings {
+        typography.draw(TextRole::Labels, name, x, y, size, WHITE);
+        typography.draw(TextRole::Metadata, value, x + column, y, size, WHITE);
+        y += line_height;
+    }
+}
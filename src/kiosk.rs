@@ -0,0 +1,128 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    process::{self, Command},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use macroquad::input::{KeyCode, is_key_down, is_key_pressed};
+
+// Set on the copy of the visualiser the supervisor runs, so it doesn't supervise itself
+const CHILD_ENV: &str = "AUDIO_VISUALISER_KIOSK_CHILD";
+// Waits before restarting grow up to this after repeated crashes
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Runs lasting longer than this count as healthy and reset the backoff
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether this process is the visualiser being run by `supervise`
+pub fn is_supervised() -> bool {
+    env::var_os(CHILD_ENV).is_some()
+}
+
+/// Whether the hidden Ctrl+Alt+Shift+Q combination was pressed this frame, the only input
+/// kiosk mode responds to
+pub fn exit_combo_pressed() -> bool {
+    let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    let alt = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+    let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+
+    ctrl && alt && shift && is_key_pressed(KeyCode::Q)
+}
+
+/// Marks the visualiser as closing on purpose, so the supervisor doesn't restart it
+pub fn request_exit() {
+    EXIT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Ends a supervised visualiser once its window has closed, with a failure status unless
+/// `request_exit` was called so the supervisor knows to restart it
+pub fn finish() {
+    if is_supervised() && !EXIT_REQUESTED.load(Ordering::Relaxed) {
+        process::exit(1);
+    }
+}
+
+/// Runs the visualiser again with the same arguments, restarting it whenever it crashes or
+/// stops for any reason other than the exit combination
+///
+/// Each start, crash and the final exit are appended to the CSV at `log_path` along with
+/// the run's uptime and the number of crashes so far
+pub fn supervise(log_path: &Path) -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut log = KioskLog::open(log_path)?;
+    let started = Instant::now();
+    let mut errors = 0;
+    let mut backoff = Duration::from_secs(1);
+
+    loop {
+        let run_started = Instant::now();
+        log.write("start", Duration::ZERO, errors, "")?;
+
+        let status = Command::new(&exe)
+            .args(&args)
+            .env(CHILD_ENV, "1")
+            .status()?;
+        let uptime = run_started.elapsed();
+
+        if status.success() {
+            log.write("exit", started.elapsed(), errors, "")?;
+            return Ok(());
+        }
+
+        errors += 1;
+        log.write("crash", uptime, errors, &status.to_string())?;
+        eprintln!(
+            "Visualiser stopped ({status}) after {:.0}s, restarting in {}s ({errors} errors so far)",
+            uptime.as_secs_f64(),
+            backoff.as_secs()
+        );
+
+        if uptime > HEALTHY_UPTIME {
+            backoff = Duration::from_secs(1);
+        }
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+struct KioskLog {
+    file: std::fs::File,
+}
+
+impl KioskLog {
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "timestamp,event,uptime_seconds,errors,status")?;
+        }
+
+        Ok(Self { file })
+    }
+
+    fn write(
+        &mut self,
+        event: &str,
+        uptime: Duration,
+        errors: usize,
+        status: &str,
+    ) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        writeln!(
+            self.file,
+            "{timestamp},{event},{:.1},{errors},\"{status}\"",
+            uptime.as_secs_f64()
+        )
+    }
+}
@@ -0,0 +1,87 @@
+//! Lining the visuals up with what's heard
+//!
+//! Audio reaches the visualiser a little after it reaches the source: the backend buffers
+//! it (PulseAudio's fragments, a JACK period or PipeWire quantum) and each window waits to
+//! fill before it's analysed. But audio captured from an output's monitor is heard later
+//! still, after the output's own latency, which for Bluetooth speakers can be a fifth of a
+//! second. The capture delay is measured as the visualiser runs, and `offset` says how much
+//! later the audio is heard:
+//!
+//! ```toml
+//! [latency]
+//! offset = 0.2
+//! compensate = true
+//! ```
+//!
+//! Visuals are held back until `offset` seconds after the audio reached the source, taking
+//! off the delay already measured when `compensate` is on, so they keep in step however the
+//! buffers fill. They can't be drawn before the audio they show has arrived, so an offset
+//! below the measured delay just shows everything as soon as it's analysed
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// Weight given to each new measurement, which jitters as the backend's buffers fill and drain
+const SMOOTHING: f64 = 0.05;
+
+/// The `[latency]` table, see the module docs
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatencyConfig {
+    /// Seconds after reaching the source that audio is heard. Larger holds the visuals back
+    pub offset: f64,
+    /// Take the measured capture and analysis delay off what the visuals are held back by
+    pub compensate: bool,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            compensate: true,
+        }
+    }
+}
+
+/// Tracks the capture delay and works out how long to hold the visuals back for
+pub struct Latency {
+    config: LatencyConfig,
+    // Smoothed seconds between audio reaching the source and the backend handing it over,
+    // once there's been a measurement
+    capture: Option<f64>,
+}
+
+impl Latency {
+    pub fn new(config: LatencyConfig) -> Self {
+        Self {
+            config,
+            capture: None,
+        }
+    }
+
+    /// Folds in the latest capture delay the backend reported
+    pub fn measure(&mut self, capture: Duration) {
+        let capture = capture.as_secs_f64();
+        self.capture = Some(match self.capture {
+            Some(smoothed) => smoothed + (capture - smoothed) * SMOOTHING,
+            None => capture,
+        });
+    }
+
+    /// Seconds between audio reaching the source and the backend handing it over
+    pub fn capture(&self) -> f64 {
+        self.capture.unwrap_or(0.0)
+    }
+
+    /// Seconds of captured audio to hold back before it's analysed, given the `analysis`
+    /// seconds that analysing it already takes
+    pub fn hold(&self, analysis: f64) -> f64 {
+        let measured = if self.config.compensate {
+            self.capture() + analysis
+        } else {
+            0.0
+        };
+        (self.config.offset - measured).max(0.0)
+    }
+}
@@ -0,0 +1,124 @@
+use macroquad::{
+    math::{Rect, Vec2, vec2},
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+
+/// Space left between neighbouring bars
+#[derive(Clone, Copy)]
+pub enum BarGap {
+    /// A fraction of each bar's slot (its share of the width), so gaps scale with the bar count
+    Proportional(f32),
+    /// A fixed number of pixels whatever the bar count
+    Pixels(f32),
+}
+
+impl Default for BarGap {
+    fn default() -> Self {
+        // Bars take up 1/1.1 of their slot, as they always have
+        BarGap::Proportional(1.0 - 1.0 / 1.1)
+    }
+}
+
+/// Horizontal position and width of each of `num_bars` bars spread across `width` pixels
+///
+/// Every bar sits centred in an equal slot, so all gaps are the same and the margins at
+/// either end are half a gap. Each edge is computed from its own index rather than by
+/// accumulating widths, so there's no drift towards the right edge. With `snap` the edges
+/// are rounded to whole pixels, which spreads the leftover fractions evenly across the bars
+pub fn bar_columns(num_bars: usize, width: f32, gap: BarGap, snap: bool) -> Vec<(f32, f32)> {
+    let slot = width / num_bars.max(1) as f32;
+    let gap = match gap {
+        BarGap::Proportional(fraction) => fraction * slot,
+        BarGap::Pixels(pixels) => pixels,
+    }
+    .clamp(0.0, slot);
+
+    (0..num_bars)
+        .map(|i| {
+            let left = i as f32 * slot + gap / 2.0;
+            let right = (i + 1) as f32 * slot - gap / 2.0;
+
+            if snap {
+                let (left, right) = (left.round(), right.round());
+                // Keep very thin bars visible rather than rounding them away
+                (left, (right - left).max(1.0))
+            } else {
+                (left, right - left)
+            }
+        })
+        .collect()
+}
+
+/// Point of the safe area an overlay element is pinned to, which the same point of the
+/// element sits on
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Centre,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// How far across and down the anchor is, from 0.0 to 1.0
+    fn fractions(self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Centre => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// Where an overlay element goes: pinned to an anchor, then nudged by an offset
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Placement {
+    pub anchor: Anchor,
+    /// Distance moved right and down from the anchor, as fractions of the window
+    pub offset: [f32; 2],
+}
+
+impl Placement {
+    pub fn new(anchor: Anchor) -> Self {
+        Self {
+            anchor,
+            offset: [0.0, 0.0],
+        }
+    }
+
+    /// Top left corner of an element `size` pixels across, placed within `area`
+    pub fn position(&self, area: Rect, size: Vec2) -> Vec2 {
+        let (across, down) = self.anchor.fractions();
+        vec2(
+            area.x + (area.w - size.x) * across + self.offset[0] * screen_width(),
+            area.y + (area.h - size.y) * down + self.offset[1] * screen_height(),
+        )
+    }
+}
+
+/// The window less `margins` on every side, given as fractions of its width and height,
+/// which overlay elements are kept within
+pub fn safe_area(margins: [f32; 2]) -> Rect {
+    let [x, y] = margins.map(|margin| margin.clamp(0.0, 0.5));
+    Rect::new(
+        x * screen_width(),
+        y * screen_height(),
+        (1.0 - 2.0 * x) * screen_width(),
+        (1.0 - 2.0 * y) * screen_height(),
+    )
+}
@@ -0,0 +1,256 @@
+//! Reactive LED strips driven over the network by WLED or any E1.31 (sACN) controller, as a
+//! sink
+//!
+//! ```toml
+//! [[sink]]
+//! rate = 40
+//! grouping = { bars = 30 }
+//! output = { kind = "led", target = "192.168.1.50", pixels = 60, protocol = "drgb" }
+//! ```
+//!
+//! The sink's levels are stretched or squeezed to `pixels` pixels, and each pixel is lit in
+//! its bar's colour from the `colour` mapper, at the bar's level times `brightness`. Any
+//! colour mapper from the main config can be used, white if left out.
+//!
+//! Protocols, with the UDP port used when `target` doesn't give one:
+//!
+//! - `warls`: WLED's realtime protocol with an index per pixel, for up to 255 pixels (21324)
+//! - `drgb`: WLED's realtime protocol with pixels in order, for up to 490 pixels (21324)
+//! - `e131`: sACN, 170 pixels to a universe from `universe` upwards (5568). `target` can be
+//!   the controller or the universe's multicast group, 239.255.0.1 for universe 1
+//!
+//! WLED shows its own effects again two seconds after the last frame
+
+use std::{io, net::UdpSocket};
+
+use macroquad::color::Color;
+use serde::Deserialize;
+
+use crate::{
+    analysis::{Feature, Features},
+    colour::{ColourMapper, blend_colours},
+    config::ColourConfig,
+    sinks::{Sink, SinkError, SinkFrame},
+};
+
+const WLED_PORT: u16 = 21324;
+const E131_PORT: u16 = 5568;
+// Seconds WLED waits after the last frame before going back to its own effects
+const WLED_TIMEOUT: u8 = 2;
+const WARLS: u8 = 1;
+const DRGB: u8 = 2;
+const WARLS_MAX_PIXELS: usize = 255;
+const DRGB_MAX_PIXELS: usize = 490;
+const PIXELS_PER_UNIVERSE: usize = 170;
+/// Bytes in every E1.31 data packet
+pub const E131_PACKET_LEN: usize = 638;
+// Offset of the DMX data in an E1.31 data packet, after the start code
+const E131_DATA_OFFSET: usize = 126;
+// Identifies this program as the source to sACN receivers
+const E131_CID: [u8; 16] = *b"rust-audio-visua";
+const E131_SOURCE_NAME: &str = "rust-audio-visualiser";
+const E131_PRIORITY: u8 = 100;
+
+/// How pixels are sent to the controller
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LedProtocol {
+    Warls,
+    #[default]
+    Drgb,
+    E131,
+}
+
+/// Settings of the `led` sink output, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LedConfig {
+    /// Address of the controller, with or without a port
+    pub target: String,
+    /// Number of pixels on the strip
+    pub pixels: usize,
+    #[serde(default)]
+    pub protocol: LedProtocol,
+    /// First sACN universe
+    #[serde(default = "default_universe")]
+    pub universe: u16,
+    /// Colour mapper giving each bar's colour
+    #[serde(default)]
+    pub colour: ColourConfig,
+    /// Scale of every pixel's brightness, from 0.0 to 1.0
+    #[serde(default = "default_brightness")]
+    pub brightness: f32,
+}
+
+fn default_universe() -> u16 {
+    1
+}
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+/// The `led` sink output
+pub struct LedSink {
+    socket: UdpSocket,
+    config: LedConfig,
+    colour: Box<dyn ColourMapper>,
+    // Sequence number of the next sACN packet, shared by every universe
+    sequence: u8,
+}
+
+impl LedSink {
+    /// Sends to the controller `config` names, colouring bars for spectra at `sample_rate`
+    pub fn connect(config: LedConfig, sample_rate: usize) -> io::Result<Self> {
+        let default_port = match config.protocol {
+            LedProtocol::Warls | LedProtocol::Drgb => WLED_PORT,
+            LedProtocol::E131 => E131_PORT,
+        };
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        if config.target.contains(':') {
+            socket.connect(&config.target)?;
+        } else {
+            socket.connect((config.target.as_str(), default_port))?;
+        }
+
+        Ok(Self {
+            socket,
+            colour: config.colour.mapper(sample_rate),
+            config,
+            sequence: 0,
+        })
+    }
+
+    // Each pixel's colour at its level, stretching or squeezing the levels to fit the strip
+    fn pixels(&mut self, frame: &SinkFrame) -> Vec<[u8; 3]> {
+        if let Some(beat) = &frame.beat {
+            self.colour.on_beat(beat);
+        }
+        let colours = self.colour.get_bar_colours(frame.analysis, frame.ranges);
+        let levels = frame.levels;
+        if levels.is_empty() || colours.is_empty() {
+            return vec![[0; 3]; self.config.pixels];
+        }
+
+        let scale = levels.len() as f32 / self.config.pixels.max(1) as f32;
+        (0..self.config.pixels)
+            .map(|pixel| {
+                // Position of the pixel's centre among the bars' centres
+                let position = ((pixel as f32 + 0.5) * scale - 0.5).max(0.0);
+                let below = (position as usize).min(levels.len() - 1);
+                let above = (below + 1).min(levels.len() - 1);
+                let t = position - below as f32;
+
+                let level = levels[below] + (levels[above] - levels[below]) * t;
+                let colour = blend_colours(
+                    colours[below % colours.len()],
+                    colours[above % colours.len()],
+                    t,
+                );
+                let brightness = (level * self.config.brightness).clamp(0.0, 1.0);
+                let Color { r, g, b, .. } = colour;
+                [r, g, b].map(|channel| (channel * brightness * 255.0).round() as u8)
+            })
+            .collect()
+    }
+
+    fn send_e131(&mut self, pixels: &[[u8; 3]]) -> io::Result<()> {
+        for (index, pixels) in pixels.chunks(PIXELS_PER_UNIVERSE).enumerate() {
+            let universe = self.config.universe.saturating_add(index as u16);
+            self.send_packet(&e131_packet(universe, self.sequence, pixels))?;
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    fn send_packet(&self, packet: &[u8]) -> io::Result<()> {
+        match self.socket.send(packet) {
+            Ok(_) => Ok(()),
+            // The controller is off or rebooting, which shouldn't stop the sink
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A WLED realtime packet of `pixels`, in WARLS for `warls` and DRGB for anything else.
+/// Pixels past the most the protocol can address are left out
+pub fn wled_packet(protocol: LedProtocol, pixels: &[[u8; 3]]) -> Vec<u8> {
+    let warls = protocol == LedProtocol::Warls;
+    let mut packet = vec![if warls { WARLS } else { DRGB }, WLED_TIMEOUT];
+    if warls {
+        for (index, pixel) in pixels.iter().enumerate().take(WARLS_MAX_PIXELS) {
+            packet.push(index as u8);
+            packet.extend_from_slice(pixel);
+        }
+    } else {
+        packet.extend(pixels.iter().take(DRGB_MAX_PIXELS).flatten());
+    }
+    packet
+}
+
+/// An E1.31 data packet for all 512 slots of `universe`, the first filled from `pixels` and
+/// the rest left dark. Pixels past the 170 a universe holds are left out
+pub fn e131_packet(universe: u16, sequence: u8, pixels: &[[u8; 3]]) -> [u8; E131_PACKET_LEN] {
+    // Each layer's length counts from its own flags and length field to the end
+    let flags_and_length =
+        |offset: usize| (0x7000 | (E131_PACKET_LEN - offset) as u16).to_be_bytes();
+
+    let mut packet = [0; E131_PACKET_LEN];
+    // Root layer
+    packet[0..2].copy_from_slice(&0x0010u16.to_be_bytes());
+    packet[4..16].copy_from_slice(b"ASC-E1.17\0\0\0");
+    packet[16..18].copy_from_slice(&flags_and_length(16));
+    packet[18..22].copy_from_slice(&0x0000_0004u32.to_be_bytes());
+    packet[22..38].copy_from_slice(&E131_CID);
+    // Framing layer
+    packet[38..40].copy_from_slice(&flags_and_length(38));
+    packet[40..44].copy_from_slice(&0x0000_0002u32.to_be_bytes());
+    packet[44..44 + E131_SOURCE_NAME.len()].copy_from_slice(E131_SOURCE_NAME.as_bytes());
+    packet[108] = E131_PRIORITY;
+    packet[111] = sequence;
+    packet[113..115].copy_from_slice(&universe.to_be_bytes());
+    // DMP layer, then the start code of 0 before the data
+    packet[115..117].copy_from_slice(&flags_and_length(115));
+    packet[117] = 0x02;
+    packet[118] = 0xa1;
+    packet[121..123].copy_from_slice(&1u16.to_be_bytes());
+    packet[123..125].copy_from_slice(&513u16.to_be_bytes());
+    for (slot, value) in packet[E131_DATA_OFFSET..]
+        .iter_mut()
+        .zip(pixels.iter().take(PIXELS_PER_UNIVERSE).flatten())
+    {
+        *slot = *value;
+    }
+    packet
+}
+
+impl Sink for LedSink {
+    fn features(&self) -> Features {
+        // Beats for the colours' `on_beat`
+        self.colour.features() | Feature::Beats.into()
+    }
+
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        let pixels = self.pixels(frame);
+        match self.config.protocol {
+            LedProtocol::Warls | LedProtocol::Drgb => {
+                self.send_packet(&wled_packet(self.config.protocol, &pixels))?
+            }
+            LedProtocol::E131 => self.send_e131(&pixels)?,
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        // Leaves the strip dark rather than frozen on the last frame
+        let dark = vec![[0; 3]; self.config.pixels];
+        match self.config.protocol {
+            LedProtocol::Warls | LedProtocol::Drgb => {
+                self.send_packet(&wled_packet(self.config.protocol, &dark))?
+            }
+            LedProtocol::E131 => self.send_e131(&dark)?,
+        }
+        Ok(())
+    }
+}
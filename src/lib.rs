@@ -0,0 +1,195 @@
+//! Audio analysis and visualisation for the `rust-audio-visualiser` binary, usable on its
+//! own by anything else that wants to react to music
+//!
+//! Also exposes the parsers, DSP entry points and sink protocol encoders to the fuzz targets
+//! in `fuzz/`
+//!
+//! # Features
+//!
+//! With default features turned off only the analysis core is built: `bins`, `grouping`,
+//! `smoothing`, `gain`, `gate`, `resample`, `peaks` and `chroma`. These need nothing but
+//! `alloc`, so the same bar and chromagram math can run on embedded targets driving LEDs
+//! directly, given spectra from a platform-specific FFT.
+//!
+//! The `analysis` feature adds the rest of the pipeline on top, still without macroquad or
+//! anything else the rendering needs: `spectra`, `analysis`, `onset`, `beat`, `tempo`,
+//! `pitch`, `chords`, `transcription`, `drops` and `pipeline`. `std`, on by default, builds
+//! everything.
+//!
+//! # The pipeline
+//!
+//! Each new window of mono samples goes through these in turn, as the binary does:
+//!
+//! 1. `spectra::FourierTransform::compute` turns it into a power spectrum
+//! 2. `analysis::Analyser::analyse` finds its loudness, bands, chromagram, onsets, beats and
+//!    tempo, keeping the history that needs between windows
+//! 3. `grouping::GroupingStrategy::group_spectrum` groups the spectrum into bars, over the
+//!    ranges from `create_ranges`
+//! 4. `smoothing::SmoothingStrategy::smooth` eases the bars towards the new levels
+//! 5. `gain::AutoGain::normalise` scales them to heights from 0.0 to 1.0
+//!
+//! `pipeline` runs the steps that make bars as `Stage`s in this order, with room to put
+//! stages of your own between them
+//!
+//! Audio capture (`audio`), configuration (`config`) and drawing (`visualiser` and the
+//! modules it draws with) build on these, and only come with `std`
+
+#![cfg_attr(not(feature = "analysis"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "analysis")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod atlas;
+#[cfg(feature = "std")]
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod autodj;
+#[cfg(feature = "std")]
+pub mod automation;
+#[cfg(feature = "analysis")]
+pub mod beat;
+pub mod bins;
+#[cfg(feature = "std")]
+pub mod budget;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "analysis")]
+pub mod chords;
+pub mod chroma;
+#[cfg(feature = "std")]
+pub mod colour;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod distortion;
+#[cfg(feature = "std")]
+pub mod dj;
+#[cfg(feature = "analysis")]
+pub mod drops;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod expression;
+pub mod gain;
+pub mod gate;
+#[cfg(feature = "std")]
+pub mod graph;
+pub mod grouping;
+#[cfg(feature = "std")]
+pub mod headless;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod hud;
+#[cfg(feature = "std")]
+pub mod idle;
+#[cfg(feature = "std")]
+pub mod keybindings;
+#[cfg(feature = "std")]
+pub mod kiosk;
+#[cfg(feature = "std")]
+pub mod latency;
+#[cfg(feature = "std")]
+pub mod layout;
+#[cfg(feature = "led")]
+pub mod led;
+#[cfg(feature = "std")]
+pub mod listenbrainz;
+#[cfg(feature = "midi")]
+pub mod midi;
+#[cfg(feature = "midi-out")]
+pub mod midiout;
+#[cfg(feature = "std")]
+pub mod modes;
+#[cfg(feature = "std")]
+pub mod mpris;
+#[cfg(feature = "std")]
+pub mod novelty;
+#[cfg(feature = "analysis")]
+pub mod onset;
+#[cfg(feature = "osc")]
+pub mod osc;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "std")]
+pub mod overlay;
+#[cfg(feature = "std")]
+pub mod pacing;
+#[cfg(feature = "std")]
+pub mod palette;
+#[cfg(feature = "std")]
+pub mod particles;
+pub mod peaks;
+#[cfg(feature = "analysis")]
+pub mod pipeline;
+#[cfg(feature = "analysis")]
+pub mod pitch;
+#[cfg(feature = "std")]
+pub mod presets;
+#[cfg(feature = "std")]
+pub mod primitives;
+#[cfg(feature = "jack")]
+mod jack;
+#[cfg(feature = "pipewire")]
+mod pipewire;
+#[cfg(feature = "pulseaudio")]
+mod pulseaudio;
+pub mod resample;
+#[cfg(feature = "std")]
+pub mod rotation;
+#[cfg(feature = "scan")]
+pub mod scan;
+#[cfg(feature = "std")]
+pub mod schedule;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "serial")]
+pub mod serial;
+#[cfg(feature = "midi")]
+pub mod session;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod shutdown;
+#[cfg(feature = "std")]
+pub mod sinks;
+pub mod smoothing;
+#[cfg(all(test, feature = "std"))]
+mod snapshots;
+#[cfg(feature = "analysis")]
+pub mod spectra;
+#[cfg(feature = "std")]
+pub mod spectrogram;
+#[cfg(feature = "analysis")]
+pub mod tempo;
+#[cfg(feature = "std")]
+pub mod timeline;
+#[cfg(feature = "std")]
+pub mod tracklog;
+#[cfg(feature = "std")]
+pub mod trails;
+#[cfg(feature = "analysis")]
+pub mod transcription;
+#[cfg(feature = "std")]
+pub mod typography;
+#[cfg(feature = "std")]
+pub mod ui;
+#[cfg(feature = "export")]
+pub mod video;
+#[cfg(feature = "std")]
+pub mod visualiser;
+#[cfg(feature = "std")]
+pub mod warmup;
+#[cfg(any(feature = "midi", feature = "export", feature = "scan"))]
+pub mod wav;
+#[cfg(feature = "std")]
+pub mod widgets;
+
+#[cfg(feature = "std")]
+pub use audio::AudioError;
+#[cfg(feature = "std")]
+pub use config::ConfigError;
+#[cfg(feature = "std")]
+pub use visualiser::VisualiserError;
@@ -0,0 +1,133 @@
+//! Submits each track in the session log to ListenBrainz, or any server with the same API,
+//! with its detected BPM and key alongside the artist and title
+//!
+//! ```toml
+//! [listenbrainz]
+//! token = "your user token from listenbrainz.org/settings"
+//! ```
+//!
+//! Submissions are sent with `curl` on a background thread, so a slow or missing network
+//! never holds up drawing
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+};
+
+use serde::Deserialize;
+
+use crate::tracklog::{TrackSummary, json_string};
+
+// Sent with every listen, so the extra fields can be traced back to where they came from
+const CLIENT_NAME: &str = "rust-audio-visualiser";
+
+/// The `[listenbrainz]` table, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListenBrainzConfig {
+    pub token: String,
+    /// Root of the API, for self-hosted servers
+    #[serde(default = "default_url")]
+    pub url: String,
+}
+
+fn default_url() -> String {
+    "https://api.listenbrainz.org".to_string()
+}
+
+/// Sends tracks to the server in `ListenBrainzConfig`
+pub struct ListenBrainz {
+    config: ListenBrainzConfig,
+}
+
+impl ListenBrainz {
+    pub fn new(config: ListenBrainzConfig) -> Self {
+        Self { config }
+    }
+
+    /// Submits `summary` as a listen on a background thread, printing any failure
+    pub fn submit(&self, summary: &TrackSummary) {
+        let url = format!("{}/1/submit-listens", self.config.url.trim_end_matches('/'));
+        let header = format!("Authorization: Token {}", self.config.token);
+        let body = listen_json(summary);
+        let title = summary.track.title.clone();
+
+        thread::spawn(move || match post(&url, &header, &body) {
+            Ok(()) => eprintln!("Submitted {title} to ListenBrainz"),
+            Err(e) => eprintln!("Failed to submit {title} to ListenBrainz: {e}"),
+        });
+    }
+}
+
+/// The body of a `single` listen submission, with the analysis in `additional_info`
+pub fn listen_json(summary: &TrackSummary) -> String {
+    let track = &summary.track;
+    let stats = &summary.stats;
+
+    let mut info = vec![
+        format!("\"submission_client\":{}", json_string(CLIENT_NAME)),
+        format!("\"duration\":{:.0}", stats.duration),
+        format!("\"mean_loudness_dbfs\":{:.1}", stats.mean_loudness),
+    ];
+    if let Some(bpm) = stats.bpm {
+        info.push(format!("\"bpm\":{bpm:.1}"));
+    }
+    if let Some(key) = stats.key {
+        info.push(format!("\"key\":{}", json_string(&key.name())));
+    }
+    if !track.player.is_empty() {
+        info.push(format!("\"media_player\":{}", json_string(&track.player)));
+    }
+
+    let mut metadata = vec![
+        format!("\"artist_name\":{}", json_string(&track.artist)),
+        format!("\"track_name\":{}", json_string(&track.title)),
+    ];
+    if !track.album.is_empty() {
+        metadata.push(format!("\"release_name\":{}", json_string(&track.album)));
+    }
+    metadata.push(format!("\"additional_info\":{{{}}}", info.join(",")));
+
+    format!(
+        "{{\"listen_type\":\"single\",\"payload\":[{{\"listened_at\":{},\"track_metadata\":{{{}}}}}]}}",
+        summary.started,
+        metadata.join(","),
+    )
+}
+
+/// POSTs `body` as JSON to `url` with `curl`, which reads the token's header from stdin so
+/// it doesn't show up in the process list
+fn post(url: &str, header: &str, body: &str) -> Result<(), String> {
+    let mut child = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            "30",
+            "--header",
+            "@-",
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            body,
+            url,
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run curl: {e}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{header}").map_err(|e| e.to_string())?;
+    }
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
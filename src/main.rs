@@ -1,106 +1,339 @@
-mod colour;
-mod grouping;
-mod smoothing;
-mod spectra;
-mod visualiser;
+mod cli;
 
-use colour::{ChromagramColour, StaticColour};
-use spectra::FourierTransform;
-use visualiser::VisualiserBuilder;
+use clap::Parser;
+use cli::{Cli, Task};
+use rust_audio_visualiser::{
+    analysis::{Analyser, FrameAnalysis},
+    audio::{BACKENDS, Backend, Downmix, RingBuffer},
+    autodj::AutoDj,
+    automation::Parameter,
+    capture::Capture,
+    config::{Config, WindowConfig},
+    dj::DualDeckVisualiser,
+    gate::NoiseGate,
+    graph::{Graph, GraphInput},
+    headless::{Headless, HeadlessFormat},
+    hud::{self, Hud, HudStats},
+    idle::IdleArt,
+    keybindings::{Action, KeyBindings, draw_help},
+    kiosk,
+    latency::Latency,
+    listenbrainz::ListenBrainz,
+    modes::{Mode, ModeInput},
+    mpris,
+    output::OutputStage,
+    overlay::Overlay,
+    palette::{Command, CommandPalette},
+    presets,
+    resample::Resampler,
+    rotation::Rotation,
+    schedule::{self, Schedule, ScheduleState},
+    shutdown,
+    sinks::Sinks,
+    spectra::{FourierTransform, SpectralAverage},
+    timeline::Timeline,
+    tracklog::{LogFormat, SESSION_LOG_PATH, SessionLog},
+    visualiser::{DisplayMode, VisualiserBuilder, VisualiserError},
+    warmup::WarmUp,
+    widgets::Widgets,
+};
+
+#[cfg(feature = "scan")]
+use rust_audio_visualiser::scan;
+#[cfg(feature = "midi")]
+use rust_audio_visualiser::session::SessionRecorder;
+#[cfg(any(feature = "midi", feature = "export"))]
+use rust_audio_visualiser::wav;
+#[cfg(feature = "export")]
+use rust_audio_visualiser::{
+    colour::ColourMap,
+    export::{self, ExportError, PlotFormat, SpectrogramKind},
+    video,
+};
+#[cfg(feature = "history")]
+use rust_audio_visualiser::{
+    history::{self, History, HistoryError, HistoryQuery, HistorySummary},
+    tracklog::HISTORY_PATH,
+};
 
 use macroquad::prelude::*;
-use psimple::Simple;
-use pulse::sample::{Format, Spec};
-use pulse::stream::Direction;
 
-use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "scan")]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::time::Instant;
+#[cfg(any(feature = "midi", feature = "history"))]
+use std::time::{SystemTime, UNIX_EPOCH};
 
-const SAMPLE_RATE: usize = 44_100;
-const FFT_SIZE: usize = 2048;
-const FRAME_RATE: usize = 60;
+const KIOSK_LOG_PATH: &str = "kiosk-log.csv";
+const AUDIO_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// Sample buffers hold this many FFT windows, so the audio thread can run ahead of a slow frame
+const BUFFER_WINDOWS: usize = 4;
+// Largest FFT size that can be switched to while running, which the sample buffers are sized for
+const MAX_FFT_SIZE: usize = 16_384;
 
-fn get_audio_source() -> Simple {
-    let spec = Spec {
-        format: Format::FLOAT32NE,
-        channels: 2,
-        rate: SAMPLE_RATE as u32,
-    };
-    assert!(spec.is_valid());
-    // Set lower latency (smaller buffer size)
-    let buffer_attr = pulse::def::BufferAttr {
-        maxlength: u32::MAX, // Let PulseAudio decide max size
-        tlength: u32::MAX,   // Only used for playback
-        prebuf: u32::MAX,    // Only used for playback
-        minreq: u32::MAX,    // Only used for playback
-        fragsize: 1024,      // Lower = lower latency (used for recording)
-    };
+/// The capture thread started by `spawn_audio_reader`
+struct AudioReader {
+    /// Capture delay the backend last reported, or `None` while disconnected
+    latency: Arc<Mutex<Option<Duration>>>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
 
-    let source_name = "bluez_sink.90_62_3F_61_71_4B.a2dp_sink.monitor";
+impl AudioReader {
+    /// Stops capturing, waiting for the thread to close the capture stream
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // A thread that panicked has already stopped
+        let _ = self.thread.join();
+    }
+}
 
-    Simple::new(
-        None,               // Use the default server
-        "AudioVisualiser",  // Our application's name
-        Direction::Record,  // We want a recording stream
-        Some(source_name),  // Use a monitor source
-        "Audio Monitor",    // Description of our stream
-        &spec,              // Our sample format
-        None,               // Use default channel map
-        Some(&buffer_attr), // Use default buffering attributes
-    )
-    .unwrap()
+/// What the capture thread fills, for the render loop to read from
+struct CaptureBuffers {
+    /// Mono samples
+    samples: Arc<RingBuffer>,
+    /// Left and right samples, if they're kept
+    channels: Option<[Arc<RingBuffer>; 2]>,
+    /// Capture delay the backend last reported, see `AudioReader`
+    latency: Arc<Mutex<Option<Duration>>>,
 }
 
-fn spawn_audio_reader(buffer: Arc<Mutex<VecDeque<f32>>>) {
-    thread::spawn(move || {
-        let mut raw_samples = [0u8; FFT_SIZE * 8]; // 8 bytes per stereo frame (2x f32)
+/// Captures from `source_name` with `backend` into `buffer` as mono, downmixed as `config`
+/// says, and also into `channels` as separate left and right samples if given. Samples
+/// arrive a hop at a time, so each new FFT window is available as soon as it can be, and
+/// are gated by the config's noise gate if there is one. Sources that can't be captured at
+/// the config's sample rate are resampled to it
+fn spawn_audio_reader(
+    buffer: Arc<RingBuffer>,
+    channels: Option<[Arc<RingBuffer>; 2]>,
+    backend: &'static Backend,
+    source_name: String,
+    config: &Config,
+) -> AudioReader {
+    let (sample_rate, hop_size, noise_gate, downmix) = (
+        config.sample_rate,
+        config.hop_size(),
+        config.noise_gate(),
+        config.downmix,
+    );
+    let latency = Arc::new(Mutex::new(None));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (shared_latency, stopped) = (latency.clone(), stop.clone());
+    let thread = thread::spawn(move || {
+        let mut gate = noise_gate
+            .map(|gate| NoiseGate::new(gate.threshold, gate.hold, gate.release, sample_rate));
+        let mut raw_samples = vec![0u8; hop_size * 8]; // 8 bytes per stereo frame (2x f32)
+        let mut new_samples = Vec::with_capacity(hop_size);
+        let mut left_samples = Vec::with_capacity(hop_size);
+        let mut right_samples = Vec::with_capacity(hop_size);
+        let mut resampled = [(); 3].map(|_| Vec::with_capacity(hop_size));
 
-        let s = get_audio_source();
+        // Reconnect whenever the source can't be opened or stops delivering, e.g. when
+        // PulseAudio restarts or a Bluetooth device drops out
+        while !stopped.load(Ordering::Relaxed) {
+            let s = match backend.open(&source_name, sample_rate) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("{e}");
+                    thread::sleep(AUDIO_RECONNECT_DELAY);
+                    continue;
+                }
+            };
+            // Mono, left and right, each carrying on from where it left off
+            let mut resamplers = (s.sample_rate() != sample_rate).then(|| {
+                eprintln!(
+                    "Resampling audio from {} Hz to {sample_rate} Hz",
+                    s.sample_rate()
+                );
+                [(); 3].map(|_| Resampler::new(s.sample_rate(), sample_rate))
+            });
 
-        loop {
-            if s.read(&mut raw_samples).is_ok() {
-                let mut new_samples = Vec::with_capacity(FFT_SIZE);
+            while !stopped.load(Ordering::Relaxed) && s.read(&mut raw_samples).is_ok() {
+                new_samples.clear();
+                left_samples.clear();
+                right_samples.clear();
 
                 for chunk in raw_samples.chunks_exact(8) {
                     let left = f32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
                     let right = f32::from_ne_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
-                    new_samples.push((left + right) / 2.0); // Mono
+                    new_samples.push(downmix.mix(left, right));
+                    left_samples.push(left);
+                    right_samples.push(right);
                 }
 
-                let mut buf = buffer.lock().unwrap();
-                for s in new_samples {
-                    buf.push_back(s);
+                if let Some(gate) = &mut gate {
+                    gate.detect(&new_samples);
+                    for ((mono, left), right) in new_samples
+                        .iter_mut()
+                        .zip(&mut left_samples)
+                        .zip(&mut right_samples)
+                    {
+                        let gain = gate.next_gain();
+                        *mono *= gain;
+                        *left *= gain;
+                        *right *= gain;
+                    }
                 }
 
-                // Trim the buffer to stay within the max size
-                while buf.len() > FFT_SIZE {
-                    buf.pop_front();
+                if let Some(resamplers) = &mut resamplers {
+                    let streams = [&mut new_samples, &mut left_samples, &mut right_samples];
+                    for ((resampler, samples), output) in
+                        resamplers.iter_mut().zip(streams).zip(&mut resampled)
+                    {
+                        output.clear();
+                        resampler.process(samples, output);
+                        std::mem::swap(samples, output);
+                    }
                 }
-            } else {
-                eprintln!("Failed to read from audio source");
+
+                if let Some([left, right]) = &channels {
+                    left.push(&left_samples);
+                    right.push(&right_samples);
+                }
+                // The last sample read was heard the backend's latency ago
+                let captured = Instant::now();
+                let captured = captured.checked_sub(s.latency()).unwrap_or(captured);
+                buffer.push_captured(&new_samples, captured);
+                *shared_latency.lock().unwrap() = Some(s.latency());
+            }
+
+            *shared_latency.lock().unwrap() = None;
+            if stopped.load(Ordering::Relaxed) {
+                break;
             }
+            eprintln!("Failed to read from audio source, reconnecting");
+            thread::sleep(AUDIO_RECONNECT_DELAY);
         }
     });
+
+    AudioReader {
+        latency,
+        stop,
+        thread,
+    }
+}
+
+/// Seconds from `start` until the last sample of the window ending at `window_end` was
+/// captured, so each window is timed by the audio rather than by when it was analysed
+fn window_time(
+    samples: &RingBuffer,
+    window_end: usize,
+    sample_rate: usize,
+    start: Instant,
+) -> Option<f64> {
+    let captured = samples.captured_at(window_end, sample_rate)?;
+    Some(captured.saturating_duration_since(start).as_secs_f64())
 }
 
-async fn run_bar_visualiser(samples: Arc<Mutex<VecDeque<f32>>>) {
-    // Visualiser setup
-    let mut visualiser = VisualiserBuilder::new()
-        .with_grouping(grouping::GroupingStrategy::LogMax { num_groups: 12 })
-        .with_colour_mapper(Box::new(StaticColour::new(WHITE)))
-        .build(SAMPLE_RATE, FFT_SIZE);
+async fn run_bar_visualiser(
+    capture: CaptureBuffers,
+    builder: VisualiserBuilder,
+    mut graph: Graph,
+    backend: &Backend,
+    source: String,
+    config: &Config,
+) -> Result<(), VisualiserError> {
+    let CaptureBuffers {
+        samples,
+        channels,
+        latency: capture_latency,
+    } = capture;
+    let Config {
+        sample_rate,
+        mut fft_size,
+        frame_rate,
+        ..
+    } = *config;
+
+    let mut visualiser = builder.build(sample_rate, fft_size)?;
 
     // For fixing visualiser FPS
     let mut last_frame_time = 0.0;
-    let target_frame_duration = 1.0 / (FRAME_RATE as f64);
+    let target_frame_duration = 1.0 / (frame_rate as f64);
+
+    // The hop stays the same if the FFT size is switched, so onset and tempo history still apply
+    let mut fft = FourierTransform::new(fft_size).with_hop_size(config.hop_size());
+    // Every hop is analysed, so onsets and tempo run at the hop rate rather than the frame rate
+    let mut analyser = Analyser::new(sample_rate, sample_rate / fft.hop_size());
+    let mut averaging =
+        (config.spectral_averaging > 1).then(|| SpectralAverage::new(config.spectral_averaging));
+    #[cfg(feature = "midi")]
+    let mut session = match config.memory_budget().session_events() {
+        Some(max_events) => SessionRecorder::new(sample_rate).with_max_events(max_events),
+        None => SessionRecorder::new(sample_rate),
+    };
+    let mut output = OutputStage::new(config.output)?;
+    let mut sinks = Sinks::spawn(&config.sinks, sample_rate)?;
+    let overlay = Overlay::new(config.overlay.clone())?;
+    let widgets = Widgets::new(config.widgets.clone());
+    let presets = presets::load(config)?;
+    let mut rotation = config
+        .rotation
+        .clone()
+        .map(|rotation| Rotation::new(rotation, &presets))
+        .transpose()?;
+    // Reused every frame rather than copying out of the shared buffers into new ones
+    let mut samples_to_use = vec![0.0; fft_size];
+    let mut channel_samples = [vec![0.0; fft_size], vec![0.0; fft_size]];
+    // End of the next window to analyse, counted in samples written to the shared buffer
+    let mut next_window_end = fft_size;
+    // End of the window `latest_analysis` was computed from, for estimating latency
+    let mut latest_window_end = 0;
+    let mut latest_analysis: Option<FrameAnalysis> = None;
+    let mut latency = Latency::new(config.latency);
+    let mut warm_up = WarmUp::new(config.warm_up);
 
-    let fft = FourierTransform::new(FFT_SIZE);
+    let mut schedule = Schedule::new(config.schedule.clone());
+    let mut idle_art = config.schedule.idle.clone().map(IdleArt::new);
+    let keybindings = KeyBindings::new();
+    let mut palette = CommandPalette::new(&keybindings);
+    let mut show_help = false;
+    let mut hud = Hud::new();
+    let mut capture = Capture::new(config.capture.clone());
+    let mut show_hud = false;
+    let mut active_preset = None;
+    let mut fullscreen = false;
+    let mut paused = false;
+
+    // Closing the window goes through the same teardown as the quit key. In kiosk mode it's
+    // ignored altogether
+    prevent_quit();
+    if config.kiosk {
+        show_mouse(false);
+    }
+
+    let now_playing = mpris::spawn_metadata_watcher(Duration::from_secs(2));
+    let active_source = backend.spawn_source_watcher(source, Duration::from_secs(2));
+    let mut active_profile = None;
+    let mut auto_dj = AutoDj::new(config.auto);
+    let mut session_log = SessionLog::new(
+        PathBuf::from(SESSION_LOG_PATH),
+        LogFormat::Csv,
+        sample_rate,
+        frame_rate,
+    );
+    #[cfg(feature = "history")]
+    let history = History::open(Path::new(HISTORY_PATH))
+        .inspect_err(|e| eprintln!("Failed to open listening history: {e}"))
+        .ok();
+    let listenbrainz = config.listenbrainz.clone().map(ListenBrainz::new);
+    // When `get_time` was 0.0, so capture times can be put on the same clock
+    let clock_start = Instant::now();
+    let clock_start = clock_start
+        .checked_sub(Duration::from_secs_f64(macroquad::prelude::get_time()))
+        .unwrap_or(clock_start);
 
     loop {
         let current_time = macroquad::prelude::get_time();
         let frame_time = current_time - last_frame_time;
 
+        output.begin();
         clear_background(Color {
             r: 0.1,
             g: 0.1,
@@ -108,15 +341,332 @@ async fn run_bar_visualiser(samples: Arc<Mutex<VecDeque<f32>>>) {
             a: 1.0,
         });
 
-        let samples_to_use: Vec<f32> = samples.lock().unwrap().clone().into();
+        // Events were shown when the analysis was last drawn, so don't show them again if
+        // there's no new window this frame
+        if let Some(analysis) = &mut latest_analysis {
+            analysis.onset = false;
+            analysis.beat = None;
+        }
+
+        if let Some(capture) = *capture_latency.lock().unwrap() {
+            latency.measure(capture);
+        }
+        // Windows are centred on the audio they're drawn for, so analysis lags by half of one
+        let hold = latency.hold((fft_size / 2) as f64 / sample_rate as f64);
+        // Held back audio still has to be in the buffer when it's analysed
+        let held = ((hold * sample_rate as f64) as usize)
+            .min(samples.capacity().saturating_sub(fft_size + fft.hop_size()));
+
+        // Windows the audio thread has already overwritten are skipped rather than fallen
+        // behind on
+        let written = samples.written();
+        let available = written.saturating_sub(held);
+        let oldest_end = (written + fft_size).saturating_sub(samples.capacity());
+        if next_window_end < oldest_end {
+            next_window_end = available.max(fft_size);
+        }
+        // Nothing captured while paused is analysed, so resuming picks up from live audio
+        if paused {
+            next_window_end = next_window_end.max(available + 1);
+        }
+
+        while next_window_end <= available {
+            let window_end = next_window_end;
+            next_window_end += fft.hop_size();
+            if !samples.read_ending_at(window_end, &mut samples_to_use) {
+                continue;
+            }
 
-        if samples_to_use.len() < FFT_SIZE {
+            let time =
+                window_time(&samples, window_end, sample_rate, clock_start).unwrap_or_else(|| {
+                    current_time - (written - window_end) as f64 / sample_rate as f64
+                });
+            let [left_samples, right_samples] = &mut channel_samples;
+            let channels = channels.as_ref().filter(|[left, right]| {
+                left.read_ending_at(window_end, left_samples)
+                    && right.read_ending_at(window_end, right_samples)
+            });
+            if !graph.is_empty() {
+                let input = GraphInput {
+                    mono: &samples_to_use,
+                    channels: channels.map(|_| [&left_samples[..], &right_samples[..]]),
+                    time,
+                };
+                if let Err(e) = graph.process(&input) {
+                    eprintln!("Failed to run processing graph: {e}");
+                }
+            }
+            let spectrum = match channels.filter(|_| config.downmix == Downmix::Spectral) {
+                Some(_) => Downmix::combine_spectra(
+                    &fft.compute(left_samples),
+                    &fft.compute(right_samples),
+                ),
+                None => fft.compute(&samples_to_use),
+            };
+            // Only what's being drawn or sent is computed, which changes with the mode
+            analyser.set_features(
+                visualiser.features()
+                    | sinks.features()
+                    | AutoDj::analysis_features(&config.scenes),
+            );
+            let mut analysis = analyser.analyse(&samples_to_use, spectrum, time);
+            // Averaged after analysis so onsets and beats aren't smeared out
+            if let Some(averaging) = &mut averaging {
+                analysis.spectrum = averaging.update(&analysis.spectrum);
+            }
+            #[cfg(feature = "midi")]
+            session.update(&analysis.spectrum, time);
+            sinks.broadcast(&analysis);
+
+            // Only the last window is drawn, so carry over events from any before it
+            if let Some(previous) = &latest_analysis {
+                analysis.onset |= previous.onset;
+                analysis.beat = analysis.beat.or(previous.beat);
+            }
+            latest_analysis = Some(analysis);
+            latest_window_end = window_end;
+        }
+
+        // Black until the analysis has settled, which the visualiser then fades in from
+        let Some(analysis) = latest_analysis
+            .as_mut()
+            .filter(|_| !warm_up.settling(current_time))
+        else {
+            if let Some(analysis) = &latest_analysis {
+                visualiser.warm_up(analysis);
+            }
+            clear_background(BLACK);
+            output.finish();
             next_frame().await;
             continue;
+        };
+        if let (Some(_), Some([left, right])) = (config.stereo, &channels)
+            && visualiser.needs(ModeInput::ChannelSpectra)
+        {
+            let [left_samples, right_samples] = &mut channel_samples;
+            // Both channels are filled together, but may be a read behind the mono buffer
+            if left.read_latest(left_samples) && right.read_latest(right_samples) {
+                analysis.channel_spectra =
+                    Some([fft.compute(left_samples), fft.compute(right_samples)]);
+            }
         }
+        let state = schedule.update(analysis.loudness, current_time);
+        match state {
+            ScheduleState::Running | ScheduleState::Idle(_) => {
+                visualiser.draw(analysis);
+                graph.draw();
+                if let Some(rotation) = &rotation {
+                    rotation.draw(current_time);
+                }
+                if let (ScheduleState::Idle(opacity), Some(idle_art)) = (&state, &mut idle_art) {
+                    idle_art.draw(*opacity, get_frame_time());
+                }
+                overlay.draw(
+                    analysis,
+                    now_playing.lock().unwrap().as_ref(),
+                    visualiser.typography(),
+                );
+                widgets.draw(analysis, config.overlay.safe_area, visualiser.typography());
+            }
+            ScheduleState::Blank => clear_background(BLACK),
+            ScheduleState::Quit => {
+                kiosk::request_exit();
+                break;
+            }
+        }
+        warm_up.draw(current_time);
+        let track = now_playing.lock().unwrap().clone();
 
-        let spectrum = fft.compute(&samples_to_use);
-        visualiser.draw_chromagram(&spectrum);
+        let profile = config.profiles.iter().position(|profile| {
+            let player = track.as_ref().map(|track| track.player.as_str());
+            profile.matches(&active_source.lock().unwrap(), player)
+        });
+        if profile != active_profile {
+            let profile = profile.map(|index| &config.profiles[index]);
+            match profile {
+                Some(profile) => eprintln!("Switched to profile {}", profile.name),
+                None => eprintln!("Switched back to the default profile"),
+            }
+            visualiser.apply_profile(config, profile);
+        }
+        active_profile = profile;
+
+        if let Some(scene) = auto_dj.update(&config.scenes, analysis) {
+            let scene = &config.scenes[scene];
+            eprintln!("Switched to scene {}", scene.name);
+            visualiser.apply_scene(scene);
+        }
+
+        if let Some(index) = rotation
+            .as_mut()
+            .and_then(|rotation| rotation.update(current_time, track.as_ref()))
+        {
+            eprintln!("Rotated to preset {}", presets[index].name);
+            visualiser.apply_preset(&presets[index]);
+            active_preset = Some(index);
+        }
+
+        match session_log.update(&samples_to_use, &analysis.spectrum, track.as_ref()) {
+            Ok(Some(summary)) => {
+                #[cfg(feature = "history")]
+                if let Some(Err(e)) = history.as_ref().map(|history| history.record(&summary)) {
+                    eprintln!("Failed to add to listening history: {e}");
+                }
+                if let Some(listenbrainz) = &listenbrainz {
+                    listenbrainz.submit(&summary);
+                }
+            }
+            Ok(None) => (),
+            Err(e) => eprintln!("Failed to write session log: {e}"),
+        }
+
+        if show_help {
+            draw_help(
+                &keybindings,
+                &visualiser.settings(),
+                visualiser.typography(),
+            );
+        }
+        hud.update();
+        if show_hud {
+            // Measured from the middle of the window, where the spectrum is centred in time
+            let age = written.saturating_sub(latest_window_end) + fft_size / 2;
+            let stats = HudStats {
+                fft_size,
+                latency: age as f32 / sample_rate as f32 + latency.capture() as f32,
+                capture_latency: latency.capture() as f32,
+                held: held as f32 / sample_rate as f32,
+                preset: active_preset.map(|index: usize| presets[index].name.as_str()),
+                device: &active_source.lock().unwrap(),
+                peak: hud::peak_level(&analysis.samples),
+                memory_budget: config.memory_budget().megabytes(),
+                #[cfg(feature = "midi")]
+                dropped_notes: session.dropped(),
+                #[cfg(not(feature = "midi"))]
+                dropped_notes: 0,
+            };
+            hud.draw(&stats, visualiser.typography());
+        }
+        palette.draw(visualiser.typography());
+        output.finish();
+        // Taken once the frame's finished, so recordings show exactly what's on screen
+        match capture.update(current_time) {
+            Ok(Some(path)) => eprintln!("Saved recording to {}", path.display()),
+            Ok(None) => (),
+            Err(e) => eprintln!("Failed to record: {e}"),
+        }
+
+        if config.kiosk {
+            if kiosk::exit_combo_pressed() || shutdown::requested() {
+                kiosk::request_exit();
+                break;
+            }
+            last_frame_time = current_time;
+            next_frame().await;
+            continue;
+        }
+
+        // Keys typed into the palette shouldn't also trigger their bindings
+        let mut actions = if palette.is_open() {
+            Vec::new()
+        } else {
+            keybindings.triggered()
+        };
+        match palette.update() {
+            Some(Command::Action(action)) => actions.push(action),
+            Some(Command::SetMode(mode)) => visualiser.set_mode(mode),
+            Some(Command::SetParameter(parameter, value)) => {
+                visualiser.set_parameter(parameter, value)
+            }
+            None => (),
+        }
+
+        let mut requested_fft_size = None;
+        for action in actions {
+            match action {
+                // Holding left/right crossfades between the colour mappers over a second
+                Action::BlendTowardsPrimary => {
+                    visualiser.adjust_parameter(Parameter::ColourBlend, -1.0 / frame_rate as f32)
+                }
+                Action::BlendTowardsSecondary => {
+                    visualiser.adjust_parameter(Parameter::ColourBlend, 1.0 / frame_rate as f32)
+                }
+                #[cfg(not(feature = "midi"))]
+                Action::ExportMidi => eprintln!("Built without MIDI support"),
+                #[cfg(feature = "midi")]
+                Action::ExportMidi => export_session_midi(&session),
+                Action::Screenshot => match capture.screenshot() {
+                    Ok(path) => eprintln!("Saved screenshot to {}", path.display()),
+                    Err(e) => eprintln!("Failed to save screenshot: {e}"),
+                },
+                Action::RecordClip if capture.is_recording() => eprintln!("Already recording"),
+                Action::RecordClip => match capture.start_recording(current_time) {
+                    Ok(path) => eprintln!("Recording to {}", path.display()),
+                    Err(e) => eprintln!("Failed to start recording: {e}"),
+                },
+                Action::ToggleHelp => show_help = !show_help,
+                Action::ToggleHud => show_hud = !show_hud,
+                Action::OpenPalette => palette.open(),
+                Action::HalveFftSize => requested_fft_size = Some(fft_size / 2),
+                Action::DoubleFftSize => requested_fft_size = Some(fft_size * 2),
+                Action::SelectPreset(index) => match presets.get(index) {
+                    Some(preset) => {
+                        eprintln!("Switched to preset {}", preset.name);
+                        visualiser.apply_preset(preset);
+                        active_preset = Some(index);
+                    }
+                    None => eprintln!("There's no preset {}", index + 1),
+                },
+                Action::AdjustBars(delta) => visualiser.adjust_bars(delta),
+                Action::CycleGrouping => visualiser.cycle_grouping(),
+                Action::AdjustRise(delta) => {
+                    visualiser.adjust_parameter(Parameter::SmoothingRise, delta)
+                }
+                Action::AdjustFall(delta) => {
+                    visualiser.adjust_parameter(Parameter::SmoothingFall, delta)
+                }
+                Action::NextMode => visualiser.next_mode(),
+                Action::ToggleFullscreen => {
+                    fullscreen = !fullscreen;
+                    set_fullscreen(fullscreen);
+                }
+                Action::TogglePause => {
+                    paused = !paused;
+                    eprintln!("{}", if paused { "Paused" } else { "Resumed" });
+                }
+                Action::Quit => shutdown::request(),
+            }
+        }
+        if shutdown::requested() || is_quit_requested() {
+            break;
+        }
+
+        // Switched between frames, redoing the latest window at the new size so the next
+        // frame never draws a spectrum the bars weren't grouped for
+        if let Some(new_size) = requested_fft_size {
+            let smallest = fft.hop_size().next_power_of_two();
+            let largest = samples.capacity() / BUFFER_WINDOWS;
+            let window_end = next_window_end - fft.hop_size();
+            let mut window = vec![0.0; new_size];
+            if !(smallest..=largest).contains(&new_size) {
+                eprintln!("FFT size can only be switched between {smallest} and {largest}");
+            } else if !samples.read_ending_at(window_end, &mut window) {
+                eprintln!("Not enough audio captured yet to switch FFT size");
+            } else {
+                fft = FourierTransform::new(new_size).with_hop_size(fft.hop_size());
+                if let Some(analysis) = &mut latest_analysis {
+                    analysis.spectrum = fft.compute(&window);
+                    analysis.samples.clone_from(&window);
+                    analysis.channel_spectra = None;
+                }
+                samples_to_use = window;
+                channel_samples = [vec![0.0; new_size], vec![0.0; new_size]];
+                visualiser.set_fft_size(new_size);
+                graph.set_fft_size(new_size);
+                fft_size = new_size;
+                eprintln!("Switched FFT size to {fft_size}");
+            }
+        }
         last_frame_time = current_time;
 
         if frame_time < target_frame_duration {
@@ -126,14 +676,634 @@ async fn run_bar_visualiser(samples: Arc<Mutex<VecDeque<f32>>>) {
 
         next_frame().await
     }
+
+    // Everything being written is finished off, so nothing's cut short when the process exits
+    eprintln!("Shutting down");
+    sinks.finish();
+    // An unfinished recording wouldn't play, so it's thrown away
+    capture.cancel();
+    if let Err(e) = graph.flush() {
+        eprintln!("Failed to write processing graph output: {e}");
+    }
+    if let Err(e) = session_log.finish_track() {
+        eprintln!("Failed to write session log: {e}");
+    }
+    Ok(())
+}
+
+/// Writes the session's transcription so far to a timestamped MIDI file
+#[cfg(feature = "midi")]
+fn export_session_midi(session: &SessionRecorder) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = PathBuf::from(format!("session-{timestamp}.mid"));
+
+    match session.export_midi(&path) {
+        Ok(()) if session.dropped() > 0 => eprintln!(
+            "Saved transcription to {}, missing the oldest {} notes dropped to keep to the \
+             memory budget",
+            path.display(),
+            session.dropped()
+        ),
+        Ok(()) => eprintln!("Saved transcription to {}", path.display()),
+        Err(e) => eprintln!("Failed to save transcription: {e}"),
+    }
+}
+
+/// Renders the spectrogram of a WAV file next to it as a PNG with the same name
+#[cfg(feature = "export")]
+fn export_spectrogram(path: &Path, cli: &Cli, config: &Config) -> Result<PathBuf, ExportError> {
+    // The command line parser only accepts valid names
+    let kind = SpectrogramKind::from_name(&cli.spectrogram_kind).unwrap_or(SpectrogramKind::Fft);
+    let colour_map = ColourMap::from_name(&cli.colour_map).unwrap_or_else(ColourMap::heat);
+
+    let (samples, sample_rate) = wav::read_mono(path)?;
+    let output = path.with_extension("png");
+    export::spectrogram_png(
+        &samples,
+        sample_rate,
+        kind,
+        config.fft_size,
+        config.hop_size(),
+        &colour_map,
+        &output,
+    )?;
+    Ok(output)
+}
+
+/// Plots the chromagram, loudness and novelty of a WAV file next to it, as an image named
+/// like `song.features.png`
+#[cfg(feature = "export")]
+fn export_feature_plot(path: &Path, cli: &Cli, config: &Config) -> Result<PathBuf, ExportError> {
+    // The command line parser only accepts valid names
+    let format = PlotFormat::from_name(&cli.plot_format).unwrap_or(PlotFormat::Png);
+    let colour_map = ColourMap::from_name(&cli.colour_map).unwrap_or_else(ColourMap::heat);
+
+    let (samples, sample_rate) = wav::read_mono(path)?;
+    let output = path.with_extension(format!("features.{}", format.extension()));
+    export::feature_plot(
+        &samples,
+        sample_rate,
+        config.fft_size,
+        config.hop_size(),
+        &colour_map,
+        format,
+        &output,
+    )?;
+    Ok(output)
 }
 
-#[macroquad::main("Audio Visualiser")]
-async fn main() {
-    let shared_buffer: Arc<Mutex<VecDeque<f32>>> =
-        Arc::new(Mutex::new(VecDeque::with_capacity(FFT_SIZE)));
+/// Runs a WAV file through the session transcription and writes the result next to it
+/// as a MIDI file with the same name
+#[cfg(feature = "midi")]
+fn transcribe_file(path: &Path, config: &Config) -> Result<PathBuf, hound::Error> {
+    let (mono, sample_rate) = wav::read_mono(path)?;
+    let fft_size = config.fft_size;
+    let hop_size = config.hop_size();
+    let fft = FourierTransform::new(fft_size).with_hop_size(hop_size);
+    let mut session = SessionRecorder::new(sample_rate);
 
-    spawn_audio_reader(shared_buffer.clone());
+    // Analyse the file in the same overlapping windows as live capture
+    for (hop, spectrum) in fft.compute_hops(&mono).enumerate() {
+        let end = fft_size + hop * hop_size;
+        session.update(&spectrum, end as f64 / sample_rate as f64);
+    }
+
+    let output = path.with_extension("mid");
+    session.export_midi(&output)?;
+
+    Ok(output)
+}
+
+async fn run_dual_deck_visualiser(
+    samples_a: Arc<RingBuffer>,
+    samples_b: Arc<RingBuffer>,
+    config: &Config,
+) -> Result<(), VisualiserError> {
+    let typography = config.text.typography()?;
+    let mut visualiser =
+        DualDeckVisualiser::new(config.sample_rate, config.fft_size, config.frame_rate)
+            .with_typography(typography);
+    let fft = FourierTransform::new(config.fft_size);
+    let mut output = OutputStage::new(config.output)?;
+    let mut window_a = vec![0.0; config.fft_size];
+    let mut window_b = vec![0.0; config.fft_size];
+
+    prevent_quit();
+    if config.kiosk {
+        show_mouse(false);
+    }
 
-    run_bar_visualiser(shared_buffer.clone()).await;
+    loop {
+        if config.kiosk && (kiosk::exit_combo_pressed() || shutdown::requested()) {
+            kiosk::request_exit();
+            return Ok(());
+        }
+        if !config.kiosk && (shutdown::requested() || is_quit_requested()) {
+            return Ok(());
+        }
+
+        output.begin();
+        clear_background(Color {
+            r: 0.1,
+            g: 0.1,
+            b: 0.1,
+            a: 1.0,
+        });
+
+        if !samples_a.read_latest(&mut window_a) || !samples_b.read_latest(&mut window_b) {
+            output.finish();
+            next_frame().await;
+            continue;
+        }
+
+        visualiser.draw(&fft.compute(&window_a), &fft.compute(&window_b));
+        output.finish();
+
+        next_frame().await
+    }
+}
+
+/// Analyses every WAV file under `dir`, printing each result as it comes in, and saves them
+/// all to `output` as JSON
+#[cfg(feature = "scan")]
+fn scan_library(
+    dir: &Path,
+    output: &Path,
+    jobs: Option<usize>,
+    config: &Config,
+) -> std::io::Result<()> {
+    let files = scan::find_audio_files(dir)?;
+    let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    println!("Analysing {} files on {jobs} threads", files.len());
+
+    let done = AtomicUsize::new(0);
+    let results = scan::scan(&files, config.fft_size, config.hop_size(), jobs, |file| {
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = file.path.display();
+        match &file.stats {
+            Ok(stats) => println!(
+                "[{done}/{}] {path}: {} BPM, {}, {:.1}dBFS",
+                files.len(),
+                stats.bpm.map_or("?".to_string(), |bpm| format!("{bpm:.0}")),
+                stats.key.map_or("no key".to_string(), |key| key.name()),
+                stats.mean_loudness,
+            ),
+            Err(e) => eprintln!("[{done}/{}] Failed to analyse {path}: {e}", files.len()),
+        }
+    });
+
+    scan::write_json(output, &results)?;
+    println!("Saved library analysis to {}", output.display());
+    Ok(())
+}
+
+/// Adds the CSV session log at `import` to the history in `database`, if there is one, then
+/// prints the tracks matching `query` and their totals
+#[cfg(feature = "history")]
+fn show_history(
+    database: &Path,
+    import: Option<&Path>,
+    query: &HistoryQuery,
+) -> Result<(), HistoryError> {
+    let mut history = History::open(database)?;
+    if let Some(log) = import {
+        let added = history.import(&history::load(log)?)?;
+        println!("Added {added} tracks from {}", log.display());
+    }
+
+    let matching = history.query(query)?;
+    for entry in &matching {
+        let album = if entry.album.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", entry.album)
+        };
+        println!(
+            "{} - {}{album}: {} BPM, {}, {:.1}dBFS",
+            entry.artist,
+            entry.title,
+            entry.bpm.map_or("?".to_string(), |bpm| format!("{bpm:.0}")),
+            entry.key.as_deref().unwrap_or("no key"),
+            entry.mean_loudness,
+        );
+    }
+
+    let summary = HistorySummary::new(&matching);
+    println!(
+        "{} tracks, {:.0} minutes, mean {} BPM, mostly {}",
+        summary.tracks,
+        summary.duration / 60.0,
+        summary
+            .mean_bpm
+            .map_or("?".to_string(), |bpm| format!("{bpm:.0}")),
+        summary.most_common_key.as_deref().unwrap_or("no key"),
+    );
+    Ok(())
+}
+
+/// Resizable window rendering at the display's full resolution on high-DPI screens, or
+/// fullscreen in kiosk mode
+fn window_conf(window: &WindowConfig, kiosk: bool) -> Conf {
+    Conf {
+        window_title: "Audio Visualiser".to_string(),
+        window_width: window.width,
+        window_height: window.height,
+        high_dpi: true,
+        window_resizable: true,
+        fullscreen: kiosk,
+        ..Default::default()
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    shutdown::install_signal_handlers();
+
+    if cli.list_backends {
+        for backend in BACKENDS {
+            let status = match backend.probe() {
+                Ok(()) => "available".to_string(),
+                Err(e) => e.to_string(),
+            };
+            println!("{}: {} ({status})", backend.name, backend.description);
+        }
+        return;
+    }
+
+    if cli.list_modes {
+        for mode in Mode::all() {
+            println!("{}: {}", mode.name, mode.description);
+        }
+        return;
+    }
+
+    if cli.install_autostart {
+        let args: Vec<String> = std::env::args()
+            .skip(1)
+            .filter(|arg| arg != "--install-autostart")
+            .collect();
+        match schedule::install_autostart(&args) {
+            Ok(path) => println!("Added autostart entry {}", path.display()),
+            Err(e) => eprintln!("Failed to add autostart entry: {e}"),
+        }
+        return;
+    }
+
+    let mut config = match cli.config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid settings: {e}");
+            return;
+        }
+    };
+
+    if let Some(Task::Scan { dir, output, jobs }) = &cli.task {
+        #[cfg(feature = "scan")]
+        if let Err(e) = scan_library(dir, output, *jobs, &config) {
+            eprintln!("Failed to scan {}: {e}", dir.display());
+        }
+        #[cfg(not(feature = "scan"))]
+        {
+            let _ = (output, jobs);
+            eprintln!("Built without scan support, can't scan {}", dir.display());
+        }
+        return;
+    }
+
+    #[cfg(feature = "history")]
+    if let Some(Task::History {
+        database,
+        import,
+        artist,
+        key,
+        min_bpm,
+        max_bpm,
+        days,
+    }) = &cli.task
+    {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut query = HistoryQuery {
+            artist: artist.clone(),
+            key: key.clone(),
+            min_bpm: *min_bpm,
+            max_bpm: *max_bpm,
+            since: None,
+        };
+        if let Some(days) = days {
+            query = query.with_days(*days, now);
+        }
+        if let Err(e) = show_history(database, import.as_deref(), &query) {
+            eprintln!("Failed to read history from {}: {e}", database.display());
+        }
+        return;
+    }
+    #[cfg(not(feature = "history"))]
+    if let Some(Task::History { .. }) = &cli.task {
+        eprintln!("Built without history support");
+        return;
+    }
+
+    if cli.list_devices {
+        let backend = Backend::select(config.backend.as_deref());
+        match backend.devices() {
+            Ok(devices) => {
+                for (index, device) in devices.iter().enumerate() {
+                    let kind = if device.is_monitor {
+                        "monitor"
+                    } else {
+                        "input"
+                    };
+                    println!("{index}: {} ({kind}) {}", device.description, device.name);
+                }
+            }
+            Err(e) => eprintln!("Failed to list audio devices: {e}"),
+        }
+        return;
+    }
+
+    if config.kiosk && !kiosk::is_supervised() {
+        if let Err(e) = kiosk::supervise(Path::new(KIOSK_LOG_PATH)) {
+            eprintln!("Kiosk supervisor failed: {e}");
+        }
+        return;
+    }
+
+    let window = window_conf(&config.window, config.kiosk);
+
+    if let Some(path) = &cli.spectrogram {
+        #[cfg(feature = "export")]
+        match export_spectrogram(path, &cli, &config) {
+            Ok(output) => println!("Saved spectrogram to {}", output.display()),
+            Err(e) => eprintln!("Failed to render spectrogram of {}: {e}", path.display()),
+        }
+        #[cfg(not(feature = "export"))]
+        eprintln!(
+            "Built without export support, can't render the spectrogram of {}",
+            path.display()
+        );
+        return;
+    }
+
+    if let Some(path) = &cli.plot {
+        #[cfg(feature = "export")]
+        match export_feature_plot(path, &cli, &config) {
+            Ok(output) => println!("Saved feature plot to {}", output.display()),
+            Err(e) => eprintln!("Failed to plot {}: {e}", path.display()),
+        }
+        #[cfg(not(feature = "export"))]
+        eprintln!(
+            "Built without export support, can't plot {}",
+            path.display()
+        );
+        return;
+    }
+
+    let timeline = match &cli.timeline {
+        Some(path) => match Timeline::load(path) {
+            Ok(timeline) => Some(timeline),
+            Err(e) => {
+                eprintln!("Failed to load timeline {}: {e}", path.display());
+                return;
+            }
+        },
+        None => None,
+    };
+
+    if let Some(path) = cli.render.clone() {
+        #[cfg(feature = "export")]
+        macroquad::Window::from_config(window, async move {
+            let customise = |mut builder: VisualiserBuilder| {
+                if let Some(script) = cli.script {
+                    builder = builder.with_mode(DisplayMode::Script(script));
+                }
+                if let Some(timeline) = timeline {
+                    builder = builder.with_timeline(timeline);
+                }
+                builder
+            };
+            let fps = config.frame_rate;
+            match video::render(&path, cli.render_output, fps, &config, customise).await {
+                Ok(output) => println!("Saved video to {}", output.display()),
+                Err(e) => eprintln!("Failed to render {}: {e}", path.display()),
+            }
+        });
+        #[cfg(not(feature = "export"))]
+        eprintln!(
+            "Built without export support, can't render {}",
+            path.display()
+        );
+        return;
+    }
+
+    if let Some(path) = &cli.transcribe {
+        #[cfg(feature = "midi")]
+        match transcribe_file(path, &config) {
+            Ok(output) => println!("Saved transcription to {}", output.display()),
+            Err(e) => eprintln!("Failed to transcribe {}: {e}", path.display()),
+        }
+        #[cfg(not(feature = "midi"))]
+        eprintln!(
+            "Built without MIDI support, can't transcribe {}",
+            path.display()
+        );
+        return;
+    }
+
+    let backend = Backend::select(config.backend.as_deref());
+
+    // The command line parser only accepts valid formats
+    if let Some(format) = cli.headless.as_deref().and_then(HeadlessFormat::from_name) {
+        let source = backend.source_name(config.device.as_deref(), config.input);
+        match_device_rate(&mut config, backend, &source);
+        run_headless(&config, backend, source, format);
+        return;
+    }
+
+    if let Some([source_a, source_b]) = cli.decks.as_deref() {
+        let source_a = backend.source_name(Some(source_a), config.input);
+        let source_b = backend.source_name(Some(source_b), config.input);
+        match_device_rate(&mut config, backend, &source_a);
+        macroquad::Window::from_config(window, async move {
+            let buffer_a = Arc::new(RingBuffer::new(config.fft_size * BUFFER_WINDOWS));
+            let buffer_b = Arc::new(RingBuffer::new(config.fft_size * BUFFER_WINDOWS));
+
+            let reader_a = spawn_audio_reader(buffer_a.clone(), None, backend, source_a, &config);
+            let reader_b = spawn_audio_reader(buffer_b.clone(), None, backend, source_b, &config);
+
+            if let Err(e) = run_dual_deck_visualiser(buffer_a, buffer_b, &config).await {
+                eprintln!("Failed to set up visualiser: {e}");
+            }
+            reader_a.stop();
+            reader_b.stop();
+        });
+        kiosk::finish();
+        return;
+    }
+
+    let source = backend.source_name(config.device.as_deref(), config.input);
+    match_device_rate(&mut config, backend, &source);
+    macroquad::Window::from_config(
+        window,
+        run(config, backend, source, move |mut builder| {
+            if let Some(script) = cli.script {
+                builder = builder.with_mode(DisplayMode::Script(script));
+            }
+            if let Some(timeline) = timeline {
+                builder = builder.with_timeline(timeline);
+            }
+            builder
+        }),
+    );
+    kiosk::finish();
+}
+
+/// Captures from `source` with `backend` and writes the analysis of every window to stdout
+/// in `format`, until stdout is closed or a shutdown is requested
+fn run_headless(
+    config: &Config,
+    backend: &'static Backend,
+    source: String,
+    format: HeadlessFormat,
+) {
+    let Config {
+        sample_rate,
+        fft_size,
+        ..
+    } = *config;
+    let buffer_len = fft_size * BUFFER_WINDOWS;
+    let samples = Arc::new(RingBuffer::new(buffer_len));
+    let channels = (config.downmix == Downmix::Spectral)
+        .then(|| [(); 2].map(|_| Arc::new(RingBuffer::new(buffer_len))));
+    let reader = spawn_audio_reader(samples.clone(), channels.clone(), backend, source, config);
+
+    let fft = FourierTransform::new(fft_size).with_hop_size(config.hop_size());
+    let mut analyser = Analyser::new(sample_rate, sample_rate / fft.hop_size());
+    let mut averaging =
+        (config.spectral_averaging > 1).then(|| SpectralAverage::new(config.spectral_averaging));
+    let mut headless = Headless::new(config, format);
+    let mut window = vec![0.0; fft_size];
+    let mut channel_windows = [vec![0.0; fft_size], vec![0.0; fft_size]];
+    let mut next_window_end = fft_size;
+    // Half a hop, so a new window is picked up soon after it's captured
+    let poll_interval = Duration::from_secs_f64(fft.hop_size() as f64 / sample_rate as f64 / 2.0);
+    let start = Instant::now();
+    let mut stdout = io::stdout().lock();
+
+    while !shutdown::requested() {
+        let written = samples.written();
+        let oldest_end = (written + fft_size).saturating_sub(samples.capacity());
+        if next_window_end < oldest_end {
+            next_window_end = written.max(fft_size);
+        }
+        if next_window_end > written {
+            thread::sleep(poll_interval);
+            continue;
+        }
+
+        let window_end = next_window_end;
+        next_window_end += fft.hop_size();
+        if !samples.read_ending_at(window_end, &mut window) {
+            continue;
+        }
+
+        let time = window_time(&samples, window_end, sample_rate, start).unwrap_or_else(|| {
+            start.elapsed().as_secs_f64() - (written - window_end) as f64 / sample_rate as f64
+        });
+        let [left_window, right_window] = &mut channel_windows;
+        let spectrum = match &channels {
+            Some([left, right])
+                if left.read_ending_at(window_end, left_window)
+                    && right.read_ending_at(window_end, right_window) =>
+            {
+                Downmix::combine_spectra(&fft.compute(left_window), &fft.compute(right_window))
+            }
+            _ => fft.compute(&window),
+        };
+        let mut analysis = analyser.analyse(&window, spectrum, time);
+        if let Some(averaging) = &mut averaging {
+            analysis.spectrum = averaging.update(&analysis.spectrum);
+        }
+        match headless.write(&mut stdout, &analysis) {
+            Ok(()) => (),
+            // Whatever was reading has exited
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => break,
+            Err(e) => {
+                eprintln!("Failed to write analysis: {e}");
+                break;
+            }
+        }
+    }
+
+    reader.stop();
+}
+
+/// Analyses at the rate `source` runs at, if the config asks to and it can be found
+fn match_device_rate(config: &mut Config, backend: &Backend, source: &str) {
+    if !config.match_device_rate {
+        return;
+    }
+    if let Some(rate) = backend.native_rate(source)
+        && rate != config.sample_rate
+    {
+        eprintln!("Analysing at {rate} Hz to match the audio source");
+        config.sample_rate = rate;
+    }
+}
+
+/// Captures from `source` with `backend` and runs the visualiser set up from `config`, with
+/// `customise` applying any further settings to the builder
+async fn run(
+    config: Config,
+    backend: &'static Backend,
+    source: String,
+    customise: impl FnOnce(VisualiserBuilder) -> VisualiserBuilder,
+) {
+    // Built here rather than in main as loading fonts needs the window to exist
+    let builder = match VisualiserBuilder::from_config(&config) {
+        Ok(builder) => customise(builder),
+        Err(e) => {
+            eprintln!("Failed to set up visualiser: {e}");
+            return;
+        }
+    };
+
+    let graph = match Graph::new(&config.nodes, config.sample_rate, config.fft_size) {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("Failed to set up processing graph: {e}");
+            return;
+        }
+    };
+
+    // Big enough for the largest FFT size that can be switched to
+    let buffer_len = config.fft_size.max(MAX_FFT_SIZE) * BUFFER_WINDOWS;
+    let shared_buffer = Arc::new(RingBuffer::new(buffer_len));
+
+    // Left and right are only kept when they're going to be used
+    let channels =
+        (config.stereo.is_some() || graph.needs_channels() || config.downmix == Downmix::Spectral)
+            .then(|| [(); 2].map(|_| Arc::new(RingBuffer::new(buffer_len))));
+
+    let reader = spawn_audio_reader(
+        shared_buffer.clone(),
+        channels.clone(),
+        backend,
+        source.clone(),
+        &config,
+    );
+
+    let capture = CaptureBuffers {
+        samples: shared_buffer.clone(),
+        channels,
+        latency: reader.latency.clone(),
+    };
+    if let Err(e) = run_bar_visualiser(capture, builder, graph, backend, source, &config).await {
+        eprintln!("Failed to set up visualiser: {e}");
+    }
+    reader.stop();
 }
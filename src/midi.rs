@@ -0,0 +1,105 @@
+use std::{fs::File, io::Write, path::Path};
+
+use crate::transcription::NoteEvent;
+
+const TICKS_PER_QUARTER: u16 = 480;
+// 120 BPM, in microseconds per quarter note
+const TEMPO: u32 = 500_000;
+
+/// A named set of notes written to its own track and MIDI channel
+pub struct MidiTrack<'a> {
+    pub name: &'a str,
+    pub channel: u8,
+    pub notes: &'a [NoteEvent],
+}
+
+/// Writes `tracks` to a Standard MIDI File at 120 BPM (format 1, or format 0 for one track)
+///
+/// Note times are in seconds, relative to the earliest onset across all tracks.
+/// Notes that are still sounding are ended at `end_time`
+pub fn write_midi_file(path: &Path, tracks: &[MidiTrack], end_time: f64) -> std::io::Result<()> {
+    let start_time = tracks
+        .iter()
+        .flat_map(|t| t.notes)
+        .map(|n| n.onset)
+        .fold(f64::MAX, f64::min);
+
+    let mut file = File::create(path)?;
+
+    let format: u16 = if tracks.len() > 1 { 1 } else { 0 };
+
+    file.write_all(b"MThd")?;
+    file.write_all(&6_u32.to_be_bytes())?;
+    file.write_all(&format.to_be_bytes())?;
+    file.write_all(&(tracks.len() as u16).to_be_bytes())?;
+    file.write_all(&TICKS_PER_QUARTER.to_be_bytes())?;
+
+    for (i, track) in tracks.iter().enumerate() {
+        // Tempo only belongs in the first track of a format 1 file
+        let data = encode_track(track, start_time, end_time, i == 0);
+
+        file.write_all(b"MTrk")?;
+        file.write_all(&(data.len() as u32).to_be_bytes())?;
+        file.write_all(&data)?;
+    }
+
+    Ok(())
+}
+
+fn encode_track(track: &MidiTrack, start_time: f64, end_time: f64, with_tempo: bool) -> Vec<u8> {
+    let ticks_per_second = TICKS_PER_QUARTER as f64 * 1_000_000.0 / TEMPO as f64;
+    let to_ticks = |time: f64| ((time - start_time).max(0.0) * ticks_per_second).round() as u32;
+
+    // (tick, is_note_on, pitch, velocity)
+    let mut messages: Vec<(u32, bool, u8, u8)> = Vec::with_capacity(track.notes.len() * 2);
+    for note in track.notes {
+        let onset = to_ticks(note.onset);
+        // Always give notes a length so the on/off pair isn't collapsed to nothing
+        let offset = to_ticks(note.offset.unwrap_or(end_time)).max(onset + 1);
+
+        messages.push((onset, true, note.pitch, note.velocity));
+        messages.push((offset, false, note.pitch, 0));
+    }
+    // Note-offs first on shared ticks, so repeated notes retrigger properly
+    messages.sort_by_key(|&(tick, is_on, _, _)| (tick, is_on));
+
+    let mut data: Vec<u8> = Vec::new();
+
+    // Track name meta event
+    data.extend([0x00, 0xFF, 0x03]);
+    write_variable_length(&mut data, track.name.len() as u32);
+    data.extend(track.name.as_bytes());
+
+    if with_tempo {
+        data.extend([0x00, 0xFF, 0x51, 0x03]);
+        data.extend(&TEMPO.to_be_bytes()[1..]);
+    }
+
+    let channel = track.channel & 0x0F;
+    let mut last_tick = 0;
+    for (tick, is_on, pitch, velocity) in messages {
+        write_variable_length(&mut data, tick - last_tick);
+        last_tick = tick;
+
+        let status = if is_on { 0x90 } else { 0x80 };
+        data.extend([status | channel, pitch & 0x7F, velocity & 0x7F]);
+    }
+
+    // End of track meta event
+    data.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+    data
+}
+
+/// Appends `value` as a MIDI variable-length quantity (7 bits per byte, most significant first)
+fn write_variable_length(buffer: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut remaining = value >> 7;
+
+    while remaining > 0 {
+        bytes.push((remaining & 0x7F) as u8 | 0x80);
+        remaining >>= 7;
+    }
+
+    buffer.extend(bytes.iter().rev());
+}
@@ -0,0 +1,207 @@
+//! Live MIDI output of the notes heard and the beat, for driving synths or lighting
+//! controllers, as a sink
+//!
+//! ```toml
+//! [[sink]]
+//! output = { kind = "midi", port = "Midi Through", channel = 1, clock = true, beat_note = 36 }
+//! ```
+//!
+//! Notes are transcribed as for MIDI files, and a note on is sent when each starts sounding
+//! and a note off when it stops. With `clock`, MIDI clock follows the tempo at 24 pulses a
+//! beat once it's known. With `beat_note`, that note is played on every beat, louder for
+//! clearer beats. `port` picks the first output port whose name contains it, or without it
+//! a virtual port called "rust-audio-visualiser" is opened for others to connect to
+
+use midir::{MidiOutput, MidiOutputConnection, os::unix::VirtualOutput};
+use serde::Deserialize;
+
+use crate::{
+    analysis::{Feature, Features},
+    sinks::{Sink, SinkError, SinkFrame},
+    transcription::NoteTracker,
+};
+
+const CLIENT_NAME: &str = "rust-audio-visualiser";
+const NOTE_ON: u8 = 0x90;
+const NOTE_OFF: u8 = 0x80;
+const CLOCK: u8 = 0xf8;
+const START: u8 = 0xfa;
+const STOP: u8 = 0xfc;
+const PULSES_PER_BEAT: f64 = 24.0;
+
+/// Settings of the `midi` sink output, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MidiOutputConfig {
+    /// Part of the name of the port to send to, or a virtual port if left out
+    pub port: Option<String>,
+    /// Channel from 1 to 16
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+    /// Send the notes heard
+    #[serde(default = "default_notes")]
+    pub notes: bool,
+    /// Send MIDI clock at the detected tempo
+    #[serde(default)]
+    pub clock: bool,
+    /// Note played on every beat
+    pub beat_note: Option<u8>,
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+fn default_notes() -> bool {
+    true
+}
+
+/// The `midi` sink output
+pub struct MidiSink {
+    connection: MidiOutputConnection,
+    config: MidiOutputConfig,
+    // Status byte nibble for the configured channel
+    channel: u8,
+    notes: Option<NoteTracker>,
+    sounding: [bool; 128],
+    beat_sounding: bool,
+    // Clock pulses owed, carried over between frames so none are lost to rounding
+    pulses: f64,
+    clock_running: bool,
+    last_time: Option<f64>,
+}
+
+impl MidiSink {
+    /// Opens the port `config` asks for, transcribing at `sampling_rate`
+    pub fn open(config: MidiOutputConfig, sampling_rate: usize) -> Result<Self, SinkError> {
+        let output = MidiOutput::new(CLIENT_NAME).map_err(|e| SinkError::Midi(e.to_string()))?;
+        let connection = match &config.port {
+            Some(name) => {
+                let port = output
+                    .ports()
+                    .into_iter()
+                    .find(|port| output.port_name(port).is_ok_and(|port| port.contains(name)))
+                    .ok_or_else(|| {
+                        SinkError::Midi(format!("no MIDI output port matches `{name}`"))
+                    })?;
+                output.connect(&port, CLIENT_NAME)
+            }
+            None => output.create_virtual(CLIENT_NAME),
+        }
+        .map_err(|e| SinkError::Midi(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            channel: config.channel.clamp(1, 16) - 1,
+            notes: config.notes.then(|| NoteTracker::new(sampling_rate)),
+            config,
+            sounding: [false; 128],
+            beat_sounding: false,
+            pulses: 0.0,
+            clock_running: false,
+            last_time: None,
+        })
+    }
+
+    fn send_message(&mut self, message: &[u8]) -> Result<(), SinkError> {
+        self.connection
+            .send(message)
+            .map_err(|e| SinkError::Midi(e.to_string()))
+    }
+
+    fn send_note(&mut self, status: u8, pitch: u8, velocity: u8) -> Result<(), SinkError> {
+        self.send_message(&[status | self.channel, pitch & 0x7f, velocity & 0x7f])
+    }
+
+    // Sends note ons and offs for the notes that started or stopped since the last frame
+    fn update_notes(&mut self, spectrum: &[f32], time: f64) -> Result<(), SinkError> {
+        let Some(notes) = &mut self.notes else {
+            return Ok(());
+        };
+        notes.update(spectrum, time);
+
+        let mut now_sounding = [None; 128];
+        for note in notes
+            .events_since(time)
+            .filter(|note| note.offset.is_none())
+        {
+            now_sounding[note.pitch as usize] = Some(note.velocity);
+        }
+        for (pitch, velocity) in now_sounding.into_iter().enumerate() {
+            match (self.sounding[pitch], velocity) {
+                (false, Some(velocity)) => self.send_note(NOTE_ON, pitch as u8, velocity)?,
+                (true, None) => self.send_note(NOTE_OFF, pitch as u8, 0)?,
+                _ => (),
+            }
+            self.sounding[pitch] = velocity.is_some();
+        }
+        Ok(())
+    }
+
+    // Sends the clock pulses due over the `elapsed` seconds since the last frame
+    fn update_clock(&mut self, bpm: Option<f32>, elapsed: f64) -> Result<(), SinkError> {
+        let Some(bpm) = bpm.filter(|_| self.config.clock) else {
+            return Ok(());
+        };
+        if !self.clock_running {
+            self.send_message(&[START])?;
+            self.clock_running = true;
+        }
+
+        self.pulses += elapsed * bpm as f64 / 60.0 * PULSES_PER_BEAT;
+        while self.pulses >= 1.0 {
+            self.send_message(&[CLOCK])?;
+            self.pulses -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for MidiSink {
+    fn features(&self) -> Features {
+        Feature::Beats.into()
+    }
+
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        let analysis = frame.analysis;
+        let elapsed = self
+            .last_time
+            .map_or(0.0, |last| (analysis.time - last).max(0.0));
+        self.last_time = Some(analysis.time);
+
+        self.update_notes(&analysis.spectrum, analysis.time)?;
+        self.update_clock(analysis.bpm, elapsed)?;
+
+        if let Some(note) = self.config.beat_note {
+            // Each beat's note lasts until the next frame
+            if self.beat_sounding {
+                self.send_note(NOTE_OFF, note, 0)?;
+                self.beat_sounding = false;
+            }
+            if let Some(beat) = frame.beat {
+                let velocity = (beat.confidence * 127.0).clamp(1.0, 127.0) as u8;
+                self.send_note(NOTE_ON, note, velocity)?;
+                self.beat_sounding = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        let sounding = self.sounding;
+        for (pitch, _) in sounding
+            .iter()
+            .enumerate()
+            .filter(|(_, sounding)| **sounding)
+        {
+            self.send_note(NOTE_OFF, pitch as u8, 0)?;
+        }
+        if let (Some(note), true) = (self.config.beat_note, self.beat_sounding) {
+            self.send_note(NOTE_OFF, note, 0)?;
+        }
+        if self.clock_running {
+            self.send_message(&[STOP])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! The built-in visual modes, each registered once with its name, what it draws from and how
+//! it's drawn
+//!
+//! The command line's `--mode`, the config file, presets, scenes, timelines and the mode
+//! switcher all look modes up by name here, so a new mode only needs a draw method and an
+//! entry in `MODES`. Programs using the crate can add modes of their own at runtime with
+//! `register`, which are then found the same way
+
+use std::sync::RwLock;
+
+use crate::{
+    analysis::{Feature, Features, FrameAnalysis},
+    visualiser::Visualiser,
+};
+
+// Modes added with `register`, after the built-in ones
+static REGISTERED: RwLock<Vec<&'static Mode>> = RwLock::new(Vec::new());
+
+/// Parts of a frame's analysis that only some modes draw from
+#[derive(Clone, Copy, PartialEq)]
+pub enum ModeInput {
+    /// The power spectrum of the mono mix
+    Spectrum,
+    /// Separate spectra of the left and right channels, when showing stereo
+    ChannelSpectra,
+    /// The samples the spectrum was computed from
+    Samples,
+}
+
+/// A mode, built in or registered
+pub struct Mode {
+    /// Name used for the mode in the config file and on the command line
+    pub name: &'static str,
+    pub description: &'static str,
+    /// What the mode draws from, so the rest can be skipped while it's showing
+    pub needs: &'static [ModeInput],
+    /// Parts of the analysis the mode draws from, so the rest needn't be computed
+    pub features: Features,
+    /// Draws one frame of the mode
+    pub draw: fn(&mut Visualiser, &FrameAnalysis),
+}
+
+/// Every built-in mode, in the order the mode switcher cycles through them
+pub const MODES: &[Mode] = &[
+    Mode {
+        name: "bars",
+        description: "Bars for the grouped frequencies, or each channel's with stereo",
+        needs: &[ModeInput::Spectrum, ModeInput::ChannelSpectra],
+        features: Features::NONE,
+        draw: Visualiser::draw_fft,
+    },
+    Mode {
+        name: "midi-pitches",
+        description: "The loudest MIDI pitches and their notes",
+        needs: &[ModeInput::Spectrum],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_midi_pitches(&analysis.spectrum),
+    },
+    Mode {
+        name: "chromagram",
+        description: "A bar for each of the 12 pitch classes",
+        needs: &[ModeInput::Spectrum],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_chromagram(&analysis.spectrum),
+    },
+    Mode {
+        name: "chroma-wheel",
+        description: "The chromagram round the circle of fifths, pointing to the key centre",
+        needs: &[ModeInput::Spectrum],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_chroma_wheel(&analysis.spectrum),
+    },
+    Mode {
+        name: "pitch-coach",
+        description: "The sung pitch over time on a piano roll of the scale, coloured by tuning",
+        needs: &[ModeInput::Spectrum],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_pitch_coach(&analysis.spectrum),
+    },
+    Mode {
+        name: "note-tracking",
+        description: "A scrolling piano roll of the notes heard",
+        needs: &[ModeInput::Spectrum],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_note_tracking(&analysis.spectrum),
+    },
+    Mode {
+        name: "spectrogram",
+        description: "A scrolling spectrogram",
+        needs: &[ModeInput::Spectrum],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_spectrogram(&analysis.spectrum),
+    },
+    Mode {
+        name: "waveform",
+        description: "The samples of each window as a line",
+        needs: &[ModeInput::Samples],
+        features: Features::NONE,
+        draw: |visualiser, analysis| visualiser.draw_waveform(&analysis.samples),
+    },
+    Mode {
+        name: "novelty",
+        description: "The spectral flux over time against the onset threshold, with onsets marked",
+        // The flux is taken from the spectrum
+        needs: &[ModeInput::Spectrum],
+        features: Features::of(&[Feature::Onsets, Feature::Beats]),
+        draw: Visualiser::draw_novelty,
+    },
+    Mode {
+        name: "cqt",
+        description: "One bar per semitone from a constant-Q transform",
+        needs: &[ModeInput::Samples],
+        features: Features::NONE,
+        draw: Visualiser::draw_cqt,
+    },
+];
+
+/// The mode the visualiser starts in and falls back to
+pub const BARS: &Mode = &MODES[0];
+
+/// Adds `mode` after the others, returning false if there's already a mode with its name
+pub fn register(mode: &'static Mode) -> bool {
+    let mut registered = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+    if MODES
+        .iter()
+        .chain(registered.iter().copied())
+        .any(|m| m.name == mode.name)
+    {
+        return false;
+    }
+    registered.push(mode);
+    true
+}
+
+impl Mode {
+    /// Every mode, built-in ones first and then any registered, in the order the mode
+    /// switcher cycles through them
+    pub fn all() -> Vec<&'static Mode> {
+        let registered = REGISTERED.read().unwrap_or_else(|e| e.into_inner());
+        MODES.iter().chain(registered.iter().copied()).collect()
+    }
+
+    /// The mode called `name`
+    pub fn find(name: &str) -> Option<&'static Mode> {
+        Self::all().into_iter().find(|mode| mode.name == name)
+    }
+
+    pub fn needs(&self, input: ModeInput) -> bool {
+        self.needs.contains(&input)
+    }
+}
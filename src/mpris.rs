@@ -0,0 +1,72 @@
+use std::{
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Metadata of the track an MPRIS media player reports as currently playing
+#[derive(Clone, PartialEq)]
+pub struct NowPlaying {
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    /// Name of the player, e.g. `spotify` or `firefox`
+    pub player: String,
+}
+
+/// Asks the active MPRIS player for its current track via `playerctl`
+///
+/// Returns `None` if playerctl isn't installed, no player is running, or nothing is playing
+pub fn now_playing() -> Option<NowPlaying> {
+    let output = Command::new("playerctl")
+        .args([
+            "metadata",
+            "--format",
+            "{{status}}\t{{playerName}}\t{{artist}}\t{{title}}\t{{album}}",
+        ])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut fields = text.trim_end_matches('\n').split('\t');
+
+    if fields.next()? != "Playing" {
+        return None;
+    }
+    let player = fields.next()?.to_string();
+
+    let now_playing = NowPlaying {
+        artist: fields.next()?.to_string(),
+        title: fields.next()?.to_string(),
+        album: fields.next().unwrap_or_default().to_string(),
+        player,
+    };
+
+    (!now_playing.title.is_empty()).then_some(now_playing)
+}
+
+/// Polls the media player on a background thread so the render loop never waits on it
+///
+/// The returned value always holds the most recent result of `now_playing()`
+pub fn spawn_metadata_watcher(poll_interval: Duration) -> Arc<Mutex<Option<NowPlaying>>> {
+    let current = Arc::new(Mutex::new(None));
+    let shared = current.clone();
+
+    thread::spawn(move || {
+        loop {
+            let latest = now_playing();
+            if let Ok(mut shared) = shared.lock() {
+                *shared = latest;
+            }
+
+            thread::sleep(poll_interval);
+        }
+    });
+
+    current
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+use macroquad::{
+    color::{Color, WHITE},
+    shapes::{draw_circle, draw_line},
+    window::{screen_height, screen_width},
+};
+
+use crate::{analysis::FrameAnalysis, ui::ui_scale};
+
+// Seconds of history shown across the screen
+const HISTORY_SECONDS: f64 = 6.0;
+// How fast the running peak the curve is scaled to falls back, per window
+const PEAK_DECAY: f32 = 0.999;
+// Fraction of the screen's height left above the running peak
+const HEADROOM: f32 = 0.1;
+const THRESHOLD_COLOUR: Color = Color {
+    r: 1.0,
+    g: 0.8,
+    b: 0.2,
+    a: 0.8,
+};
+const ONSET_COLOUR: Color = Color {
+    r: 1.0,
+    g: 0.3,
+    b: 0.3,
+    a: 1.0,
+};
+const BEAT_COLOUR: Color = Color {
+    r: 0.3,
+    g: 0.6,
+    b: 1.0,
+    a: 1.0,
+};
+
+// One analysed window's part of the curve
+struct Point {
+    time: f64,
+    flux: f32,
+    threshold: Option<f32>,
+    onset: bool,
+    beat: bool,
+}
+
+/// Scrolling plot of the spectral flux each window, newest on the right
+///
+/// The threshold onsets are detected against is drawn under the curve, with a line down from
+/// the curve at every onset and a tick along the top at every beat, so how far peaks clear
+/// the threshold shows how sensitive the detection is. Windows are placed by their analysis
+/// time, so the curve scrolls at the same speed whatever the frame rate
+pub struct NoveltyCurve {
+    points: VecDeque<Point>,
+    peak: f32,
+}
+
+impl Default for NoveltyCurve {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NoveltyCurve {
+    pub fn new() -> Self {
+        Self {
+            points: VecDeque::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Adds `analysis` as the newest point, unless it's already been added
+    pub fn push(&mut self, analysis: &FrameAnalysis) {
+        if let Some(latest) = self.points.back().map(|point| point.time) {
+            // A restarted analysis clock goes back, which starts the curve over
+            if analysis.time < latest - HISTORY_SECONDS {
+                self.points.clear();
+            } else if analysis.time <= latest {
+                return;
+            }
+        }
+
+        self.peak = (self.peak * PEAK_DECAY)
+            .max(analysis.flux)
+            .max(analysis.onset_threshold.unwrap_or(0.0));
+        self.points.push_back(Point {
+            time: analysis.time,
+            flux: analysis.flux,
+            threshold: analysis.onset_threshold,
+            onset: analysis.onset,
+            beat: analysis.beat.is_some(),
+        });
+        while self
+            .points
+            .front()
+            .is_some_and(|point| point.time < analysis.time - HISTORY_SECONDS)
+        {
+            self.points.pop_front();
+        }
+    }
+
+    /// Draws the curve over the whole screen
+    pub fn draw(&self) {
+        let Some(latest) = self.points.back() else {
+            return;
+        };
+        let (width, height) = (screen_width(), screen_height());
+        let start = latest.time - HISTORY_SECONDS;
+        let x = |point: &Point| ((point.time - start) / HISTORY_SECONDS) as f32 * width;
+        let scale = (1.0 - HEADROOM) * height / self.peak.max(f32::EPSILON);
+        let y = |flux: f32| height - flux * scale;
+        let thickness = 2.0 * ui_scale();
+
+        for (previous, point) in self.points.iter().zip(self.points.iter().skip(1)) {
+            if let (Some(from), Some(to)) = (previous.threshold, point.threshold) {
+                draw_line(
+                    x(previous),
+                    y(from),
+                    x(point),
+                    y(to),
+                    thickness / 2.0,
+                    THRESHOLD_COLOUR,
+                );
+            }
+            draw_line(
+                x(previous),
+                y(previous.flux),
+                x(point),
+                y(point.flux),
+                thickness,
+                WHITE,
+            );
+        }
+
+        for point in &self.points {
+            if point.onset {
+                draw_line(
+                    x(point),
+                    y(point.flux),
+                    x(point),
+                    height,
+                    thickness,
+                    ONSET_COLOUR,
+                );
+                draw_circle(x(point), y(point.flux), 2.0 * thickness, ONSET_COLOUR);
+            }
+            if point.beat {
+                draw_line(
+                    x(point),
+                    0.0,
+                    x(point),
+                    HEADROOM * height / 2.0,
+                    thickness,
+                    BEAT_COLOUR,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+
+/// Detects note onsets from successive FFT frames using half-wave rectified spectral flux
+///
+/// A frame is an onset when its flux exceeds the recent average by `sensitivity` standard
+/// deviations, is at least `min_ratio` times that average, and at least `min_gap` frames
+/// have passed since the previous onset
+pub struct OnsetDetector {
+    previous_spectrum: Vec<f32>,
+    flux_history: VecDeque<f32>,
+    history_len: usize,
+    sensitivity: f32,
+    min_ratio: f32,
+    min_gap: usize,
+    frames_since_onset: usize,
+    threshold: Option<f32>,
+}
+
+impl Default for OnsetDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OnsetDetector {
+    pub fn new() -> Self {
+        Self {
+            previous_spectrum: Vec::new(),
+            flux_history: VecDeque::new(),
+            history_len: 43, // ~0.7 seconds at 60fps
+            sensitivity: 2.0,
+            // Stops steady tones from triggering when their flux barely varies
+            min_ratio: 2.0,
+            min_gap: 6,
+            frames_since_onset: 0,
+            threshold: None,
+        }
+    }
+
+    /// Spectral flux between the previous frame and `spectrum`, on log-compressed magnitudes
+    pub fn flux(&self, spectrum: &[f32]) -> f32 {
+        if self.previous_spectrum.len() != spectrum.len() {
+            return 0.0;
+        }
+
+        spectrum
+            .iter()
+            .zip(&self.previous_spectrum)
+            .map(|(&current, &previous)| ((current + 1.0).ln() - (previous + 1.0).ln()).max(0.0))
+            .sum()
+    }
+
+    /// Flux the last frame had to exceed to be an onset, once there's a full history to
+    /// judge it against
+    pub fn threshold(&self) -> Option<f32> {
+        self.threshold
+    }
+
+    /// Feeds a new frame, returning the onset strength (flux over the adaptive threshold)
+    /// if this frame is an onset
+    pub fn update(&mut self, spectrum: &[f32]) -> Option<f32> {
+        let flux = self.flux(spectrum);
+        self.previous_spectrum.clear();
+        self.previous_spectrum.extend_from_slice(spectrum);
+        self.frames_since_onset += 1;
+
+        let count = self.flux_history.len() as f32;
+        let (mean, std_dev) = if count > 0.0 {
+            let mean = self.flux_history.iter().sum::<f32>() / count;
+            let variance = self
+                .flux_history
+                .iter()
+                .map(|f| (f - mean).powi(2))
+                .sum::<f32>()
+                / count;
+            (mean, variance.sqrt())
+        } else {
+            (0.0, 0.0)
+        };
+
+        if self.flux_history.len() == self.history_len {
+            self.flux_history.pop_front();
+        }
+        self.flux_history.push_back(flux);
+
+        // Wait for a full history so the threshold is meaningful
+        if self.flux_history.len() < self.history_len {
+            self.threshold = None;
+            return None;
+        }
+
+        let threshold = (mean + self.sensitivity * std_dev).max(mean * self.min_ratio);
+        self.threshold = Some(threshold);
+
+        if flux > threshold && self.frames_since_onset >= self.min_gap {
+            self.frames_since_onset = 0;
+            Some(flux - threshold)
+        } else {
+            None
+        }
+    }
+}
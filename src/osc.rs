@@ -0,0 +1,116 @@
+//! Open Sound Control output, for driving TouchDesigner, SuperCollider or VJ software, as a
+//! sink
+//!
+//! ```toml
+//! [[sink]]
+//! rate = 30
+//! grouping = { bars = 16 }
+//! output = { kind = "osc", target = "127.0.0.1:9000", addresses = { beat = "/kick" } }
+//! ```
+//!
+//! Each frame is sent to `target` over UDP as separate messages, all with float arguments:
+//!
+//! - `bars`: the levels from 0.0 to 1.0, one argument each
+//! - `chromagram`: the 12 pitch classes from C, each from 0.0 to 1.0
+//! - `level`: the RMS level of the window, from 0.0 to 1.0
+//! - `beat`: the beat's confidence, only sent in frames with a beat
+//!
+//! Addresses default to `/visualiser/bars` and so on, and any set to "" isn't sent
+
+use std::{io, net::UdpSocket};
+
+use serde::Deserialize;
+
+use crate::{
+    analysis::{Feature, Features},
+    sinks::{Sink, SinkError, SinkFrame},
+};
+
+/// The OSC address of each message, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OscAddresses {
+    pub bars: String,
+    pub chromagram: String,
+    pub level: String,
+    pub beat: String,
+}
+
+impl Default for OscAddresses {
+    fn default() -> Self {
+        Self {
+            bars: "/visualiser/bars".to_string(),
+            chromagram: "/visualiser/chromagram".to_string(),
+            level: "/visualiser/level".to_string(),
+            beat: "/visualiser/beat".to_string(),
+        }
+    }
+}
+
+/// The `osc` sink output, sending each frame as in the module docs
+pub struct OscSink {
+    socket: UdpSocket,
+    addresses: OscAddresses,
+}
+
+impl OscSink {
+    /// Sends to `target`, like "127.0.0.1:9000"
+    pub fn connect(target: &str, addresses: OscAddresses) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(target)?;
+        Ok(Self { socket, addresses })
+    }
+
+    fn send_message(&self, address: &str, arguments: &[f32]) -> io::Result<()> {
+        if address.is_empty() {
+            return Ok(());
+        }
+
+        match self.socket.send(&message(address, arguments)) {
+            Ok(_) => Ok(()),
+            // Nothing listening yet, which is fine for a fire-and-forget protocol
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// An OSC message to `address` with float `arguments`, as sent for each part of a frame
+pub fn message(address: &str, arguments: &[f32]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(address.len() + arguments.len() * 5 + 8);
+    push_padded(&mut packet, address.as_bytes());
+    let mut tags = vec![b','];
+    tags.resize(arguments.len() + 1, b'f');
+    push_padded(&mut packet, &tags);
+    for argument in arguments {
+        packet.extend_from_slice(&argument.to_be_bytes());
+    }
+    packet
+}
+
+// Appends an OSC string: null terminated, then padded with nulls to a multiple of 4 bytes
+fn push_padded(packet: &mut Vec<u8>, bytes: &[u8]) {
+    packet.extend_from_slice(bytes);
+    packet.resize((packet.len() + 4) & !3, 0);
+}
+
+impl Sink for OscSink {
+    fn features(&self) -> Features {
+        Features::of(&[Feature::Chromagram, Feature::Beats])
+    }
+
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        let analysis = frame.analysis;
+        let max_chroma = analysis.chromagram.iter().cloned().fold(1e-6, f32::max);
+        let chromagram = analysis.chromagram.map(|chroma| chroma / max_chroma);
+        let rms = 10f32.powf(analysis.loudness / 20.0);
+
+        self.send_message(&self.addresses.bars, frame.levels)?;
+        self.send_message(&self.addresses.chromagram, &chromagram)?;
+        self.send_message(&self.addresses.level, &[rms])?;
+        if let Some(beat) = frame.beat {
+            self.send_message(&self.addresses.beat, &[beat.confidence])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use macroquad::{
+    camera::{Camera2D, set_camera, set_default_camera},
+    color::{Color, WHITE},
+    material::{Material, MaterialParams, gl_use_default_material, gl_use_material, load_material},
+    math::{Rect, vec2},
+    miniquad::{ShaderSource, UniformDesc, UniformType},
+    texture::{DrawTextureParams, RenderTarget, draw_texture_ex, render_target},
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+
+/// Colour corrections applied to the whole output, for matching projectors and LED fixtures
+/// whose response differs from a monitor's
+///
+/// Applied in order: contrast (around mid grey), brightness, saturation, then gamma
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputAdjustments {
+    pub gamma: f32,
+    /// Added to each channel, -1.0 to 1.0
+    pub brightness: f32,
+    pub contrast: f32,
+    /// 0.0 is greyscale, 1.0 unchanged
+    pub saturation: f32,
+}
+
+impl Default for OutputAdjustments {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+impl OutputAdjustments {
+    pub fn is_identity(&self) -> bool {
+        self.gamma == 1.0
+            && self.brightness == 0.0
+            && self.contrast == 1.0
+            && self.saturation == 1.0
+    }
+
+    /// Applies the adjustments to a single colour, matching the output shader, for outputs
+    /// that send colours somewhere other than the screen
+    pub fn apply(&self, colour: Color) -> Color {
+        let adjusted = [colour.r, colour.g, colour.b]
+            .map(|c| ((c - 0.5) * self.contrast + 0.5 + self.brightness).clamp(0.0, 1.0));
+
+        let luma = 0.2126 * adjusted[0] + 0.7152 * adjusted[1] + 0.0722 * adjusted[2];
+        let [r, g, b] = adjusted.map(|c| {
+            let saturated = (luma + (c - luma) * self.saturation).clamp(0.0, 1.0);
+            saturated.powf(1.0 / self.gamma.max(f32::EPSILON))
+        });
+
+        Color {
+            r,
+            g,
+            b,
+            a: colour.a,
+        }
+    }
+}
+
+/// Renders each frame offscreen and draws it to the screen through a shader applying
+/// `OutputAdjustments`
+///
+/// Call `begin` before drawing a frame and `finish` after. Does nothing if the adjustments
+/// leave colours unchanged, or in builds without the `shaders` feature
+pub struct OutputStage {
+    adjustments: OutputAdjustments,
+    target: Option<RenderTarget>,
+    material: Option<Material>,
+}
+
+impl OutputStage {
+    pub fn new(adjustments: OutputAdjustments) -> Result<Self, macroquad::Error> {
+        if adjustments.is_identity() || !cfg!(feature = "shaders") {
+            if !adjustments.is_identity() {
+                eprintln!("Built without shaders, so ignoring the output adjustments");
+            }
+
+            return Ok(Self {
+                adjustments,
+                target: None,
+                material: None,
+            });
+        }
+
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: VERTEX_SHADER,
+                fragment: FRAGMENT_SHADER,
+            },
+            MaterialParams {
+                uniforms: ["gamma", "brightness", "contrast", "saturation"]
+                    .map(|name| UniformDesc::new(name, UniformType::Float1))
+                    .to_vec(),
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            adjustments,
+            target: None,
+            material: Some(material),
+        })
+    }
+
+    /// Redirects drawing to the offscreen frame, recreating it if the window was resized
+    pub fn begin(&mut self) {
+        if self.material.is_none() {
+            return;
+        }
+
+        let (width, height) = (screen_width() as u32, screen_height() as u32);
+        let resized = self.target.as_ref().is_none_or(|t| {
+            t.texture.width() as u32 != width || t.texture.height() as u32 != height
+        });
+        if resized {
+            self.target = Some(render_target(width, height));
+        }
+
+        let mut camera =
+            Camera2D::from_display_rect(Rect::new(0.0, 0.0, screen_width(), screen_height()));
+        camera.render_target = self.target.clone();
+        set_camera(&camera);
+    }
+
+    /// Draws the offscreen frame to the screen with the adjustments applied
+    pub fn finish(&self) {
+        let (Some(target), Some(material)) = (&self.target, &self.material) else {
+            return;
+        };
+
+        set_default_camera();
+
+        let OutputAdjustments {
+            gamma,
+            brightness,
+            contrast,
+            saturation,
+        } = self.adjustments;
+        material.set_uniform("gamma", gamma);
+        material.set_uniform("brightness", brightness);
+        material.set_uniform("contrast", contrast);
+        material.set_uniform("saturation", saturation);
+
+        gl_use_material(material);
+        draw_texture_ex(
+            &target.texture,
+            0.0,
+            0.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(screen_width(), screen_height())),
+                // Render targets come out upside down
+                flip_y: true,
+                ..Default::default()
+            },
+        );
+        gl_use_default_material();
+    }
+}
+
+pub const VERTEX_SHADER: &str = "#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+
+varying mediump vec2 uv;
+varying mediump vec4 color;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+}
+";
+
+const FRAGMENT_SHADER: &str = "#version 100
+precision mediump float;
+
+varying vec4 color;
+varying vec2 uv;
+
+uniform sampler2D Texture;
+uniform float gamma;
+uniform float brightness;
+uniform float contrast;
+uniform float saturation;
+
+void main() {
+    vec3 c = texture2D(Texture, uv).rgb * color.rgb;
+    c = clamp((c - 0.5) * contrast + 0.5 + brightness, 0.0, 1.0);
+
+    float luma = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    c = clamp(mix(vec3(luma), c, saturation), 0.0, 1.0);
+    c = pow(c, vec3(1.0 / max(gamma, 0.0001)));
+
+    gl_FragColor = vec4(c, 1.0);
+}
+";
@@ -0,0 +1,199 @@
+//! Elements drawn over every scene for stream layouts: the playing track, a loudness meter
+//! and a logo, each pinned to a corner, edge or the centre of the safe area
+//!
+//! ```toml
+//! [overlay]
+//! safe_area = [0.03, 0.05]
+//! notes = { anchor = "top" }
+//! track = { anchor = "bottom-left" }
+//! meter = { anchor = "bottom-right", width = 0.2 }
+//! logo = { image = "logo.png", anchor = "top-left", height = 0.1 }
+//! ```
+
+use std::{fs, io, path::PathBuf};
+
+use macroquad::{
+    color::{Color, DARKGRAY, WHITE},
+    math::vec2,
+    shapes::draw_rectangle,
+    texture::{DrawTextureParams, Image, Texture2D, draw_texture_ex},
+    window::screen_height,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    analysis::FrameAnalysis,
+    config::ConfigColour,
+    layout::{Anchor, Placement, safe_area},
+    mpris::NowPlaying,
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+};
+
+#[derive(Debug, Error)]
+pub enum OverlayError {
+    #[error("couldn't read logo: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Image(#[from] macroquad::Error),
+}
+
+/// The `[overlay]` table, see the module docs. Elements left out aren't drawn
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct OverlayConfig {
+    /// Margins kept clear on every side as fractions of the window width and height, e.g.
+    /// for a frame or logo added by the streaming software
+    pub safe_area: [f32; 2],
+    /// Note names shown by the chromagram and note tracking modes
+    pub notes: Placement,
+    pub track: Option<TrackConfig>,
+    pub meter: Option<MeterConfig>,
+    pub logo: Option<LogoConfig>,
+}
+
+/// Artist and title of the track an MPRIS player is playing
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TrackConfig {
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    /// Text size in pixels at the reference window height
+    #[serde(default = "default_track_size")]
+    pub size: f32,
+}
+
+fn default_track_size() -> f32 {
+    24.0
+}
+
+/// A horizontal bar showing the loudness from -60dBFS to 0dBFS
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MeterConfig {
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    /// Length as a fraction of the window height
+    #[serde(default = "default_meter_width")]
+    pub width: f32,
+    pub colour: Option<ConfigColour>,
+}
+
+fn default_meter_width() -> f32 {
+    0.2
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LogoConfig {
+    pub image: PathBuf,
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    /// Height as a fraction of the window, keeping the image's aspect ratio
+    #[serde(default = "default_logo_height")]
+    pub height: f32,
+    #[serde(default = "default_logo_opacity")]
+    pub opacity: f32,
+}
+
+fn default_logo_height() -> f32 {
+    0.1
+}
+
+fn default_logo_opacity() -> f32 {
+    1.0
+}
+
+/// Draws the elements in `OverlayConfig`
+pub struct Overlay {
+    config: OverlayConfig,
+    logo: Option<Texture2D>,
+}
+
+impl Overlay {
+    /// Loads the logo, so needs the window to exist
+    pub fn new(config: OverlayConfig) -> Result<Self, OverlayError> {
+        let logo = match &config.logo {
+            Some(logo) => {
+                let bytes = fs::read(&logo.image)?;
+                Some(Texture2D::from_image(&Image::from_file_with_format(
+                    &bytes, None,
+                )?))
+            }
+            None => None,
+        };
+
+        Ok(Self { config, logo })
+    }
+
+    pub fn draw(
+        &self,
+        analysis: &FrameAnalysis,
+        track: Option<&NowPlaying>,
+        typography: &Typography,
+    ) {
+        let area = safe_area(self.config.safe_area);
+        let scale = ui_scale();
+
+        if let (Some(config), Some(track)) = (&self.config.track, track) {
+            let text = format!("{} - {}", track.artist, track.title);
+            let size = config.size * scale;
+            let dimensions = typography.measure(TextRole::Metadata, &text, size);
+            let placement = Placement {
+                anchor: config.anchor,
+                offset: config.offset,
+            };
+            let position = placement.position(area, vec2(dimensions.width, dimensions.height));
+            typography.draw(
+                TextRole::Metadata,
+                &text,
+                position.x,
+                position.y + dimensions.offset_y,
+                size,
+                WHITE,
+            );
+        }
+
+        if let Some(config) = &self.config.meter {
+            let (width, height) = (config.width * screen_height(), 8.0 * scale);
+            let placement = Placement {
+                anchor: config.anchor,
+                offset: config.offset,
+            };
+            let position = placement.position(area, vec2(width, height));
+            let colour = config.colour.map_or(WHITE, |colour| colour.0);
+            draw_rectangle(position.x, position.y, width, height, DARKGRAY);
+            draw_rectangle(
+                position.x,
+                position.y,
+                width * analysis.normalised_loudness(),
+                height,
+                colour,
+            );
+        }
+
+        if let (Some(config), Some(logo)) = (&self.config.logo, &self.logo) {
+            let height = config.height * screen_height();
+            let width = height * logo.width() / logo.height().max(1.0);
+            let placement = Placement {
+                anchor: config.anchor,
+                offset: config.offset,
+            };
+            let position = placement.position(area, vec2(width, height));
+            draw_texture_ex(
+                logo,
+                position.x,
+                position.y,
+                Color::new(1.0, 1.0, 1.0, config.opacity),
+                DrawTextureParams {
+                    dest_size: Some(vec2(width, height)),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
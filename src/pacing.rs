@@ -0,0 +1,157 @@
+//! Playing the analysis back to a sink at the sink's own rate
+//!
+//! Windows are analysed in bursts, as many as have been captured each time the screen is
+//! drawn, so they reach the sinks unevenly and at the screen's cadence rather than the
+//! audio's. A `Pacer` keeps a sink's levels by when their audio was captured and plays them
+//! back a little behind, just far enough that the next window has always arrived, so a
+//! frame can be taken whenever the sink's due one with its levels interpolated between the
+//! windows either side. An LED strip can then be sent 100 frames a second and an OSC client
+//! 30, both moving smoothly, while the screen draws at 60
+
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::{analysis::FrameAnalysis, sinks::SinkFrame};
+
+// Seconds a second the lag shrinks by once windows arrive sooner, too slow to be seen
+const LAG_RECOVERY: f64 = 0.01;
+// Seconds latency can drop by, or analysis time go back by, before it's taken as the
+// analysis clock restarting and caught up with straight away
+const LAG_RESET: f64 = 1.0;
+
+// A window's levels, as grouped and smoothed for the sink
+struct Window {
+    analysis: Arc<FrameAnalysis>,
+    levels: Vec<f32>,
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Window {
+    fn time(&self) -> f64 {
+        self.analysis.time
+    }
+}
+
+/// Buffers a sink's windows and plays them back at whatever rate frames are taken
+pub struct Pacer {
+    // Oldest first, from the one just before the playback time onwards
+    windows: VecDeque<Window>,
+    // Seconds after their capture windows are played back, the most any has taken to arrive
+    lag: Option<f64>,
+    // When the latest window arrived
+    last_arrival: f64,
+    // Analysis time played up to, so each window's events are only sent once
+    played: f64,
+    delay: f64,
+    // Interpolated levels of the latest frame
+    levels: Vec<f32>,
+}
+
+impl Pacer {
+    /// Plays windows back `delay` seconds later than they could be
+    pub fn new(delay: f64) -> Self {
+        Self {
+            windows: VecDeque::new(),
+            lag: None,
+            last_arrival: 0.0,
+            played: f64::NEG_INFINITY,
+            delay: delay.max(0.0),
+            levels: Vec::new(),
+        }
+    }
+
+    /// Adds a window's levels, received at `now` seconds on the sink's own clock
+    pub fn push(
+        &mut self,
+        now: f64,
+        analysis: Arc<FrameAnalysis>,
+        levels: Vec<f32>,
+        ranges: Vec<(usize, usize)>,
+    ) {
+        let latency = now - analysis.time;
+        let since_last = now - self.last_arrival;
+        self.lag = Some(match self.lag {
+            Some(lag) if latency > lag - LAG_RESET => latency.max(lag - LAG_RECOVERY * since_last),
+            _ => latency,
+        });
+        self.last_arrival = now;
+
+        // The analysis started over, so nothing before it follows on
+        if self
+            .windows
+            .back()
+            .is_some_and(|window| analysis.time < window.time() - LAG_RESET)
+        {
+            self.windows.clear();
+            self.played = f64::NEG_INFINITY;
+        }
+        self.windows.push_back(Window {
+            analysis,
+            levels,
+            ranges,
+        });
+    }
+
+    /// The frame to send at `now` seconds on the sink's own clock, or none if the first
+    /// window isn't due yet. The latest window is held if no more have arrived
+    pub fn frame(&mut self, now: f64) -> Option<SinkFrame<'_>> {
+        let time = now - self.lag? - self.delay;
+        if self.windows.front()?.time() > time {
+            return None;
+        }
+
+        let mut onset = false;
+        let mut beat = None;
+        for window in &self.windows {
+            if window.time() > time {
+                break;
+            }
+            if window.time() > self.played {
+                onset |= window.analysis.onset;
+                beat = window.analysis.beat.or(beat);
+            }
+        }
+        self.played = self.played.max(time);
+        // Only the window just before `time` is needed to interpolate from
+        while self
+            .windows
+            .get(1)
+            .is_some_and(|window| window.time() <= time)
+        {
+            self.windows.pop_front();
+        }
+
+        let before = self.windows.front()?;
+        let nearest = match self.windows.get(1) {
+            // Bars added or removed between the windows can't be interpolated
+            Some(after) if after.levels.len() == before.levels.len() => {
+                let span = after.time() - before.time();
+                let t = if span > 0.0 {
+                    ((time - before.time()) / span).clamp(0.0, 1.0) as f32
+                } else {
+                    1.0
+                };
+                self.levels.clear();
+                self.levels.extend(
+                    before
+                        .levels
+                        .iter()
+                        .zip(&after.levels)
+                        .map(|(from, to)| from + (to - from) * t),
+                );
+                if t < 0.5 { before } else { after }
+            }
+            _ => {
+                self.levels.clone_from(&before.levels);
+                before
+            }
+        };
+
+        Some(SinkFrame {
+            analysis: &nearest.analysis,
+            levels: &self.levels,
+            ranges: &nearest.ranges,
+            onset,
+            beat,
+        })
+    }
+}
@@ -0,0 +1,245 @@
+use std::cmp::Reverse;
+
+use macroquad::{
+    color::{Color, GRAY, WHITE},
+    input::{KeyCode, clear_input_queue, get_char_pressed, is_key_pressed},
+    shapes::draw_rectangle,
+    window::screen_width,
+};
+
+use crate::{
+    automation::Parameter,
+    keybindings::{Action, KeyBindings, Trigger},
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+    visualiser::DisplayMode,
+};
+
+// Most matches listed at once
+const MAX_RESULTS: usize = 10;
+
+/// Something chosen from the palette, for the caller to carry out
+#[derive(Clone)]
+pub enum Command {
+    Action(Action),
+    SetMode(DisplayMode),
+    SetParameter(Parameter, f32),
+}
+
+// What an entry does when chosen: parameters ask for a value before becoming a `Command`
+#[derive(Clone)]
+enum Entry {
+    Command(Command),
+    Parameter(Parameter),
+}
+
+/// Searchable list of every action, mode and parameter, opened with Ctrl+P
+///
+/// Typing filters the list by fuzzy match, Up/Down moves the selection and Enter chooses it.
+/// Choosing a parameter then asks for its new value. Escape closes the palette
+pub struct CommandPalette {
+    entries: Vec<(String, Entry)>,
+    open: bool,
+    query: String,
+    selected: usize,
+    // Parameter whose new value is being typed in
+    editing: Option<Parameter>,
+}
+
+impl CommandPalette {
+    pub fn new(bindings: &KeyBindings) -> Self {
+        let mut entries = Vec::new();
+
+        // Held actions only nudge a value each frame, so aren't worth choosing once
+        for binding in bindings.iter() {
+            if matches!(binding.trigger, Trigger::Pressed) && binding.action != Action::OpenPalette
+            {
+                entries.push((
+                    binding.description.to_string(),
+                    Entry::Command(Command::Action(binding.action)),
+                ));
+            }
+        }
+        for mode in DisplayMode::built_in() {
+            entries.push((
+                format!("Mode: {}", mode.name()),
+                Entry::Command(Command::SetMode(mode)),
+            ));
+        }
+        for parameter in Parameter::ALL {
+            entries.push((
+                format!("Set {}", parameter.name()),
+                Entry::Parameter(parameter),
+            ));
+        }
+
+        Self {
+            entries,
+            open: false,
+            query: String::new(),
+            selected: 0,
+            editing: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+        self.query.clear();
+        self.selected = 0;
+        self.editing = None;
+        // Drop the key press that opened the palette
+        clear_input_queue();
+    }
+
+    /// Handles this frame's typing, returning a command once one has been chosen
+    pub fn update(&mut self) -> Option<Command> {
+        if !self.open {
+            return None;
+        }
+
+        while let Some(c) = get_char_pressed() {
+            if !c.is_control() {
+                self.query.push(c);
+                self.selected = 0;
+            }
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            self.open = false;
+        } else if is_key_pressed(KeyCode::Backspace) {
+            self.query.pop();
+            self.selected = 0;
+        } else if is_key_pressed(KeyCode::Down) {
+            self.selected = (self.selected + 1).min(MAX_RESULTS - 1);
+        } else if is_key_pressed(KeyCode::Up) {
+            self.selected = self.selected.saturating_sub(1);
+        } else if is_key_pressed(KeyCode::Enter) {
+            return self.choose();
+        }
+
+        None
+    }
+
+    fn choose(&mut self) -> Option<Command> {
+        if let Some(parameter) = self.editing {
+            let value = self.query.trim().parse().ok()?;
+            self.open = false;
+            return Some(Command::SetParameter(parameter, value));
+        }
+
+        let matches = self.matches();
+        let index = *matches.get(self.selected.min(matches.len().checked_sub(1)?))?;
+
+        match self.entries[index].1.clone() {
+            Entry::Command(command) => {
+                self.open = false;
+                Some(command)
+            }
+            Entry::Parameter(parameter) => {
+                self.editing = Some(parameter);
+                self.query.clear();
+                None
+            }
+        }
+    }
+
+    /// Indices of entries matching the query, best match first
+    fn matches(&self) -> Vec<usize> {
+        let mut scored: Vec<(i32, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (label, _))| fuzzy_score(&self.query, label).map(|score| (score, i)))
+            .collect();
+        // Stable, so equally good matches keep their listed order
+        scored.sort_by_key(|&(score, _)| Reverse(score));
+
+        scored.into_iter().map(|(_, i)| i).collect()
+    }
+
+    pub fn draw(&self, typography: &Typography) {
+        if !self.open {
+            return;
+        }
+
+        let scale = ui_scale();
+        let size = 22.0 * scale;
+        let line_height = size * 1.4;
+        let width = (screen_width() * 0.6)
+            .max(400.0 * scale)
+            .min(screen_width());
+        let x = (screen_width() - width) / 2.0;
+        let padding = 12.0 * scale;
+
+        let (prompt, results) = match self.editing {
+            Some(parameter) => (format!("{} = {}", parameter.name(), self.query), Vec::new()),
+            None => (format!("> {}", self.query), self.matches()),
+        };
+        let shown = results.len().min(MAX_RESULTS);
+
+        draw_rectangle(
+            x,
+            padding,
+            width,
+            (shown + 1) as f32 * line_height + padding * 2.0,
+            Color::new(0.0, 0.0, 0.0, 0.85),
+        );
+
+        let mut y = padding * 2.0 + size;
+        typography.draw(TextRole::Labels, &prompt, x + padding, y, size, WHITE);
+
+        let selected = self.selected.min(shown.saturating_sub(1));
+        for (row, &index) in results.iter().take(shown).enumerate() {
+            y += line_height;
+            let colour = if row == selected { WHITE } else { GRAY };
+            if row == selected {
+                draw_rectangle(
+                    x,
+                    y - size,
+                    width,
+                    line_height,
+                    Color::new(1.0, 1.0, 1.0, 0.1),
+                );
+            }
+            typography.draw(
+                TextRole::Labels,
+                &self.entries[index].0,
+                x + padding,
+                y,
+                size,
+                colour,
+            );
+        }
+    }
+}
+
+/// Scores how well `query` matches `candidate`, or None if its characters don't all appear
+/// in order. Consecutive characters and matches at the start of words score higher
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + candidate[position..].iter().position(|&c| c == q)?;
+
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+
+        previous = Some(found);
+        position = found + 1;
+    }
+
+    // Prefer shorter labels among otherwise equal matches
+    Some(score * 100 - candidate.len() as i32)
+}
@@ -0,0 +1,310 @@
+//! Particles thrown out by frequency bands, drawn over every scene
+//!
+//! Each `[[emitter]]` table turns the energy in one band into particles, released from an
+//! area of the window in a direction and then left to gravity and drag. A band emits `rate`
+//! particles a second at its recent peak, and fewer as it gets quieter:
+//!
+//! ```toml
+//! # Bass fire rising from the bottom edge
+//! [[emitter]]
+//! band = [20.0, 200.0]
+//! rate = 300.0
+//! sprite = "circle"
+//! colour = "#ff6a00"
+//! area = [0.0, 0.98, 1.0, 0.02]
+//! direction = 90.0
+//! spread = 20.0
+//! speed = [0.3, 0.6]
+//! gravity = -0.2
+//! lifetime = 1.2
+//!
+//! # Treble snow falling from the top edge
+//! [[emitter]]
+//! band = [6000.0, 16000.0]
+//! rate = 120.0
+//! sprite = "square"
+//! colour = "#e0f0ffc0"
+//! area = [0.0, 0.0, 1.0, 0.02]
+//! direction = 270.0
+//! spread = 40.0
+//! speed = [0.05, 0.15]
+//! gravity = 0.05
+//! drag = 0.5
+//! lifetime = 6.0
+//! size = 3.0
+//! ```
+//!
+//! `sprite` can also name a sprite in the `[atlas]`, drawn `size` pixels wide and tinted by
+//! the emitter's colour
+
+use macroquad::{
+    color::{Color, WHITE},
+    math::{Rect, Vec2, vec2},
+    rand::gen_range,
+    shapes::{draw_circle, draw_line, draw_rectangle},
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+
+use crate::{
+    analysis::FrameAnalysis, atlas::TextureAtlas, bins, config::ConfigColour, ui::ui_scale,
+};
+
+// Per-frame decay of each band's running peak, which its level is relative to
+const PEAK_DECAY: f32 = 0.998;
+// Most particles alive for one emitter, so a loud band can't bring the frame rate down
+const MAX_PARTICLES: usize = 2000;
+// Longest step particles are moved by at once, so a stall doesn't fling them off screen
+const MAX_STEP_SECONDS: f32 = 0.1;
+
+/// Shape each particle is drawn as
+#[derive(Deserialize, Clone, Default)]
+#[serde(from = "String")]
+pub enum Sprite {
+    #[default]
+    Circle,
+    Square,
+    /// A short streak along the particle's direction of travel
+    Spark,
+    /// A sprite from the `[atlas]`, by name
+    Atlas(String),
+}
+
+impl From<String> for Sprite {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "circle" => Sprite::Circle,
+            "square" => Sprite::Square,
+            "spark" => Sprite::Spark,
+            _ => Sprite::Atlas(name),
+        }
+    }
+}
+
+/// One `[[emitter]]` table, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmitterConfig {
+    /// Lowest and highest frequency in Hz whose energy drives the emitter
+    pub band: [f32; 2],
+    /// Particles a second when the band is at its recent peak
+    pub rate: f32,
+    /// Level from 0.0 to 1.0 the band has to reach before anything is emitted
+    pub threshold: f32,
+    pub sprite: Sprite,
+    pub colour: ConfigColour,
+    /// Left, top, width and height as fractions of the window that particles start in
+    pub area: [f32; 4],
+    /// Angle particles are thrown at in degrees, anticlockwise from the right, so 90.0 is up
+    pub direction: f32,
+    /// Degrees either side of `direction` particles are spread over
+    pub spread: f32,
+    /// Slowest and fastest starting speed, in window heights a second
+    pub speed: [f32; 2],
+    /// Downward acceleration in window heights a second squared, negative to rise
+    pub gravity: f32,
+    /// Fraction of their speed particles lose a second
+    pub drag: f32,
+    /// Seconds each particle lasts
+    pub lifetime: f32,
+    /// Width of each particle in pixels at the reference window height
+    pub size: f32,
+    /// Whether particles fade out over their lifetime rather than vanishing at the end
+    pub fade: bool,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            band: [20.0, 20_000.0],
+            rate: 100.0,
+            threshold: 0.0,
+            sprite: Sprite::Circle,
+            colour: ConfigColour(WHITE),
+            area: [0.0, 0.0, 1.0, 1.0],
+            direction: 90.0,
+            spread: 30.0,
+            speed: [0.2, 0.5],
+            gravity: 0.0,
+            drag: 0.0,
+            lifetime: 2.0,
+            size: 4.0,
+            fade: true,
+        }
+    }
+}
+
+impl EmitterConfig {
+    /// Describes the first setting that can't work, if any, given the names of the sprites
+    /// in the `[atlas]`
+    pub fn validate(&self, sprites: &[&str]) -> Result<(), String> {
+        if let Sprite::Atlas(name) = &self.sprite
+            && !sprites.contains(&name.as_str())
+        {
+            return Err(format!("emitter sprite `{name}` isn't in the atlas"));
+        }
+        let [low, high] = self.band;
+        if !(0.0..high).contains(&low) {
+            return Err(format!("emitter band {low}Hz to {high}Hz is empty"));
+        }
+        if self.lifetime <= 0.0 {
+            return Err("emitter `lifetime` must be above 0".to_string());
+        }
+        if self.rate < 0.0 || self.speed[0] > self.speed[1] {
+            return Err("emitter `rate` and `speed` can't be negative or reversed".to_string());
+        }
+        Ok(())
+    }
+}
+
+struct Particle {
+    // In pixels, with y down like the rest of the drawing
+    position: Vec2,
+    // In window heights a second, with y down
+    velocity: Vec2,
+    age: f32,
+}
+
+struct Emitter {
+    config: EmitterConfig,
+    peak: f32,
+    // Fractional particles carried over to the next frame, so low rates still emit
+    owed: f32,
+    particles: Vec<Particle>,
+}
+
+impl Emitter {
+    /// Level of the band in `spectrum` relative to its recent peak
+    fn level(&mut self, spectrum: &[f32], sampling_rate: usize) -> f32 {
+        let freq_per_bin = bins::bin_width(spectrum.len(), sampling_rate);
+        let [low, high] = self.config.band;
+        let start = ((low / freq_per_bin) as usize).min(spectrum.len());
+        let end = ((high / freq_per_bin).ceil() as usize).clamp(start, spectrum.len());
+        let energy = spectrum[start..end].iter().sum::<f32>().sqrt();
+
+        self.peak = energy.max(self.peak * PEAK_DECAY);
+        if self.peak > 0.0 {
+            energy / self.peak
+        } else {
+            0.0
+        }
+    }
+
+    fn emit(&mut self, count: usize) {
+        let config = &self.config;
+        let [left, top, width, height] = config.area;
+        for _ in 0..count.min(MAX_PARTICLES.saturating_sub(self.particles.len())) {
+            let angle = (config.direction + gen_range(-config.spread, config.spread)).to_radians();
+            let speed = gen_range(config.speed[0], config.speed[1]);
+            self.particles.push(Particle {
+                position: vec2(
+                    (left + gen_range(0.0, width)) * screen_width(),
+                    (top + gen_range(0.0, height)) * screen_height(),
+                ),
+                velocity: vec2(angle.cos(), -angle.sin()) * speed,
+                age: 0.0,
+            });
+        }
+    }
+
+    fn step(&mut self, elapsed: f32) {
+        let config = &self.config;
+        let drag = (1.0 - config.drag * elapsed).max(0.0);
+        for particle in &mut self.particles {
+            particle.velocity.y += config.gravity * elapsed;
+            particle.velocity *= drag;
+            particle.position += particle.velocity * screen_height() * elapsed;
+            particle.age += elapsed;
+        }
+        self.particles
+            .retain(|particle| particle.age < config.lifetime);
+    }
+
+    fn draw(&self, atlas: Option<&TextureAtlas>) {
+        let config = &self.config;
+        let size = config.size * ui_scale();
+        for particle in &self.particles {
+            let colour = if config.fade {
+                let Color { r, g, b, a } = config.colour.0;
+                Color::new(r, g, b, a * (1.0 - particle.age / config.lifetime))
+            } else {
+                config.colour.0
+            };
+            let Vec2 { x, y } = particle.position;
+            match &config.sprite {
+                Sprite::Circle => draw_circle(x, y, size / 2.0, colour),
+                Sprite::Square => {
+                    draw_rectangle(x - size / 2.0, y - size / 2.0, size, size, colour)
+                }
+                Sprite::Spark => {
+                    let tail =
+                        particle.position - particle.velocity.normalize_or_zero() * size * 3.0;
+                    draw_line(x, y, tail.x, tail.y, size / 2.0, colour);
+                }
+                Sprite::Atlas(name) => {
+                    let Some(atlas) = atlas else {
+                        continue;
+                    };
+                    let height = size / atlas.aspect(name).unwrap_or(1.0);
+                    let dest = Rect::new(x - size / 2.0, y - height / 2.0, size, height);
+                    atlas.draw(name, dest, colour);
+                }
+            }
+        }
+    }
+}
+
+/// Every `[[emitter]]` and the particles they've thrown out
+pub struct Particles {
+    emitters: Vec<Emitter>,
+    last_time: Option<f64>,
+}
+
+impl Particles {
+    pub fn new(configs: &[EmitterConfig]) -> Self {
+        Self {
+            emitters: configs
+                .iter()
+                .map(|config| Emitter {
+                    config: config.clone(),
+                    peak: 0.0,
+                    owed: 0.0,
+                    particles: Vec::new(),
+                })
+                .collect(),
+            last_time: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty()
+    }
+
+    /// Emits particles for the band levels in `analysis` and moves every particle on to
+    /// `time` in seconds
+    pub fn update(&mut self, analysis: &FrameAnalysis, sampling_rate: usize, time: f64) {
+        let elapsed = self.last_time.map_or(0.0, |last| {
+            ((time - last).max(0.0) as f32).min(MAX_STEP_SECONDS)
+        });
+        self.last_time = Some(time);
+
+        for emitter in &mut self.emitters {
+            emitter.step(elapsed);
+
+            let level = emitter.level(&analysis.spectrum, sampling_rate);
+            if level >= emitter.config.threshold {
+                emitter.owed += emitter.config.rate * level * elapsed;
+            }
+            let count = emitter.owed as usize;
+            emitter.owed -= count as f32;
+            emitter.emit(count);
+        }
+    }
+
+    /// Draws every particle, with sprites from `atlas` if there is one
+    pub fn draw(&self, atlas: Option<&TextureAtlas>) {
+        for emitter in &self.emitters {
+            emitter.draw(atlas);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use alloc::{vec, vec::Vec};
+
+/// Peak-hold markers: the highest recent level of each bar, held for a while before falling
+/// back down to meet it
+pub struct PeakHold {
+    hold: f32,
+    fall: f32,
+    peaks: Vec<f32>,
+    // Seconds since each peak was last pushed up
+    held: Vec<f32>,
+    last_time: Option<f64>,
+}
+
+impl PeakHold {
+    /// Holds each peak for `hold` seconds, then lets it fall at `fall` bar heights a second
+    pub fn new(hold: f32, fall: f32) -> Self {
+        Self {
+            hold,
+            fall,
+            peaks: Vec::new(),
+            held: Vec::new(),
+            last_time: None,
+        }
+    }
+
+    /// Raises the peaks to any of `levels` above them and lets the rest fall, as of `time`
+    /// in seconds
+    pub fn update(&mut self, levels: &[f32], time: f64) {
+        let elapsed = self
+            .last_time
+            .map_or(0.0, |last| (time - last).max(0.0) as f32);
+        self.last_time = Some(time);
+
+        // Starts again if the number of bars changes
+        if self.peaks.len() != levels.len() {
+            self.peaks = levels.to_vec();
+            self.held = vec![0.0; levels.len()];
+            return;
+        }
+
+        for ((peak, held), &level) in self.peaks.iter_mut().zip(&mut self.held).zip(levels) {
+            if level >= *peak {
+                *peak = level;
+                *held = 0.0;
+            } else {
+                *held += elapsed;
+                if *held > self.hold {
+                    *peak = (*peak - self.fall * elapsed).max(level);
+                }
+            }
+        }
+    }
+
+    /// Current peak of each bar, on the same scale as the levels
+    pub fn peaks(&self) -> &[f32] {
+        &self.peaks
+    }
+}
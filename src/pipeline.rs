@@ -0,0 +1,466 @@
+//! The steps from samples to bar heights as separate stages, run in an explicit order
+//!
+//! Each `Stage` takes a `Frame` and passes on the frame it leaves. The standard stages are,
+//! in the order they run:
+//!
+//! 1. `WindowStage` ("window") keeps the latest window of samples
+//! 2. `FftStage` ("fft") turns them into a power spectrum
+//! 3. `WeightingStage` ("weighting") scales the spectrum by a frequency weighting curve
+//! 4. `GroupingStage` ("grouping") groups the spectrum into bars
+//! 5. `SmoothingStage` ("smoothing") eases the bars towards the new levels
+//! 6. `NormaliseStage` ("normalise") scales the bars to heights from 0.0 to 1.0
+//!
+//! Anything else implementing `Stage` can be put between them as middleware, to log or
+//! change the frame on its way through, and `FnStage` wraps a closure as one:
+//!
+//! ```ignore
+//! let mut pipeline = Pipeline::bars(sample_rate, grouping, smoothing, None, gain);
+//! pipeline.insert_before(
+//!     "grouping",
+//!     FnStage::new("notch", |mut frame: Frame| {
+//!         frame.spectrum[10] = 0.0;
+//!         frame
+//!     }),
+//! );
+//! let bars = pipeline.process(Frame::from_spectrum(spectrum)).bars;
+//! ```
+
+use crate::{
+    bins,
+    gain::AutoGain,
+    grouping::GroupingStrategy,
+    smoothing::SmoothingStrategy,
+    spectra::{FourierTransform, Weighting},
+};
+
+/// What's known about a window as it goes through the pipeline. Each stage fills in its
+/// part, so later stages and middleware can still see what earlier ones started from
+#[derive(Clone, Default)]
+pub struct Frame {
+    /// Mono samples, oldest first
+    pub samples: Vec<f32>,
+    /// Power spectrum laid out as described in `bins`
+    pub spectrum: Vec<f32>,
+    /// One level per bar, heights from 0.0 to 1.0 once normalised
+    pub bars: Vec<f32>,
+    /// Range of spectrum bins each bar covers, as from `GroupingStrategy::create_ranges`
+    pub ranges: Vec<(usize, usize)>,
+}
+
+impl Frame {
+    pub fn from_samples(samples: Vec<f32>) -> Self {
+        Self {
+            samples,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_spectrum(spectrum: Vec<f32>) -> Self {
+        Self {
+            spectrum,
+            ..Default::default()
+        }
+    }
+}
+
+/// One step of the pipeline
+pub trait Stage: Send {
+    /// Name that other stages are inserted around by, see `Pipeline::insert_before`
+    fn name(&self) -> &str;
+
+    fn process(&mut self, frame: Frame) -> Frame;
+}
+
+/// Keeps the last `size` samples, padding shorter windows with silence at the start
+pub struct WindowStage {
+    size: usize,
+}
+
+impl WindowStage {
+    pub fn new(size: usize) -> Self {
+        Self { size }
+    }
+}
+
+impl Stage for WindowStage {
+    fn name(&self) -> &str {
+        "window"
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        let len = frame.samples.len();
+        if len >= self.size {
+            frame.samples.drain(..len - self.size);
+        } else {
+            frame
+                .samples
+                .splice(0..0, core::iter::repeat_n(0.0, self.size - len));
+        }
+        frame
+    }
+}
+
+/// Computes the power spectrum of the samples
+pub struct FftStage(pub FourierTransform);
+
+impl Stage for FftStage {
+    fn name(&self) -> &str {
+        "fft"
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        frame.spectrum = self.0.compute(&frame.samples);
+        frame
+    }
+}
+
+/// Multiplies the spectrum by the gain of `weighting` at each bin
+pub struct WeightingStage {
+    weighting: Weighting,
+    sample_rate: usize,
+    // Gain of each bin, rebuilt when the spectrum length changes
+    table: Vec<f32>,
+}
+
+impl WeightingStage {
+    pub fn new(weighting: Weighting, sample_rate: usize) -> Self {
+        Self {
+            weighting,
+            sample_rate,
+            table: Vec::new(),
+        }
+    }
+}
+
+impl Stage for WeightingStage {
+    fn name(&self) -> &str {
+        "weighting"
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        if self.table.len() != frame.spectrum.len() {
+            self.table = self.weighting.table(self.sample_rate, frame.spectrum.len());
+        }
+        for (power, gain) in frame.spectrum.iter_mut().zip(&self.table) {
+            *power *= gain;
+        }
+        frame
+    }
+}
+
+/// Groups the spectrum into bars with `strategy`
+pub struct GroupingStage {
+    strategy: GroupingStrategy,
+    sample_rate: usize,
+    // Ranges for spectra of `fft_size`, recreated when the FFT size is switched
+    ranges: Vec<(usize, usize)>,
+    fft_size: usize,
+}
+
+impl GroupingStage {
+    pub fn new(strategy: GroupingStrategy, sample_rate: usize) -> Self {
+        Self {
+            strategy,
+            sample_rate,
+            ranges: Vec::new(),
+            fft_size: 0,
+        }
+    }
+}
+
+impl Stage for GroupingStage {
+    fn name(&self) -> &str {
+        "grouping"
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        let fft_size = bins::fft_size(frame.spectrum.len());
+        if fft_size != self.fft_size {
+            self.fft_size = fft_size;
+            self.ranges = self.strategy.create_ranges(self.sample_rate, fft_size);
+        }
+        frame.bars = self.strategy.group_spectrum(&frame.spectrum, &self.ranges);
+        frame.ranges.clone_from(&self.ranges);
+        frame
+    }
+}
+
+/// Eases the bars from their last levels towards the new ones with `strategy`
+pub struct SmoothingStage {
+    strategy: SmoothingStrategy,
+    levels: Vec<f32>,
+}
+
+impl SmoothingStage {
+    pub fn new(strategy: SmoothingStrategy) -> Self {
+        Self {
+            strategy,
+            levels: Vec::new(),
+        }
+    }
+}
+
+impl Stage for SmoothingStage {
+    fn name(&self) -> &str {
+        "smoothing"
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        // Starts from the first levels, and again whenever the number of bars changes
+        if matches!(self.strategy, SmoothingStrategy::None) || self.levels.len() != frame.bars.len()
+        {
+            self.levels.clone_from(&frame.bars);
+        } else {
+            self.strategy.smooth(&mut self.levels, &frame.bars);
+            frame.bars.copy_from_slice(&self.levels);
+        }
+        frame
+    }
+}
+
+/// Scales the bars to heights from 0.0 to 1.0 with automatic gain control
+pub struct NormaliseStage(pub AutoGain);
+
+impl Stage for NormaliseStage {
+    fn name(&self) -> &str {
+        "normalise"
+    }
+
+    fn process(&mut self, mut frame: Frame) -> Frame {
+        frame.bars = self.0.normalise(&frame.bars);
+        frame
+    }
+}
+
+/// A closure run as a stage, for middleware too small to need its own type
+pub struct FnStage<F> {
+    name: String,
+    function: F,
+}
+
+impl<F: FnMut(Frame) -> Frame + Send> FnStage<F> {
+    pub fn new(name: &str, function: F) -> Self {
+        Self {
+            name: name.to_string(),
+            function,
+        }
+    }
+}
+
+impl<F: FnMut(Frame) -> Frame + Send> Stage for FnStage<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&mut self, frame: Frame) -> Frame {
+        (self.function)(frame)
+    }
+}
+
+/// Stages run one after another on every frame
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The standard stages from a spectrum to bar heights, as the visualiser runs them
+    pub fn bars(
+        sample_rate: usize,
+        grouping: GroupingStrategy,
+        smoothing: SmoothingStrategy,
+        weighting: Option<Weighting>,
+        gain: AutoGain,
+    ) -> Self {
+        let mut pipeline = Self::new();
+        if let Some(weighting) = weighting {
+            pipeline = pipeline.with_stage(WeightingStage::new(weighting, sample_rate));
+        }
+        pipeline
+            .with_stage(GroupingStage::new(grouping, sample_rate))
+            .with_stage(SmoothingStage::new(smoothing))
+            .with_stage(NormaliseStage(gain))
+    }
+
+    /// The standard stages from samples to bar heights, computing spectra of `fft`'s size
+    pub fn from_samples(
+        fft: FourierTransform,
+        sample_rate: usize,
+        grouping: GroupingStrategy,
+        smoothing: SmoothingStrategy,
+        weighting: Option<Weighting>,
+        gain: AutoGain,
+    ) -> Self {
+        let mut pipeline = Self::bars(sample_rate, grouping, smoothing, weighting, gain);
+        pipeline.stages.splice(
+            0..0,
+            [
+                Box::new(WindowStage::new(fft.fft_size())) as Box<dyn Stage>,
+                Box::new(FftStage(fft)),
+            ],
+        );
+        pipeline
+    }
+
+    /// Adds `stage` after all the others
+    pub fn with_stage(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Runs `stage` just before the stage called `name`, returning false if there's none
+    pub fn insert_before(&mut self, name: &str, stage: impl Stage + 'static) -> bool {
+        self.insert_at(name, 0, stage)
+    }
+
+    /// Runs `stage` just after the stage called `name`, returning false if there's none
+    pub fn insert_after(&mut self, name: &str, stage: impl Stage + 'static) -> bool {
+        self.insert_at(name, 1, stage)
+    }
+
+    fn insert_at(&mut self, name: &str, offset: usize, stage: impl Stage + 'static) -> bool {
+        let Some(index) = self.stages.iter().position(|stage| stage.name() == name) else {
+            return false;
+        };
+        self.stages.insert(index + offset, Box::new(stage));
+        true
+    }
+
+    /// Names of the stages in the order they run
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// Runs `frame` through every stage in turn
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        self.stages
+            .iter_mut()
+            .fold(frame, |frame, stage| stage.process(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const SAMPLE_RATE: usize = 44_100;
+    const FFT_SIZE: usize = 1024;
+
+    fn grouping() -> GroupingStrategy {
+        GroupingStrategy::LogMean { num_groups: 16 }
+    }
+
+    fn smoothing() -> SmoothingStrategy {
+        SmoothingStrategy::RiseFall {
+            rise: 0.5,
+            fall: 0.9,
+        }
+    }
+
+    fn tone(frequency: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (core::f32::consts::TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    fn pipeline() -> Pipeline {
+        Pipeline::from_samples(
+            FourierTransform::new(FFT_SIZE),
+            SAMPLE_RATE,
+            grouping(),
+            smoothing(),
+            Some(Weighting::A),
+            AutoGain::new(0.0, 0.0, 60),
+        )
+    }
+
+    #[test]
+    fn stages_run_in_the_standard_order() {
+        assert_eq!(
+            pipeline().stage_names(),
+            [
+                "window",
+                "fft",
+                "weighting",
+                "grouping",
+                "smoothing",
+                "normalise"
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_running_the_steps_by_hand() {
+        let fft = FourierTransform::new(FFT_SIZE);
+        let grouping = grouping();
+        let ranges = grouping.create_ranges(SAMPLE_RATE, FFT_SIZE);
+        let table = Weighting::A.table(SAMPLE_RATE, bins::bin_count(FFT_SIZE));
+        let mut gain = AutoGain::new(0.0, 0.0, 60);
+        let mut levels: Vec<f32> = Vec::new();
+
+        let mut pipeline = pipeline();
+        for frequency in [220.0, 440.0, 3000.0] {
+            let samples = tone(frequency, FFT_SIZE);
+            let spectrum: Vec<f32> = fft
+                .compute(&samples)
+                .iter()
+                .zip(&table)
+                .map(|(power, gain)| power * gain)
+                .collect();
+            let grouped = grouping.group_spectrum(&spectrum, &ranges);
+            if levels.is_empty() {
+                levels = grouped;
+            } else {
+                smoothing().smooth(&mut levels, &grouped);
+            }
+            let expected = gain.normalise(&levels);
+
+            let bars = pipeline.process(Frame::from_samples(samples)).bars;
+            assert_eq!(bars, expected);
+        }
+    }
+
+    #[test]
+    fn window_keeps_the_latest_samples() {
+        let mut window = WindowStage::new(4);
+        let frame = window.process(Frame::from_samples(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        assert_eq!(frame.samples, [3.0, 4.0, 5.0, 6.0]);
+        let frame = window.process(Frame::from_samples(vec![1.0, 2.0]));
+        assert_eq!(frame.samples, [0.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn middleware_sees_and_changes_the_spectrum() {
+        let seen = Arc::new(Mutex::new(0));
+        let counter = seen.clone();
+        let mut pipeline = pipeline();
+        assert!(pipeline.insert_before(
+            "grouping",
+            FnStage::new("silence", move |mut frame: Frame| {
+                *counter.lock().unwrap() = frame.spectrum.len();
+                frame.spectrum.fill(0.0);
+                frame
+            }),
+        ));
+        assert_eq!(pipeline.stage_names()[3], "silence");
+
+        let bars = pipeline
+            .process(Frame::from_samples(tone(440.0, FFT_SIZE)))
+            .bars;
+        assert_eq!(*seen.lock().unwrap(), bins::bin_count(FFT_SIZE));
+        assert!(bars.iter().all(|&bar| bar == 0.0));
+    }
+
+    #[test]
+    fn inserting_around_a_missing_stage_does_nothing() {
+        let mut pipeline = Pipeline::new();
+        assert!(!pipeline.insert_after("fft", FnStage::new("log", |frame| frame)));
+        assert!(pipeline.stage_names().is_empty());
+    }
+}
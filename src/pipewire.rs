@@ -0,0 +1,400 @@
+//! Native PipeWire capture backend, built with the `pipewire` feature
+//!
+//! Sources are named like PulseAudio's, so configs carry over:
+//!
+//! - `@DEFAULT_MONITOR@` and `@DEFAULT_SOURCE@` follow the default output and input
+//! - `<sink>.monitor` captures what an output is playing, and any other node name that node
+//! - `app:<name>` captures just the output of an application, like `app:firefox`, matched
+//!   against its name when the stream is opened so it's found again after it restarts
+//!
+//! Each capture runs its own PipeWire main loop on a background thread, as PipeWire objects
+//! can't leave the thread they were made on. PipeWire resamples to the rate asked for
+
+use std::{
+    cell::RefCell,
+    io::Cursor,
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use pipewire::{
+    self as pw,
+    context::Context,
+    core::Core,
+    main_loop::MainLoop,
+    properties::properties,
+    spa::{
+        param::{
+            ParamType,
+            audio::{AudioFormat, AudioInfoRaw},
+        },
+        pod::{Object, Pod, Value, serialize::PodSerializer},
+        utils::{Direction, SpaTypes},
+    },
+    stream::{Stream, StreamFlags, StreamState},
+    types::ObjectType,
+};
+
+use crate::audio::{AudioError, CallbackBuffer, Device, RingBuffer};
+
+/// Source following the monitor of whichever output is currently the default
+pub const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+/// Source following whichever input, like a microphone, is currently the default
+pub const DEFAULT_SOURCE: &str = "@DEFAULT_SOURCE@";
+/// Prefix of sources naming an application whose output is captured
+pub const APP_PREFIX: &str = "app:";
+
+const CLIENT_NAME: &str = "AudioVisualiser";
+// Frames the deinterleaving buffers start with room for, enough for any usual quantum
+const QUANTUM_CAPACITY: usize = 8192;
+
+/// A capture stream running on its own thread, copying what it receives into a buffer that
+/// reads are served from
+pub struct PipeWireCapture {
+    buffer: CallbackBuffer,
+    // Frames a second, which PipeWire resamples to
+    sample_rate: usize,
+    // Frames in the last buffer the stream delivered, which PipeWire holds until it's full
+    quantum: Arc<AtomicUsize>,
+    // Cleared once the stream fails or its thread stops
+    running: Arc<AtomicBool>,
+    // Quits the stream's main loop when the capture is dropped
+    quit: pw::channel::Sender<()>,
+}
+
+impl Drop for PipeWireCapture {
+    fn drop(&mut self) {
+        // Fails only if the loop has already quit
+        let _ = self.quit.send(());
+    }
+}
+
+impl PipeWireCapture {
+    /// Fills `buffer` with the next interleaved stereo frames, blocking until they arrive
+    ///
+    /// A stream that's paused, like an application between tracks, reads as silence
+    pub fn read(&self, buffer: &mut [u8]) -> Result<(), AudioError> {
+        // 8 bytes per stereo frame, waiting twice as long as they should take to arrive
+        let timeout = Duration::from_secs_f64(
+            2.0 * (buffer.len() / 8) as f64 / self.sample_rate.max(1) as f64,
+        );
+        if self.buffer.read(buffer, timeout) {
+            return Ok(());
+        }
+        if !self.running.load(Ordering::Acquire) {
+            return Err(AudioError::PipeWireStopped(
+                "the stream was disconnected".to_string(),
+            ));
+        }
+
+        buffer.fill(0);
+        Ok(())
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// A quantum, which is how long the graph takes to deliver each buffer, plus the frames
+    /// waiting to be read
+    pub fn latency(&self) -> Duration {
+        let frames = self.quantum.load(Ordering::Relaxed) + self.buffer.queued();
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// A node in the PipeWire graph, as listed by the registry
+struct Node {
+    class: String,
+    name: String,
+    description: String,
+    application: Option<String>,
+    serial: Option<String>,
+}
+
+fn connect() -> Result<(MainLoop, Context, Core), AudioError> {
+    pw::init();
+    let mainloop = MainLoop::new(None).map_err(AudioError::PipeWire)?;
+    let context = Context::new(&mainloop).map_err(AudioError::PipeWire)?;
+    let core = context.connect(None).map_err(AudioError::PipeWire)?;
+    Ok((mainloop, context, core))
+}
+
+/// Checks a PipeWire server is running and accepting connections
+pub fn probe() -> Result<(), AudioError> {
+    connect().map(drop)
+}
+
+/// Every audio node in the graph: outputs, inputs and application streams
+fn nodes() -> Result<Vec<Node>, AudioError> {
+    let (mainloop, _context, core) = connect()?;
+    let registry = core.get_registry().map_err(AudioError::PipeWire)?;
+
+    let nodes = Rc::new(RefCell::new(Vec::new()));
+    let _registry_listener = registry
+        .add_listener_local()
+        .global({
+            let nodes = nodes.clone();
+            move |global| {
+                if global.type_ != ObjectType::Node {
+                    return;
+                }
+                let Some(props) = global.props else {
+                    return;
+                };
+                let (Some(class), Some(name)) = (props.get("media.class"), props.get("node.name"))
+                else {
+                    return;
+                };
+                nodes.borrow_mut().push(Node {
+                    class: class.to_string(),
+                    name: name.to_string(),
+                    description: props
+                        .get("node.description")
+                        .or_else(|| props.get("application.name"))
+                        .unwrap_or(name)
+                        .to_string(),
+                    application: props.get("application.name").map(str::to_string),
+                    serial: props.get("object.serial").map(str::to_string),
+                });
+            }
+        })
+        .register();
+
+    // The registry has announced everything that existed once the server answers this
+    let pending = core.sync(0).map_err(AudioError::PipeWire)?;
+    let _core_listener = core
+        .add_listener_local()
+        .done({
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    mainloop.run();
+
+    Ok(nodes.take())
+}
+
+/// Lists the monitor of every output, every input, and every application playing audio
+pub fn devices() -> Result<Vec<Device>, AudioError> {
+    let mut devices = Vec::new();
+    for node in nodes()? {
+        match node.class.as_str() {
+            "Audio/Sink" => devices.push(Device {
+                name: format!("{}.monitor", node.name),
+                description: format!("Monitor of {}", node.description),
+                is_monitor: true,
+            }),
+            "Audio/Source" => devices.push(Device {
+                name: node.name,
+                description: node.description,
+                is_monitor: false,
+            }),
+            "Stream/Output/Audio" => {
+                let application = node.application.unwrap_or(node.name);
+                devices.push(Device {
+                    name: format!("{APP_PREFIX}{application}"),
+                    description: format!("Output of {}", node.description),
+                    is_monitor: true,
+                });
+            }
+            _ => (),
+        }
+    }
+    Ok(devices)
+}
+
+/// Object `source` names, if any, and whether it's captured from an output's monitor
+fn target(source: &str) -> Result<(Option<String>, bool), AudioError> {
+    if source == DEFAULT_MONITOR {
+        return Ok((None, true));
+    }
+    if source == DEFAULT_SOURCE {
+        return Ok((None, false));
+    }
+    if let Some(sink) = source.strip_suffix(".monitor") {
+        return Ok((Some(sink.to_string()), true));
+    }
+    let Some(application) = source.strip_prefix(APP_PREFIX) else {
+        return Ok((Some(source.to_string()), false));
+    };
+
+    // Streams come and go with their applications, so they're found again on every open
+    let application = application.to_lowercase();
+    let stream = nodes()?.into_iter().find(|node| {
+        node.class == "Stream/Output/Audio"
+            && node
+                .application
+                .as_deref()
+                .unwrap_or(&node.name)
+                .to_lowercase()
+                .contains(&application)
+    });
+    match stream.and_then(|stream| stream.serial) {
+        Some(serial) => Ok((Some(serial), false)),
+        None => Err(AudioError::NotFound(source.to_string())),
+    }
+}
+
+/// Opens a stereo capture stream on `source`, named as in the module docs
+pub fn open_capture(source: &str, sample_rate: usize) -> Result<PipeWireCapture, AudioError> {
+    let (target, capture_sink) = target(source)?;
+    // A second of audio, so reads can fall well behind before losing any
+    let buffer = CallbackBuffer::new(sample_rate.max(1));
+    let running = Arc::new(AtomicBool::new(true));
+    let quantum = Arc::new(AtomicUsize::new(0));
+    let (quit, quit_receiver) = pw::channel::channel();
+    let (ready, ready_receiver) = mpsc::channel();
+
+    let channels = buffer.channels();
+    let stream_running = running.clone();
+    let stream_quantum = quantum.clone();
+    thread::spawn(move || {
+        let result = run_stream(
+            target,
+            capture_sink,
+            sample_rate,
+            channels,
+            stream_quantum,
+            &stream_running,
+            quit_receiver,
+            &ready,
+        );
+        stream_running.store(false, Ordering::Release);
+        // Only reported here if the stream never started, otherwise reads notice it stopped
+        let _ = ready.send(result);
+    });
+
+    match ready_receiver.recv() {
+        Ok(Ok(())) => Ok(PipeWireCapture {
+            buffer,
+            sample_rate,
+            quantum,
+            running,
+            quit,
+        }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(AudioError::PipeWireStopped(
+            "the stream thread exited".to_string(),
+        )),
+    }
+}
+
+// Runs the stream's main loop until the capture is dropped or the connection fails, sending
+// `Ok` through `ready` once the stream is connected
+fn run_stream(
+    target: Option<String>,
+    capture_sink: bool,
+    sample_rate: usize,
+    channels: [Arc<RingBuffer>; 2],
+    quantum: Arc<AtomicUsize>,
+    running: &Arc<AtomicBool>,
+    quit: pw::channel::Receiver<()>,
+    ready: &mpsc::Sender<Result<(), AudioError>>,
+) -> Result<(), AudioError> {
+    let (mainloop, _context, core) = connect()?;
+
+    let mut props = properties! {
+        "media.type" => "Audio",
+        "media.category" => "Capture",
+        "media.role" => "Music",
+        "node.name" => CLIENT_NAME,
+    };
+    if capture_sink {
+        props.insert("stream.capture.sink", "true");
+    }
+    if let Some(target) = &target {
+        props.insert("target.object", target.as_str());
+    }
+
+    let stream = Stream::new(&core, CLIENT_NAME, props).map_err(AudioError::PipeWire)?;
+    let deinterleaved = (
+        Vec::with_capacity(QUANTUM_CAPACITY),
+        Vec::with_capacity(QUANTUM_CAPACITY),
+    );
+    let _listener = stream
+        .add_local_listener_with_user_data(deinterleaved)
+        .state_changed({
+            let (mainloop, running) = (mainloop.clone(), running.clone());
+            move |_, _, _, state| {
+                if let StreamState::Error(e) = &state {
+                    eprintln!("PipeWire capture failed: {e}");
+                }
+                if matches!(state, StreamState::Error(_) | StreamState::Unconnected) {
+                    running.store(false, Ordering::Release);
+                    mainloop.quit();
+                }
+            }
+        })
+        .process(move |stream, (left, right): &mut (Vec<f32>, Vec<f32>)| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let Some(data) = buffer.datas_mut().first_mut() else {
+                return;
+            };
+            let size = data.chunk().size() as usize;
+            let Some(bytes) = data.data() else {
+                return;
+            };
+
+            left.clear();
+            right.clear();
+            // 8 bytes per stereo frame, in the little-endian format asked for below
+            for frame in bytes[..size.min(bytes.len())].chunks_exact(8) {
+                left.push(f32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]));
+                right.push(f32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]));
+            }
+            channels[0].push(left);
+            channels[1].push(right);
+            quantum.store(left.len(), Ordering::Relaxed);
+        })
+        .register()
+        .map_err(AudioError::PipeWire)?;
+
+    let mut format = AudioInfoRaw::new();
+    format.set_format(AudioFormat::F32LE);
+    format.set_rate(sample_rate as u32);
+    format.set_channels(2);
+    let format = PodSerializer::serialize(
+        Cursor::new(Vec::new()),
+        &Value::Object(Object {
+            type_: SpaTypes::ObjectParamFormat.as_raw(),
+            id: ParamType::EnumFormat.as_raw(),
+            properties: format.into(),
+        }),
+    )
+    .expect("a raw audio format always serialises")
+    .0
+    .into_inner();
+    let mut params = [Pod::from_bytes(&format).expect("the format was just serialised")];
+
+    stream
+        .connect(
+            Direction::Input,
+            None,
+            StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS,
+            &mut params,
+        )
+        .map_err(AudioError::PipeWire)?;
+
+    let _quit = quit.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |()| mainloop.quit()
+    });
+    // Nothing's waiting if the capture was dropped already, and the loop quits straight away
+    let _ = ready.send(Ok(()));
+    mainloop.run();
+
+    Ok(())
+}
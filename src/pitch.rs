@@ -0,0 +1,172 @@
+use crate::{bins, chroma::frequency_to_harmonic_product_spectrum, spectra::chroma_index_to_note};
+
+/// Converts a frequency in Hz to a (fractional) MIDI pitch, where 69.0 is A4 (440Hz)
+pub fn frequency_to_midi(frequency: f32) -> f32 {
+    69.0 + 12.0 * (frequency / 440.0).log2()
+}
+
+/// Names a MIDI pitch with its octave, e.g. 69 -> "A4"
+pub fn midi_to_note_name(pitch: usize) -> String {
+    format!(
+        "{}{}",
+        chroma_index_to_note(pitch % 12),
+        (pitch / 12) as i32 - 1
+    )
+}
+
+/// Signed distance from `pitch` to `target` in cents (hundredths of a semitone)
+pub fn cents_deviation(pitch: f32, target: f32) -> f32 {
+    (pitch - target) * 100.0
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScaleKind {
+    Chromatic,
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+}
+
+impl ScaleKind {
+    /// Semitone offsets from the root that belong to the scale
+    fn intervals(&self) -> &'static [usize] {
+        match self {
+            ScaleKind::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Blues => &[0, 3, 5, 6, 7, 10],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleKind::Chromatic => "chromatic",
+            ScaleKind::Major => "major",
+            ScaleKind::NaturalMinor => "minor",
+            ScaleKind::HarmonicMinor => "harmonic minor",
+            ScaleKind::MajorPentatonic => "major pentatonic",
+            ScaleKind::MinorPentatonic => "minor pentatonic",
+            ScaleKind::Blues => "blues",
+        }
+    }
+}
+
+/// A musical key: a root chroma (0 = C ... 11 = B) and a scale built on top of it
+#[derive(Clone, Copy, PartialEq)]
+pub struct Scale {
+    pub root: usize,
+    pub kind: ScaleKind,
+}
+
+impl Scale {
+    pub fn new(root: usize, kind: ScaleKind) -> Self {
+        Self {
+            root: root % 12,
+            kind,
+        }
+    }
+
+    pub fn contains(&self, pitch: usize) -> bool {
+        let offset = (pitch + 12 - self.root) % 12;
+        self.kind.intervals().contains(&offset)
+    }
+
+    /// e.g. "A minor"
+    pub fn name(&self) -> String {
+        format!("{} {}", chroma_index_to_note(self.root), self.kind.name())
+    }
+
+    pub fn is_root(&self, pitch: usize) -> bool {
+        pitch % 12 == self.root
+    }
+
+    /// Finds the in-scale MIDI note closest to a fractional `pitch`
+    pub fn nearest_note(&self, pitch: f32) -> usize {
+        let rounded = pitch.round().max(0.0) as usize;
+
+        // Scale degrees are never more than 3 semitones apart, so a small search suffices
+        (rounded.saturating_sub(3)..=rounded + 3)
+            .filter(|&p| self.contains(p))
+            .min_by(|&a, &b| {
+                (a as f32 - pitch)
+                    .abs()
+                    .total_cmp(&(b as f32 - pitch).abs())
+            })
+            .unwrap_or(rounded)
+    }
+}
+
+/// Monophonic pitch tracker based on the Harmonic Product Spectrum
+///
+/// Intended for a single dominant voice (e.g. a singer over a backing track), so
+/// it only searches within `min_freq..max_freq` and reports `None` when the peak
+/// isn't prominent enough to be considered voiced
+pub struct PitchTracker {
+    sampling_rate: usize,
+    min_freq: f32,
+    max_freq: f32,
+    harmonics: usize,
+    // How many times larger than the average the HPS peak must be to count as a pitch
+    voicing_threshold: f32,
+}
+
+impl PitchTracker {
+    pub fn new(sampling_rate: usize) -> Self {
+        Self {
+            sampling_rate,
+            min_freq: 80.0,
+            max_freq: 1000.0,
+            harmonics: 3,
+            voicing_threshold: 8.0,
+        }
+    }
+
+    /// Estimates the fundamental frequency in Hz of the strongest voice in `spectrum`
+    ///
+    /// `spectrum` is laid out as described in `bins`
+    pub fn estimate(&self, spectrum: &[f32]) -> Option<f32> {
+        if spectrum.is_empty() {
+            return None;
+        }
+
+        let freq_per_bin = bins::bin_width(spectrum.len(), self.sampling_rate);
+        let hps = frequency_to_harmonic_product_spectrum(spectrum, self.harmonics);
+
+        let start = ((self.min_freq / freq_per_bin).floor() as usize).max(1);
+        let end = ((self.max_freq / freq_per_bin).ceil() as usize).min(hps.len().saturating_sub(1));
+
+        if start >= end {
+            return None;
+        }
+
+        let search = &hps[start..end];
+        let (peak_offset, &peak) = search
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))?;
+        let mean = search.iter().sum::<f32>() / search.len() as f32;
+
+        if peak <= 0.0 || peak < mean * self.voicing_threshold {
+            return None;
+        }
+
+        let peak_bin = start + peak_offset;
+
+        // Parabolic interpolation between neighbouring bins for sub-bin accuracy
+        let (left, right) = (hps[peak_bin - 1], hps[peak_bin + 1]);
+        let denominator = left - 2.0 * peak + right;
+        let shift = if denominator.abs() > f32::EPSILON {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        Some((peak_bin as f32 + shift) * freq_per_bin)
+    }
+}
@@ -0,0 +1,138 @@
+//! Named combinations of grouping, smoothing, colour, mode and sensitivity, switched between
+//! live with the number keys
+//!
+//! Presets come from `[[preset]]` tables in the config, then from each `.toml` file in
+//! `preset_dir` in name order, which hold a single preset's settings at the top level. A
+//! preset keeps the current value of anything it leaves out:
+//!
+//! ```toml
+//! [[preset]]
+//! name = "punchy"
+//! mode = "bars"
+//! grouping = { strategy = "log-max", bars = 32 }
+//! smoothing = { rise = 0.2, fall = 0.8 }
+//! colour = { mapper = "gradient", by = "amplitude" }
+//! gain = 6.0
+//! normalisation = { amplitude = "dbfs", floor = -60.0 }
+//! ```
+//!
+//! `gain` and `normalisation` set how strongly the bars react, like `gain` and the
+//! `amplitude`, `floor` and `agc` settings in `[bars]`, so a subtle preset can be calmer than
+//! an aggressive one as well as look different
+//!
+//! With no presets configured, a few built-in ones are used instead
+
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    config::{
+        ColourConfig, Config, ConfigError, GroupingConfig, GroupingKind, NormalisationConfig,
+        SmoothingConfig,
+    },
+    grouping::DEFAULT_GAMMA,
+    visualiser::DisplayMode,
+};
+
+/// One `[[preset]]` table or preset file
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PresetConfig {
+    /// Taken from the file name for preset files that leave it out
+    #[serde(default)]
+    pub name: String,
+    pub mode: Option<String>,
+    pub grouping: Option<GroupingConfig>,
+    pub smoothing: Option<SmoothingConfig>,
+    pub colour: Option<ColourConfig>,
+    /// Decibels added to the spectrum before grouping
+    pub gain: Option<f32>,
+    pub normalisation: Option<NormalisationConfig>,
+}
+
+impl PresetConfig {
+    /// Parses and validates the contents of a preset file
+    pub fn parse(source: &str) -> Result<Self, ConfigError> {
+        let preset: PresetConfig = toml::from_str(source)?;
+        preset.validate()?;
+        Ok(preset)
+    }
+
+    /// Fails on a mode or colour that can't be used
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(mode) = &self.mode
+            && DisplayMode::from_name(mode).is_none()
+        {
+            return Err(ConfigError::Invalid(format!("unknown mode `{mode}`")));
+        }
+        if let Some(colour) = &self.colour {
+            colour.validate().map_err(ConfigError::Invalid)?;
+        }
+        Ok(())
+    }
+}
+
+/// The presets in `config`, then those in its `preset_dir`, or the built-in ones if there
+/// are none
+pub fn load(config: &Config) -> Result<Vec<PresetConfig>, ConfigError> {
+    let mut presets = config.presets.clone();
+    if let Some(dir) = &config.preset_dir {
+        presets.extend(load_dir(dir)?);
+    }
+
+    if presets.is_empty() {
+        presets = built_in();
+    }
+    Ok(presets)
+}
+
+fn load_dir(dir: &Path) -> Result<Vec<PresetConfig>, ConfigError> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "toml")
+    });
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let mut preset = PresetConfig::parse(&fs::read_to_string(path)?)
+                .map_err(|e| ConfigError::Invalid(format!("in preset {}: {e}", path.display())))?;
+            if preset.name.is_empty() {
+                preset.name = path
+                    .file_stem()
+                    .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+            }
+            Ok(preset)
+        })
+        .collect()
+}
+
+/// Presets used when none are configured
+pub fn built_in() -> Vec<PresetConfig> {
+    let preset = |name: &str, mode: &str, bars, (rise, fall), colour: &str| PresetConfig {
+        name: name.to_string(),
+        mode: Some(mode.to_string()),
+        grouping: Some(GroupingConfig {
+            strategy: GroupingKind::LogMax,
+            bars,
+            gamma: DEFAULT_GAMMA,
+        }),
+        smoothing: Some(SmoothingConfig { rise, fall }),
+        colour: ColourConfig::from_name(colour),
+        gain: None,
+        normalisation: None,
+    };
+
+    vec![
+        preset("classic", "bars", 12, (0.5, 0.9), "static"),
+        preset("detailed", "bars", 48, (0.3, 0.85), "gradient"),
+        preset("smooth", "bars", 24, (0.8, 0.97), "palette"),
+        preset("chroma", "chromagram", 12, (0.5, 0.9), "chromagram"),
+        preset("spectrogram", "spectrogram", 12, (0.5, 0.9), "bar-chroma"),
+    ]
+}
@@ -0,0 +1,205 @@
+use macroquad::{
+    color::Color,
+    material::{Material, MaterialParams, gl_use_default_material, gl_use_material, load_material},
+    math::{Rect, vec4},
+    miniquad::{BlendFactor, BlendState, BlendValue, Equation, PipelineParams, ShaderSource},
+    models::{Mesh, Vertex, draw_mesh},
+};
+
+// Quads per draw call, keeping within macroquad's default batch size of 5000 indices
+const QUADS_PER_DRAW: usize = 800;
+// Pixels added around anti-aliased shapes so their soft edges aren't clipped by the quad
+const AA_MARGIN: f32 = 1.0;
+
+/// Shape bars are drawn with
+#[derive(Clone, Copy)]
+pub enum BarStyle {
+    /// Plain rectangles, as cheap as possible
+    Square,
+    /// Anti-aliased rectangles with corners rounded to `radius` pixels. A radius of 0.0
+    /// gives anti-aliased square bars that hold up better than `Square` at thin widths
+    Rounded { radius: f32 },
+    /// Fully rounded ends, with a radius of half the bar width
+    Capsule,
+}
+
+/// Batches bars into a single mesh and draws them in the chosen `BarStyle`
+///
+/// Rounded styles are drawn as signed distance fields: each quad carries its own size and
+/// corner radius in the vertex normal, and the fragment shader turns the distance to the
+/// shape's edge into coverage for a one pixel anti-aliased edge. Builds without the
+/// `shaders` feature always draw `Square` bars
+pub struct BarRenderer {
+    style: BarStyle,
+    material: Option<Material>,
+}
+
+impl BarRenderer {
+    pub fn new(style: BarStyle) -> Result<Self, macroquad::Error> {
+        if matches!(style, BarStyle::Square) || !cfg!(feature = "shaders") {
+            return Ok(Self {
+                style: BarStyle::Square,
+                material: None,
+            });
+        }
+
+        let blend = BlendState::new(
+            Equation::Add,
+            BlendFactor::Value(BlendValue::SourceAlpha),
+            BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+        );
+
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: SDF_VERTEX_SHADER,
+                fragment: SDF_FRAGMENT_SHADER,
+            },
+            MaterialParams {
+                pipeline_params: PipelineParams {
+                    color_blend: Some(blend),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            style,
+            material: Some(material),
+        })
+    }
+
+    /// Whether bars get soft edges, and so can be placed at sub-pixel positions
+    pub fn is_anti_aliased(&self) -> bool {
+        !matches!(self.style, BarStyle::Square)
+    }
+
+    pub fn new_mesh() -> Mesh {
+        Mesh {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            texture: None,
+        }
+    }
+
+    /// Adds a bar covering `rect` to `mesh`, its colour fading from `top` to `bottom`
+    pub fn push(&self, mesh: &mut Mesh, rect: Rect, top: Color, bottom: Color) {
+        let Rect {
+            x,
+            y,
+            w: width,
+            h: height,
+        } = rect;
+        let radius = match self.style {
+            BarStyle::Square => None,
+            BarStyle::Rounded { radius } => Some(radius),
+            BarStyle::Capsule => Some(width / 2.0),
+        };
+
+        // Indices count from the start of the bar's draw call rather than the whole mesh, so
+        // they fit in a u16 however many bars there are
+        let first = (mesh.vertices.len() % (QUADS_PER_DRAW * 4)) as u16;
+
+        match radius {
+            None => mesh.vertices.extend([
+                Vertex::new(x, y, 0.0, 0.0, 0.0, top),
+                Vertex::new(x + width, y, 0.0, 1.0, 0.0, top),
+                Vertex::new(x + width, y + height, 0.0, 1.0, 1.0, bottom),
+                Vertex::new(x, y + height, 0.0, 0.0, 1.0, bottom),
+            ]),
+            Some(radius) => {
+                let (half_width, half_height) = (width / 2.0, height / 2.0);
+                let radius = radius.min(half_width).min(half_height).max(0.0);
+                let shape = vec4(half_width, half_height, radius, 0.0);
+
+                // Texture coordinates are the offset from the bar's centre in pixels
+                let (left, right) = (-half_width - AA_MARGIN, half_width + AA_MARGIN);
+                let (upper, lower) = (-half_height - AA_MARGIN, half_height + AA_MARGIN);
+                let (centre_x, centre_y) = (x + half_width, y + half_height);
+
+                mesh.vertices.extend(
+                    [
+                        (left, upper, top),
+                        (right, upper, top),
+                        (right, lower, bottom),
+                        (left, lower, bottom),
+                    ]
+                    .map(|(u, v, colour)| Vertex {
+                        normal: shape,
+                        ..Vertex::new(centre_x + u, centre_y + v, 0.0, u, v, colour)
+                    }),
+                );
+            }
+        }
+
+        mesh.indices
+            .extend([0, 1, 2, 0, 2, 3].map(|offset| first + offset));
+    }
+
+    pub fn draw(&self, mesh: &Mesh) {
+        if mesh.vertices.is_empty() {
+            return;
+        }
+
+        if let Some(material) = &self.material {
+            gl_use_material(material);
+        }
+
+        let quads = mesh.vertices.len() / 4;
+        if quads <= QUADS_PER_DRAW {
+            draw_mesh(mesh);
+        } else {
+            for start in (0..quads).step_by(QUADS_PER_DRAW) {
+                let end = (start + QUADS_PER_DRAW).min(quads);
+
+                draw_mesh(&Mesh {
+                    vertices: mesh.vertices[start * 4..end * 4].to_vec(),
+                    indices: mesh.indices[start * 6..end * 6].to_vec(),
+                    texture: None,
+                });
+            }
+        }
+
+        if self.material.is_some() {
+            gl_use_default_material();
+        }
+    }
+}
+
+const SDF_VERTEX_SHADER: &str = "#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+attribute vec4 normal;
+
+varying mediump vec2 local;
+varying lowp vec4 color;
+varying mediump vec4 shape;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    local = texcoord;
+    shape = normal;
+}
+";
+
+const SDF_FRAGMENT_SHADER: &str = "#version 100
+precision mediump float;
+
+varying vec2 local;
+varying vec4 color;
+// Half width, half height and corner radius
+varying vec4 shape;
+
+void main() {
+    vec2 q = abs(local) - shape.xy + shape.z;
+    float d = length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - shape.z;
+    float coverage = clamp(0.5 - d, 0.0, 1.0);
+
+    gl_FragColor = vec4(color.rgb, color.a * coverage);
+}
+";
@@ -0,0 +1,181 @@
+//! PulseAudio capture backend, built with the `pulseaudio` feature
+
+use std::{cell::RefCell, rc::Rc};
+
+use psimple::Simple;
+use pulse::{
+    callbacks::ListResult,
+    context::{Context, FlagSet, State, introspect::ServerInfo},
+    def::BufferAttr,
+    error::PAErr,
+    mainloop::standard::{IterateResult, Mainloop},
+    sample::{Format, Spec},
+    stream::Direction,
+};
+
+use crate::audio::{AudioError, Device};
+
+/// PulseAudio's name for the monitor of whichever output is currently the default
+pub const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+/// PulseAudio's name for whichever input, like a microphone, is currently the default
+pub const DEFAULT_SOURCE: &str = "@DEFAULT_SOURCE@";
+
+/// Opens a low latency stereo capture stream on the source called `source_name`
+pub fn open_capture(source_name: &str, sample_rate: usize) -> Result<Simple, AudioError> {
+    let spec = Spec {
+        format: Format::FLOAT32NE,
+        channels: 2,
+        rate: sample_rate as u32,
+    };
+    if !spec.is_valid() {
+        return Err(AudioError::Stream(
+            source_name.to_string(),
+            PAErr::from(pulse::error::Code::Invalid),
+        ));
+    }
+    // Set lower latency (smaller buffer size)
+    let buffer_attr = BufferAttr {
+        maxlength: u32::MAX, // Let PulseAudio decide max size
+        tlength: u32::MAX,   // Only used for playback
+        prebuf: u32::MAX,    // Only used for playback
+        minreq: u32::MAX,    // Only used for playback
+        fragsize: 1024,      // Lower = lower latency (used for recording)
+    };
+
+    Simple::new(
+        None,               // Use the default server
+        "AudioVisualiser",  // Our application's name
+        Direction::Record,  // We want a recording stream
+        Some(source_name),  // Use a monitor source
+        "Audio Monitor",    // Description of our stream
+        &spec,              // Our sample format
+        None,               // Use default channel map
+        Some(&buffer_attr), // Use default buffering attributes
+    )
+    .map_err(|e| AudioError::Stream(source_name.to_string(), e))
+}
+
+/// Connects to PulseAudio, blocking until the connection is ready for queries
+fn connect() -> Result<(Mainloop, Context), AudioError> {
+    let mut mainloop = Mainloop::new().ok_or(AudioError::Disconnected)?;
+    let mut context = Context::new(&mainloop, "AudioVisualiser").ok_or(AudioError::Disconnected)?;
+    context
+        .connect(None, FlagSet::NOFLAGS, None)
+        .map_err(AudioError::Connection)?;
+
+    loop {
+        iterate(&mut mainloop)?;
+        match context.get_state() {
+            State::Ready => break,
+            State::Failed | State::Terminated => return Err(AudioError::Disconnected),
+            _ => (),
+        }
+    }
+
+    Ok((mainloop, context))
+}
+
+/// Checks a PulseAudio server is running and accepting connections
+pub fn probe() -> Result<(), AudioError> {
+    let (_mainloop, mut context) = connect()?;
+    context.disconnect();
+    Ok(())
+}
+
+/// Lists every capture and monitor source PulseAudio knows about
+pub fn devices() -> Result<Vec<Device>, AudioError> {
+    let (mut mainloop, mut context) = connect()?;
+
+    let devices = Rc::new(RefCell::new(Vec::new()));
+    let done = Rc::new(RefCell::new(false));
+    let _operation = context.introspect().get_source_info_list({
+        let (devices, done) = (devices.clone(), done.clone());
+        move |result| match result {
+            ListResult::Item(info) => devices.borrow_mut().push(Device {
+                name: info.name.as_deref().unwrap_or_default().to_string(),
+                description: info.description.as_deref().unwrap_or_default().to_string(),
+                is_monitor: info.monitor_of_sink.is_some(),
+            }),
+            ListResult::End | ListResult::Error => *done.borrow_mut() = true,
+        }
+    });
+
+    while !*done.borrow() {
+        iterate(&mut mainloop)?;
+    }
+    context.disconnect();
+
+    Ok(devices.take())
+}
+
+/// Sample rate of the source called `source_name`, which can be `DEFAULT_MONITOR` or
+/// `DEFAULT_SOURCE`
+pub fn source_rate(source_name: &str) -> Result<usize, AudioError> {
+    let (mut mainloop, mut context) = connect()?;
+
+    let rate = Rc::new(RefCell::new(None));
+    let done = Rc::new(RefCell::new(false));
+    let _operation = context.introspect().get_source_info_by_name(source_name, {
+        let (rate, done) = (rate.clone(), done.clone());
+        move |result| match result {
+            ListResult::Item(info) => *rate.borrow_mut() = Some(info.sample_spec.rate as usize),
+            ListResult::End | ListResult::Error => *done.borrow_mut() = true,
+        }
+    });
+
+    while !*done.borrow() {
+        iterate(&mut mainloop)?;
+    }
+    context.disconnect();
+
+    rate.take()
+        .ok_or_else(|| AudioError::NotFound(source_name.to_string()))
+}
+
+/// Real name of `DEFAULT_MONITOR` or `DEFAULT_SOURCE`, or any other source as it is
+pub fn resolve_default(source: &str) -> Result<String, AudioError> {
+    match source {
+        DEFAULT_MONITOR => default_monitor(),
+        DEFAULT_SOURCE => default_input(),
+        _ => Ok(source.to_string()),
+    }
+}
+
+/// Name of the monitor source of the current default output
+pub fn default_monitor() -> Result<String, AudioError> {
+    let sink = server_default(|info| info.default_sink_name.as_deref().map(str::to_string))?;
+    Ok(format!("{sink}.monitor"))
+}
+
+/// Name of the current default input source
+pub fn default_input() -> Result<String, AudioError> {
+    server_default(|info| info.default_source_name.as_deref().map(str::to_string))
+}
+
+/// Picks a default device's name out of the server info
+fn server_default(
+    pick: impl Fn(&ServerInfo) -> Option<String> + 'static,
+) -> Result<String, AudioError> {
+    let (mut mainloop, mut context) = connect()?;
+
+    let name = Rc::new(RefCell::new(None));
+    let _operation = context.introspect().get_server_info({
+        let name = name.clone();
+        move |info| *name.borrow_mut() = Some(pick(info).unwrap_or_default())
+    });
+
+    while name.borrow().is_none() {
+        iterate(&mut mainloop)?;
+    }
+    context.disconnect();
+
+    Ok(name.take().unwrap_or_default())
+}
+
+fn iterate(mainloop: &mut Mainloop) -> Result<(), AudioError> {
+    match mainloop.iterate(true) {
+        IterateResult::Success(_) => Ok(()),
+        IterateResult::Quit(_) => Err(AudioError::Disconnected),
+        IterateResult::Err(e) => Err(AudioError::Connection(e)),
+    }
+}
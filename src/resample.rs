@@ -0,0 +1,50 @@
+use alloc::vec::Vec;
+
+/// Converts a stream of samples from one sample rate to another by linear interpolation,
+/// for devices that deliver audio at a different rate to the one being analysed
+///
+/// Samples can be given in blocks of any length, carrying on smoothly from the last block.
+/// There's no low-pass filter, so downsampling folds anything above the new Nyquist
+/// frequency back down, but that's little more than the top of the audible range between
+/// the usual 48kHz and 44.1kHz
+pub struct Resampler {
+    // Input samples for each output sample
+    step: f64,
+    // Position of the next output sample in input samples, counting from `previous`
+    position: f64,
+    // Last sample of the previous block
+    previous: f32,
+}
+
+impl Resampler {
+    pub fn new(from_rate: usize, to_rate: usize) -> Self {
+        Self {
+            step: from_rate.max(1) as f64 / to_rate.max(1) as f64,
+            position: 0.0,
+            previous: 0.0,
+        }
+    }
+
+    /// Appends the resampled `input` to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        // `previous` sits at position 0 and `input[i]` at position i + 1, so every output
+        // sample lies between two known samples
+        while self.position < input.len() as f64 {
+            // Never negative, so truncating is rounding down
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let before = if index == 0 {
+                self.previous
+            } else {
+                input[index - 1]
+            };
+            output.push(before + (input[index] - before) * fraction);
+            self.position += self.step;
+        }
+
+        self.position -= input.len() as f64;
+        if let Some(&last) = input.last() {
+            self.previous = last;
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Switching presets on a schedule, so an installation doesn't look the same all day
+//!
+//! ```toml
+//! [rotation]
+//! every = "30 minutes"
+//! presets = ["classic", "smooth", "chroma"]
+//! transition = 2.0
+//! ```
+//!
+//! `every` is `"hour"` for the top of each hour, `"track"` whenever the playing track
+//! changes, or a number of minutes. Presets are named as in `presets`, all of them in order
+//! if left out. Each switch dips to black over `transition` seconds, changing preset while
+//! the screen is dark
+
+use macroquad::{
+    color::Color,
+    shapes::draw_rectangle,
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+
+use crate::{config::ConfigError, mpris::NowPlaying, presets::PresetConfig, schedule::TimeOfDay};
+
+/// When the next preset is switched to
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RotationInterval {
+    Hour,
+    Track,
+    Minutes(f64),
+}
+
+impl TryFrom<String> for RotationInterval {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "hour" => Ok(RotationInterval::Hour),
+            "track" => Ok(RotationInterval::Track),
+            _ => value
+                .strip_suffix("minutes")
+                .or_else(|| value.strip_suffix("minute"))
+                .and_then(|minutes| minutes.trim().parse::<f64>().ok())
+                .filter(|&minutes| minutes > 0.0)
+                .map(RotationInterval::Minutes)
+                .ok_or_else(|| {
+                    format!("`{value}` isn't \"hour\", \"track\" or a time like \"15 minutes\"")
+                }),
+        }
+    }
+}
+
+/// The `[rotation]` table, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RotationConfig {
+    pub every: RotationInterval,
+    /// Names of the presets rotated through, or all of them if empty
+    #[serde(default)]
+    pub presets: Vec<String>,
+    /// Seconds each switch takes, fading to black and back
+    #[serde(default = "default_transition")]
+    pub transition: f64,
+}
+
+fn default_transition() -> f64 {
+    2.0
+}
+
+// A switch under way
+struct Transition {
+    start: f64,
+    // Index into the loaded presets
+    preset: usize,
+    switched: bool,
+}
+
+/// Decides when to move on to the next preset, and draws the transition
+pub struct Rotation {
+    config: RotationConfig,
+    // Indices into the loaded presets, in the order they're shown
+    order: Vec<usize>,
+    // Position in `order` of the preset last switched to
+    position: usize,
+    last_switch: Option<f64>,
+    last_hour: Option<u32>,
+    last_track: Option<(String, String)>,
+    transition: Option<Transition>,
+}
+
+impl Rotation {
+    /// Fails if `config` names a preset that isn't in `presets`
+    pub fn new(config: RotationConfig, presets: &[PresetConfig]) -> Result<Self, ConfigError> {
+        let order: Vec<usize> = if config.presets.is_empty() {
+            (0..presets.len()).collect()
+        } else {
+            config
+                .presets
+                .iter()
+                .map(|name| {
+                    presets
+                        .iter()
+                        .position(|preset| &preset.name == name)
+                        .ok_or_else(|| {
+                            ConfigError::Invalid(format!("no preset `{name}` to rotate through"))
+                        })
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        Ok(Self {
+            config,
+            // So the first switch is to the first preset
+            position: order.len().saturating_sub(1),
+            order,
+            last_switch: None,
+            last_hour: None,
+            last_track: None,
+            transition: None,
+        })
+    }
+
+    /// Starts a transition when the next preset is due at `time` seconds, and returns the
+    /// index of the preset to apply once it's halfway through
+    pub fn update(&mut self, time: f64, track: Option<&NowPlaying>) -> Option<usize> {
+        if self.order.is_empty() {
+            return None;
+        }
+
+        if self.transition.is_none() && self.is_due(time, track) {
+            self.position = (self.position + 1) % self.order.len();
+            self.last_switch = Some(time);
+            self.transition = Some(Transition {
+                start: time,
+                preset: self.order[self.position],
+                switched: false,
+            });
+        }
+
+        let transition = self.transition.as_mut()?;
+        let progress = (time - transition.start) / self.config.transition.max(f64::EPSILON);
+        if progress >= 1.0 {
+            self.transition = None;
+            return None;
+        }
+        if progress >= 0.5 && !transition.switched {
+            transition.switched = true;
+            return Some(transition.preset);
+        }
+        None
+    }
+
+    // Whether the interval has passed, starting the first one from the first update
+    fn is_due(&mut self, time: f64, track: Option<&NowPlaying>) -> bool {
+        match self.config.every {
+            RotationInterval::Minutes(minutes) => {
+                let last_switch = *self.last_switch.get_or_insert(time);
+                time - last_switch >= minutes * 60.0
+            }
+            RotationInterval::Hour => {
+                let hour = TimeOfDay::now().hour();
+                self.last_hour
+                    .replace(hour)
+                    .is_some_and(|last| last != hour)
+            }
+            RotationInterval::Track => {
+                // Pauses between tracks don't count as a change
+                let Some(track) = track else {
+                    return false;
+                };
+                let current = (track.artist.clone(), track.title.clone());
+                self.last_track
+                    .replace(current.clone())
+                    .is_some_and(|last| last != current)
+            }
+        }
+    }
+
+    /// Darkens the frame during a transition, fully black at its midpoint
+    pub fn draw(&self, time: f64) {
+        let Some(transition) = &self.transition else {
+            return;
+        };
+        let progress = (time - transition.start) / self.config.transition.max(f64::EPSILON);
+        let darkness = 1.0 - (2.0 * progress - 1.0).abs();
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            screen_height(),
+            Color::new(0.0, 0.0, 0.0, darkness.clamp(0.0, 1.0) as f32),
+        );
+    }
+}
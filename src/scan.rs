@@ -0,0 +1,146 @@
+//! Batch analysis of a music library, for tagging files with their BPM, key and loudness
+//!
+//! Every WAV file under a directory is analysed on a pool of threads with the same
+//! `TrackAnalyser` the session log uses live, and the results written out as a JSON array:
+//!
+//! ```text
+//! rust-audio-visualiser scan ~/Music --output library.json
+//! ```
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    spectra::FourierTransform,
+    tracklog::{TrackAnalyser, TrackStats, json_string},
+    wav,
+};
+
+/// Result of analysing one file
+pub struct ScannedFile {
+    pub path: PathBuf,
+    pub stats: Result<TrackStats, hound::Error>,
+}
+
+/// Every WAV file under `dir` and its subdirectories, in path order
+pub fn find_audio_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"))
+            {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Analyses a whole WAV file in windows of `fft_size` samples, `hop_size` apart
+pub fn analyse_file(
+    path: &Path,
+    fft_size: usize,
+    hop_size: usize,
+) -> Result<TrackStats, hound::Error> {
+    let (samples, sample_rate) = wav::read_mono(path)?;
+    let fft = FourierTransform::new(fft_size).with_hop_size(hop_size);
+    let mut analyser = TrackAnalyser::new(sample_rate, (sample_rate / hop_size).max(1));
+
+    for end in (fft_size..=samples.len()).step_by(hop_size) {
+        let window = &samples[end - fft_size..end];
+        analyser.update(window, &fft.compute(window));
+    }
+
+    Ok(analyser.finish())
+}
+
+/// Analyses `files` on `jobs` threads, calling `progress` as each one finishes. Results
+/// are in the same order as `files`
+pub fn scan(
+    files: &[PathBuf],
+    fft_size: usize,
+    hop_size: usize,
+    jobs: usize,
+    progress: impl Fn(&ScannedFile) + Sync,
+) -> Vec<ScannedFile> {
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, ScannedFile)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, files.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut scanned = Vec::new();
+                    // Files are taken one at a time, so a few long ones don't hold up a thread
+                    // that was given them all up front
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(index) else {
+                            break;
+                        };
+                        let file = ScannedFile {
+                            path: path.clone(),
+                            stats: analyse_file(path, fft_size, hop_size),
+                        };
+                        progress(&file);
+                        scanned.push((index, file));
+                    }
+                    scanned
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect()
+    });
+
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, file)| file).collect()
+}
+
+/// Writes the files that could be analysed to `path` as a JSON array of objects, one per
+/// file, with the same fields as the session log's JSON lines
+pub fn write_json(path: &Path, files: &[ScannedFile]) -> io::Result<()> {
+    let mut output = io::BufWriter::new(fs::File::create(path)?);
+    writeln!(output, "[")?;
+
+    let analysed: Vec<_> = files
+        .iter()
+        .filter_map(|file| Some((&file.path, file.stats.as_ref().ok()?)))
+        .collect();
+    for (index, (path, stats)) in analysed.iter().enumerate() {
+        let separator = if index + 1 < analysed.len() { "," } else { "" };
+        writeln!(
+            output,
+            "  {{\"path\":{},\"duration_s\":{:.0},\"bpm\":{},\"key\":{},\"mean_loudness_dbfs\":{:.1},\"peak_loudness_dbfs\":{:.1}}}{separator}",
+            json_string(&path.to_string_lossy()),
+            stats.duration,
+            stats
+                .bpm
+                .map_or(String::from("null"), |bpm| format!("{bpm:.1}")),
+            stats
+                .key
+                .map_or(String::from("null"), |key| json_string(&key.name())),
+            stats.mean_loudness,
+            stats.peak_loudness,
+        )?;
+    }
+
+    writeln!(output, "]")?;
+    output.flush()
+}
@@ -0,0 +1,209 @@
+use std::{
+    env, fmt, fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+use crate::{config::config_dir, idle::IdleConfig};
+
+/// `time` broken down into the date and time in the local time zone
+pub(crate) fn local_time(time: SystemTime) -> libc::tm {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: localtime_r only writes to the tm struct it's given
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&seconds, &mut tm);
+        tm
+    }
+}
+
+/// A time of day in minutes since midnight, written as `"HH:MM"`
+#[derive(Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(u32);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("`{value}` isn't a time like \"23:30\"");
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+        if hours >= 24 || minutes >= 60 {
+            return Err(invalid());
+        }
+
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+impl TimeOfDay {
+    /// The current local time
+    pub fn now() -> Self {
+        let tm = local_time(SystemTime::now());
+        TimeOfDay(tm.tm_hour as u32 * 60 + tm.tm_min as u32)
+    }
+
+    pub fn hour(self) -> u32 {
+        self.0 / 60
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// When the visualiser should show nothing, for displays left running all day
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Minutes of silence after which the screen goes black until sound returns
+    pub blank_after_silence: Option<f64>,
+    /// Loudness in dBFS below which audio counts as silence
+    pub silence_threshold: f32,
+    /// Time of day to go blank, e.g. `"23:30"`
+    pub stop_at: Option<TimeOfDay>,
+    /// Time of day to come back on after `stop_at`, e.g. `"08:00"`
+    pub start_at: Option<TimeOfDay>,
+    /// Exit at `stop_at` instead of blanking the screen
+    pub quit_at_stop: bool,
+    /// Generative animations shown during shorter silences, see `idle`
+    pub idle: Option<IdleConfig>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            blank_after_silence: None,
+            silence_threshold: -50.0,
+            stop_at: None,
+            start_at: None,
+            quit_at_stop: false,
+            idle: None,
+        }
+    }
+}
+
+impl ScheduleConfig {
+    /// Whether `now` falls outside the hours between `start_at` and `stop_at`
+    fn is_off_hours(&self, now: TimeOfDay) -> bool {
+        match (self.start_at, self.stop_at) {
+            (None, None) => false,
+            (Some(start), None) => now < start,
+            (None, Some(stop)) => now >= stop,
+            // Off from the stop time until the start time, which may be the next day
+            (Some(start), Some(stop)) if stop < start => now >= stop && now < start,
+            (Some(start), Some(stop)) => now >= stop || now < start,
+        }
+    }
+}
+
+pub enum ScheduleState {
+    Running,
+    /// Running with the idle animations drawn over it at this opacity, from 0.0 to 1.0
+    Idle(f32),
+    Blank,
+    Quit,
+}
+
+/// Decides each frame whether the visualiser should be drawing, blanked or closed
+pub struct Schedule {
+    config: ScheduleConfig,
+    // Time of the last frame louder than the silence threshold
+    last_sound: Option<f64>,
+    // Time of the last update, for fading the idle animations in and out
+    last_update: Option<f64>,
+    // Opacity of the idle animations
+    idle_level: f32,
+}
+
+impl Schedule {
+    pub fn new(config: ScheduleConfig) -> Self {
+        Self {
+            config,
+            last_sound: None,
+            last_update: None,
+            idle_level: 0.0,
+        }
+    }
+
+    /// `loudness` is this frame's loudness in dBFS and `time` the frame time in seconds
+    pub fn update(&mut self, loudness: f32, time: f64) -> ScheduleState {
+        let last_sound = self.last_sound.get_or_insert(time);
+        if loudness > self.config.silence_threshold {
+            *last_sound = time;
+        }
+        let silent_for = time - *last_sound;
+        let dt = time - self.last_update.replace(time).unwrap_or(time);
+
+        if self.config.is_off_hours(TimeOfDay::now()) {
+            return if self.config.quit_at_stop {
+                ScheduleState::Quit
+            } else {
+                ScheduleState::Blank
+            };
+        }
+
+        if self
+            .config
+            .blank_after_silence
+            .is_some_and(|minutes| silent_for >= minutes * 60.0)
+        {
+            return ScheduleState::Blank;
+        }
+
+        let Some(idle) = &self.config.idle else {
+            return ScheduleState::Running;
+        };
+        // Fades towards the idle animations while silent, and straight back once sound returns
+        let target = if silent_for >= idle.after * 60.0 {
+            1.0
+        } else {
+            0.0
+        };
+        let step = if idle.fade > 0.0 {
+            (dt / idle.fade) as f32
+        } else {
+            1.0
+        };
+        self.idle_level += (target - self.idle_level).clamp(-step, step);
+
+        if self.idle_level > 0.0 {
+            ScheduleState::Idle(self.idle_level)
+        } else {
+            ScheduleState::Running
+        }
+    }
+}
+
+/// Adds a desktop autostart entry so the visualiser opens at login, with `args` passed to it
+pub fn install_autostart(args: &[String]) -> io::Result<PathBuf> {
+    let autostart = config_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?
+        .join("autostart");
+    fs::create_dir_all(&autostart)?;
+
+    let exe = env::current_exe()?;
+    let mut exec = format!("\"{}\"", exe.display());
+    for arg in args {
+        exec.push_str(&format!(" \"{arg}\""));
+    }
+
+    let path = autostart.join("rust-audio-visualiser.desktop");
+    fs::write(
+        &path,
+        format!(
+            "[Desktop Entry]\nType=Application\nName=Audio Visualiser\nExec={exec}\nX-GNOME-Autostart-enabled=true\n"
+        ),
+    )?;
+
+    Ok(path)
+}
@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use macroquad::{
+    color::{Color, RED},
+    shapes::{draw_circle, draw_line, draw_rectangle},
+    text::{TextParams, draw_text_ex},
+    window::{clear_background, screen_height, screen_width},
+};
+use rhai::{AST, Array, CallFnOptions, Dynamic, Engine, Map, Scope};
+
+use crate::{
+    analysis::FrameAnalysis,
+    colour::hsv_to_rgb,
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+};
+
+// Most operations one call to `draw` may run before it's stopped, so a script stuck in a loop
+// shows an error instead of freezing the window
+const MAX_OPERATIONS: u64 = 1_000_000;
+// Deepest function calls may nest, so runaway recursion errors before it overflows the stack
+const MAX_CALL_LEVELS: usize = 64;
+
+/// A visual mode written as a Rhai script, reloaded whenever the file changes
+///
+/// The script defines `fn draw(frame)`, called once per frame with the `FrameAnalysis` as an
+/// object map (`frame.bass`, `frame.spectrum[i]`, ...). State that should survive between
+/// frames can be stored on `this`, which starts as an empty map and is kept across reloads.
+///
+/// Drawing functions available to scripts:
+/// `rect(x, y, w, h, colour)`, `circle(x, y, radius, colour)`,
+/// `line(x1, y1, x2, y2, thickness, colour)`, `text(string, x, y, size, colour)`,
+/// `clear(colour)`, `width()`, `height()`, `rgb(r, g, b)`, `rgba(r, g, b, a)` and
+/// `hsv(h, s, v)`. `scale()` gives the factor to multiply text sizes and line widths by to
+/// keep them readable on large displays
+///
+/// A `draw` that runs for too long or recurses too deeply is stopped, and the error shown
+/// like any other until the script is fixed
+pub struct ScriptedScene {
+    path: PathBuf,
+    engine: Engine,
+    ast: Option<AST>,
+    state: Dynamic,
+    modified: Option<SystemTime>,
+    // Last compile or runtime error, shown on screen until the script is fixed
+    error: Option<String>,
+    typography: Typography,
+}
+
+impl ScriptedScene {
+    /// Text drawn by the script uses `typography`'s font, at the size and colour it asks for
+    pub fn new(path: PathBuf, typography: Typography) -> Self {
+        let mut scene = Self {
+            path,
+            engine: create_engine(&typography),
+            ast: None,
+            state: Dynamic::from_map(Map::new()),
+            modified: None,
+            error: None,
+            typography,
+        };
+        scene.reload_if_changed();
+        scene
+    }
+
+    /// Recompiles the script if its modification time has changed since it was last loaded
+    ///
+    /// A script that fails to compile leaves the previous version running
+    fn reload_if_changed(&mut self) {
+        let modified = modified_time(&self.path);
+        if modified.is_some() && modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        match self.engine.compile_file(self.path.clone()) {
+            Ok(ast) => {
+                self.ast = Some(ast);
+                self.error = None;
+            }
+            Err(error) => {
+                eprintln!("Failed to load {}: {}", self.path.display(), error);
+                self.error = Some(error.to_string());
+            }
+        }
+    }
+
+    /// Runs the script's `draw` function for this frame
+    pub fn draw(&mut self, analysis: &FrameAnalysis) {
+        self.reload_if_changed();
+
+        if let Some(ast) = &self.ast {
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut self.state);
+
+            let result = self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut Scope::new(),
+                ast,
+                "draw",
+                (frame_map(analysis),),
+            );
+
+            match result {
+                Ok(_) => (),
+                // Only report each distinct error once rather than every frame
+                Err(error) if self.error.as_deref() != Some(&error.to_string()) => {
+                    eprintln!("Error in {}: {}", self.path.display(), error);
+                    self.error = Some(error.to_string());
+                }
+                Err(_) => (),
+            }
+        }
+
+        if let Some(error) = &self.error {
+            let scale = ui_scale();
+            self.typography.draw(
+                TextRole::Labels,
+                error,
+                12.0 * scale,
+                screen_height() - 12.0 * scale,
+                20.0 * scale,
+                RED,
+            );
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Converts the frame's analysis into the object map passed to the script's `draw`
+fn frame_map(analysis: &FrameAnalysis) -> Map {
+    let to_array = |values: &[f32]| -> Array {
+        values
+            .iter()
+            .map(|&v| Dynamic::from_float(v as f64))
+            .collect()
+    };
+    let optional = |value: Option<f32>| value.map_or(Dynamic::UNIT, |v| (v as f64).into());
+
+    let mut frame = Map::new();
+    frame.insert("time".into(), analysis.time.into());
+    frame.insert("spectrum".into(), to_array(&analysis.spectrum).into());
+    frame.insert("chromagram".into(), to_array(&analysis.chromagram).into());
+    frame.insert("loudness".into(), (analysis.loudness as f64).into());
+    frame.insert("bass".into(), (analysis.bass as f64).into());
+    frame.insert("mids".into(), (analysis.mids as f64).into());
+    frame.insert("treble".into(), (analysis.treble as f64).into());
+    frame.insert("flux".into(), (analysis.flux as f64).into());
+    frame.insert("onset".into(), analysis.onset.into());
+    frame.insert("bpm".into(), optional(analysis.bpm));
+    frame.insert("beat_phase".into(), optional(analysis.beat_phase));
+    frame.insert(
+        "beat".into(),
+        optional(analysis.beat.map(|beat| beat.confidence)),
+    );
+    frame
+}
+
+/// Reads a script number as f32, accepting both integers and floats
+fn number(value: Dynamic) -> f32 {
+    value
+        .as_float()
+        .or_else(|_| value.as_int().map(|i| i as f64))
+        .unwrap_or(0.0) as f32
+}
+
+/// Builds an engine with the drawing API registered
+fn create_engine(typography: &Typography) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS);
+    let font = typography.font().cloned();
+    engine.register_type_with_name::<Color>("Colour");
+
+    engine
+        .register_fn("width", || screen_width() as f64)
+        .register_fn("height", || screen_height() as f64)
+        .register_fn("scale", || ui_scale() as f64)
+        .register_fn("rgb", |r: Dynamic, g: Dynamic, b: Dynamic| {
+            Color::new(number(r), number(g), number(b), 1.0)
+        })
+        .register_fn("rgba", |r: Dynamic, g: Dynamic, b: Dynamic, a: Dynamic| {
+            Color::new(number(r), number(g), number(b), number(a))
+        })
+        .register_fn("hsv", |h: Dynamic, s: Dynamic, v: Dynamic| {
+            let (r, g, b) = hsv_to_rgb(number(h), number(s), number(v));
+            Color::new(r, g, b, 1.0)
+        })
+        .register_fn("clear", clear_background)
+        .register_fn(
+            "rect",
+            |x: Dynamic, y: Dynamic, w: Dynamic, h: Dynamic, colour: Color| {
+                draw_rectangle(number(x), number(y), number(w), number(h), colour)
+            },
+        )
+        .register_fn(
+            "circle",
+            |x: Dynamic, y: Dynamic, radius: Dynamic, colour: Color| {
+                draw_circle(number(x), number(y), number(radius), colour)
+            },
+        )
+        .register_fn(
+            "line",
+            |x1: Dynamic,
+             y1: Dynamic,
+             x2: Dynamic,
+             y2: Dynamic,
+             thickness: Dynamic,
+             colour: Color| {
+                draw_line(
+                    number(x1),
+                    number(y1),
+                    number(x2),
+                    number(y2),
+                    number(thickness),
+                    colour,
+                )
+            },
+        )
+        .register_fn(
+            "text",
+            move |text: &str, x: Dynamic, y: Dynamic, size: Dynamic, colour: Color| {
+                let params = TextParams {
+                    font: font.as_ref(),
+                    font_size: number(size) as u16,
+                    font_scale: 1.0,
+                    color: colour,
+                    ..Default::default()
+                };
+                draw_text_ex(text, number(x), number(y), params);
+            },
+        );
+
+    engine
+}
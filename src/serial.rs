@@ -0,0 +1,255 @@
+//! Bars and colours sent over a serial port, for LED matrices and other displays driven by
+//! an Arduino or similar microcontroller, as a sink
+//!
+//! ```toml
+//! [[sink]]
+//! rate = 30
+//! grouping = { bars = 8 }
+//! output = { kind = "serial", port = "/dev/ttyACM0", colour = { mapper = "palette" } }
+//! ```
+//!
+//! Each frame is one packet, in which the length counts the bytes after it and before the
+//! checksum:
+//!
+//! | Bytes  | Value                                                               |
+//! |--------|---------------------------------------------------------------------|
+//! | 1      | 0xa5, the start of a packet                                         |
+//! | 2      | length, little-endian                                               |
+//! | 1      | flags: 1 if there was an onset, 2 if a beat, 4 if colours follow    |
+//! | 1      | number of bars                                                      |
+//! | bars   | each bar's level from 0 to 255                                      |
+//! | bars*3 | each bar's colour as red, green and blue, if flagged                |
+//! | 1      | checksum, the sum of the bytes counted by the length, wrapping      |
+//!
+//! so a receiver that starts listening part way through, or drops a byte, can wait for the
+//! next 0xa5 whose packet checks out. Colours come from the `colour` mapper, as on screen,
+//! and are only sent if it's given. If the port goes away, like when the board is unplugged
+//! or reset, it's opened again once a second until it's back, with frames dropped meanwhile.
+//! Most Arduinos reset when the port is opened, so the first second or two of frames after
+//! opening go unread
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use macroquad::color::Color;
+use serde::Deserialize;
+
+use crate::{
+    analysis::{Feature, Features},
+    colour::ColourMapper,
+    config::ColourConfig,
+    sinks::{Sink, SinkError, SinkFrame},
+};
+
+const START: u8 = 0xa5;
+const ONSET_FLAG: u8 = 1;
+const BEAT_FLAG: u8 = 2;
+const COLOUR_FLAG: u8 = 4;
+// The bar count is sent in one byte
+const MAX_BARS: usize = 255;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings of the `serial` sink output, see the module docs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SerialConfig {
+    /// Path of the serial device
+    pub port: PathBuf,
+    /// Bits a second, one of the standard rates from 9600 to 4000000
+    #[serde(default = "default_baud")]
+    pub baud: u32,
+    /// Colour mapper giving each bar's colour, or none to send only the levels
+    pub colour: Option<ColourConfig>,
+}
+
+fn default_baud() -> u32 {
+    115200
+}
+
+/// The `serial` sink output
+pub struct SerialSink {
+    config: SerialConfig,
+    colour: Option<Box<dyn ColourMapper>>,
+    // None while the port is gone, until it's opened again
+    port: Option<File>,
+    last_attempt: Instant,
+}
+
+impl SerialSink {
+    /// Opens the port `config` names, colouring bars for spectra at `sample_rate`
+    pub fn open(config: SerialConfig, sample_rate: usize) -> io::Result<Self> {
+        let port = open_port(&config)?;
+        Ok(Self {
+            colour: config
+                .colour
+                .as_ref()
+                .map(|colour| colour.mapper(sample_rate)),
+            config,
+            port: Some(port),
+            last_attempt: Instant::now(),
+        })
+    }
+
+    fn packet(&mut self, frame: &SinkFrame) -> Vec<u8> {
+        let colours = self.colour.as_mut().map(|colour| {
+            if let Some(beat) = &frame.beat {
+                colour.on_beat(beat);
+            }
+            colour.get_bar_colours(frame.analysis, frame.ranges)
+        });
+        packet(
+            frame.onset,
+            frame.beat.is_some(),
+            frame.levels,
+            colours.as_deref(),
+        )
+    }
+}
+
+/// A packet in the format in the module docs, with each bar's colour if `colours` are given,
+/// repeating them if there are fewer than the bars. Only the first 255 `levels` are sent
+pub fn packet(onset: bool, beat: bool, levels: &[f32], colours: Option<&[Color]>) -> Vec<u8> {
+    let levels = &levels[..levels.len().min(MAX_BARS)];
+
+    let mut flags = 0;
+    if onset {
+        flags |= ONSET_FLAG;
+    }
+    if beat {
+        flags |= BEAT_FLAG;
+    }
+    if colours.is_some() {
+        flags |= COLOUR_FLAG;
+    }
+
+    let mut payload = Vec::with_capacity(2 + levels.len() * 4);
+    payload.push(flags);
+    payload.push(levels.len() as u8);
+    payload.extend(
+        levels
+            .iter()
+            .map(|level| (level.clamp(0.0, 1.0) * 255.0).round() as u8),
+    );
+    if let Some(colours) = colours {
+        for bar in 0..levels.len() {
+            let colour = colours
+                .get(bar % colours.len().max(1))
+                .copied()
+                .unwrap_or_default();
+            payload.extend(
+                [colour.r, colour.g, colour.b]
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8),
+            );
+        }
+    }
+
+    let mut packet = Vec::with_capacity(payload.len() + 4);
+    packet.push(START);
+    packet.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    packet.extend_from_slice(&payload);
+    packet.push(
+        payload
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+    );
+    packet
+}
+
+// Opens the port raw, so bytes pass through unchanged, at the configured rate
+fn open_port(config: &SerialConfig) -> io::Result<File> {
+    let speed = baud_speed(config.baud).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported baud rate {}", config.baud),
+        )
+    })?;
+    let port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&config.port)?;
+
+    let fd = port.as_raw_fd();
+    // SAFETY: `fd` is open for as long as `port` is, and `termios` is fully initialised by
+    // `tcgetattr` before it's changed
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        // Ignore the modem control lines, which many USB serial adapters don't wire up
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        if libc::cfsetispeed(&mut termios, speed) != 0
+            || libc::cfsetospeed(&mut termios, speed) != 0
+            || libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(port)
+}
+
+fn baud_speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        500000 => libc::B500000,
+        921600 => libc::B921600,
+        1000000 => libc::B1000000,
+        2000000 => libc::B2000000,
+        4000000 => libc::B4000000,
+        _ => return None,
+    })
+}
+
+impl Sink for SerialSink {
+    fn features(&self) -> Features {
+        // Beats for the colours' `on_beat`, and both for the flags
+        let colour = self
+            .colour
+            .as_ref()
+            .map_or(Features::NONE, |colour| colour.features());
+        colour | Features::of(&[Feature::Onsets, Feature::Beats])
+    }
+
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        // Built even while the port is gone, so the colours still see every beat
+        let packet = self.packet(frame);
+
+        if self.port.is_none() && self.last_attempt.elapsed() >= RECONNECT_INTERVAL {
+            self.last_attempt = Instant::now();
+            self.port = open_port(&self.config).ok();
+        }
+        let Some(port) = &mut self.port else {
+            return Ok(());
+        };
+
+        if let Err(e) = port.write_all(&packet) {
+            eprintln!(
+                "Lost serial port {}, reconnecting: {e}",
+                self.config.port.display()
+            );
+            self.port = None;
+            self.last_attempt = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        if let Some(port) = &mut self.port {
+            port.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+//! A WebSocket server streaming the analysis to browsers or other machines, as a sink
+//!
+//! ```toml
+//! [[sink]]
+//! rate = 60
+//! grouping = { bars = 32 }
+//! output = { kind = "websocket", address = "0.0.0.0:9001" }
+//! ```
+//!
+//! Every client connected to `address` is sent each frame as one binary message, all
+//! numbers little-endian:
+//!
+//! | Offset | Type  | Contents                                              |
+//! |--------|-------|-------------------------------------------------------|
+//! | 0      | `u8`  | Format version, currently 1                           |
+//! | 1      | `u8`  | Flags: 1 for an onset, 2 for a beat since the last    |
+//! | 2      | `u16` | Number of levels, `n`                                 |
+//! | 4      | `f64` | Time in seconds since the analysis started            |
+//! | 12     | `f32` | Loudness in dBFS                                      |
+//! | 16     | `f32` | Tempo in BPM, 0 until it's known                      |
+//! | 20     | `f32` | Confidence of the beat from 0.0 to 1.0, 0 without one |
+//! | 24     | `u8`  | `n` levels, from 0 (silent) to 255 (the loudest)      |
+//!
+//! Anything clients send is ignored, and clients that fall behind are disconnected
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    analysis::{Feature, Features},
+    sinks::{Sink, SinkError, SinkFrame},
+};
+
+const FORMAT_VERSION: u8 = 1;
+const ONSET_FLAG: u8 = 1;
+const BEAT_FLAG: u8 = 2;
+// Appended to the client's key before hashing it to accept the connection, from RFC 6455
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Longest handshake request read before giving up on a client
+const MAX_REQUEST_LEN: usize = 8192;
+// How long a handshake or a frame can take before the client is given up on
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Accepts WebSocket clients on a background thread and sends binary messages to all of them
+pub struct Server {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Server {
+    /// Listens on `address`, like "127.0.0.1:9001"
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                match handshake(&mut stream) {
+                    Ok(()) => accepted
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(stream),
+                    Err(e) => eprintln!("Refused WebSocket client: {e}"),
+                }
+            }
+        });
+
+        Ok(Self { clients })
+    }
+
+    /// Sends `message` to every client, dropping any that can't keep up or have gone
+    pub fn broadcast(&self, message: &[u8]) {
+        let frame = frame(message);
+        self.clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain_mut(|client| client.write_all(&frame).is_ok());
+    }
+}
+
+/// `message` as a WebSocket binary frame, as the server sends it
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 10);
+    // A final binary frame, unmasked as servers always send them
+    frame.push(0x82);
+    match message.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// The response accepting the WebSocket upgrade `request`, everything up to and including
+/// its blank line, or none if it isn't one
+pub fn handshake_response(request: &[u8]) -> Option<String> {
+    let request = String::from_utf8_lossy(request);
+    let key = request
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, value)| value.trim())?;
+
+    let accept = base64(&sha1(format!("{key}{HANDSHAKE_GUID}").as_bytes()));
+    Some(format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    ))
+}
+
+// Reads the client's upgrade request and accepts it
+fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 || request.len() + read > MAX_REQUEST_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete handshake",
+            ));
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = handshake_response(&request)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a WebSocket request"))?;
+    stream.write_all(response.as_bytes())
+}
+
+// SHA-1, which the handshake needs and nothing else does
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((bits >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The `websocket` sink output, sending each frame in the format in the module docs
+pub struct WebSocketSink(pub Server);
+
+impl Sink for WebSocketSink {
+    fn features(&self) -> Features {
+        Features::of(&[Feature::Onsets, Feature::Beats])
+    }
+
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        let analysis = frame.analysis;
+        let mut flags = 0;
+        if frame.onset {
+            flags |= ONSET_FLAG;
+        }
+        if frame.beat.is_some() {
+            flags |= BEAT_FLAG;
+        }
+        let levels = &frame.levels[..frame.levels.len().min(u16::MAX as usize)];
+
+        let mut message = Vec::with_capacity(24 + levels.len());
+        message.push(FORMAT_VERSION);
+        message.push(flags);
+        message.extend_from_slice(&(levels.len() as u16).to_le_bytes());
+        message.extend_from_slice(&analysis.time.to_le_bytes());
+        message.extend_from_slice(&analysis.loudness.to_le_bytes());
+        message.extend_from_slice(&analysis.bpm.unwrap_or(0.0).to_le_bytes());
+        message.extend_from_slice(&frame.beat.map_or(0.0, |beat| beat.confidence).to_le_bytes());
+        message.extend(
+            levels
+                .iter()
+                .map(|level| (level.clamp(0.0, 1.0) * 255.0).round() as u8),
+        );
+
+        self.0.broadcast(&message);
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::path::Path;
+
+use crate::{
+    chords::{Chord, detect_chord},
+    chroma::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram},
+    midi::{MidiTrack, write_midi_file},
+    onset::OnsetDetector,
+    pitch::{PitchTracker, frequency_to_midi},
+    transcription::{NoteEvent, NoteTracker},
+};
+
+// Melody notes shorter than this (in seconds) are treated as tracking glitches
+const MIN_MELODY_NOTE: f64 = 0.08;
+// A new chord must be detected for this many consecutive frames before it replaces the current one
+const CHORD_CHANGE_FRAMES: usize = 10;
+// Octave chord tones are written in (C3)
+const CHORD_BASE_PITCH: usize = 48;
+
+/// Records a rough transcription of everything heard during a session
+///
+/// Combines three analyses into separate MIDI tracks:
+/// - Melody: the monophonic pitch track, split into notes on pitch changes and onsets
+/// - Notes: polyphonic note tracking
+/// - Chords: detected triads, held as block chords until the harmony changes
+pub struct SessionRecorder {
+    sampling_rate: usize,
+    onsets: OnsetDetector,
+    pitch_tracker: PitchTracker,
+    note_tracker: NoteTracker,
+    melody: Vec<NoteEvent>,
+    // Pitch and onset time of the melody note currently sounding
+    current_melody: Option<(u8, f64)>,
+    chords: Vec<NoteEvent>,
+    current_chord: Option<(Chord, f64)>,
+    candidate_chord: Option<Chord>,
+    candidate_frames: usize,
+    last_time: f64,
+    // Most melody and chord notes kept each, and how many of the oldest have been dropped
+    max_events: Option<usize>,
+    dropped: usize,
+}
+
+impl SessionRecorder {
+    pub fn new(sampling_rate: usize) -> Self {
+        Self {
+            sampling_rate,
+            onsets: OnsetDetector::new(),
+            pitch_tracker: PitchTracker::new(sampling_rate),
+            note_tracker: NoteTracker::new(sampling_rate),
+            melody: Vec::new(),
+            current_melody: None,
+            chords: Vec::new(),
+            current_chord: None,
+            candidate_chord: None,
+            candidate_frames: 0,
+            last_time: 0.0,
+            max_events: None,
+            dropped: 0,
+        }
+    }
+
+    /// Keeps at most `max_events` notes across the tracks, a third to each, forgetting the
+    /// oldest of a track once it has more
+    pub fn with_max_events(mut self, max_events: usize) -> Self {
+        let per_track = max_events / 3;
+        self.note_tracker = self.note_tracker.with_max_events(per_track);
+        self.max_events = Some(per_track);
+        self
+    }
+
+    /// How many of the oldest notes have been dropped to keep within `with_max_events`
+    pub fn dropped(&self) -> usize {
+        self.dropped + self.note_tracker.dropped()
+    }
+
+    /// Analyses a new FFT frame captured at `time` seconds
+    pub fn update(&mut self, spectrum: &[f32], time: f64) {
+        self.last_time = time;
+
+        let onset = self.onsets.update(spectrum).is_some();
+        self.note_tracker.update(spectrum, time);
+        self.update_melody(spectrum, time, onset);
+        self.update_chords(spectrum, time);
+
+        if let Some(max_events) = self.max_events {
+            self.dropped += evict(&mut self.melody, max_events);
+            self.dropped += evict(&mut self.chords, max_events);
+        }
+    }
+
+    fn update_melody(&mut self, spectrum: &[f32], time: f64, onset: bool) {
+        let pitch = self
+            .pitch_tracker
+            .estimate(spectrum)
+            .map(|f| frequency_to_midi(f).round().clamp(0.0, 127.0) as u8);
+
+        let continues =
+            matches!((self.current_melody, pitch), (Some((current, _)), Some(p)) if current == p);
+
+        // A re-articulated note of the same pitch starts a new note
+        if continues && !onset {
+            return;
+        }
+
+        self.end_melody_note(time);
+
+        if let Some(p) = pitch {
+            self.current_melody = Some((p, time));
+        }
+    }
+
+    fn end_melody_note(&mut self, time: f64) {
+        if let Some((pitch, onset)) = self.current_melody.take()
+            && time - onset >= MIN_MELODY_NOTE
+        {
+            self.melody.push(NoteEvent {
+                pitch,
+                velocity: 100,
+                onset,
+                offset: Some(time),
+            });
+        }
+    }
+
+    fn update_chords(&mut self, spectrum: &[f32], time: f64) {
+        let chromagram = pitch_spectrum_to_chromagram(&frequency_to_pitch_spectrum(
+            spectrum,
+            self.sampling_rate,
+        ));
+        // Compare magnitudes rather than power so a loud melody doesn't drown out the harmony
+        let detected = detect_chord(&chromagram.map(f32::sqrt), 0.6);
+
+        if detected == self.current_chord.map(|(chord, _)| chord) {
+            self.candidate_chord = None;
+            self.candidate_frames = 0;
+            return;
+        }
+
+        if detected == self.candidate_chord {
+            self.candidate_frames += 1;
+        } else {
+            self.candidate_chord = detected;
+            self.candidate_frames = 1;
+        }
+
+        if self.candidate_frames >= CHORD_CHANGE_FRAMES {
+            self.end_chord(time);
+            self.current_chord = detected.map(|chord| (chord, time));
+            self.candidate_chord = None;
+            self.candidate_frames = 0;
+        }
+    }
+
+    fn end_chord(&mut self, time: f64) {
+        if let Some((chord, onset)) = self.current_chord.take() {
+            self.chords.extend(chord_notes(chord, onset, Some(time)));
+        }
+    }
+
+    /// Writes the session so far to a multi-track MIDI file
+    pub fn export_midi(&self, path: &Path) -> std::io::Result<()> {
+        let mut melody = self.melody.clone();
+        if let Some((pitch, onset)) = self.current_melody {
+            melody.push(NoteEvent {
+                pitch,
+                velocity: 100,
+                onset,
+                offset: None,
+            });
+        }
+
+        let mut chords = self.chords.clone();
+        if let Some((chord, onset)) = self.current_chord {
+            chords.extend(chord_notes(chord, onset, None));
+        }
+
+        let tracks = [
+            MidiTrack {
+                name: "Melody",
+                channel: 0,
+                notes: &melody,
+            },
+            MidiTrack {
+                name: "Notes",
+                channel: 1,
+                notes: self.note_tracker.events(),
+            },
+            MidiTrack {
+                name: "Chords",
+                channel: 2,
+                notes: &chords,
+            },
+        ];
+
+        write_midi_file(path, &tracks, self.last_time)
+    }
+}
+
+/// Drops the oldest of `events` once there are more than `max_events`, returning how many
+/// were dropped. A quarter more go than need to, so the rest aren't moved every note
+fn evict(events: &mut Vec<NoteEvent>, max_events: usize) -> usize {
+    if events.len() <= max_events {
+        return 0;
+    }
+    let excess = (events.len() - max_events + max_events / 4).min(events.len());
+    events.drain(..excess);
+    excess
+}
+
+/// Voices a chord as a close-position triad starting from its root above `CHORD_BASE_PITCH`
+fn chord_notes(chord: Chord, onset: f64, offset: Option<f64>) -> [NoteEvent; 3] {
+    chord.chroma_indices().map(|chroma| {
+        // Chord tones below the root in chroma order belong to the next octave up
+        let octave_shift = if chroma < chord.root { 12 } else { 0 };
+
+        NoteEvent {
+            pitch: (CHORD_BASE_PITCH + chroma + octave_shift) as u8,
+            velocity: 80,
+            onset,
+            offset,
+        }
+    })
+}
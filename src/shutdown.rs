@@ -0,0 +1,40 @@
+//! Quitting cleanly, whether from the quit key, closing the window, or Ctrl+C or `kill` in a
+//! terminal
+//!
+//! Signals only set a flag here. The render loop checks it once a frame and returns, so
+//! outputs are flushed and the capture stopped before the process exits, rather than it
+//! being killed part way through writing a file. A second signal exits straight away, in
+//! case shutting down hangs
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Exit status for a second signal, 128 plus SIGINT like a shell gives
+const FORCED_EXIT_STATUS: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::Relaxed) {
+        // SAFETY: _exit is async-signal-safe, unlike `process::exit` which runs destructors
+        unsafe { libc::_exit(FORCED_EXIT_STATUS) };
+    }
+}
+
+/// Turns SIGINT and SIGTERM into shutdown requests
+pub fn install_signal_handlers() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches an atomic and calls _exit, both async-signal-safe
+        unsafe { libc::signal(signal, handler) };
+    }
+}
+
+/// Asks the render loop to finish up and return
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether a shutdown has been asked for, by `request` or a signal
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
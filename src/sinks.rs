@@ -0,0 +1,416 @@
+//! Outputs fed from the analysis on their own threads, alongside the screen
+//!
+//! The render loop broadcasts each `FrameAnalysis` to every sink through its own bounded
+//! channel, so a slow sink drops frames rather than holding up drawing. Each sink groups
+//! and smooths the spectrum into its own number of levels, and sends them at its own rate,
+//! played back steadily however unevenly the windows arrive, see `pacing`:
+//!
+//! ```toml
+//! [[sink]]
+//! rate = 10
+//! grouping = { bars = 16 }
+//! smoothing = { rise = 0.3, fall = 0.95 }
+//! output = { kind = "csv", path = "levels.csv" }
+//! ```
+//!
+//! Sinks are sent each frame as it's drawn, which outputs like LED strips then show sooner
+//! than a TV shows the screen. A sink's `delay` holds its frames back by that many seconds,
+//! so lights, screen and sound all land together. To have an output lead the screen
+//! instead, hold everything back with the `[latency]` offset and delay the other outputs
+//! by less
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[cfg(feature = "led")]
+use crate::led::{LedConfig, LedSink};
+#[cfg(feature = "midi-out")]
+use crate::midiout::{MidiOutputConfig, MidiSink};
+#[cfg(feature = "osc")]
+use crate::osc::{OscAddresses, OscSink};
+#[cfg(feature = "serial")]
+use crate::serial::{SerialConfig, SerialSink};
+#[cfg(feature = "server")]
+use crate::server::{Server, WebSocketSink};
+use crate::{
+    analysis::{Feature, Features, FrameAnalysis},
+    beat::BeatEvent,
+    config::{GroupingConfig, SmoothingConfig},
+    gain::AutoGain,
+    pacing::Pacer,
+    pipeline::{Frame, Pipeline},
+    smoothing::SmoothingStrategy,
+};
+
+// Frames queued for each sink before new ones are dropped
+const SINK_QUEUE_LEN: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("couldn't open {0}: {1}")]
+    Open(PathBuf, io::Error),
+    #[error("couldn't send to {0}: {1}")]
+    Connect(String, io::Error),
+    #[cfg(feature = "midi-out")]
+    #[error("MIDI output error: {0}")]
+    Midi(String),
+    #[cfg(feature = "server")]
+    #[error("couldn't listen on {0}: {1}")]
+    Listen(String, io::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// One `[[sink]]` table
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SinkConfig {
+    /// Frames sent a second, evenly and with the levels interpolated between analysed
+    /// windows, or every window as it arrives if left out
+    pub rate: Option<f32>,
+    #[serde(default)]
+    pub grouping: GroupingConfig,
+    /// Smoothing of the levels between analysed windows, none if left out
+    pub smoothing: Option<SmoothingConfig>,
+    /// Seconds each frame is held back for after it's drawn
+    #[serde(default)]
+    pub delay: f64,
+    pub output: OutputConfig,
+}
+
+/// Where a sink sends its levels, told apart by `kind`
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum OutputConfig {
+    /// Appends a line to a CSV file for each frame: the time, then the levels
+    Csv { path: PathBuf },
+    /// Prints the levels as a line of block characters, for checking a setup in a terminal
+    Print,
+    /// Lights an LED strip through WLED or sACN, see `led`
+    #[cfg(feature = "led")]
+    Led(LedConfig),
+    /// Plays the notes heard and the beat on a MIDI port, see `midiout`
+    #[cfg(feature = "midi-out")]
+    Midi(MidiOutputConfig),
+    /// Sends OSC messages over UDP to `target`, see `osc`
+    #[cfg(feature = "osc")]
+    Osc {
+        target: String,
+        #[serde(default)]
+        addresses: OscAddresses,
+    },
+    /// Sends bars and colours to a microcontroller over a serial port, see `serial`
+    #[cfg(feature = "serial")]
+    Serial(SerialConfig),
+    /// Streams frames to WebSocket clients connecting to `address`, see `server`
+    #[cfg(feature = "server")]
+    Websocket { address: String },
+}
+
+impl OutputConfig {
+    #[cfg_attr(
+        not(any(feature = "led", feature = "midi-out", feature = "serial")),
+        allow(unused_variables)
+    )]
+    fn open(&self, sample_rate: usize) -> Result<Box<dyn Sink>, SinkError> {
+        Ok(match self {
+            OutputConfig::Csv { path } => Box::new(CsvSink(BufWriter::new(
+                File::create(path).map_err(|e| SinkError::Open(path.clone(), e))?,
+            ))),
+            OutputConfig::Print => Box::new(PrintSink),
+            #[cfg(feature = "led")]
+            OutputConfig::Led(config) => Box::new(
+                LedSink::connect(config.clone(), sample_rate)
+                    .map_err(|e| SinkError::Connect(config.target.clone(), e))?,
+            ),
+            #[cfg(feature = "midi-out")]
+            OutputConfig::Midi(config) => Box::new(MidiSink::open(config.clone(), sample_rate)?),
+            #[cfg(feature = "osc")]
+            OutputConfig::Osc { target, addresses } => Box::new(
+                OscSink::connect(target, addresses.clone())
+                    .map_err(|e| SinkError::Connect(target.clone(), e))?,
+            ),
+            #[cfg(feature = "serial")]
+            OutputConfig::Serial(config) => Box::new(
+                SerialSink::open(config.clone(), sample_rate)
+                    .map_err(|e| SinkError::Open(config.port.clone(), e))?,
+            ),
+            #[cfg(feature = "server")]
+            OutputConfig::Websocket { address } => Box::new(WebSocketSink(
+                Server::bind(address).map_err(|e| SinkError::Listen(address.clone(), e))?,
+            )),
+        })
+    }
+}
+
+/// What each sink is sent, at its own rate
+pub struct SinkFrame<'a> {
+    /// The latest analysis
+    pub analysis: &'a FrameAnalysis,
+    /// The sink's own grouped and smoothed levels, normalised to the largest
+    pub levels: &'a [f32],
+    /// Range of spectrum bins each level covers, for `ColourMapper::get_bar_colours`
+    pub ranges: &'a [(usize, usize)],
+    /// Whether there was an onset in any analysis since the last frame sent
+    pub onset: bool,
+    /// The latest beat since the last frame sent
+    pub beat: Option<BeatEvent>,
+}
+
+/// An output that runs on its own thread
+pub trait Sink: Send {
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError>;
+
+    /// Called once after the last frame, to write out anything still buffered
+    fn finish(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Parts of the analysis the sink sends, besides the loudness and spectrum
+    fn features(&self) -> Features {
+        Features::NONE
+    }
+}
+
+struct CsvSink(BufWriter<File>);
+
+impl Sink for CsvSink {
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        write!(self.0, "{:.3}", frame.analysis.time)?;
+        for level in frame.levels {
+            write!(self.0, ",{level}")?;
+        }
+        writeln!(self.0)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+struct PrintSink;
+
+impl Sink for PrintSink {
+    fn features(&self) -> Features {
+        Feature::Onsets.into()
+    }
+
+    fn send(&mut self, frame: &SinkFrame) -> Result<(), SinkError> {
+        const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+        let line: String = frame
+            .levels
+            .iter()
+            .map(|level| BLOCKS[((level.clamp(0.0, 1.0) * 7.0).round()) as usize])
+            .collect();
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{line}{}", if frame.onset { " *" } else { "" })?;
+        Ok(())
+    }
+}
+
+/// Sends each analysis to every sink's thread
+pub struct Sinks {
+    senders: Vec<SyncSender<Arc<FrameAnalysis>>>,
+    threads: Vec<JoinHandle<()>>,
+    features: Features,
+}
+
+impl Sinks {
+    /// Opens every sink and starts its thread
+    pub fn spawn(configs: &[SinkConfig], sample_rate: usize) -> Result<Self, SinkError> {
+        let mut senders = Vec::with_capacity(configs.len());
+        let mut threads = Vec::with_capacity(configs.len());
+        let mut features = Features::NONE;
+        for config in configs {
+            let sink = config.output.open(sample_rate)?;
+            features = features | sink.features();
+            let (sender, receiver) = mpsc::sync_channel(SINK_QUEUE_LEN);
+            let config = config.clone();
+            threads.push(thread::spawn(move || {
+                run_sink(sink, &config, receiver, sample_rate)
+            }));
+            senders.push(sender);
+        }
+
+        Ok(Self {
+            senders,
+            threads,
+            features,
+        })
+    }
+
+    /// Parts of the analysis any of the sinks send
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    /// Stops every sink once it's sent the frames already queued, waiting for them to
+    /// finish writing
+    pub fn finish(self) {
+        drop(self.senders);
+        for thread in self.threads {
+            // A sink that panicked has nothing left to finish
+            let _ = thread.join();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    /// Queues `analysis` for every sink still running, skipping any that are behind
+    pub fn broadcast(&mut self, analysis: &FrameAnalysis) {
+        if self.senders.is_empty() {
+            return;
+        }
+
+        let analysis = Arc::new(analysis.clone());
+        self.senders
+            .retain(|sender| match sender.try_send(analysis.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+fn run_sink(
+    mut sink: Box<dyn Sink>,
+    config: &SinkConfig,
+    frames: Receiver<Arc<FrameAnalysis>>,
+    sample_rate: usize,
+) {
+    let smoothing = config
+        .smoothing
+        .map_or(SmoothingStrategy::None, |smoothing| smoothing.strategy());
+    // Follows every peak, so the tallest level is always 1.0
+    let mut pipeline = Pipeline::bars(
+        sample_rate,
+        config.grouping.strategy(),
+        smoothing,
+        None,
+        AutoGain::new(0.0, 0.0, 1),
+    );
+    let delay = config.delay.max(0.0);
+
+    let sent = match config.rate.filter(|rate| *rate > 0.0) {
+        Some(rate) => send_paced(&mut *sink, &mut pipeline, &frames, rate, delay),
+        None => send_each(&mut *sink, &mut pipeline, &frames, delay),
+    };
+    if let Err(e) = sent {
+        eprintln!("Stopping sink: {e}");
+        return;
+    }
+
+    if let Err(e) = sink.finish() {
+        eprintln!("Failed to finish sink: {e}");
+    }
+}
+
+// Sends every window `delay` seconds after it's received, until the render loop stops
+fn send_each(
+    sink: &mut dyn Sink,
+    pipeline: &mut Pipeline,
+    frames: &Receiver<Arc<FrameAnalysis>>,
+    delay: f64,
+) -> Result<(), SinkError> {
+    let delay = Duration::from_secs_f64(delay);
+    let mut send = |analysis: &FrameAnalysis| {
+        let levels = pipeline.process(Frame::from_spectrum(analysis.spectrum.clone()));
+        sink.send(&SinkFrame {
+            analysis,
+            levels: &levels.bars,
+            ranges: &levels.ranges,
+            onset: analysis.onset,
+            beat: analysis.beat,
+        })
+    };
+
+    // Frames held back for `delay`, with when each is due
+    let mut held: VecDeque<(Instant, Arc<FrameAnalysis>)> = VecDeque::new();
+    loop {
+        // Waits for the next frame, or until the oldest held back one is due
+        let received = match held.front() {
+            Some((due, _)) => frames.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => frames.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(analysis) => held.push_back((Instant::now() + delay, analysis)),
+            Err(RecvTimeoutError::Timeout) => (),
+            // The render loop dropped its `Sinks`, or called `Sinks::finish`
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        while held.front().is_some_and(|(due, _)| *due <= Instant::now()) {
+            let Some((_, analysis)) = held.pop_front() else {
+                break;
+            };
+            send(&analysis)?;
+        }
+    }
+    // Anything still held back goes out straight away, rather than being lost
+    for (_, analysis) in held {
+        send(&analysis)?;
+    }
+    Ok(())
+}
+
+// Sends `rate` frames a second played back by a `Pacer`, until the render loop stops
+fn send_paced(
+    sink: &mut dyn Sink,
+    pipeline: &mut Pipeline,
+    frames: &Receiver<Arc<FrameAnalysis>>,
+    rate: f32,
+    delay: f64,
+) -> Result<(), SinkError> {
+    let interval = Duration::from_secs_f32(1.0 / rate);
+    let start = Instant::now();
+    let mut pacer = Pacer::new(delay);
+    let mut next_frame = start;
+    loop {
+        // Takes in windows until the next frame's due
+        match frames.recv_timeout(next_frame.saturating_duration_since(Instant::now())) {
+            Ok(analysis) => {
+                // Smoothed on every window so the sink's rate doesn't change how smooth it
+                // looks
+                let levels = pipeline.process(Frame::from_spectrum(analysis.spectrum.clone()));
+                pacer.push(
+                    start.elapsed().as_secs_f64(),
+                    analysis,
+                    levels.bars,
+                    levels.ranges,
+                );
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        let now = Instant::now();
+        if next_frame > now {
+            continue;
+        }
+        if let Some(frame) = pacer.frame(now.duration_since(start).as_secs_f64()) {
+            sink.send(&frame)?;
+        }
+        next_frame += interval;
+        // Frames missed while the sink was slow are skipped rather than sent all at once
+        if next_frame <= now {
+            next_frame = now + interval;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Golden snapshot tests of the bar pipeline
+//!
+//! Deterministic synthetic audio is run through the FFT, analysis, grouping and smoothing
+//! exactly as the live visualiser does, and the bar heights of every few frames are compared
+//! against the files in `snapshots/`. Rendering itself needs a window, so the bar values are
+//! what gets compared rather than pixels.
+//!
+//! After an intended change to the visuals, regenerate the files with
+//! `UPDATE_SNAPSHOTS=1 cargo test snapshots` and review the diff.
+
+use std::{env, f32::consts::TAU, fs, path::PathBuf};
+
+use crate::{
+    analysis::Analyser,
+    gain::AutoGain,
+    grouping::GroupingStrategy,
+    smoothing::SmoothingStrategy,
+    spectra::FourierTransform,
+    visualiser::{AmplitudeScale, update_bar_levels},
+};
+
+const SAMPLE_RATE: usize = 44_100;
+const FFT_SIZE: usize = 2048;
+const FRAME_RATE: usize = 60;
+const FRAMES: usize = 40;
+// Sound stops after this frame, so the snapshots also cover the bars falling
+const SOUND_FRAMES: usize = 24;
+// Every this many frames is recorded in the snapshot
+const RECORD_EVERY: usize = 4;
+// Largest difference from the snapshot allowed, to absorb floating point differences
+// between platforms
+const TOLERANCE: f32 = 1e-3;
+
+/// Synthetic mono signal, `SOUND_FRAMES` worth of `tone` (given the sample index) followed
+/// by silence
+fn signal(mut tone: impl FnMut(usize) -> f32) -> Vec<f32> {
+    let hop = SAMPLE_RATE / FRAME_RATE;
+    let length = FFT_SIZE + FRAMES * hop;
+    let sound_end = FFT_SIZE + SOUND_FRAMES * hop;
+
+    (0..length)
+        .map(|i| if i < sound_end { tone(i) } else { 0.0 })
+        .collect()
+}
+
+fn sine(frequency: f32) -> impl Fn(usize) -> f32 {
+    move |i| (TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin()
+}
+
+/// Uniform noise from a fixed-seed LCG, so it's the same on every run
+fn noise() -> Vec<f32> {
+    let mut state: u32 = 0x1234_5678;
+    signal(|_| {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        ((state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0) * 0.5
+    })
+}
+
+/// Runs `samples` through the pipeline a frame at a time, returning the recorded bar heights
+/// as lines of text
+fn render_bars(samples: &[f32], grouping: GroupingStrategy) -> String {
+    let hop = SAMPLE_RATE / FRAME_RATE;
+    let ranges = grouping.create_ranges(SAMPLE_RATE, FFT_SIZE);
+    let smoothing = SmoothingStrategy::RiseFall {
+        rise: 0.5,
+        fall: 0.9,
+    };
+    let fft = FourierTransform::new(FFT_SIZE);
+    let mut analyser = Analyser::new(SAMPLE_RATE, FRAME_RATE);
+    let mut bars = vec![0.0; grouping.num_bars()];
+    // Follows every peak, normalising to the tallest bar each frame
+    let mut gain = AutoGain::new(0.0, 0.0, FRAME_RATE);
+
+    let mut output = String::new();
+    for frame in 0..FRAMES {
+        let window = &samples[frame * hop..frame * hop + FFT_SIZE];
+        let spectrum = fft.compute(window);
+        let analysis = analyser.analyse(window, spectrum, frame as f64 / FRAME_RATE as f64);
+        let levels = update_bar_levels(
+            &grouping,
+            &ranges,
+            &smoothing,
+            &AmplitudeScale::Log2,
+            &mut gain,
+            &mut bars,
+            &analysis.spectrum,
+        );
+
+        if frame % RECORD_EVERY == 0 {
+            let line: Vec<String> = levels.iter().map(|level| format!("{level:.4}")).collect();
+            output.push_str(&line.join(" "));
+            output.push('\n');
+        }
+    }
+
+    output
+}
+
+/// Compares `actual` with the snapshot called `name`, or overwrites the snapshot when
+/// `UPDATE_SNAPSHOTS` is set
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("snapshots")
+        .join(format!("{name}.txt"));
+
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "couldn't read snapshot {} ({e}), run with UPDATE_SNAPSHOTS=1 to create it",
+            path.display()
+        )
+    });
+
+    let parse = |text: &str| -> Vec<Vec<f32>> {
+        text.lines()
+            .map(|line| line.split(' ').map(|v| v.parse().unwrap()).collect())
+            .collect()
+    };
+    let (expected, actual) = (parse(&expected), parse(actual));
+    assert_eq!(
+        expected.len(),
+        actual.len(),
+        "{name}: number of frames changed"
+    );
+
+    for (frame, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+        assert_eq!(
+            expected.len(),
+            actual.len(),
+            "{name}: number of bars changed"
+        );
+        for (bar, (e, a)) in expected.iter().zip(actual).enumerate() {
+            assert!(
+                (e - a).abs() <= TOLERANCE,
+                "{name}: bar {bar} of recorded frame {frame} is {a}, snapshot has {e}"
+            );
+        }
+    }
+}
+
+#[test]
+fn sine_log_max() {
+    let bars = render_bars(
+        &signal(sine(440.0)),
+        GroupingStrategy::LogMax { num_groups: 24 },
+    );
+    assert_snapshot("sine_log_max", &bars);
+}
+
+#[test]
+fn chord_log_mean() {
+    // A major triad
+    let chord = |i| (sine(440.0)(i) + sine(554.37)(i) + sine(659.25)(i)) / 3.0;
+    let bars = render_bars(&signal(chord), GroupingStrategy::LogMean { num_groups: 24 });
+    assert_snapshot("chord_log_mean", &bars);
+}
+
+#[test]
+fn noise_log_mean() {
+    let bars = render_bars(&noise(), GroupingStrategy::LogMean { num_groups: 32 });
+    assert_snapshot("noise_log_mean", &bars);
+}
+
+#[test]
+fn sweep_log_max() {
+    // Rises exponentially from 100Hz to 5kHz over the sound
+    let duration = (FFT_SIZE + SOUND_FRAMES * SAMPLE_RATE / FRAME_RATE) as f32 / SAMPLE_RATE as f32;
+    let rate = (5000.0_f32 / 100.0).ln() / duration;
+    let sweep = |i: usize| {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        (TAU * 100.0 * ((rate * t).exp() - 1.0) / rate).sin()
+    };
+    let bars = render_bars(&signal(sweep), GroupingStrategy::LogMax { num_groups: 12 });
+    assert_snapshot("sweep_log_max", &bars);
+}
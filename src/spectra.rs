@@ -1,9 +1,19 @@
-use cqt_rs::{CQTParams, Cqt};
-use rustfft::FftPlanner;
-use rustfft::num_complex::Complex;
-use std::sync::Arc;
+use cqt_rs::{CQTParams, CQTParamsError, Cqt};
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
 use windowfunctions::{Symmetry, WindowFunction, window};
 
+use crate::bins;
+
+// MIDI pitch of the lowest constant-Q bin, C1
+const CQT_LOWEST_NOTE: u8 = 24;
+const CQT_BINS_PER_OCTAVE: usize = 12;
+// Pole frequencies in Hz of the IEC 61672 weighting curves
+const WEIGHTING_POLES: [f64; 4] = [20.6, 107.7, 737.9, 12194.0];
+
 pub fn get_n_largest_indices(items: &[f32], n: usize) -> Vec<usize> {
     let mut values = vec![0.0; n];
     let mut indices: Vec<usize> = vec![items.len(); n];
@@ -46,19 +56,38 @@ pub fn chroma_index_to_note(index: usize) -> String {
 }
 
 pub struct FourierTransform {
-    fft: Arc<dyn rustfft::Fft<f32>>,
+    fft: Arc<dyn RealToComplex<f32>>,
     fft_size: usize,
+    hop_size: usize,
     window_vec: Vec<f32>,
+    // Reused by every `compute` rather than allocated per frame. Behind a mutex only so the
+    // transform can still be shared between threads
+    buffers: Mutex<FftBuffers>,
+}
+
+struct FftBuffers {
+    input: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
 }
 
 /// Struct that computes Fast Fourier Transforms of size `fft_size`
 ///
-/// Applies a window to signals before processing.
+/// Applies a window to signals before processing. As the input is real, only the positive
+/// frequencies are computed, which takes about half the work of a complex FFT
+///
+/// Streams are analysed in overlapping windows, each starting `hop_size` samples after the
+/// last. The hop defaults to the whole window, i.e. no overlap
 impl FourierTransform {
     pub fn new(fft_size: usize) -> Self {
         // FFT setup
-        let mut planner = FftPlanner::<f32>::new();
-        let fft: Arc<dyn rustfft::Fft<f32>> = planner.plan_fft_forward(fft_size);
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft: Arc<dyn RealToComplex<f32>> = planner.plan_fft_forward(fft_size);
+        let buffers = FftBuffers {
+            input: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            scratch: fft.make_scratch_vec(),
+        };
 
         // Hann window to apply pre-FFT
         let window_type = WindowFunction::Hann;
@@ -68,94 +97,213 @@ impl FourierTransform {
         Self {
             fft,
             fft_size,
+            hop_size: fft_size,
             window_vec,
+            buffers: Mutex::new(buffers),
         }
     }
 
+    /// Starts each window `hop_size` samples after the previous one, e.g. a quarter of
+    /// `fft_size` for 75% overlap. Clamped to between 1 and `fft_size`
+    pub fn with_hop_size(mut self, hop_size: usize) -> Self {
+        self.hop_size = hop_size.clamp(1, self.fft_size);
+        self
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn hop_size(&self) -> usize {
+        self.hop_size
+    }
+
     /// Computes a single FFT on a buffer of real-valued audio samples
     ///
-    /// Returns the real half of the FFT spectrum, with length `signal.len() / 2`
+    /// Returns the positive frequency half of the power spectrum, from 0Hz up to and
+    /// including the Nyquist frequency, with `fft_size / 2 + 1` bins as described in `bins`.
+    /// Signals shorter than `fft_size` are padded with silence and longer ones truncated
     pub fn compute(&self, signal: &[f32]) -> Vec<f32> {
-        let mut complex_samples: Vec<Complex<f32>> = signal
-            .iter()
-            .zip(&self.window_vec)
-            .map(|(&value, &w)| Complex {
-                re: value * w,
-                im: 0.0,
-            })
-            .collect();
-
-        self.fft.process(&mut complex_samples);
-
-        // Convert to magnitudes
-        let magnitudes: Vec<f32> = complex_samples
-            .iter()
-            .take(complex_samples.len() / 2)
-            .map(|c| c.norm().powf(2.0))
-            .collect();
-
-        magnitudes
+        // Everything in the buffers is overwritten, so a panic mid-compute can't leave them bad
+        let mut buffers = self.buffers.lock().unwrap_or_else(PoisonError::into_inner);
+        let FftBuffers {
+            input,
+            spectrum,
+            scratch,
+        } = &mut *buffers;
+
+        input.fill(0.0);
+        for ((sample, &value), &w) in input.iter_mut().zip(signal).zip(&self.window_vec) {
+            *sample = value * w;
+        }
+
+        // Only fails if the buffers are the wrong length, which they can't be
+        self.fft
+            .process_with_scratch(input, spectrum, scratch)
+            .expect("FFT buffers sized by the planner");
+
+        spectrum.iter().map(|c| c.norm_sqr()).collect()
+    }
+
+    /// Computes the spectrum of every whole window in `signal`, starting a new window every
+    /// `hop_size` samples
+    pub fn compute_hops<'a>(&'a self, signal: &'a [f32]) -> impl Iterator<Item = Vec<f32>> + 'a {
+        (self.fft_size..=signal.len())
+            .step_by(self.hop_size)
+            .map(|end| self.compute(&signal[end - self.fft_size..end]))
     }
 }
 
-/// Takes a frequency-domain spectrum of any length and
-///  groups it into a 128-pitch log frequency spectrogram
-///
-///  Assumes `frequencies` represents 0Hz to (sampling_rate / 2)Hz in uniform intervals
-pub fn frequency_to_pitch_spectrum(frequencies: &[f32], sampling_rate: usize) -> [f32; 128] {
-    let mut spectrogram = [0.0; 128];
-    let freq_per_bin = (sampling_rate as f32 / 2.0) / frequencies.len() as f32;
-
-    let min_pitch: usize = 40; // E2
-    let max_pitch: usize = 84; // C6
-
-    for (bin_idx, value) in frequencies.iter().enumerate() {
-        let bin_freq = bin_idx as f32 * freq_per_bin;
-        let pitch = 69.0 + 12.0 * (bin_freq / 440.0).log2(); // MIDI pitch estimate
-        let pitch_idx = pitch.round() as usize;
-        // Ignore pitches outside desired range (e.g ignore signals from percussion instruments)
-        if pitch_idx < min_pitch || pitch_idx > max_pitch {
-            continue;
-        }
-        if pitch_idx < 128 {
-            spectrogram[pitch_idx] += value;
+/// Moving average of the last few power spectra, which evens out the noisy spectra of short
+/// windows at the cost of a little lag
+pub struct SpectralAverage {
+    frames: usize,
+    history: VecDeque<Vec<f32>>,
+    sum: Vec<f32>,
+}
+
+impl SpectralAverage {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames: frames.max(1),
+            history: VecDeque::with_capacity(frames),
+            sum: Vec::new(),
         }
     }
 
-    spectrogram
+    /// Adds `spectrum`, returning its average with the spectra before it
+    pub fn update(&mut self, spectrum: &[f32]) -> Vec<f32> {
+        // Spectra of another length can't be averaged in, e.g. after switching FFT size
+        if self.sum.len() != spectrum.len() {
+            self.history.clear();
+            self.sum = vec![0.0; spectrum.len()];
+        }
+
+        if self.history.len() == self.frames
+            && let Some(oldest) = self.history.pop_front()
+        {
+            self.sum
+                .iter_mut()
+                .zip(&oldest)
+                .for_each(|(sum, old)| *sum -= old);
+        }
+        self.sum
+            .iter_mut()
+            .zip(spectrum)
+            .for_each(|(sum, new)| *sum += new);
+        self.history.push_back(spectrum.to_vec());
+
+        let count = self.history.len() as f32;
+        // Rounding can leave the running sum slightly below zero
+        self.sum.iter().map(|sum| (sum / count).max(0.0)).collect()
+    }
 }
 
-/// Takes a MIDI standard 128-pitch spectrum and collects
-///  melodic frequencies into the twelve Western musical notes:
+/// Constant-Q transform with one bin per semitone from C1, each centred on a note
 ///
-/// C, C#, D, D#, E, F, F#, G, G#, A, A#, B
-pub fn pitch_spectrum_to_chromagram(pitches: &[f32]) -> [f32; 12] {
-    let mut chromagram = [0.0; 12];
+/// Bins are spaced like musical pitch, so the bottom octaves get as many bins as the top
+/// ones, where grouping a linear FFT leaves them a bin or two each. Goes up to the highest
+/// whole octave below the Nyquist frequency
+pub struct CqtAnalyzer {
+    cqt: Cqt,
+    window_length: usize,
+    num_bins: usize,
+}
+
+impl CqtAnalyzer {
+    /// Analyses windows of `window_length` samples, which is rounded up to a power of two
+    pub fn new(sample_rate: usize, window_length: usize) -> Result<Self, CQTParamsError> {
+        let min_freq = 440.0 * 2_f32.powf((CQT_LOWEST_NOTE as f32 - 69.0) / 12.0);
+        let octaves = (sample_rate as f32 / 2.0 / min_freq)
+            .log2()
+            .floor()
+            .max(1.0);
+        let params = CQTParams::new(
+            min_freq,
+            min_freq * 2_f32.powf(octaves),
+            CQT_BINS_PER_OCTAVE,
+            sample_rate,
+            window_length,
+        )?;
 
-    for (p, &val) in pitches.iter().enumerate() {
-        chromagram[p % 12] += val;
+        Ok(Self {
+            window_length: params.window_length,
+            num_bins: params.num_bins(),
+            cqt: Cqt::new(params),
+        })
     }
 
-    chromagram
-}
+    pub fn window_length(&self) -> usize {
+        self.window_length
+    }
 
-/// Computes the Harmonic Product Spectrum from a uniformly-spaced frequency spectrum
-///
-/// `downsamples` dictates the number of products used to compute the final result, which
-/// will be of length `frequencies.len() / downsamples`
-pub fn frequency_to_harmonic_product_spectrum(frequencies: &[f32], downsamples: usize) -> Vec<f32> {
-    if downsamples <= 1 {
-        return frequencies.to_vec();
+    pub fn num_bins(&self) -> usize {
+        self.num_bins
+    }
+
+    /// MIDI pitch that `bin` is centred on
+    pub fn bin_pitch(&self, bin: usize) -> usize {
+        CQT_LOWEST_NOTE as usize + bin * 12 / CQT_BINS_PER_OCTAVE
     }
 
-    let output_len = frequencies.len() / downsamples;
-    let mut result: Vec<f32> = frequencies[0..output_len].to_vec();
+    /// Magnitude of each bin, lowest note first. Signals shorter than the window are padded
+    /// with silence and longer ones truncated
+    pub fn compute(&self, signal: &[f32]) -> Vec<f32> {
+        let mut window = vec![0.0; self.window_length];
+        let len = signal.len().min(self.window_length);
+        window[..len].copy_from_slice(&signal[..len]);
+
+        // A hop of the whole window gives exactly one frame
+        let frames = self
+            .cqt
+            .process(&window, self.window_length)
+            .expect("window is non-empty and the hop fits in it");
+        frames.row(0).to_vec()
+    }
+}
+
+/// Standard frequency weighting curves (IEC 61672), which scale a spectrum to follow how
+/// loud each frequency sounds
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weighting {
+    /// Follows the ear at moderate levels, cutting lows and the very top hard
+    A,
+    /// Flatter, for loud music, only rolling off the extremes
+    C,
+}
 
-    for i in 1..output_len {
-        for j in 2..=downsamples {
-            result[i] *= frequencies[j * i];
+impl Weighting {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "a" => Some(Weighting::A),
+            "c" => Some(Weighting::C),
+            _ => None,
         }
     }
 
-    result
+    /// Power gain at `frequency` Hz, 1.0 at 1kHz
+    pub fn gain(&self, frequency: f32) -> f32 {
+        let f2 = (frequency as f64).powi(2);
+        let [p1, p2, p3, p4] = WEIGHTING_POLES.map(|pole| pole * pole);
+
+        // Amplitude response, then the offset that brings 1kHz to 0dB
+        let (response, offset_db) = match self {
+            Weighting::A => (
+                p4 * f2 * f2 / ((f2 + p1) * ((f2 + p2) * (f2 + p3)).sqrt() * (f2 + p4)),
+                2.0,
+            ),
+            Weighting::C => (p4 * f2 / ((f2 + p1) * (f2 + p4)), 0.06),
+        };
+
+        (response * response * 10f64.powf(offset_db / 10.0)) as f32
+    }
+
+    /// Gain of each bin of a power spectrum with `bins` bins, laid out as described in
+    /// `bins`, to be multiplied into it
+    pub fn table(&self, sample_rate: usize, bins: usize) -> Vec<f32> {
+        (0..bins)
+            .map(|bin| self.gain(bins::bin_frequency(bin, bins, sample_rate)))
+            .collect()
+    }
 }
@@ -0,0 +1,156 @@
+use macroquad::{
+    color::WHITE,
+    math::vec2,
+    texture::{DrawTextureParams, FilterMode, Image, Texture2D, draw_texture_ex},
+    window::{screen_height, screen_width},
+};
+
+use crate::{bins, colour::ColourMap};
+
+/// Number of past frames shown across the screen (~8.5 seconds at 60fps)
+pub const HISTORY_LEN: usize = 512;
+/// Frequency rows in the texture, spaced logarithmically
+pub const ROWS: usize = 256;
+/// Lowest frequency shown, and the bottom of the rows from `log_rows`, in Hz
+pub const MIN_FREQUENCY: f32 = 30.0;
+// Range of levels shown below the running peak, in dB
+const DYNAMIC_RANGE: f32 = 80.0;
+// How fast the running peak falls back after something loud, in dB per frame
+const PEAK_DECAY: f32 = 0.05;
+
+/// Scrolling time-frequency heatmap of recent spectra, newest on the right
+///
+/// Each frame's spectrum is resampled onto log-spaced rows, converted to dB relative to a
+/// slowly decaying peak, and written into a ring buffer of columns. The buffer is unrolled
+/// into an image through a colour map and uploaded to a texture stretched over the screen.
+/// Kept to fewer columns, it fills only the right of the screen, as time goes at the same speed
+pub struct Spectrogram {
+    // Levels from 0.0 to 1.0, `columns` columns of ROWS each, lowest frequency first
+    history: Vec<f32>,
+    // Columns kept, HISTORY_LEN unless cut short
+    columns: usize,
+    // Column the next frame is written to, which is also the oldest one
+    next_column: usize,
+    peak_db: f32,
+    colour_map: ColourMap,
+    image: Image,
+    texture: Option<Texture2D>,
+}
+
+impl Default for Spectrogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Spectrogram {
+    pub fn new() -> Self {
+        Self::with_columns(HISTORY_LEN)
+    }
+
+    /// Keeps only the latest `columns` frames, at least one and at most `HISTORY_LEN`
+    pub fn with_columns(columns: usize) -> Self {
+        let columns = columns.clamp(1, HISTORY_LEN);
+        Self {
+            history: vec![0.0; columns * ROWS],
+            columns,
+            next_column: 0,
+            peak_db: -DYNAMIC_RANGE,
+            colour_map: ColourMap::heat(),
+            image: Image::gen_image_color(columns as u16, ROWS as u16, WHITE),
+            texture: None,
+        }
+    }
+
+    /// Whether fewer than `HISTORY_LEN` frames are kept, leaving the left of the screen blank
+    pub fn is_truncated(&self) -> bool {
+        self.columns < HISTORY_LEN
+    }
+
+    /// Adds a power spectrum covering 0Hz to (sampling_rate / 2)Hz as the newest column
+    pub fn push(&mut self, spectrum: &[f32], sampling_rate: usize) {
+        let rows = log_rows(spectrum, sampling_rate, ROWS);
+        let levels_db: Vec<f32> = rows.iter().map(|&p| 10.0 * p.max(1e-12).log10()).collect();
+
+        let loudest = levels_db.iter().cloned().fold(f32::MIN, f32::max);
+        self.peak_db = (self.peak_db - PEAK_DECAY).max(loudest);
+
+        let column = &mut self.history[self.next_column * ROWS..(self.next_column + 1) * ROWS];
+        for (level, db) in column.iter_mut().zip(levels_db) {
+            *level = 1.0 - (self.peak_db - db) / DYNAMIC_RANGE;
+        }
+        self.next_column = (self.next_column + 1) % self.columns;
+    }
+
+    /// Renders the history over the screen, low frequencies at the bottom, returning where
+    /// it starts from the left
+    pub fn draw(&mut self) -> f32 {
+        let pixels = self.image.get_image_data_mut();
+        for x in 0..self.columns {
+            // Oldest column on the left
+            let column = (self.next_column + x) % self.columns;
+            let levels = &self.history[column * ROWS..(column + 1) * ROWS];
+
+            for (row, &level) in levels.iter().enumerate() {
+                let y = ROWS - 1 - row;
+                pixels[y * self.columns + x] = self.colour_map.lookup(level);
+            }
+        }
+
+        // Created on first use as textures need the window to exist
+        let texture = self.texture.get_or_insert_with(|| {
+            let texture = Texture2D::from_image(&self.image);
+            texture.set_filter(FilterMode::Linear);
+            texture
+        });
+        texture.update(&self.image);
+
+        let width = screen_width() * self.columns as f32 / HISTORY_LEN as f32;
+        let left = screen_width() - width;
+        draw_texture_ex(
+            texture,
+            left,
+            0.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(width, screen_height())),
+                ..Default::default()
+            },
+        );
+        left
+    }
+}
+
+/// Resamples `spectrum` onto `num_rows` log-spaced frequency bands from 30Hz to the Nyquist
+/// frequency, taking the loudest bin in each band so high frequencies aren't smeared, or
+/// interpolating where bands are narrower than a bin
+pub fn log_rows(spectrum: &[f32], sampling_rate: usize, num_rows: usize) -> Vec<f32> {
+    let mut rows = vec![0.0; num_rows];
+    if spectrum.is_empty() {
+        return rows;
+    }
+
+    let nyquist = sampling_rate as f32 / 2.0;
+    let bins_per_hz = 1.0 / bins::bin_width(spectrum.len(), sampling_rate);
+    let ratio = (nyquist / MIN_FREQUENCY).powf(1.0 / num_rows as f32);
+    let last = spectrum.len() - 1;
+
+    for (row, value) in rows.iter_mut().enumerate() {
+        let low = MIN_FREQUENCY * ratio.powi(row as i32) * bins_per_hz;
+        let high = MIN_FREQUENCY * ratio.powi(row as i32 + 1) * bins_per_hz;
+
+        *value = if high - low < 1.0 {
+            let position = ((low + high) / 2.0).min(last as f32);
+            let i = position as usize;
+            let t = position - i as f32;
+            spectrum[i] * (1.0 - t) + spectrum[(i + 1).min(last)] * t
+        } else {
+            spectrum[low as usize..(high as usize).min(last) + 1]
+                .iter()
+                .cloned()
+                .fold(0.0, f32::max)
+        };
+    }
+
+    rows
+}
@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+
+use crate::onset::OnsetDetector;
+
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 180.0;
+// Tempo most music sits around, used to bias the estimate away from half/double tempo errors
+const PREFERRED_BPM: f32 = 120.0;
+
+/// Estimates tempo by autocorrelating a few seconds of the spectral flux onset envelope
+///
+/// Expects one frame every `1 / frame_rate` seconds
+pub struct TempoEstimator {
+    onsets: OnsetDetector,
+    frame_rate: f32,
+    envelope: VecDeque<f32>,
+    envelope_len: usize,
+}
+
+impl TempoEstimator {
+    pub fn new(frame_rate: usize) -> Self {
+        let envelope_len = frame_rate * 8;
+
+        Self {
+            onsets: OnsetDetector::new(),
+            frame_rate: frame_rate as f32,
+            envelope: VecDeque::with_capacity(envelope_len),
+            envelope_len,
+        }
+    }
+
+    /// Adds a new FFT frame to the onset envelope
+    pub fn update(&mut self, spectrum: &[f32]) {
+        let flux = self.onsets.flux(spectrum);
+        self.onsets.update(spectrum);
+
+        if self.envelope.len() == self.envelope_len {
+            self.envelope.pop_front();
+        }
+        self.envelope.push_back(flux);
+    }
+
+    /// Period of one beat in frames, or `None` until enough of the envelope has been seen
+    fn beat_period(&self) -> Option<f32> {
+        let min_lag = (60.0 * self.frame_rate / MAX_BPM).floor() as usize;
+        let max_lag = (60.0 * self.frame_rate / MIN_BPM).ceil() as usize;
+
+        // Need a few beats worth of envelope for the autocorrelation to mean anything
+        if self.envelope.len() < max_lag * 3 {
+            return None;
+        }
+
+        let mean = self.envelope.iter().sum::<f32>() / self.envelope.len() as f32;
+        let centred: Vec<f32> = self.envelope.iter().map(|e| e - mean).collect();
+
+        let autocorrelation: Vec<f32> = (0..=max_lag + 1)
+            .map(|lag| {
+                centred
+                    .iter()
+                    .zip(&centred[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>()
+            })
+            .collect();
+
+        let weight = |lag: usize| {
+            let bpm = 60.0 * self.frame_rate / lag as f32;
+            // Log-Gaussian prior over tempo, one octave wide
+            (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp()
+        };
+
+        let best_lag = (min_lag..=max_lag).max_by(|&a, &b| {
+            (autocorrelation[a] * weight(a)).total_cmp(&(autocorrelation[b] * weight(b)))
+        })?;
+
+        if autocorrelation[best_lag] <= 0.0 {
+            return None;
+        }
+
+        // Parabolic interpolation between neighbouring lags for sub-frame accuracy
+        let (left, peak, right) = (
+            autocorrelation[best_lag - 1],
+            autocorrelation[best_lag],
+            autocorrelation[best_lag + 1],
+        );
+        let denominator = left - 2.0 * peak + right;
+        let shift = if denominator.abs() > f32::EPSILON {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        Some(best_lag as f32 + shift)
+    }
+
+    /// Current tempo estimate in beats per minute
+    pub fn bpm(&self) -> Option<f32> {
+        self.beat_period()
+            .map(|period| 60.0 * self.frame_rate / period)
+    }
+
+    /// How far through the current beat the latest frame is, from 0.0 (on the beat) to 1.0
+    ///
+    /// Found by testing every offset within one beat period and picking the one whose
+    /// comb of beat positions back through the envelope collects the most onset energy
+    pub fn beat_phase(&self) -> Option<f32> {
+        let period = self.beat_period()?;
+        let latest = self.envelope.len() - 1;
+
+        let frames_since_beat = (0..period.round() as usize).max_by(|&a, &b| {
+            self.comb_energy(latest, a, period)
+                .total_cmp(&self.comb_energy(latest, b, period))
+        })?;
+
+        Some(frames_since_beat as f32 / period)
+    }
+
+    /// Sum of the envelope at `offset` frames before `latest`, and every beat `period` before that
+    fn comb_energy(&self, latest: usize, offset: usize, period: f32) -> f32 {
+        (0..)
+            .map(|beat| offset as f32 + beat as f32 * period)
+            .take_while(|&back| back.round() as usize <= latest)
+            .map(|back| self.envelope[latest - back.round() as usize])
+            .sum()
+    }
+}
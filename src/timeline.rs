@@ -0,0 +1,151 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{automation::Parameter, visualiser::DisplayMode};
+
+#[derive(Debug, Error)]
+pub enum TimelineError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] toml::de::Error),
+    /// A cue that parsed but can't be scheduled, with its index in the file
+    #[error("cue {0}: {1}")]
+    InvalidCue(usize, String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimelineFile {
+    /// Tempo of the show, required if any cue is placed by bar
+    bpm: Option<f32>,
+    #[serde(default = "default_beats_per_bar")]
+    beats_per_bar: u32,
+    /// Seconds from the start of the show to the first downbeat (bar 1)
+    #[serde(default)]
+    offset: f64,
+    #[serde(default, rename = "cue")]
+    cues: Vec<CueEntry>,
+}
+
+fn default_beats_per_bar() -> u32 {
+    4
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CueEntry {
+    /// Absolute time in seconds
+    at: Option<f64>,
+    /// Bar number counted from 1, optionally fractional to land part way through a bar
+    bar: Option<f64>,
+    scene: Option<String>,
+    #[serde(default)]
+    set: HashMap<String, f32>,
+}
+
+/// A scheduled change to the scene and/or parameters
+pub struct Cue {
+    /// Seconds from the start of the show
+    pub time: f64,
+    pub scene: Option<DisplayMode>,
+    pub parameters: Vec<(Parameter, f32)>,
+}
+
+/// Storyboard of scene and parameter changes for choreographed shows, loaded from TOML:
+///
+/// ```toml
+/// bpm = 128
+/// offset = 0.5
+///
+/// [[cue]]
+/// at = 0.0
+/// scene = "bars"
+///
+/// [[cue]]
+/// bar = 17
+/// scene = "chromagram"
+/// set = { hue = 120, zoom = 1.5 }
+/// ```
+///
+/// Cues are placed at absolute times (`at`, in seconds) or on bars (`bar`, using `bpm`,
+/// `beats_per_bar` and `offset`), and fire in time order as the show plays
+pub struct Timeline {
+    cues: Vec<Cue>,
+    next: usize,
+}
+
+impl Timeline {
+    pub fn load(path: &Path) -> Result<Self, TimelineError> {
+        let source = fs::read_to_string(path).map_err(TimelineError::Io)?;
+        Self::parse(&source)
+    }
+
+    /// Parses the contents of a timeline file
+    pub fn parse(source: &str) -> Result<Self, TimelineError> {
+        let file: TimelineFile = toml::from_str(source).map_err(TimelineError::Parse)?;
+
+        let mut cues = Vec::with_capacity(file.cues.len());
+
+        for (index, entry) in file.cues.into_iter().enumerate() {
+            let invalid = |message: &str| TimelineError::InvalidCue(index, message.to_string());
+
+            let time = match (entry.at, entry.bar) {
+                (Some(at), None) => at,
+                (None, Some(bar)) => {
+                    let bpm = file
+                        .bpm
+                        .ok_or_else(|| invalid("`bar` needs a `bpm` for the timeline"))?;
+                    if bpm.is_nan() || bpm <= 0.0 || file.beats_per_bar == 0 {
+                        return Err(invalid(
+                            "`bar` needs a `bpm` and `beats_per_bar` above zero",
+                        ));
+                    }
+                    let bar_seconds = file.beats_per_bar as f64 * 60.0 / bpm as f64;
+                    file.offset + (bar - 1.0) * bar_seconds
+                }
+                _ => return Err(invalid("needs exactly one of `at` or `bar`")),
+            };
+
+            let scene = match entry.scene {
+                Some(name) => Some(
+                    DisplayMode::from_name(&name)
+                        .ok_or_else(|| invalid(&format!("unknown scene `{name}`")))?,
+                ),
+                None => None,
+            };
+
+            let parameters = entry
+                .set
+                .iter()
+                .map(|(name, &value)| {
+                    Parameter::from_name(name)
+                        .map(|parameter| (parameter, value))
+                        .ok_or_else(|| invalid(&format!("unknown parameter `{name}`")))
+                })
+                .collect::<Result<_, _>>()?;
+
+            cues.push(Cue {
+                time,
+                scene,
+                parameters,
+            });
+        }
+
+        cues.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Ok(Self { cues, next: 0 })
+    }
+
+    /// Returns the cues due since the last call, `elapsed` seconds into the show
+    pub fn update(&mut self, elapsed: f64) -> &[Cue] {
+        let start = self.next;
+        while self.next < self.cues.len() && self.cues[self.next].time <= elapsed {
+            self.next += 1;
+        }
+
+        &self.cues[start..self.next]
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    chords::detect_key,
+    chroma::{frequency_to_pitch_spectrum, pitch_spectrum_to_chromagram},
+    mpris::NowPlaying,
+    pitch::Scale,
+    tempo::TempoEstimator,
+};
+
+// Tracks shorter than this (in seconds) are skipped, e.g. when skipping through a playlist
+const MIN_TRACK_SECONDS: f32 = 15.0;
+
+/// Where the visualiser keeps its session log, relative to the working directory
+pub const SESSION_LOG_PATH: &str = "session-log.csv";
+
+/// Where the visualiser keeps its listening history, see `history`
+pub const HISTORY_PATH: &str = "history.sqlite";
+
+pub(crate) const CSV_HEADER: &str =
+    "started,artist,title,album,duration_s,bpm,key,mean_loudness_dbfs,peak_loudness_dbfs";
+
+pub enum LogFormat {
+    Csv,
+    // One JSON object per line
+    JsonLines,
+}
+
+/// BPM, key and loudness of a whole track
+pub struct TrackStats {
+    /// In seconds
+    pub duration: f32,
+    pub bpm: Option<f32>,
+    pub key: Option<Scale>,
+    /// In dBFS, over the whole track
+    pub mean_loudness: f32,
+    /// In dBFS, of the loudest frame
+    pub peak_loudness: f32,
+}
+
+/// Accumulates `TrackStats` one frame at a time, from live audio or a file
+pub struct TrackAnalyser {
+    sampling_rate: usize,
+    frame_rate: usize,
+    frames: usize,
+    tempo: TempoEstimator,
+    bpm_estimates: Vec<f32>,
+    chroma_profile: [f32; 12],
+    // Sum of each frame's mean square sample value
+    energy_sum: f64,
+    peak_loudness: f32,
+}
+
+impl TrackAnalyser {
+    /// Expects one frame every `1 / frame_rate` seconds
+    pub fn new(sampling_rate: usize, frame_rate: usize) -> Self {
+        Self {
+            sampling_rate,
+            frame_rate,
+            frames: 0,
+            tempo: TempoEstimator::new(frame_rate),
+            bpm_estimates: Vec::new(),
+            chroma_profile: [0.0; 12],
+            energy_sum: 0.0,
+            peak_loudness: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Adds a frame's samples and their power spectrum
+    pub fn update(&mut self, samples: &[f32], spectrum: &[f32]) {
+        self.frames += 1;
+
+        let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+        self.energy_sum += mean_square as f64;
+        self.peak_loudness = self.peak_loudness.max(to_dbfs(mean_square));
+
+        let chromagram = pitch_spectrum_to_chromagram(&frequency_to_pitch_spectrum(
+            spectrum,
+            self.sampling_rate,
+        ));
+        for (total, value) in self.chroma_profile.iter_mut().zip(chromagram) {
+            *total += value.sqrt();
+        }
+
+        // Sample the live tempo estimate once a second and take the median at the end
+        self.tempo.update(spectrum);
+        if self.frames.is_multiple_of(self.frame_rate.max(1))
+            && let Some(bpm) = self.tempo.bpm()
+        {
+            self.bpm_estimates.push(bpm);
+        }
+    }
+
+    /// Seconds of audio analysed so far
+    pub fn duration(&self) -> f32 {
+        self.frames as f32 / self.frame_rate as f32
+    }
+
+    pub fn finish(self) -> TrackStats {
+        let duration = self.duration();
+        let mut bpm_estimates = self.bpm_estimates;
+        bpm_estimates.sort_by(f32::total_cmp);
+
+        TrackStats {
+            duration,
+            bpm: bpm_estimates.get(bpm_estimates.len() / 2).copied(),
+            key: detect_key(&self.chroma_profile),
+            mean_loudness: to_dbfs((self.energy_sum / self.frames.max(1) as f64) as f32),
+            peak_loudness: self.peak_loudness,
+        }
+    }
+}
+
+/// Analysis accumulated while a single track plays
+struct TrackAnalysis {
+    track: NowPlaying,
+    started: u64,
+    analyser: TrackAnalyser,
+}
+
+/// Summary of a finished track, as written to the session log
+pub struct TrackSummary {
+    pub track: NowPlaying,
+    pub started: u64,
+    pub stats: TrackStats,
+}
+
+/// Appends a line of BPM, key and loudness stats to a log file for every track played
+///
+/// Track boundaries come from MPRIS metadata, so only audio played while a media
+/// player reports a track is analysed
+pub struct SessionLog {
+    path: PathBuf,
+    format: LogFormat,
+    sampling_rate: usize,
+    frame_rate: usize,
+    current: Option<TrackAnalysis>,
+}
+
+impl SessionLog {
+    pub fn new(path: PathBuf, format: LogFormat, sampling_rate: usize, frame_rate: usize) -> Self {
+        Self {
+            path,
+            format,
+            sampling_rate,
+            frame_rate,
+            current: None,
+        }
+    }
+
+    /// Feeds one frame of audio, starting a new track (and logging the previous one)
+    /// whenever `now_playing` changes. Returns the previous track once it's been logged
+    pub fn update(
+        &mut self,
+        samples: &[f32],
+        spectrum: &[f32],
+        now_playing: Option<&NowPlaying>,
+    ) -> std::io::Result<Option<TrackSummary>> {
+        let mut finished = None;
+        if self.current.as_ref().map(|analysis| &analysis.track) != now_playing {
+            finished = self.finish_track()?;
+
+            self.current = now_playing.map(|track| TrackAnalysis {
+                track: track.clone(),
+                started: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+                analyser: TrackAnalyser::new(self.sampling_rate, self.frame_rate),
+            });
+        }
+
+        if let Some(analysis) = self.current.as_mut() {
+            analysis.analyser.update(samples, spectrum);
+        }
+        Ok(finished)
+    }
+
+    /// Writes the current track to the log, if it played for long enough, and returns it
+    pub fn finish_track(&mut self) -> std::io::Result<Option<TrackSummary>> {
+        let Some(analysis) = self.current.take() else {
+            return Ok(None);
+        };
+
+        if analysis.analyser.duration() < MIN_TRACK_SECONDS {
+            return Ok(None);
+        }
+
+        let summary = TrackSummary {
+            track: analysis.track,
+            started: analysis.started,
+            stats: analysis.analyser.finish(),
+        };
+
+        self.append(&summary)?;
+        Ok(Some(summary))
+    }
+
+    fn append(&self, summary: &TrackSummary) -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        let stats = &summary.stats;
+        let bpm = stats.bpm.map(|b| format!("{b:.1}"));
+        let key = stats.key.map(|k| k.name());
+
+        match self.format {
+            LogFormat::Csv => {
+                if file.metadata()?.len() == 0 {
+                    writeln!(file, "{CSV_HEADER}")?;
+                }
+
+                writeln!(
+                    file,
+                    "{},{},{},{},{:.0},{},{},{:.1},{:.1}",
+                    summary.started,
+                    csv_field(&summary.track.artist),
+                    csv_field(&summary.track.title),
+                    csv_field(&summary.track.album),
+                    stats.duration,
+                    bpm.unwrap_or_default(),
+                    csv_field(&key.unwrap_or_default()),
+                    stats.mean_loudness,
+                    stats.peak_loudness,
+                )
+            }
+            LogFormat::JsonLines => writeln!(
+                file,
+                "{{\"started\":{},\"artist\":{},\"title\":{},\"album\":{},\"duration_s\":{:.0},\"bpm\":{},\"key\":{},\"mean_loudness_dbfs\":{:.1},\"peak_loudness_dbfs\":{:.1}}}",
+                summary.started,
+                json_string(&summary.track.artist),
+                json_string(&summary.track.title),
+                json_string(&summary.track.album),
+                stats.duration,
+                bpm.unwrap_or(String::from("null")),
+                key.map(|k| json_string(&k)).unwrap_or(String::from("null")),
+                stats.mean_loudness,
+                stats.peak_loudness,
+            ),
+        }
+    }
+}
+
+/// Converts a mean square sample value to decibels relative to full scale
+fn to_dbfs(mean_square: f32) -> f32 {
+    10.0 * mean_square.max(1e-10).log10()
+}
+
+/// Quotes a CSV field if it contains separators, quotes or newlines
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
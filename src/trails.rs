@@ -0,0 +1,193 @@
+use macroquad::{
+    camera::{Camera2D, pop_camera_state, push_camera_state, set_camera},
+    color::{Color, WHITE},
+    material::{Material, MaterialParams, gl_use_default_material, gl_use_material, load_material},
+    math::{Rect, vec2},
+    miniquad::{
+        BlendFactor, BlendState, BlendValue, Equation, PipelineParams, ShaderSource, UniformDesc,
+        UniformType,
+    },
+    shapes::draw_rectangle,
+    texture::{DrawTextureParams, RenderTarget, draw_texture_ex, render_target},
+    window::{clear_background, screen_height, screen_width},
+};
+
+use crate::output::VERTEX_SHADER;
+
+// Fraction of a trail's brightness left after its full length, when it's considered gone
+const TRAIL_END_LEVEL: f32 = 0.01;
+
+/// Fading ghosts of previous frames, accumulated in an offscreen buffer
+///
+/// Each frame the buffer is faded towards transparent and whatever is drawn between `begin`
+/// and `end` is added to it, then the buffer is composited onto the frame. Drawing the same
+/// shapes again afterwards keeps the current frame crisp on top of its trail
+pub struct Trails {
+    // Alpha of the fade applied each frame, derived from the trail length
+    fade: f32,
+    opacity: f32,
+    // Most pixels the buffer has, drawn at a lower resolution than the screen if need be
+    max_pixels: Option<usize>,
+    target: Option<RenderTarget>,
+    fade_material: Material,
+    composite_material: Material,
+}
+
+impl Trails {
+    /// `length` is roughly how many seconds a ghost stays visible and `opacity` how strongly
+    /// the trail is drawn, from 0.0 to 1.0
+    pub fn new(length: f32, opacity: f32, frame_rate: usize) -> Result<Self, macroquad::Error> {
+        let frames = (length * frame_rate as f32).max(1.0);
+
+        Ok(Self {
+            fade: 1.0 - TRAIL_END_LEVEL.powf(1.0 / frames),
+            opacity,
+            max_pixels: None,
+            target: None,
+            fade_material: blended_material(
+                FADE_FRAGMENT_SHADER,
+                // dst * (1 - src_alpha) on every channel, leaving the buffer premultiplied
+                BlendState::new(
+                    Equation::Add,
+                    BlendFactor::Zero,
+                    BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+                ),
+                Vec::new(),
+            )?,
+            composite_material: blended_material(
+                COMPOSITE_FRAGMENT_SHADER,
+                BlendState::new(
+                    Equation::Add,
+                    BlendFactor::One,
+                    BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+                ),
+                vec![UniformDesc::new("opacity", UniformType::Float1)],
+            )?,
+        })
+    }
+
+    /// Keeps the buffer to at most `max_pixels`, scaling it down from the screen's size
+    pub fn with_max_pixels(mut self, max_pixels: usize) -> Self {
+        self.max_pixels = Some(max_pixels);
+        self
+    }
+
+    /// Fades the trail buffer and redirects drawing into it until `end`
+    pub fn begin(&mut self) {
+        let (width, height) = self.buffer_size();
+        let resized = self.target.as_ref().is_none_or(|t| {
+            t.texture.width() as u32 != width || t.texture.height() as u32 != height
+        });
+        if resized {
+            self.target = Some(render_target(width, height));
+        }
+
+        push_camera_state();
+        let mut camera =
+            Camera2D::from_display_rect(Rect::new(0.0, 0.0, screen_width(), screen_height()));
+        camera.render_target = self.target.clone();
+        set_camera(&camera);
+
+        if resized {
+            clear_background(Color::new(0.0, 0.0, 0.0, 0.0));
+        }
+
+        gl_use_material(&self.fade_material);
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            screen_height(),
+            Color::new(0.0, 0.0, 0.0, self.fade),
+        );
+        gl_use_default_material();
+    }
+
+    /// Returns to the previous camera and draws the trail buffer onto it
+    pub fn end(&self) {
+        pop_camera_state();
+
+        let Some(target) = &self.target else {
+            return;
+        };
+
+        self.composite_material.set_uniform("opacity", self.opacity);
+        gl_use_material(&self.composite_material);
+        draw_texture_ex(
+            &target.texture,
+            0.0,
+            0.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(screen_width(), screen_height())),
+                // Render targets come out upside down
+                flip_y: true,
+                ..Default::default()
+            },
+        );
+        gl_use_default_material();
+    }
+
+    // Size of the buffer for the current screen, as large as the screen unless that's more
+    // than `max_pixels`
+    fn buffer_size(&self) -> (u32, u32) {
+        let (width, height) = (screen_width(), screen_height());
+        let scale = self.max_pixels.map_or(1.0, |max_pixels| {
+            (max_pixels as f32 / (width * height).max(1.0))
+                .sqrt()
+                .min(1.0)
+        });
+        (
+            ((width * scale) as u32).max(1),
+            ((height * scale) as u32).max(1),
+        )
+    }
+}
+
+fn blended_material(
+    fragment: &str,
+    blend: BlendState,
+    uniforms: Vec<UniformDesc>,
+) -> Result<Material, macroquad::Error> {
+    load_material(
+        ShaderSource::Glsl {
+            vertex: VERTEX_SHADER,
+            fragment,
+        },
+        MaterialParams {
+            pipeline_params: PipelineParams {
+                color_blend: Some(blend),
+                alpha_blend: Some(blend),
+                ..Default::default()
+            },
+            uniforms,
+            ..Default::default()
+        },
+    )
+}
+
+const FADE_FRAGMENT_SHADER: &str = "#version 100
+precision mediump float;
+
+varying vec4 color;
+varying vec2 uv;
+
+void main() {
+    gl_FragColor = color;
+}
+";
+
+const COMPOSITE_FRAGMENT_SHADER: &str = "#version 100
+precision mediump float;
+
+varying vec4 color;
+varying vec2 uv;
+
+uniform sampler2D Texture;
+uniform float opacity;
+
+void main() {
+    // The buffer is premultiplied, so scaling every channel scales its opacity
+    gl_FragColor = texture2D(Texture, uv) * opacity;
+}
+";
@@ -0,0 +1,208 @@
+use crate::chroma::frequency_to_pitch_spectrum_in_range;
+
+// Semitone offsets of the 2nd to 7th harmonics above a fundamental
+const HARMONIC_OFFSETS: [usize; 6] = [12, 19, 24, 28, 31, 34];
+
+/// A single transcribed note. `offset` is `None` while the note is still sounding
+#[derive(Clone, Copy)]
+pub struct NoteEvent {
+    pub pitch: u8,
+    pub velocity: u8,
+    pub onset: f64,
+    pub offset: Option<f64>,
+}
+
+/// Basic polyphonic transcription using harmonic peak grouping on a semitone spectrum
+///
+/// Each frame, peaks in the pitch spectrum are taken as candidate fundamentals from the
+/// lowest upwards, and the energy their harmonics would explain is removed from higher
+/// pitches before those are considered, so overtones aren't reported as extra notes.
+/// Candidates are debounced over several frames before becoming `NoteEvent`s.
+pub struct NoteTracker {
+    sampling_rate: usize,
+    min_pitch: usize,
+    max_pitch: usize,
+    // Candidates quieter than this fraction of the loudest pitch are ignored
+    relative_threshold: f32,
+    // Frames with less total magnitude than this are treated as silence
+    silence_threshold: f32,
+    max_polyphony: usize,
+    frames_to_start: usize,
+    frames_to_end: usize,
+    // Per-pitch count of consecutive frames the pitch has been detected / missing
+    present_frames: [usize; 128],
+    absent_frames: [usize; 128],
+    // Index into `events` of each currently sounding pitch
+    active: [Option<usize>; 128],
+    first_seen: [f64; 128],
+    last_seen: [f64; 128],
+    events: Vec<NoteEvent>,
+    // Most events kept before the oldest are dropped, and how many have been
+    max_events: Option<usize>,
+    dropped: usize,
+}
+
+impl NoteTracker {
+    pub fn new(sampling_rate: usize) -> Self {
+        Self {
+            sampling_rate,
+            min_pitch: 40, // E2
+            max_pitch: 96, // C7
+            relative_threshold: 0.15,
+            silence_threshold: 1.0,
+            max_polyphony: 6,
+            frames_to_start: 2,
+            frames_to_end: 3,
+            present_frames: [0; 128],
+            absent_frames: [0; 128],
+            active: [None; 128],
+            first_seen: [0.0; 128],
+            last_seen: [0.0; 128],
+            events: Vec::new(),
+            max_events: None,
+            dropped: 0,
+        }
+    }
+
+    /// Keeps at most `max_events` notes, forgetting the oldest once there are more
+    pub fn with_max_events(mut self, max_events: usize) -> Self {
+        self.max_events = Some(max_events);
+        self
+    }
+
+    /// Finds the fundamentals present in a power `spectrum`, returning `(pitch, relative level)`
+    /// pairs where the loudest fundamental has level 1.0
+    ///
+    /// Assumes `spectrum` represents 0Hz to (sampling_rate / 2)Hz in uniform intervals
+    pub fn detect_pitches(&self, spectrum: &[f32]) -> Vec<(usize, f32)> {
+        let pitch_spectrum = frequency_to_pitch_spectrum_in_range(
+            spectrum,
+            self.sampling_rate,
+            self.min_pitch,
+            self.max_pitch,
+        );
+        // Work in magnitudes rather than power so harmonic subtraction is less extreme
+        let mut residual: Vec<f32> = pitch_spectrum.iter().map(|p| p.sqrt()).collect();
+
+        let total: f32 = residual.iter().sum();
+        let max_val = residual.iter().cloned().fold(0.0, f32::max);
+
+        if total < self.silence_threshold || max_val <= 0.0 {
+            return Vec::new();
+        }
+
+        let threshold = max_val * self.relative_threshold;
+        let mut found = Vec::new();
+
+        for pitch in self.min_pitch.max(1)..self.max_pitch.min(126) {
+            let value = residual[pitch];
+
+            let is_peak = value >= residual[pitch - 1] && value >= residual[pitch + 1];
+            if !is_peak || value < threshold {
+                continue;
+            }
+
+            found.push((pitch, value / max_val));
+
+            // Explain away the harmonics of this fundamental, with weaker upper partials
+            let mut expected = value;
+            for offset in HARMONIC_OFFSETS {
+                expected *= 0.6;
+                if let Some(harmonic) = residual.get_mut(pitch + offset) {
+                    *harmonic -= harmonic.min(expected);
+                }
+            }
+        }
+
+        found.sort_by(|a, b| b.1.total_cmp(&a.1));
+        found.truncate(self.max_polyphony);
+
+        found
+    }
+
+    /// Updates the note state with a new frame captured at `time` seconds
+    pub fn update(&mut self, spectrum: &[f32], time: f64) {
+        let mut detected = [None; 128];
+        for (pitch, level) in self.detect_pitches(spectrum) {
+            detected[pitch] = Some(level);
+        }
+
+        for (pitch, detection) in detected.into_iter().enumerate() {
+            match detection {
+                Some(level) => {
+                    if self.present_frames[pitch] == 0 {
+                        self.first_seen[pitch] = time;
+                    }
+                    self.present_frames[pitch] += 1;
+                    self.absent_frames[pitch] = 0;
+                    self.last_seen[pitch] = time;
+
+                    if self.active[pitch].is_none()
+                        && self.present_frames[pitch] >= self.frames_to_start
+                    {
+                        self.active[pitch] = Some(self.events.len());
+                        self.events.push(NoteEvent {
+                            pitch: pitch as u8,
+                            velocity: (level.sqrt() * 127.0).clamp(1.0, 127.0) as u8,
+                            onset: self.first_seen[pitch],
+                            offset: None,
+                        });
+                    }
+                }
+                None => {
+                    self.present_frames[pitch] = 0;
+                    self.absent_frames[pitch] += 1;
+
+                    if self.absent_frames[pitch] >= self.frames_to_end
+                        && let Some(index) = self.active[pitch].take()
+                    {
+                        self.events[index].offset = Some(self.last_seen[pitch]);
+                    }
+                }
+            }
+        }
+
+        self.evict();
+    }
+
+    // Drops the oldest notes once there are more than `max_events`, though never one that's
+    // still sounding. A quarter more go than need to, so the rest aren't moved every note
+    fn evict(&mut self) {
+        let Some(max_events) = self.max_events else {
+            return;
+        };
+        if self.events.len() <= max_events {
+            return;
+        }
+        let oldest_active = self.active.iter().flatten().min().copied();
+        let excess = (self.events.len() - max_events + max_events / 4)
+            .min(self.events.len())
+            .min(oldest_active.unwrap_or(usize::MAX));
+        if excess == 0 {
+            return;
+        }
+
+        self.events.drain(..excess);
+        for index in self.active.iter_mut().flatten() {
+            *index -= excess;
+        }
+        self.dropped += excess;
+    }
+
+    /// Every note transcribed so far, in onset order, less any dropped
+    pub fn events(&self) -> &[NoteEvent] {
+        &self.events
+    }
+
+    /// How many of the oldest notes have been dropped to keep within `with_max_events`
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Notes that have not finished sounding or ended after `since` seconds
+    pub fn events_since(&self, since: f64) -> impl Iterator<Item = &NoteEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.offset.is_none_or(|offset| offset >= since))
+    }
+}
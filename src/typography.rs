@@ -0,0 +1,118 @@
+use std::{fs, io, path::Path};
+
+use macroquad::{
+    color::Color,
+    text::{
+        Font, TextDimensions, TextParams, draw_text_ex, load_ttf_font_from_bytes, measure_text,
+    },
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FontError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Font(#[from] macroquad::Error),
+}
+
+/// Kinds of overlay text, each of which can be styled separately
+#[derive(Clone, Copy)]
+pub enum TextRole {
+    /// Detected notes and pitches, e.g. the chromagram's top notes
+    Notes,
+    /// Readouts about the music, e.g. BPM and beat alignment
+    Metadata,
+    /// Fixed labels, e.g. piano roll keys, crossfader ends and script errors
+    Labels,
+}
+
+#[derive(Clone, Copy)]
+pub struct TextStyle {
+    /// Multiplier on each element's own text size
+    pub size: f32,
+    /// Replaces each element's own colour if set
+    pub colour: Option<Color>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            size: 1.0,
+            colour: None,
+        }
+    }
+}
+
+/// Font and per-role styling used for all overlay text
+#[derive(Clone, Default)]
+pub struct Typography {
+    // macroquad's built-in font if None
+    font: Option<Font>,
+    notes: TextStyle,
+    metadata: TextStyle,
+    labels: TextStyle,
+}
+
+impl Typography {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draws all text in the TTF font at `path`
+    pub fn with_font(mut self, path: &Path) -> Result<Self, FontError> {
+        let bytes = fs::read(path).map_err(FontError::Io)?;
+        self.font = Some(load_ttf_font_from_bytes(&bytes).map_err(FontError::Font)?);
+        Ok(self)
+    }
+
+    pub fn with_style(mut self, role: TextRole, style: TextStyle) -> Self {
+        *self.style_mut(role) = style;
+        self
+    }
+
+    pub fn font(&self) -> Option<&Font> {
+        self.font.as_ref()
+    }
+
+    fn style(&self, role: TextRole) -> &TextStyle {
+        match role {
+            TextRole::Notes => &self.notes,
+            TextRole::Metadata => &self.metadata,
+            TextRole::Labels => &self.labels,
+        }
+    }
+
+    fn style_mut(&mut self, role: TextRole) -> &mut TextStyle {
+        match role {
+            TextRole::Notes => &mut self.notes,
+            TextRole::Metadata => &mut self.metadata,
+            TextRole::Labels => &mut self.labels,
+        }
+    }
+
+    /// Draws `text` with its baseline at `y`, styled for `role`. `size` and `colour` are the
+    /// element's own, which the role's style scales and may replace
+    pub fn draw(&self, role: TextRole, text: &str, x: f32, y: f32, size: f32, colour: Color) {
+        let style = self.style(role);
+
+        draw_text_ex(
+            text,
+            x,
+            y,
+            TextParams {
+                font: self.font.as_ref(),
+                font_size: (size * style.size) as u16,
+                font_scale: 1.0,
+                color: style.colour.unwrap_or(colour),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Size `text` would take up if drawn by `draw` with the same `role` and `size`
+    pub fn measure(&self, role: TextRole, text: &str, size: f32) -> TextDimensions {
+        let size = size * self.style(role).size;
+        measure_text(text, self.font.as_ref(), size as u16, 1.0)
+    }
+}
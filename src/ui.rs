@@ -0,0 +1,13 @@
+use macroquad::window::screen_height;
+
+// Window height (in logical pixels) that fixed text sizes and line widths were designed for
+const REFERENCE_HEIGHT: f32 = 1080.0;
+
+/// Factor to multiply fixed text sizes, line widths and margins by
+///
+/// Window coordinates are already in logical pixels, so this only needs to make up for
+/// displays with more logical pixels than usual, e.g. a 4K monitor without OS scaling.
+/// Never shrinks below the designed size
+pub fn ui_scale() -> f32 {
+    (screen_height() / REFERENCE_HEIGHT).max(1.0)
+}
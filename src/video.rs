@@ -0,0 +1,203 @@
+//! Rendering the visualiser for an audio file to a video, offline
+//!
+//! ```text
+//! rust-audio-visualiser --render song.wav --fps 60 --mode spectrogram
+//! ```
+//!
+//! Frames are drawn one after another at `fps`, each from the analysis of the audio up to
+//! its own time and with the visualiser's clock set to that time, so a render comes out the
+//! same however long the frames take to draw. They're read back from the window and piped
+//! to `ffmpeg`, which needs to be on the `PATH`, as raw pixels to encode as H.264 with the
+//! audio muxed in, into an MP4 next to the audio file unless another path is given
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, ExitStatus, Stdio},
+};
+
+use macroquad::{
+    color::Color,
+    rand::srand,
+    texture::{Image, get_screen_data},
+    window::{clear_background, next_frame},
+};
+use thiserror::Error;
+
+use crate::{
+    analysis::{Analyser, FrameAnalysis},
+    config::Config,
+    output::OutputStage,
+    spectra::FourierTransform,
+    visualiser::{VisualiserBuilder, VisualiserError},
+    wav,
+};
+
+// Same background as the live visualiser
+const BACKGROUND: Color = Color {
+    r: 0.1,
+    g: 0.1,
+    b: 0.1,
+    a: 1.0,
+};
+
+#[derive(Debug, Error)]
+pub enum VideoError {
+    #[error("couldn't read WAV file: {0}")]
+    Wav(#[from] hound::Error),
+    #[error(transparent)]
+    Visualiser(#[from] VisualiserError),
+    #[error("couldn't run ffmpeg: {0}")]
+    Spawn(io::Error),
+    #[error("couldn't send a frame to ffmpeg: {0}")]
+    Io(#[from] io::Error),
+    #[error("ffmpeg failed with {0}")]
+    Encode(ExitStatus),
+}
+
+/// Frames piped to an `ffmpeg` process, which encodes them with the audio
+pub struct VideoEncoder {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl VideoEncoder {
+    /// Starts encoding `width` by `height` frames at `fps` into `output`, with the audio
+    /// from `audio`
+    pub fn spawn(
+        output: &Path,
+        audio: &Path,
+        width: u16,
+        height: u16,
+        fps: usize,
+    ) -> Result<Self, VideoError> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{width}x{height}")])
+            .args(["-framerate", &fps.to_string()])
+            .args(["-i", "-", "-i"])
+            .arg(audio)
+            .args(["-map", "0:v", "-map", "1:a"])
+            // Frames are read back bottom row first, and H.264 needs even dimensions
+            .args(["-vf", "vflip,pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+            .args([
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-shortest",
+            ])
+            .arg(output)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(VideoError::Spawn)?;
+        let stdin = child.stdin.take().expect("ffmpeg's stdin is piped");
+
+        Ok(Self { child, stdin })
+    }
+
+    pub fn write_frame(&mut self, frame: &Image) -> Result<(), VideoError> {
+        self.stdin.write_all(&frame.bytes)?;
+        Ok(())
+    }
+
+    /// Waits for `ffmpeg` to finish writing the video
+    pub fn finish(self) -> Result<(), VideoError> {
+        let Self { mut child, stdin } = self;
+        // Closing stdin tells ffmpeg there are no more frames
+        drop(stdin);
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(VideoError::Encode(status));
+        }
+        Ok(())
+    }
+}
+
+/// Renders the visualiser `config` describes for the WAV file at `path` into a video at
+/// `output`, or next to it as an MP4 with the same name, returning where it was saved.
+/// `customise` can change the visualiser before it's built, as with the live one
+pub async fn render(
+    path: &Path,
+    output: Option<PathBuf>,
+    fps: usize,
+    config: &Config,
+    customise: impl FnOnce(VisualiserBuilder) -> VisualiserBuilder,
+) -> Result<PathBuf, VideoError> {
+    let output = output.unwrap_or_else(|| path.with_extension("mp4"));
+    let (samples, sample_rate) = wav::read_mono(path)?;
+    let fft_size = config.fft_size;
+
+    let mut visualiser =
+        customise(VisualiserBuilder::from_config(config).map_err(VisualiserError::from)?)
+            .with_frame_rate(fps)
+            .build(sample_rate, fft_size)?;
+    let mut stage = OutputStage::new(config.output).map_err(VisualiserError::from)?;
+    let fft = FourierTransform::new(fft_size).with_hop_size(config.hop_size());
+    let mut analyser = Analyser::new(sample_rate, sample_rate / fft.hop_size());
+    // Particles and anything else random come out the same on every render
+    srand(0);
+
+    // Silence before the audio starts, so the first windows are full
+    let mut padded = vec![0.0; fft_size];
+    padded.extend_from_slice(&samples);
+    let frames = (samples.len() * fps).div_ceil(sample_rate);
+
+    let mut encoder = None;
+    // End of the next window to analyse, counted in samples of the audio
+    let mut next_window_end = 0;
+    let mut latest: Option<FrameAnalysis> = None;
+    for frame in 0..frames {
+        let time = frame as f64 / fps as f64;
+        // Windows are centred on the frame they're drawn for, as they are live
+        let frame_end = (time * sample_rate as f64) as usize + fft_size / 2;
+
+        while next_window_end <= frame_end.min(samples.len()) {
+            let window = &padded[next_window_end..next_window_end + fft_size];
+            let window_time = next_window_end as f64 / sample_rate as f64;
+            let mut analysis = analyser.analyse(window, fft.compute(window), window_time);
+            // Only the last window is drawn, so carry over events from any before it
+            if let Some(previous) = &latest {
+                analysis.onset |= previous.onset;
+                analysis.beat = analysis.beat.or(previous.beat);
+            }
+            latest = Some(analysis);
+            next_window_end += fft.hop_size();
+        }
+
+        stage.begin();
+        clear_background(BACKGROUND);
+        if let Some(analysis) = &mut latest {
+            visualiser.set_clock(Some(time));
+            visualiser.draw(analysis);
+            // Events are only drawn on the frame they happened in
+            analysis.onset = false;
+            analysis.beat = None;
+        }
+        stage.finish();
+
+        // Started on the first frame, once the size frames are read back at is known
+        let image = get_screen_data();
+        if encoder.is_none() {
+            encoder = Some(VideoEncoder::spawn(
+                &output,
+                path,
+                image.width,
+                image.height,
+                fps,
+            )?);
+        }
+        if let Some(encoder) = &mut encoder {
+            encoder.write_frame(&image)?;
+        }
+        next_frame().await;
+    }
+
+    if let Some(encoder) = encoder {
+        encoder.finish()?;
+    }
+    Ok(output)
+}
@@ -1,52 +1,429 @@
-use std::f32;
+use std::{borrow::Cow, collections::VecDeque, f32, path::PathBuf};
 
 use macroquad::{
-    color::{BLUE, Color, WHITE},
+    color::{BLUE, Color, GRAY, WHITE},
+    math::{Rect, vec2},
     miniquad::log,
-    shapes::draw_rectangle,
-    text::{draw_text, measure_text},
+    shapes::{draw_circle, draw_line, draw_rectangle, draw_triangle},
+    time::get_time,
     window::{screen_height, screen_width},
 };
 
+use serde::Deserialize;
+use thiserror::Error;
+
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptedScene;
 use crate::{
-    colour::{ColourMapper, StaticColour},
+    analysis::{Feature, Features, FrameAnalysis},
+    atlas::{SkinFit, TextureAtlas},
+    autodj::SceneConfig,
+    automation::{ModulationMatrix, Parameter, Parameters},
+    beat::BeatEvent,
+    bins,
+    budget::MemoryBudget,
+    chroma::{
+        frequency_to_harmonic_product_spectrum, frequency_to_pitch_spectrum,
+        pitch_spectrum_to_chromagram,
+    },
+    colour::{ColourMapper, StaticColour, blend_colours, chroma_vector, hsv_to_rgb, rotate_hue},
+    config::{Config, ConfigError, NormalisationConfig, ProfileConfig, SmoothingConfig},
+    distortion::{DistortionConfig, DistortionError, DistortionLayer},
+    drops::{DropPredictor, DropState},
+    gain::AutoGain,
     grouping::GroupingStrategy,
-    smoothing::SmoothingStrategy,
-    spectra::{
-        chroma_index_to_note, frequency_to_harmonic_product_spectrum, frequency_to_pitch_spectrum,
-        get_n_largest_indices, pitch_spectrum_to_chromagram,
+    layout::{BarGap, Placement, bar_columns, safe_area},
+    modes::{self, Mode, ModeInput},
+    novelty::NoveltyCurve,
+    overlay::OverlayError,
+    particles::{EmitterConfig, Particles},
+    peaks::PeakHold,
+    pitch::{
+        PitchTracker, Scale, ScaleKind, cents_deviation, frequency_to_midi, midi_to_note_name,
     },
+    presets::PresetConfig,
+    primitives::{BarRenderer, BarStyle},
+    sinks::SinkError,
+    smoothing::SmoothingStrategy,
+    spectra::{CqtAnalyzer, Weighting, chroma_index_to_note, get_n_largest_indices},
+    spectrogram::{self, Spectrogram},
+    timeline::Timeline,
+    trails::Trails,
+    transcription::NoteTracker,
+    typography::{FontError, TextRole, Typography},
+    ui::ui_scale,
 };
 
+// Number of frames of pitch history shown in the pitch coach (~5 seconds at 60fps)
+const PITCH_HISTORY_LEN: usize = 300;
+// MIDI pitch range of the pitch coach's piano roll (E2 to C6, the same range used for chromagrams)
+const PIANO_ROLL_LOW: usize = 40;
+const PIANO_ROLL_HIGH: usize = 84;
+// Seconds of transcribed notes visible in the note tracking piano roll
+const PIANO_ROLL_SECONDS: f64 = 5.0;
+// How long the flash fired on a drop takes to fade out, in seconds
+const DROP_FLASH_SECONDS: f64 = 0.5;
+// How long the flash fired on a beat takes to fade out, in seconds
+const BEAT_FLASH_SECONDS: f64 = 0.15;
+// Most bars the keyboard controls can add up to
+const MAX_BARS: usize = 256;
+// Semitones in a fifth, the step from one wedge of the chroma wheel to the next
+const FIFTH: usize = 7;
+// How far below the loudest pitch class a wedge of the chroma wheel goes dark, as a
+// difference of natural logs
+const WHEEL_RANGE: f32 = 5.0;
+// Triangles making up the curve of each wedge of the chroma wheel
+const WEDGE_STEPS: usize = 8;
+
+/// Everything that can go wrong setting up a visualiser
+#[derive(Debug, Error)]
+pub enum VisualiserError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("couldn't load font: {0}")]
+    Font(#[from] FontError),
+    #[error("couldn't compile shader: {0}")]
+    Shader(#[from] macroquad::Error),
+    #[error("couldn't start sink: {0}")]
+    Sink(#[from] SinkError),
+    #[error("couldn't load distortion layer: {0}")]
+    Distortion(#[from] DistortionError),
+    #[error("couldn't load overlay: {0}")]
+    Overlay(#[from] OverlayError),
+}
+
+/// Mirrored "glass floor" reflection of the bars below their baseline
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Reflection {
+    /// Fraction of the screen height taken up by the floor
+    pub height: f32,
+    /// Opacity of the reflection at the baseline, fading to nothing at the bottom
+    pub opacity: f32,
+}
+
+/// How bar heights follow the level of their frequencies, see `update_bar_levels`
+#[derive(Clone, Copy)]
+pub enum AmplitudeScale {
+    /// Amplitude relative to the tallest bar
+    Linear,
+    /// Log2 of power relative to the tallest bar, as the bars are grouped
+    Log2,
+    /// Absolute level from `floor` dBFS up to full scale, so quiet passages look quiet
+    Dbfs { floor: f32 },
+}
+
+impl AmplitudeScale {
+    /// Heights from 0.0 to 1.0 for `bars` grouped from a spectrum of `spectrum_len` bins,
+    /// with `gain` setting the reference for the scales relative to the tallest bar
+    pub fn heights(&self, bars: &[f32], spectrum_len: usize, gain: &mut AutoGain) -> Vec<f32> {
+        // Grouping gives log2 of one more than the power
+        let power = |bar: f32| (bar.exp2() - 1.0).max(0.0);
+
+        match *self {
+            AmplitudeScale::Linear => {
+                let amplitudes: Vec<f32> = bars.iter().map(|&bar| power(bar).sqrt()).collect();
+                gain.normalise(&amplitudes)
+            }
+            AmplitudeScale::Log2 => gain.normalise(bars),
+            AmplitudeScale::Dbfs { floor } => {
+                // A full scale sine through the Hann window peaks at a quarter of the FFT size
+                let full_scale = (bins::fft_size(spectrum_len) as f32 / 4.0).powi(2);
+                bars.iter()
+                    .map(|&bar| {
+                        let level = 10.0 * (power(bar) / full_scale).max(1e-12).log10();
+                        ((level - floor) / -floor).clamp(0.0, 1.0)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// How the bars mode lays out the left and right channels when showing them separately
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StereoLayout {
+    /// Left channel across the left half of the screen and right across the right half,
+    /// both running from low to high frequencies
+    Split,
+    /// Low frequencies meet in the middle, with the left channel spreading out to the left
+    /// and the right channel to the right
+    Mirrored,
+}
+
+impl StereoLayout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "split" => Some(StereoLayout::Split),
+            "mirrored" => Some(StereoLayout::Mirrored),
+            _ => None,
+        }
+    }
+}
+
+/// Which visualisation `Visualiser::draw` renders each frame
+#[derive(Clone)]
+pub enum DisplayMode {
+    /// One of the modes in `modes::MODES`, or added with `modes::register`
+    BuiltIn(&'static Mode),
+    /// A scene drawn by the Rhai script at this path, see `ScriptedScene`
+    Script(PathBuf),
+}
+
+impl DisplayMode {
+    /// Looks up a mode by name as used in timelines, treating `.rhai` files as scripts
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            path if path.ends_with(".rhai") => Some(DisplayMode::Script(PathBuf::from(path))),
+            name => Mode::find(name).map(DisplayMode::BuiltIn),
+        }
+    }
+
+    /// Every mode that doesn't need a script
+    pub fn built_in() -> impl Iterator<Item = DisplayMode> {
+        Mode::all().into_iter().map(DisplayMode::BuiltIn)
+    }
+
+    /// Name as accepted by `from_name`
+    pub fn name(&self) -> String {
+        match self {
+            DisplayMode::BuiltIn(mode) => mode.name.to_string(),
+            DisplayMode::Script(path) => path.display().to_string(),
+        }
+    }
+
+    /// Whether the mode draws from `input`. Scripts are given everything
+    pub fn needs(&self, input: ModeInput) -> bool {
+        match self {
+            DisplayMode::BuiltIn(mode) => mode.needs(input),
+            DisplayMode::Script(_) => true,
+        }
+    }
+
+    /// Parts of the analysis the mode draws from. Scripts are given everything
+    pub fn features(&self) -> Features {
+        match self {
+            DisplayMode::BuiltIn(mode) => mode.features,
+            DisplayMode::Script(_) => Features::ALL,
+        }
+    }
+
+    // Compared by name, as the same mode can be at a different address in each use of `MODES`
+    fn is(&self, built_in: &Mode) -> bool {
+        matches!(self, DisplayMode::BuiltIn(mode) if mode.name == built_in.name)
+    }
+}
+
 pub struct VisualiserBuilder {
+    mode: DisplayMode,
     grouping: GroupingStrategy,
     smoothing: SmoothingStrategy,
     colour: Box<dyn ColourMapper>,
+    crossfade_colour: Option<Box<dyn ColourMapper>>,
+    scale: Scale,
+    frame_rate: usize,
+    modulation: ModulationMatrix,
+    timeline: Option<Timeline>,
+    // Length in seconds and opacity of bar trails
+    trails: Option<(f32, f32)>,
+    reflection: Option<Reflection>,
+    // Hold time and fall rate of the peak caps
+    peak_hold: Option<(f32, f32)>,
+    bar_style: BarStyle,
+    bar_gap: BarGap,
+    waveform_trigger: bool,
+    beat_flash: f32,
+    stereo: Option<StereoLayout>,
+    amplitude_scale: AmplitudeScale,
+    // Attack and release of the reference level in seconds
+    auto_gain: (f32, f32),
+    // Decibels added to the spectrum
+    gain: f32,
+    weighting: Option<Weighting>,
+    emitters: Vec<EmitterConfig>,
+    atlas: Option<TextureAtlas>,
+    bar_skin: Option<(String, SkinFit)>,
+    distortion: Option<DistortionConfig>,
+    notes_placement: Placement,
+    safe_area: [f32; 2],
+    typography: Typography,
+    memory_budget: MemoryBudget,
 }
 
 pub struct Visualiser {
     sampling_rate: usize,
+    fft_size: usize,
+    mode: DisplayMode,
     grouping: GroupingStrategy,
     smoothing: SmoothingStrategy,
     colour: Box<dyn ColourMapper>,
+    // Mapper faded towards by the colour blend parameter
+    crossfade_colour: Option<Box<dyn ColourMapper>>,
     grouping_ranges: Vec<(usize, usize)>,
     // Bars need to be tracked over time to work with smoothing
     bars_to_display: Vec<f32>,
+    // Left and right channel bars, tracked separately when showing stereo
+    channel_bars: [Vec<f32>; 2],
+    stereo: Option<StereoLayout>,
+    amplitude_scale: AmplitudeScale,
+    auto_gain: AutoGain,
+    // Frames a second the auto gain is updated at
+    frame_rate: usize,
+    // Multiplier on the power of every bin
+    gain: f32,
+    weighting: Option<Weighting>,
+    // Gain of each bin for `weighting`, rebuilt when the spectrum length changes
+    weights: Vec<f32>,
     smoothed_chromagram: Vec<f32>,
+    pitch_tracker: PitchTracker,
+    // Most recent pitch estimates (fractional MIDI pitch), None where no voice was detected
+    pitch_history: VecDeque<Option<f32>>,
+    scale: Scale,
+    note_tracker: NoteTracker,
+    // Recent spectra shown by the spectrogram
+    spectrogram: Spectrogram,
+    // Recent flux and onsets shown by the novelty mode
+    novelty: NoveltyCurve,
+    // Built when the CQT mode is first drawn, and again if the window length changes
+    cqt: Option<CqtAnalyzer>,
+    cqt_bars: Vec<f32>,
+    drop_predictor: DropPredictor,
+    modulation: ModulationMatrix,
+    // Parameter values before modulation, and after it for the current frame
+    base_parameters: Parameters,
+    parameters: Parameters,
+    #[cfg(feature = "scripting")]
+    script: Option<ScriptedScene>,
+    timeline: Option<Timeline>,
+    // Time of the first frame, which the timeline's cue times are relative to
+    show_start: Option<f64>,
+    // Mode to go back to when leaving a profile that changed it
+    mode_before_profile: Option<DisplayMode>,
+    trails: Option<Trails>,
+    reflection: Option<Reflection>,
+    peak_hold: Option<PeakHold>,
+    particles: Particles,
+    atlas: Option<TextureAtlas>,
+    // Atlas sprite bars are drawn with instead of solid colour
+    bar_skin: Option<(String, SkinFit)>,
+    distortion: Option<DistortionLayer>,
+    bar_renderer: BarRenderer,
+    bar_gap: BarGap,
+    // Whether the waveform starts at a rising zero crossing rather than the oldest sample
+    waveform_trigger: bool,
+    // Opacity of the flash on a fully confident beat, 0.0 for none
+    beat_flash: f32,
+    last_beat: Option<BeatEvent>,
+    // Time frames are drawn at when set with `set_clock`, instead of the wall clock
+    clock: Option<f64>,
+    // Where note names go, within the window less the safe area margins
+    notes_placement: Placement,
+    safe_area: [f32; 2],
+    typography: Typography,
+}
+
+impl Default for VisualiserBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VisualiserBuilder {
     pub fn new() -> Self {
         Self {
+            mode: DisplayMode::BuiltIn(modes::BARS),
             grouping: GroupingStrategy::LogMax { num_groups: 24 },
             smoothing: SmoothingStrategy::RiseFall {
                 rise: 0.5,
                 fall: 0.9,
             },
             colour: Box::new(StaticColour::new(WHITE)),
+            crossfade_colour: None,
+            scale: Scale::new(0, ScaleKind::Major),
+            frame_rate: 60,
+            modulation: ModulationMatrix::new(),
+            timeline: None,
+            trails: None,
+            reflection: None,
+            peak_hold: None,
+            bar_style: BarStyle::Square,
+            bar_gap: BarGap::default(),
+            waveform_trigger: true,
+            beat_flash: 0.0,
+            stereo: None,
+            amplitude_scale: AmplitudeScale::Log2,
+            auto_gain: (0.0, 0.0),
+            gain: 0.0,
+            weighting: None,
+            emitters: Vec::new(),
+            atlas: None,
+            bar_skin: None,
+            distortion: None,
+            notes_placement: Placement::default(),
+            safe_area: [0.0, 0.0],
+            typography: Typography::new(),
+            memory_budget: MemoryBudget::default(),
         }
     }
 
+    /// Starts from the settings in `config`, which the other `with_*` methods can then override
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut builder = Self::new()
+            .with_mode(config.mode())
+            .with_grouping(config.grouping.strategy())
+            .with_smoothing(config.smoothing.strategy())
+            .with_colour_mapper(config.colour.mapper(config.sample_rate))
+            .with_frame_rate(config.frame_rate)
+            .with_bar_style(config.bars.style())
+            .with_bar_gap(config.bars.gap())
+            .with_amplitude_scale(config.bars.amplitude_scale())
+            .with_auto_gain(config.bars.agc.attack, config.bars.agc.release)
+            .with_gain(config.bars.gain)
+            .with_waveform_trigger(config.waveform.trigger)
+            .with_beat_flash(config.beat.flash)
+            .with_emitters(config.emitters.clone())
+            .with_notes_placement(config.overlay.notes, config.overlay.safe_area)
+            .with_typography(config.text.typography().map_err(ConfigError::Font)?)
+            .with_modulation(config.modulation_matrix()?)
+            .with_memory_budget(config.memory_budget());
+
+        if let Some(colour) = &config.crossfade_colour {
+            builder = builder.with_crossfade_colour_mapper(colour.mapper(config.sample_rate));
+        }
+        if let Some(trails) = config.bars.trails {
+            builder = builder.with_trails(trails.length, trails.opacity);
+        }
+        if let Some(reflection) = config.bars.reflection {
+            builder = builder.with_reflection(reflection);
+        }
+        if let Some(peaks) = config.bars.peaks {
+            builder = builder.with_peak_hold(peaks.hold, peaks.fall);
+        }
+        if let Some(image) = &config.atlas.image {
+            builder = builder.with_atlas(TextureAtlas::load(image, &config.atlas.sprites)?);
+        }
+        if let Some(skin) = &config.bars.skin {
+            builder = builder.with_bar_skin(&skin.sprite, skin.fit);
+        }
+        if let Some(distortion) = &config.distortion {
+            builder = builder.with_distortion(distortion.clone());
+        }
+        if let Some(weighting) = config.bars.weighting {
+            builder = builder.with_weighting(weighting);
+        }
+        if let Some(stereo) = config.stereo {
+            builder = builder.with_stereo(stereo);
+        }
+
+        Ok(builder)
+    }
+
+    pub fn with_mode(mut self, mode: DisplayMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     pub fn with_grouping(mut self, grouping: GroupingStrategy) -> Self {
         self.grouping = grouping;
         self
@@ -62,47 +439,865 @@ impl VisualiserBuilder {
         self
     }
 
-    pub fn build(self, sampling_rate: usize, fft_size: usize) -> Visualiser {
+    /// Sets a second colour mapper to crossfade to as `Parameter::ColourBlend` goes from
+    /// 0.0 to 1.0
+    pub fn with_crossfade_colour_mapper(mut self, colour: Box<dyn ColourMapper>) -> Self {
+        self.crossfade_colour = Some(colour);
+        self
+    }
+
+    /// Sets the key used to draw the pitch coach's piano roll and judge intonation
+    pub fn with_scale(mut self, scale: Scale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Sets how many frames per second `Visualiser::draw` will be called, for time-based analysis
+    pub fn with_frame_rate(mut self, frame_rate: usize) -> Self {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    /// Sets the modulations applied to visual parameters every frame
+    pub fn with_modulation(mut self, modulation: ModulationMatrix) -> Self {
+        self.modulation = modulation;
+        self
+    }
+
+    /// Sets a timeline of scene and parameter changes to play from the first frame
+    pub fn with_timeline(mut self, timeline: Timeline) -> Self {
+        self.timeline = Some(timeline);
+        self
+    }
+
+    /// Leaves a fading ghost of previous bar heights behind the bars, lasting roughly
+    /// `length` seconds and drawn at `opacity` (0.0 to 1.0)
+    pub fn with_trails(mut self, length: f32, opacity: f32) -> Self {
+        self.trails = Some((length, opacity));
+        self
+    }
+
+    /// Draws a thin cap above each bar at its recent peak, which stays put for `hold` seconds
+    /// and then falls at `fall` bar heights a second
+    pub fn with_peak_hold(mut self, hold: f32, fall: f32) -> Self {
+        self.peak_hold = Some((hold, fall));
+        self
+    }
+
+    /// Throws out particles from each emitter over every mode, driven by its frequency band
+    pub fn with_emitters(mut self, emitters: Vec<EmitterConfig>) -> Self {
+        self.emitters = emitters;
+        self
+    }
+
+    /// Sprites that bar skins and particles can be drawn with
+    pub fn with_atlas(mut self, atlas: TextureAtlas) -> Self {
+        self.atlas = Some(atlas);
+        self
+    }
+
+    /// Draws bars with the named atlas sprite, tinted by their colours, instead of solid
+    /// colour
+    pub fn with_bar_skin(mut self, sprite: &str, fit: SkinFit) -> Self {
+        self.bar_skin = Some((sprite.to_string(), fit));
+        self
+    }
+
+    /// Draws an image behind every mode, warped by the spectrum
+    pub fn with_distortion(mut self, distortion: DistortionConfig) -> Self {
+        self.distortion = Some(distortion);
+        self
+    }
+
+    /// Places note names by `placement` within the window less `safe_area` margins, given
+    /// as fractions of its width and height
+    pub fn with_notes_placement(mut self, placement: Placement, safe_area: [f32; 2]) -> Self {
+        self.notes_placement = placement;
+        self.safe_area = safe_area;
+        self
+    }
+
+    /// Caps the spectrogram, trails and piano roll history to their parts of `budget`
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory_budget = budget;
+        self
+    }
+
+    /// Draws a faded reflection of the bars on a floor below them
+    pub fn with_reflection(mut self, reflection: Reflection) -> Self {
+        self.reflection = Some(reflection);
+        self
+    }
+
+    /// Sets the shape bars are drawn with
+    pub fn with_bar_style(mut self, bar_style: BarStyle) -> Self {
+        self.bar_style = bar_style;
+        self
+    }
+
+    /// Sets the space between bars
+    pub fn with_bar_gap(mut self, bar_gap: BarGap) -> Self {
+        self.bar_gap = bar_gap;
+        self
+    }
+
+    pub fn with_amplitude_scale(mut self, scale: AmplitudeScale) -> Self {
+        self.amplitude_scale = scale;
+        self
+    }
+
+    /// Scales bars to a reference level that rises to louder peaks over `attack` seconds and
+    /// falls back over `release` seconds, instead of to the tallest bar each frame. Only
+    /// affects the amplitude scales relative to the tallest bar
+    pub fn with_auto_gain(mut self, attack: f32, release: f32) -> Self {
+        self.auto_gain = (attack, release);
+        self
+    }
+
+    /// Adds `gain` decibels to the spectrum before grouping it into bars
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Weights the spectrum before grouping it into bars, so they follow perceived loudness
+    pub fn with_weighting(mut self, weighting: Weighting) -> Self {
+        self.weighting = Some(weighting);
+        self
+    }
+
+    /// Starts the waveform at a rising zero crossing so periodic sounds stand still
+    pub fn with_waveform_trigger(mut self, trigger: bool) -> Self {
+        self.waveform_trigger = trigger;
+        self
+    }
+
+    /// Flashes the screen on each beat, at up to `opacity` for the most confident beats
+    pub fn with_beat_flash(mut self, opacity: f32) -> Self {
+        self.beat_flash = opacity;
+        self
+    }
+
+    /// Shows the left and right channels as separate bars, laid out as `layout`. Only takes
+    /// effect when the frames passed to `Visualiser::draw` have `channel_spectra`
+    pub fn with_stereo(mut self, layout: StereoLayout) -> Self {
+        self.stereo = Some(layout);
+        self
+    }
+
+    /// Sets the font and styling of overlay text
+    pub fn with_typography(mut self, typography: Typography) -> Self {
+        self.typography = typography;
+        self
+    }
+
+    /// Fails if the shaders can't be compiled, so needs the window to exist
+    pub fn build(
+        self,
+        sampling_rate: usize,
+        fft_size: usize,
+    ) -> Result<Visualiser, VisualiserError> {
         let ranges = self.grouping.create_ranges(sampling_rate, fft_size);
 
+        let mut base_parameters = Parameters::default();
+        if let SmoothingStrategy::RiseFall { rise, fall } = self.smoothing {
+            base_parameters.smoothing_rise = rise;
+            base_parameters.smoothing_fall = fall;
+        }
+
+        #[cfg(feature = "scripting")]
+        let script = load_script(&self.mode, &self.typography);
+        #[cfg(not(feature = "scripting"))]
+        if let DisplayMode::Script(path) = &self.mode {
+            eprintln!("Built without scripting, so can't run {}", path.display());
+        }
         let initial_bars: Vec<f32> = vec![0.0; self.grouping.num_bars()];
         let initial_chromagram: Vec<f32> = vec![(1e-6_f32).ln(); 12];
-        Visualiser {
+        let budget = self.memory_budget;
+        let mut note_tracker = NoteTracker::new(sampling_rate);
+        if let Some(max_events) = budget.note_events() {
+            note_tracker = note_tracker.with_max_events(max_events);
+        }
+        Ok(Visualiser {
             sampling_rate,
+            fft_size,
+            mode: self.mode,
             grouping: self.grouping,
             smoothing: self.smoothing,
             colour: self.colour,
+            crossfade_colour: self.crossfade_colour,
             grouping_ranges: ranges,
+            channel_bars: [initial_bars.clone(), initial_bars.clone()],
             bars_to_display: initial_bars,
+            stereo: self.stereo,
+            amplitude_scale: self.amplitude_scale,
+            auto_gain: AutoGain::new(self.auto_gain.0, self.auto_gain.1, self.frame_rate),
+            frame_rate: self.frame_rate,
+            gain: power_gain(self.gain),
+            weighting: self.weighting,
+            weights: Vec::new(),
             smoothed_chromagram: initial_chromagram,
-        }
+            pitch_tracker: PitchTracker::new(sampling_rate),
+            pitch_history: VecDeque::with_capacity(PITCH_HISTORY_LEN),
+            scale: self.scale,
+            note_tracker,
+            spectrogram: Spectrogram::with_columns(
+                budget.spectrogram_columns(spectrogram::ROWS, spectrogram::HISTORY_LEN),
+            ),
+            novelty: NoveltyCurve::new(),
+            cqt: None,
+            cqt_bars: Vec::new(),
+            drop_predictor: DropPredictor::new(sampling_rate, self.frame_rate),
+            modulation: self.modulation,
+            base_parameters,
+            parameters: base_parameters,
+            #[cfg(feature = "scripting")]
+            script,
+            timeline: self.timeline,
+            show_start: None,
+            mode_before_profile: None,
+            trails: self
+                .trails
+                .filter(|_| {
+                    if !cfg!(feature = "shaders") {
+                        eprintln!("Built without shaders, so trails are off");
+                    }
+                    cfg!(feature = "shaders")
+                })
+                .map(|(length, opacity)| {
+                    Trails::new(length, opacity, self.frame_rate).map(|trails| {
+                        match budget.trail_pixels() {
+                            Some(max_pixels) => trails.with_max_pixels(max_pixels),
+                            None => trails,
+                        }
+                    })
+                })
+                .transpose()?,
+            reflection: self.reflection,
+            peak_hold: self.peak_hold.map(|(hold, fall)| PeakHold::new(hold, fall)),
+            particles: Particles::new(&self.emitters),
+            atlas: self.atlas,
+            bar_skin: self.bar_skin,
+            distortion: self
+                .distortion
+                .filter(|_| {
+                    if !cfg!(feature = "shaders") {
+                        eprintln!("Built without shaders, so the distortion layer is off");
+                    }
+                    cfg!(feature = "shaders")
+                })
+                .map(DistortionLayer::new)
+                .transpose()?,
+            bar_renderer: BarRenderer::new(self.bar_style)?,
+            bar_gap: self.bar_gap,
+            waveform_trigger: self.waveform_trigger,
+            beat_flash: self.beat_flash,
+            last_beat: None,
+            clock: None,
+            notes_placement: self.notes_placement,
+            safe_area: self.safe_area,
+            typography: self.typography,
+        })
     }
 }
 
 impl Visualiser {
-    pub fn draw_fft(&mut self, input: &[f32]) {
-        let grouped: Vec<f32> = self.grouping.group_spectrum(input, &self.grouping_ranges);
-        self.smoothing.smooth(&mut self.bars_to_display, &grouped);
-        let colour = self.colour.get_colour(input, self.sampling_rate);
+    /// Draws a single frame of the current `DisplayMode`
+    pub fn draw(&mut self, analysis: &FrameAnalysis) {
+        self.apply_timeline(analysis.time);
+
+        self.parameters = self.modulation.apply(self.base_parameters, analysis);
+        if let SmoothingStrategy::RiseFall { rise, fall } = &mut self.smoothing {
+            *rise = self.parameters.smoothing_rise;
+            *fall = self.parameters.smoothing_fall;
+        }
+
+        if let Some(beat) = &analysis.beat {
+            self.colour.on_beat(beat);
+            if let Some(colour) = &mut self.crossfade_colour {
+                colour.on_beat(beat);
+            }
+            self.last_beat = Some(*beat);
+        }
+
+        let input = analysis.spectrum.as_slice();
+
+        let now = self.now();
+        if let Some(distortion) = &mut self.distortion {
+            distortion.update(analysis, self.sampling_rate);
+            distortion.draw(now);
+        }
+
+        match self.mode {
+            DisplayMode::BuiltIn(mode) => (mode.draw)(self, analysis),
+            DisplayMode::Script(_) =>
+            {
+                #[cfg(feature = "scripting")]
+                if let Some(script) = &mut self.script {
+                    script.draw(analysis);
+                }
+            }
+        }
+
+        if !self.particles.is_empty() {
+            self.particles.update(analysis, self.sampling_rate, now);
+            self.particles.draw(self.atlas.as_ref());
+        }
+
+        self.draw_beat_flash(analysis.time);
+
+        let drop_state = self.drop_predictor.update(input, analysis.time);
+        self.draw_drop_transition(drop_state, analysis.time);
+    }
+
+    /// Nudges the unmodulated value of `parameter` by `delta`, e.g. from a keyboard control
+    pub fn adjust_parameter(&mut self, parameter: Parameter, delta: f32) {
+        *self.base_parameters.get_mut(parameter) += delta;
+        self.base_parameters.clamp();
+    }
+
+    /// Switches to the smoothing, colours and mode of `profile`, or back to those in `config`
+    /// if there's no profile
+    pub fn apply_profile(&mut self, config: &Config, profile: Option<&ProfileConfig>) {
+        let smoothing = profile
+            .and_then(|p| p.smoothing)
+            .unwrap_or(config.smoothing);
+        self.base_parameters.smoothing_rise = smoothing.rise;
+        self.base_parameters.smoothing_fall = smoothing.fall;
+
+        let colour = profile
+            .and_then(|p| p.colour.as_ref())
+            .unwrap_or(&config.colour);
+        self.colour = colour.mapper(self.sampling_rate);
+
+        match profile
+            .and_then(|p| p.mode.as_deref())
+            .and_then(DisplayMode::from_name)
+        {
+            Some(mode) => {
+                if self.mode_before_profile.is_none() {
+                    self.mode_before_profile = Some(self.mode.clone());
+                }
+                self.set_mode(mode);
+            }
+            None => {
+                if let Some(mode) = self.mode_before_profile.take() {
+                    self.set_mode(mode);
+                }
+            }
+        }
+    }
+
+    /// Switches to the mode, colours, beat flash and sensitivity of an automatic scene,
+    /// keeping whatever it leaves out
+    pub fn apply_scene(&mut self, scene: &SceneConfig) {
+        self.apply_sensitivity(scene.gain, scene.normalisation, scene.smoothing);
+        if let Some(colour) = &scene.colour {
+            self.colour = colour.mapper(self.sampling_rate);
+        }
+        if let Some(flash) = scene.flash {
+            self.beat_flash = flash;
+        }
+        if let Some(mode) = scene.mode.as_deref().and_then(DisplayMode::from_name) {
+            self.set_mode(mode);
+        }
+    }
+
+    /// Switches to the grouping, sensitivity, colours and mode of `preset`, keeping whatever
+    /// it leaves out
+    pub fn apply_preset(&mut self, preset: &PresetConfig) {
+        if let Some(grouping) = preset.grouping {
+            self.set_grouping(grouping.strategy());
+        }
+        self.apply_sensitivity(preset.gain, preset.normalisation, preset.smoothing);
+        if let Some(colour) = &preset.colour {
+            self.colour = colour.mapper(self.sampling_rate);
+        }
+        if let Some(mode) = preset.mode.as_deref().and_then(DisplayMode::from_name) {
+            self.set_mode(mode);
+        }
+    }
+
+    // Switches how strongly the bars react to whichever of the settings are given. A new
+    // normalisation starts its reference level afresh from the next peak
+    fn apply_sensitivity(
+        &mut self,
+        gain: Option<f32>,
+        normalisation: Option<NormalisationConfig>,
+        smoothing: Option<SmoothingConfig>,
+    ) {
+        if let Some(gain) = gain {
+            self.gain = power_gain(gain);
+        }
+        if let Some(normalisation) = normalisation {
+            self.amplitude_scale = normalisation.amplitude_scale();
+            self.auto_gain = AutoGain::new(
+                normalisation.agc.attack,
+                normalisation.agc.release,
+                self.frame_rate,
+            );
+        }
+        if let Some(smoothing) = smoothing {
+            self.smoothing = smoothing.strategy();
+            self.base_parameters.smoothing_rise = smoothing.rise;
+            self.base_parameters.smoothing_fall = smoothing.fall;
+        }
+    }
+
+    /// Regroups the spectrum into a different set of bars, starting them all from empty
+    pub fn set_grouping(&mut self, grouping: GroupingStrategy) {
+        self.grouping_ranges = grouping.create_ranges(self.sampling_rate, self.fft_size);
+        let bars = vec![0.0; grouping.num_bars()];
+        self.channel_bars = [bars.clone(), bars.clone()];
+        self.bars_to_display = bars;
+        self.grouping = grouping;
+    }
+
+    /// Adds `delta` bars, keeping the grouping strategy
+    pub fn adjust_bars(&mut self, delta: isize) {
+        let bars = self
+            .grouping
+            .num_bars()
+            .saturating_add_signed(delta)
+            .clamp(1, MAX_BARS);
+        self.set_grouping(self.grouping.with_num_bars(bars));
+    }
+
+    /// Switches to the next grouping strategy, keeping the number of bars
+    pub fn cycle_grouping(&mut self) {
+        self.set_grouping(self.grouping.next());
+    }
+
+    /// Switches to the mode after the current one, or the first from a script
+    pub fn next_mode(&mut self) {
+        let modes = Mode::all();
+        let current = modes.iter().position(|mode| self.mode.is(mode));
+        let next = current.map_or(0, |index| (index + 1) % modes.len());
+        self.set_mode(DisplayMode::BuiltIn(modes[next]));
+    }
+
+    /// Whether the current mode draws from `input`, so it needn't be computed otherwise
+    pub fn needs(&self, input: ModeInput) -> bool {
+        self.mode.needs(input)
+    }
+
+    /// Parts of the analysis the mode, colours and modulation are worked out from, so the
+    /// rest needn't be computed
+    pub fn features(&self) -> Features {
+        let crossfade = self
+            .crossfade_colour
+            .as_ref()
+            .map_or(Features::NONE, |colour| colour.features());
+        // Beats for the beat flash and the colours' `on_beat`
+        self.mode.features()
+            | self.colour.features()
+            | crossfade
+            | self.modulation.features()
+            | Feature::Beats.into()
+    }
+
+    /// Draws every following frame as if it's `time` seconds in, rather than going by the
+    /// wall clock, for rendering frames offline at a fixed rate. None goes back to the wall
+    /// clock
+    pub fn set_clock(&mut self, time: Option<f64>) {
+        self.clock = time;
+    }
+
+    // Seconds in for whatever is animated by time rather than by the analysis
+    fn now(&self) -> f64 {
+        self.clock.unwrap_or_else(get_time)
+    }
+
+    /// Sets the unmodulated value of `parameter`
+    pub fn set_parameter(&mut self, parameter: Parameter, value: f32) {
+        *self.base_parameters.get_mut(parameter) = value;
+        self.base_parameters.clamp();
+    }
+
+    /// Current mode and parameter values as name/value pairs, for display
+    pub fn settings(&self) -> Vec<(String, String)> {
+        let mut settings = vec![
+            ("mode".to_string(), self.mode.name()),
+            ("fft size".to_string(), self.fft_size.to_string()),
+            ("bars".to_string(), self.grouping.num_bars().to_string()),
+            ("grouping".to_string(), self.grouping.name().to_string()),
+        ];
+        settings.extend(Parameter::ALL.map(|parameter| {
+            (
+                parameter.name().to_string(),
+                format!("{:.2}", self.parameters.get(parameter)),
+            )
+        }));
+        settings
+    }
+
+    pub fn typography(&self) -> &Typography {
+        &self.typography
+    }
+
+    /// Regroups the bars for spectra from `fft_size` sample windows, keeping their levels so
+    /// the switch doesn't show
+    pub fn set_fft_size(&mut self, fft_size: usize) {
+        self.fft_size = fft_size;
+        self.grouping_ranges = self.grouping.create_ranges(self.sampling_rate, fft_size);
+    }
+
+    /// Switches to a different visualisation
+    pub fn set_mode(&mut self, mode: DisplayMode) {
+        #[cfg(feature = "scripting")]
+        {
+            self.script = load_script(&mode, &self.typography);
+        }
+        #[cfg(not(feature = "scripting"))]
+        if let DisplayMode::Script(path) = &mode {
+            eprintln!("Built without scripting, so can't run {}", path.display());
+        }
+        self.mode = mode;
+    }
+
+    /// Fires any timeline cues that have come due by `time`
+    fn apply_timeline(&mut self, time: f64) {
+        let Some(timeline) = &mut self.timeline else {
+            return;
+        };
+        let start = *self.show_start.get_or_insert(time);
+
+        let mut scene = None;
+        for cue in timeline.update(time - start) {
+            if cue.scene.is_some() {
+                scene = cue.scene.clone();
+            }
+            for &(parameter, value) in &cue.parameters {
+                *self.base_parameters.get_mut(parameter) = value;
+            }
+        }
+
+        if let Some(mode) = scene {
+            self.set_mode(mode);
+        }
+    }
+
+    /// Overlays a white flash that fades out after each beat, brighter for confident beats
+    fn draw_beat_flash(&self, time: f64) {
+        let Some(beat) = self.last_beat.filter(|_| self.beat_flash > 0.0) else {
+            return;
+        };
+        let fade = 1.0 - ((time - beat.time) / BEAT_FLASH_SECONDS).min(1.0);
+        if fade <= 0.0 {
+            return;
+        }
 
-        let max_val = self.bars_to_display.iter().cloned().fold(1e-6, f32::max);
-        let normalised: Vec<f32> = self.bars_to_display.iter().map(|m| m / max_val).collect();
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            screen_height(),
+            Color::new(
+                1.0,
+                1.0,
+                1.0,
+                self.beat_flash * beat.confidence * fade as f32,
+            ),
+        );
+    }
+
+    /// Overlays the build-up and drop transition: the frame darkens as a predicted drop
+    /// approaches, then a flash fires on the exact frame the drop is detected
+    fn draw_drop_transition(&self, state: DropState, time: f64) {
+        match state {
+            DropState::Building { tension, .. } => {
+                draw_rectangle(
+                    0.0,
+                    0.0,
+                    screen_width(),
+                    screen_height(),
+                    Color::new(0.0, 0.0, 0.0, 0.4 * tension),
+                );
+            }
+            DropState::Dropped { at } => {
+                let fade = 1.0 - ((time - at) / DROP_FLASH_SECONDS).min(1.0);
 
-        self.draw_bars(normalised.as_slice(), colour, self.grouping.num_bars());
+                draw_rectangle(
+                    0.0,
+                    0.0,
+                    screen_width(),
+                    screen_height(),
+                    Color::new(1.0, 1.0, 1.0, 0.8 * fade as f32),
+                );
+            }
+            DropState::Idle => (),
+        }
     }
 
-    pub fn draw_bars(&self, input: &[f32], colour: Color, num_bars: usize) {
-        let bar_width: f32 = screen_width() / (num_bars as f32 * 1.1);
-        let bar_spacing: f32 = (screen_width() / num_bars as f32) - bar_width;
-        let max_height: f32 = screen_height() - 50.0;
+    /// Runs `analysis` through the bars without drawing them, so their smoothing and auto
+    /// gain have settled on the music by the time they're first shown
+    pub fn warm_up(&mut self, analysis: &FrameAnalysis) {
+        let spectrum = self.weighted(&analysis.spectrum);
+        update_bar_levels(
+            &self.grouping,
+            &self.grouping_ranges,
+            &self.smoothing,
+            &self.amplitude_scale,
+            &mut self.auto_gain,
+            &mut self.bars_to_display,
+            &spectrum,
+        );
+    }
 
-        for (i, ampl) in input.iter().enumerate() {
-            let index = i as f32;
+    pub fn draw_fft(&mut self, analysis: &FrameAnalysis) {
+        let colours: Vec<Color> = self
+            .current_bar_colours(analysis)
+            .into_iter()
+            .map(|colour| rotate_hue(colour, self.parameters.hue_offset))
+            .collect();
+
+        if let (Some(layout), Some(channels)) = (self.stereo, &analysis.channel_spectra) {
+            self.draw_stereo_fft(layout, channels, &colours);
+            return;
+        }
+
+        let spectrum = self.weighted(&analysis.spectrum);
+        let normalised = update_bar_levels(
+            &self.grouping,
+            &self.grouping_ranges,
+            &self.smoothing,
+            &self.amplitude_scale,
+            &mut self.auto_gain,
+            &mut self.bars_to_display,
+            &spectrum,
+        );
+        let now = self.now();
+        if let Some(peak_hold) = &mut self.peak_hold {
+            peak_hold.update(&normalised, now);
+        }
+
+        // Draw into the trail buffer first so the crisp bars end up on top of their ghosts
+        if let Some(trails) = &mut self.trails {
+            trails.begin();
+        }
+        if let Some(trails) = &self.trails {
+            self.draw_bars(normalised.as_slice(), &colours, self.grouping.num_bars());
+            trails.end();
+        }
+
+        self.draw_bars(normalised.as_slice(), &colours, self.grouping.num_bars());
+    }
+
+    /// `spectrum` with the gain and weighting applied, if there are any
+    fn weighted<'a>(&mut self, spectrum: &'a [f32]) -> Cow<'a, [f32]> {
+        let gain = self.gain;
+        let Some(weighting) = self.weighting else {
+            if gain == 1.0 {
+                return Cow::Borrowed(spectrum);
+            }
+            return Cow::Owned(spectrum.iter().map(|power| power * gain).collect());
+        };
+        if self.weights.len() != spectrum.len() {
+            self.weights = weighting.table(self.sampling_rate, spectrum.len());
+        }
+
+        Cow::Owned(
+            spectrum
+                .iter()
+                .zip(&self.weights)
+                .map(|(power, weight)| power * weight * gain)
+                .collect(),
+        )
+    }
+
+    /// Draws the constant-Q spectrum as one smoothed bar per semitone
+    pub fn draw_cqt(&mut self, analysis: &FrameAnalysis) {
+        let samples = &analysis.samples;
+        if self
+            .cqt
+            .as_ref()
+            .is_none_or(|cqt| cqt.window_length() != samples.len().next_power_of_two())
+        {
+            self.cqt = match CqtAnalyzer::new(self.sampling_rate, samples.len()) {
+                Ok(cqt) => Some(cqt),
+                Err(e) => {
+                    eprintln!("Couldn't set up the constant-Q transform, showing bars: {e}");
+                    self.mode = DisplayMode::BuiltIn(modes::BARS);
+                    return;
+                }
+            };
+        }
+        let Some(cqt) = &self.cqt else {
+            return;
+        };
+
+        let magnitudes = cqt.compute(samples);
+        self.cqt_bars.resize(magnitudes.len(), 0.0);
+        self.smoothing.smooth(&mut self.cqt_bars, &magnitudes);
+
+        let max_val = self.cqt_bars.iter().cloned().fold(1e-6, f32::max);
+        let normalised: Vec<f32> = self.cqt_bars.iter().map(|m| m / max_val).collect();
+
+        let colours: Vec<Color> = self
+            .current_bar_colours(analysis)
+            .into_iter()
+            .map(|colour| rotate_hue(colour, self.parameters.hue_offset))
+            .collect();
+        self.draw_bars(&normalised, &colours, normalised.len());
+    }
+
+    /// Draws the left and right channel spectra as two sets of bars, each half the width of
+    /// the screen
+    fn draw_stereo_fft(
+        &mut self,
+        layout: StereoLayout,
+        channels: &[Vec<f32>; 2],
+        colours: &[Color],
+    ) {
+        for (i, spectrum) in channels.iter().enumerate() {
+            let spectrum = self.weighted(spectrum);
+            let grouped = self
+                .grouping
+                .group_spectrum(&spectrum, &self.grouping_ranges);
+            self.smoothing.smooth(&mut self.channel_bars[i], &grouped);
+        }
+
+        // Both channels share one scale so a louder side looks louder
+        let heights = self.amplitude_scale.heights(
+            &self.channel_bars.concat(),
+            channels[0].len(),
+            &mut self.auto_gain,
+        );
+        let (left, right) = heights.split_at(self.channel_bars[0].len());
+
+        let num_bars = self.grouping.num_bars();
+        let half = screen_width() / 2.0;
+        let draw = |visualiser: &Self| {
+            let mirrored = matches!(layout, StereoLayout::Mirrored);
+            visualiser.draw_bars_in(left, colours, num_bars, 0.0, half, mirrored);
+            visualiser.draw_bars_in(right, colours, num_bars, half, half, false);
+        };
+
+        if let Some(trails) = &mut self.trails {
+            trails.begin();
+        }
+        if let Some(trails) = &self.trails {
+            draw(self);
+            trails.end();
+        }
+
+        draw(self);
+    }
+
+    /// Colour of each bar from the colour mapper, crossfaded with the secondary mapper if
+    /// there is one
+    fn current_bar_colours(&mut self, analysis: &FrameAnalysis) -> Vec<Color> {
+        let primary = self.colour.get_bar_colours(analysis, &self.grouping_ranges);
+
+        match &mut self.crossfade_colour {
+            Some(secondary) => primary
+                .into_iter()
+                .zip(secondary.get_bar_colours(analysis, &self.grouping_ranges))
+                .map(|(a, b)| blend_colours(a, b, self.parameters.colour_blend))
+                .collect(),
+            None => primary,
+        }
+    }
+
+    /// Draws `input` as bars, repeating `colours` if there are fewer colours than bars
+    pub fn draw_bars(&self, input: &[f32], colours: &[Color], num_bars: usize) {
+        self.draw_bars_in(input, colours, num_bars, 0.0, screen_width(), false);
+    }
+
+    /// Draws `input` as bars spread across `width` pixels starting at `left`, with the first
+    /// bar on the right instead if `reversed`
+    pub fn draw_bars_in(
+        &self,
+        input: &[f32],
+        colours: &[Color],
+        num_bars: usize,
+        left: f32,
+        width: f32,
+        reversed: bool,
+    ) {
+        // Square bars alias at fractional positions, so keep their edges on whole pixels
+        let mut columns = bar_columns(
+            num_bars,
+            width,
+            self.bar_gap,
+            !self.bar_renderer.is_anti_aliased(),
+        );
+        for (x, _) in &mut columns {
+            *x += left;
+        }
+        if reversed {
+            columns.reverse();
+        }
+        let floor_height = self
+            .reflection
+            .map_or(0.0, |reflection| reflection.height * screen_height());
+        let baseline = screen_height() - floor_height;
+        let max_height: f32 = (baseline - 50.0 * ui_scale()) * self.parameters.zoom;
+
+        // Peaks are only kept for the bars mode's own bars, which stereo splits in two
+        let peaks = self
+            .peak_hold
+            .as_ref()
+            .map(PeakHold::peaks)
+            .filter(|peaks| self.mode.is(modes::BARS) && peaks.len() == input.len());
+        let cap_height = 3.0 * ui_scale();
+
+        let skin = self.atlas.as_ref().zip(self.bar_skin.as_ref());
+        let mut skinned = Vec::new();
+
+        let mut mesh = BarRenderer::new_mesh();
+
+        for (i, (ampl, &(x, bar_width))) in input.iter().zip(&columns).enumerate() {
             let bar_height = ampl * max_height;
-            let x = (index * bar_width) + (index * bar_spacing) + bar_spacing;
-            let y = screen_height() - bar_height;
+            let y = baseline - bar_height;
+            let colour = colours[i % colours.len()];
+
+            let bar = Rect::new(x, y, bar_width, bar_height);
+            if skin.is_some() {
+                skinned.push((bar, colour));
+            } else {
+                self.bar_renderer.push(&mut mesh, bar, colour, colour);
+            }
+
+            if let Some(reflection) = self.reflection {
+                // Fade over the whole floor rather than the reflected bar, so every
+                // reflection is equally bright at the baseline
+                let length = bar_height.min(floor_height);
+                let fade_end = 1.0 - length / floor_height.max(f32::EPSILON);
+                let top = Color {
+                    a: colour.a * reflection.opacity,
+                    ..colour
+                };
+                let bottom = Color {
+                    a: top.a * fade_end,
+                    ..colour
+                };
 
-            draw_rectangle(x, y, bar_width, bar_height, colour);
+                self.bar_renderer.push(
+                    &mut mesh,
+                    Rect::new(x, baseline, bar_width, length),
+                    top,
+                    bottom,
+                );
+            }
+
+            if let Some(peak) = peaks.map(|peaks| peaks[i]) {
+                let y = baseline - peak * max_height - cap_height;
+                self.bar_renderer.push(
+                    &mut mesh,
+                    Rect::new(x, y, bar_width, cap_height),
+                    colour,
+                    colour,
+                );
+            }
+        }
+
+        self.bar_renderer.draw(&mesh);
+
+        if let Some((atlas, (sprite, fit))) = skin {
+            for (bar, colour) in skinned {
+                atlas.draw_bar(sprite, bar, *fit, colour);
+            }
         }
     }
 
@@ -112,22 +1307,130 @@ impl Visualiser {
 
         let pitches = frequency_to_pitch_spectrum(&normalised, self.sampling_rate);
 
-        self.draw_bars(&pitches, WHITE, 128);
+        self.draw_bars(&pitches, &[WHITE], 128);
     }
 
-    pub fn draw_centered_text(&self, output: &str) {
-        let text_dimensions = measure_text(output, None, 30, 1.0);
+    /// Draws note names where the overlay places them, the centre by default
+    pub fn draw_notes_text(&self, output: &str) {
+        let font_size = 30.0 * ui_scale();
+        let text_dimensions = self.typography.measure(TextRole::Notes, output, font_size);
+        let position = self.notes_placement.position(
+            safe_area(self.safe_area),
+            vec2(text_dimensions.width, text_dimensions.height),
+        );
 
-        draw_text(
+        self.typography.draw(
+            TextRole::Notes,
             output,
-            (screen_width() / 2.0) - text_dimensions.width / 2.0,
-            (screen_height() / 2.0) - text_dimensions.height / 2.0,
-            30.0,
+            position.x,
+            position.y + text_dimensions.offset_y,
+            font_size,
             BLUE,
         );
     }
 
     pub fn draw_chromagram(&mut self, input: &[f32]) {
+        let log_chromagram = self.update_chromagram(input);
+
+        let top_three_indices: Vec<usize> =
+            get_n_largest_indices(self.smoothed_chromagram.as_slice(), 3);
+        let top_three_notes: Vec<String> = top_three_indices
+            .iter()
+            .map(|&val| chroma_index_to_note(val))
+            .collect();
+
+        let output = format!(
+            "Top Notes: {}, {}, {}",
+            top_three_notes[0], top_three_notes[1], top_three_notes[2]
+        );
+
+        let max_val = log_chromagram.iter().cloned().fold(1e-6, f32::max);
+        let normalised: Vec<f32> = log_chromagram.iter().map(|&val| val / max_val).collect();
+
+        self.draw_bars(&normalised, &[WHITE], 12);
+        self.draw_notes_text(&output);
+    }
+
+    /// Draws the smoothed chromagram as 12 wedges around the circle of fifths, with a
+    /// pointer towards the key centre
+    ///
+    /// Each wedge is lit by how close its pitch class is to the loudest, in the pitch class's
+    /// hue from `ChromagramColour`. Keys next to each other round the circle of fifths share
+    /// all but one note, so a key's notes light up together on one side of the wheel. The
+    /// pointer follows the weighted sum of the wedges, worked out as `ChromagramColour` works
+    /// out its hue, so it settles near the key's tonic, and reaches further out the more the
+    /// chromagram leans that way
+    pub fn draw_chroma_wheel(&mut self, input: &[f32]) {
+        self.update_chromagram(input);
+        let loudest = self
+            .smoothed_chromagram
+            .iter()
+            .copied()
+            .fold(f32::MIN, f32::max);
+        let levels: Vec<f32> = self
+            .smoothed_chromagram
+            .iter()
+            .map(|&level| (1.0 - (loudest - level) / WHEEL_RANGE).clamp(0.0, 1.0))
+            .collect();
+
+        let centre = vec2(screen_width() / 2.0, screen_height() / 2.0);
+        let outer = 0.4 * screen_width().min(screen_height());
+        let inner = 0.45 * outer;
+        // Degrees clockwise from the top, as y points down the screen
+        let at = |degrees: f32, radius: f32| {
+            let angle = degrees.to_radians();
+            centre + radius * vec2(angle.sin(), -angle.cos())
+        };
+        let font_size = 20.0 * ui_scale();
+
+        for position in 0..12 {
+            let pitch_class = position * FIFTH % 12;
+            let (r, g, b) = hsv_to_rgb(
+                pitch_class as f32 * 30.0,
+                0.8,
+                0.15 + 0.85 * levels[pitch_class],
+            );
+            let colour = Color { r, g, b, a: 1.0 };
+
+            // Centred on the position, leaving a gap of a degree either side
+            let start = position as f32 * 30.0 - 14.0;
+            let step = 28.0 / WEDGE_STEPS as f32;
+            for i in 0..WEDGE_STEPS {
+                let (from, to) = (start + i as f32 * step, start + (i + 1) as f32 * step);
+                draw_triangle(at(from, inner), at(from, outer), at(to, outer), colour);
+                draw_triangle(at(from, inner), at(to, outer), at(to, inner), colour);
+            }
+
+            let name = chroma_index_to_note(pitch_class);
+            let dimensions = self.typography.measure(TextRole::Notes, &name, font_size);
+            let label = at(position as f32 * 30.0, outer + dimensions.height + 8.0);
+            self.typography.draw(
+                TextRole::Notes,
+                &name,
+                label.x - dimensions.width / 2.0,
+                label.y - dimensions.height / 2.0 + dimensions.offset_y,
+                font_size,
+                WHITE,
+            );
+        }
+
+        let (x, y) = chroma_vector(&levels, FIFTH);
+        let total: f32 = levels.iter().sum();
+        let concentration = ((x * x + y * y).sqrt() / total.max(f32::EPSILON)).min(1.0);
+        let angle = y.atan2(x).to_degrees();
+        let tip = at(angle, inner * (0.2 + 0.8 * concentration));
+        let thickness = 4.0 * ui_scale();
+        draw_line(centre.x, centre.y, tip.x, tip.y, thickness, WHITE);
+        draw_circle(centre.x, centre.y, 1.5 * thickness, WHITE);
+
+        // The pitch class of the wedge the pointer is nearest
+        let position = (angle / 30.0).round().rem_euclid(12.0) as usize;
+        let key = chroma_index_to_note(position * FIFTH % 12);
+        self.draw_notes_text(&format!("Key centre: {key}"));
+    }
+
+    // Log chromagram of `input`, which is also smoothed into `smoothed_chromagram`
+    fn update_chromagram(&mut self, input: &[f32]) -> Vec<f32> {
         let alpha = 0.2_f32;
 
         let max_val = input.iter().cloned().fold(1e-6, f32::max);
@@ -147,23 +1450,256 @@ impl Visualiser {
             self.smoothed_chromagram[index] =
                 alpha * value + (1.0 - alpha) * self.smoothed_chromagram[index];
         }
+        log_chromagram
+    }
 
-        let top_three_indices: Vec<usize> =
-            get_n_largest_indices(self.smoothed_chromagram.as_slice(), 3);
-        let top_three_notes: Vec<String> = top_three_indices
-            .iter()
-            .map(|&val| chroma_index_to_note(val))
-            .collect();
+    /// Plots the tracked vocal pitch over time on a piano roll of the selected scale
+    ///
+    /// Rows belonging to the scale are highlighted (the root most strongly) and the pitch
+    /// trace is coloured by how far it strays from the nearest in-scale note:
+    /// green when in tune, through yellow, to red at a quarter-tone or more
+    pub fn draw_pitch_coach(&mut self, input: &[f32]) {
+        let pitch = self.pitch_tracker.estimate(input).map(frequency_to_midi);
 
-        let output = format!(
-            "Top Notes: {}, {}, {}",
-            top_three_notes[0], top_three_notes[1], top_three_notes[2]
-        );
+        if self.pitch_history.len() == PITCH_HISTORY_LEN {
+            self.pitch_history.pop_front();
+        }
+        self.pitch_history.push_back(pitch);
 
-        let max_val = log_chromagram.iter().cloned().fold(1e-6, f32::max);
-        let normalised: Vec<f32> = log_chromagram.iter().map(|&val| val / max_val).collect();
+        let step = screen_width() / PITCH_HISTORY_LEN as f32;
+        let row_height = self.draw_piano_roll_background();
+        let thickness = 3.0 * ui_scale();
+
+        let mut previous: Option<(f32, f32)> = None;
+
+        for (i, &entry) in self.pitch_history.iter().enumerate() {
+            let Some(p) =
+                entry.filter(|p| (PIANO_ROLL_LOW as f32..=PIANO_ROLL_HIGH as f32).contains(p))
+            else {
+                previous = None;
+                continue;
+            };
+
+            let x = i as f32 * step;
+            let y = piano_roll_y(p, row_height);
+            let colour = intonation_colour(cents_deviation(p, self.scale.nearest_note(p) as f32));
+
+            match previous {
+                // Don't join octave jumps or other large leaps with a line
+                Some((px, py)) if (py - y).abs() < row_height * 2.0 => {
+                    draw_line(px, py, x, y, thickness, colour)
+                }
+                _ => draw_rectangle(
+                    x - thickness / 2.0,
+                    y - thickness / 2.0,
+                    thickness,
+                    thickness,
+                    colour,
+                ),
+            }
 
-        self.draw_bars(&normalised, WHITE, 12);
-        self.draw_centered_text(&output);
+            previous = Some((x, y));
+        }
+
+        let output = match pitch {
+            Some(p) => {
+                let target = self.scale.nearest_note(p);
+                format!(
+                    "{} {:+.0} cents",
+                    midi_to_note_name(target),
+                    cents_deviation(p, target as f32)
+                )
+            }
+            None => String::from("-"),
+        };
+
+        self.draw_notes_text(&output);
+    }
+
+    /// Renders polyphonic note tracking as a scrolling piano roll of the last few seconds
+    pub fn draw_note_tracking(&mut self, input: &[f32]) {
+        let now = self.now();
+        self.note_tracker.update(input, now);
+
+        let row_height = self.draw_piano_roll_background();
+        let window_start = now - PIANO_ROLL_SECONDS;
+        let time_to_x = |t: f64| ((t - window_start) / PIANO_ROLL_SECONDS) as f32 * screen_width();
+
+        for note in self.note_tracker.events_since(window_start) {
+            let pitch = note.pitch as usize;
+            if !(PIANO_ROLL_LOW..=PIANO_ROLL_HIGH).contains(&pitch) {
+                continue;
+            }
+
+            let x_start = time_to_x(note.onset).max(0.0);
+            let x_end = time_to_x(note.offset.unwrap_or(now));
+            let y = piano_roll_y(pitch as f32, row_height) - row_height / 2.0;
+
+            let brightness = 0.4 + 0.6 * (note.velocity as f32 / 127.0);
+            let (r, g, b) = hsv_to_rgb((pitch % 12) as f32 * 30.0, 0.8, brightness);
+
+            draw_rectangle(
+                x_start,
+                y,
+                (x_end - x_start).max(2.0),
+                row_height - 1.0,
+                Color { r, g, b, a: 1.0 },
+            );
+        }
+    }
+
+    /// Scrolls a heatmap of the spectrum over time, newest frame on the right
+    pub fn draw_spectrogram(&mut self, input: &[f32]) {
+        self.spectrogram.push(input, self.sampling_rate);
+        let left = self.spectrogram.draw();
+
+        // The time the memory budget has cut off is left blank, with a line where it ends
+        if self.spectrogram.is_truncated() {
+            let scale = ui_scale();
+            let (label, size) = ("History cut short by memory budget", 18.0 * scale);
+            let width = self.typography.measure(TextRole::Labels, label, size).width;
+            draw_line(left, 0.0, left, screen_height(), 2.0 * scale, GRAY);
+            self.typography.draw(
+                TextRole::Labels,
+                label,
+                (left - width - size).max(size),
+                screen_height() / 2.0,
+                size,
+                GRAY,
+            );
+        }
+    }
+
+    /// Scrolls the spectral flux of each window with the onset threshold, marking onsets
+    /// and beats
+    pub fn draw_novelty(&mut self, analysis: &FrameAnalysis) {
+        self.novelty.push(analysis);
+        self.novelty.draw();
+    }
+
+    /// Plots `samples` as a line across the screen, centred vertically
+    ///
+    /// With the trigger on, the line starts at the first rising zero crossing in the older
+    /// half of the buffer and shows half the buffer, so a steady tone stays still rather than
+    /// drifting with wherever the capture happened to start
+    pub fn draw_waveform(&self, samples: &[f32]) {
+        let window = if self.waveform_trigger && samples.len() >= 4 {
+            let half = samples.len() / 2;
+            let start = trigger_position(&samples[..half + 1]).unwrap_or(0);
+            &samples[start..start + half]
+        } else {
+            samples
+        };
+        if window.len() < 2 {
+            return;
+        }
+
+        let centre = screen_height() / 2.0;
+        let amplitude = centre * self.parameters.zoom;
+        let step = screen_width() / (window.len() - 1) as f32;
+        let thickness = 2.0 * ui_scale();
+
+        for (i, pair) in window.windows(2).enumerate() {
+            draw_line(
+                i as f32 * step,
+                centre - pair[0].clamp(-1.0, 1.0) * amplitude,
+                (i + 1) as f32 * step,
+                centre - pair[1].clamp(-1.0, 1.0) * amplitude,
+                thickness,
+                WHITE,
+            );
+        }
     }
+
+    /// Shades one row per pitch in the piano roll range according to the selected scale
+    ///
+    /// Returns the height of a row
+    fn draw_piano_roll_background(&self) -> f32 {
+        let num_rows = PIANO_ROLL_HIGH - PIANO_ROLL_LOW + 1;
+        let row_height = screen_height() / num_rows as f32;
+
+        for note in PIANO_ROLL_LOW..=PIANO_ROLL_HIGH {
+            let shade = if self.scale.is_root(note) {
+                0.3
+            } else if self.scale.contains(note) {
+                0.2
+            } else {
+                0.12
+            };
+            let y = piano_roll_y(note as f32, row_height) - row_height / 2.0;
+
+            draw_rectangle(
+                0.0,
+                y,
+                screen_width(),
+                row_height - 1.0,
+                Color::new(shade, shade, shade, 1.0),
+            );
+
+            if self.scale.contains(note) {
+                self.typography.draw(
+                    TextRole::Labels,
+                    &midi_to_note_name(note),
+                    4.0,
+                    y + row_height - 2.0,
+                    row_height,
+                    WHITE,
+                );
+            }
+        }
+
+        row_height
+    }
+}
+
+/// Groups `spectrum` into bars, smooths them into `bars` and returns the smoothed bars
+/// scaled by `scale` and `gain`, from 0.0 to 1.0
+pub fn update_bar_levels(
+    grouping: &GroupingStrategy,
+    ranges: &[(usize, usize)],
+    smoothing: &SmoothingStrategy,
+    scale: &AmplitudeScale,
+    gain: &mut AutoGain,
+    bars: &mut [f32],
+    spectrum: &[f32],
+) -> Vec<f32> {
+    let grouped = grouping.group_spectrum(spectrum, ranges);
+    smoothing.smooth(bars, &grouped);
+
+    scale.heights(bars, spectrum.len(), gain)
+}
+
+/// Multiplier on power for a gain of `decibels`
+fn power_gain(decibels: f32) -> f32 {
+    10.0f32.powf(decibels / 10.0)
+}
+
+/// Loads the script for `mode` if it's a scripted scene
+#[cfg(feature = "scripting")]
+fn load_script(mode: &DisplayMode, typography: &Typography) -> Option<ScriptedScene> {
+    match mode {
+        DisplayMode::Script(path) => Some(ScriptedScene::new(path.clone(), typography.clone())),
+        _ => None,
+    }
+}
+
+/// Index of the first sample where `samples` crosses zero going upwards
+fn trigger_position(samples: &[f32]) -> Option<usize> {
+    samples
+        .windows(2)
+        .position(|pair| pair[0] <= 0.0 && pair[1] > 0.0)
+        .map(|i| i + 1)
+}
+
+/// Maps a fractional MIDI pitch to the vertical centre of its piano roll row
+fn piano_roll_y(pitch: f32, row_height: f32) -> f32 {
+    screen_height() - (pitch - PIANO_ROLL_LOW as f32 + 0.5) * row_height
+}
+
+/// Colour for a pitch `cents` away from its target: green (in tune) to red (50+ cents out)
+fn intonation_colour(cents: f32) -> Color {
+    let accuracy = 1.0 - (cents.abs() / 50.0).min(1.0);
+    let (r, g, b) = hsv_to_rgb(120.0 * accuracy, 1.0, 1.0);
+
+    Color { r, g, b, a: 1.0 }
 }
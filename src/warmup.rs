@@ -0,0 +1,74 @@
+//! Starting up cleanly: the first windows are analysed without being drawn, so smoothing
+//! and the bars' auto gain have settled on the music, then the visualiser fades in from
+//! black
+//!
+//! ```toml
+//! warm_up = { settle = 0.5, fade = 1.0 }
+//! ```
+
+use macroquad::{
+    color::Color,
+    shapes::draw_rectangle,
+    window::{screen_height, screen_width},
+};
+use serde::Deserialize;
+
+/// How long startup takes, see the module docs
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmUpConfig {
+    /// Seconds of audio analysed before anything's drawn
+    pub settle: f64,
+    /// Seconds the visualiser then takes to fade in
+    pub fade: f64,
+}
+
+impl Default for WarmUpConfig {
+    fn default() -> Self {
+        Self {
+            settle: 0.5,
+            fade: 1.0,
+        }
+    }
+}
+
+/// Tracks how far through startup the visualiser is
+pub struct WarmUp {
+    config: WarmUpConfig,
+    // Time the first window was analysed
+    start: Option<f64>,
+}
+
+impl WarmUp {
+    pub fn new(config: WarmUpConfig) -> Self {
+        Self {
+            config,
+            start: None,
+        }
+    }
+
+    /// Whether the analysis is still settling at `time` seconds, counting from the first
+    /// call, which should be once there's a window to analyse
+    pub fn settling(&mut self, time: f64) -> bool {
+        let start = *self.start.get_or_insert(time);
+        time - start < self.config.settle
+    }
+
+    /// Covers the frame in black, clearing over `fade` seconds once the analysis has settled
+    pub fn draw(&self, time: f64) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let faded = (time - start - self.config.settle) / self.config.fade.max(f64::EPSILON);
+        if faded >= 1.0 {
+            return;
+        }
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            screen_height(),
+            Color::new(0.0, 0.0, 0.0, (1.0 - faded).clamp(0.0, 1.0) as f32),
+        );
+    }
+}
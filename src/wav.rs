@@ -0,0 +1,27 @@
+use std::path::Path;
+
+/// Reads a WAV file as mono samples from -1.0 to 1.0, averaging its channels, along with
+/// its sample rate
+pub fn read_mono(path: &Path) -> Result<(Vec<f32>, usize), hound::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|v| v as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    let channels = (spec.channels as usize).max(1);
+    let mono = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    Ok((mono, spec.sample_rate as usize))
+}
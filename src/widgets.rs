@@ -0,0 +1,365 @@
+//! Ambient widgets for displays left running as a dashboard: a clock, CPU and memory use,
+//! and the weather, each pinned within the overlay's safe area and tinted towards an accent
+//! colour that glows with the loudness and pulses on the beat
+//!
+//! ```toml
+//! [widgets]
+//! accent = "#ff4080"
+//! clock = { anchor = "top-right" }
+//! system = { anchor = "bottom-left" }
+//! weather = { anchor = "top-left", command = "curl -s 'wttr.in/?format=%c+%t'" }
+//! ```
+//!
+//! System stats and the weather are gathered on background threads, so a slow network never
+//! holds up drawing. The weather comes from a `WeatherFetcher`, running `command` unless
+//! another fetcher is given to `Widgets::with_weather_fetcher`
+
+use std::{
+    fs, io,
+    process::Command,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use macroquad::{
+    color::{Color, WHITE},
+    math::vec2,
+    shapes::draw_rectangle,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    analysis::FrameAnalysis,
+    config::ConfigColour,
+    layout::{Anchor, Placement, safe_area},
+    schedule::TimeOfDay,
+    typography::{TextRole, Typography},
+    ui::ui_scale,
+};
+
+// Accent used when none is configured
+const DEFAULT_ACCENT: Color = Color::new(0.53, 0.8, 1.0, 1.0);
+
+#[derive(Debug, Error)]
+pub enum WeatherError {
+    #[error("couldn't run weather command: {0}")]
+    Io(#[from] io::Error),
+    #[error("weather command failed: {0}")]
+    Failed(String),
+}
+
+/// The `[widgets]` table, see the module docs. Widgets left out aren't drawn
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WidgetsConfig {
+    /// Colour the widgets are tinted towards as the music gets louder and on each beat
+    pub accent: Option<ConfigColour>,
+    pub clock: Option<ClockConfig>,
+    pub system: Option<SystemConfig>,
+    pub weather: Option<WeatherConfig>,
+}
+
+/// The local time as hours and minutes
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClockConfig {
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    /// Text size in pixels at the reference window height
+    #[serde(default = "default_clock_size")]
+    pub size: f32,
+}
+
+fn default_clock_size() -> f32 {
+    48.0
+}
+
+/// CPU and memory use of the whole system, read from `/proc`
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SystemConfig {
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    #[serde(default = "default_widget_size")]
+    pub size: f32,
+    /// Seconds between readings, which CPU use is averaged over
+    #[serde(default = "default_system_interval")]
+    pub interval: f64,
+}
+
+fn default_system_interval() -> f64 {
+    2.0
+}
+
+/// The first line printed by a command, usually a short weather report
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WeatherConfig {
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub offset: [f32; 2],
+    #[serde(default = "default_widget_size")]
+    pub size: f32,
+    /// Run with `sh -c`, e.g. `curl -s 'wttr.in/?format=%c+%t'`
+    #[serde(default)]
+    pub command: String,
+    /// Seconds between fetches
+    #[serde(default = "default_weather_interval")]
+    pub interval: f64,
+}
+
+fn default_widget_size() -> f32 {
+    24.0
+}
+
+fn default_weather_interval() -> f64 {
+    900.0
+}
+
+/// Gets the text the weather widget shows. Called on a background thread every
+/// `WeatherConfig::interval` seconds, keeping the last report if it fails
+pub trait WeatherFetcher: Send {
+    fn fetch(&mut self) -> Result<String, WeatherError>;
+}
+
+/// Runs a shell command and reports the first line it prints
+pub struct CommandFetcher {
+    command: String,
+}
+
+impl CommandFetcher {
+    pub fn new(command: String) -> Self {
+        Self { command }
+    }
+}
+
+impl WeatherFetcher for CommandFetcher {
+    fn fetch(&mut self) -> Result<String, WeatherError> {
+        let output = Command::new("sh").arg("-c").arg(&self.command).output()?;
+        if !output.status.success() {
+            return Err(WeatherError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+
+        let text = String::from_utf8_lossy(&output.stdout);
+        Ok(text.lines().next().unwrap_or_default().trim().to_string())
+    }
+}
+
+/// Whole-system resource use, as fractions from 0.0 to 1.0
+#[derive(Clone, Copy)]
+pub struct SystemStats {
+    pub cpu: f32,
+    pub memory: f32,
+}
+
+/// Draws the widgets in `WidgetsConfig`, with the latest readings from their threads
+pub struct Widgets {
+    config: WidgetsConfig,
+    system: Option<Arc<Mutex<Option<SystemStats>>>>,
+    weather: Option<Arc<Mutex<Option<String>>>>,
+}
+
+impl Widgets {
+    /// Starts polling for the configured widgets, running `WeatherConfig::command` for the
+    /// weather
+    pub fn new(config: WidgetsConfig) -> Self {
+        let fetcher = config
+            .weather
+            .as_ref()
+            .map(|weather| CommandFetcher::new(weather.command.clone()));
+        Self::spawn(
+            config,
+            fetcher.map(|fetcher| Box::new(fetcher) as Box<dyn WeatherFetcher>),
+        )
+    }
+
+    /// Starts polling for the configured widgets, asking `fetcher` for the weather instead of
+    /// running a command
+    pub fn with_weather_fetcher(config: WidgetsConfig, fetcher: Box<dyn WeatherFetcher>) -> Self {
+        Self::spawn(config, Some(fetcher))
+    }
+
+    fn spawn(config: WidgetsConfig, fetcher: Option<Box<dyn WeatherFetcher>>) -> Self {
+        let system = config.system.as_ref().map(|system| {
+            let mut previous = read_cpu_times();
+            spawn_poller(system.interval, move || {
+                let times = read_cpu_times();
+                let cpu = previous
+                    .zip(times)
+                    .map(|(before, after)| cpu_use(before, after));
+                previous = times;
+                Some(SystemStats {
+                    cpu: cpu?,
+                    memory: read_memory_use()?,
+                })
+            })
+        });
+
+        let weather = config
+            .weather
+            .as_ref()
+            .zip(fetcher)
+            .map(|(weather, mut fetcher)| {
+                spawn_poller(weather.interval, move || match fetcher.fetch() {
+                    Ok(report) => Some(report),
+                    Err(e) => {
+                        eprintln!("Failed to fetch the weather: {e}");
+                        None
+                    }
+                })
+            });
+
+        Self {
+            config,
+            system,
+            weather,
+        }
+    }
+
+    pub fn draw(
+        &self,
+        analysis: &FrameAnalysis,
+        safe_area_margins: [f32; 2],
+        typography: &Typography,
+    ) {
+        let area = safe_area(safe_area_margins);
+        let scale = ui_scale();
+        let accent = self.config.accent.map_or(DEFAULT_ACCENT, |accent| accent.0);
+        // Glows with the loudness, and pulses at the start of each beat
+        let pulse = analysis
+            .beat_phase
+            .map_or(0.0, |phase| (1.0 - phase).powi(3));
+        let amount = (0.6 * analysis.normalised_loudness() + 0.4 * pulse).clamp(0.0, 1.0);
+        let colour = Color::new(
+            WHITE.r + (accent.r - WHITE.r) * amount,
+            WHITE.g + (accent.g - WHITE.g) * amount,
+            WHITE.b + (accent.b - WHITE.b) * amount,
+            1.0,
+        );
+
+        let draw_widget = |text: &str, anchor: Anchor, offset: [f32; 2], size: f32| {
+            let size = size * scale;
+            let dimensions = typography.measure(TextRole::Metadata, text, size);
+            // Room below the text for an accent line as long as the music is loud
+            let line = 3.0 * scale;
+            let placement = Placement { anchor, offset };
+            let position =
+                placement.position(area, vec2(dimensions.width, dimensions.height + 2.0 * line));
+            typography.draw(
+                TextRole::Metadata,
+                text,
+                position.x,
+                position.y + dimensions.offset_y,
+                size,
+                colour,
+            );
+            draw_rectangle(
+                position.x,
+                position.y + dimensions.height + line,
+                dimensions.width * amount,
+                line,
+                accent,
+            );
+        };
+
+        if let Some(config) = &self.config.clock {
+            let time = TimeOfDay::now().to_string();
+            draw_widget(&time, config.anchor, config.offset, config.size);
+        }
+
+        if let (Some(config), Some(system)) = (&self.config.system, &self.system)
+            && let Some(stats) = *system.lock().unwrap_or_else(PoisonError::into_inner)
+        {
+            let text = format!(
+                "CPU {:.0}%  RAM {:.0}%",
+                stats.cpu * 100.0,
+                stats.memory * 100.0
+            );
+            draw_widget(&text, config.anchor, config.offset, config.size);
+        }
+
+        if let (Some(config), Some(weather)) = (&self.config.weather, &self.weather)
+            && let Some(report) = weather
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .as_deref()
+        {
+            draw_widget(report, config.anchor, config.offset, config.size);
+        }
+    }
+}
+
+/// Calls `poll` every `interval` seconds on a background thread, keeping its latest result
+/// that wasn't `None`
+fn spawn_poller<T: Send + 'static>(
+    interval: f64,
+    mut poll: impl FnMut() -> Option<T> + Send + 'static,
+) -> Arc<Mutex<Option<T>>> {
+    let current = Arc::new(Mutex::new(None));
+    let shared = current.clone();
+    let interval = Duration::from_secs_f64(interval.max(0.1));
+
+    thread::spawn(move || {
+        loop {
+            if let Some(latest) = poll()
+                && let Ok(mut shared) = shared.lock()
+            {
+                *shared = Some(latest);
+            }
+
+            thread::sleep(interval);
+        }
+    });
+
+    current
+}
+
+// Busy and total time across all CPUs since boot, from the first line of `/proc/stat`
+fn read_cpu_times() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let times = stat
+        .lines()
+        .next()?
+        .strip_prefix("cpu")?
+        .split_whitespace()
+        .map(|time| time.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    let total = times.iter().sum();
+    // Idle and waiting on IO
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some((total - idle, total))
+}
+
+fn cpu_use((busy_before, total_before): (u64, u64), (busy, total): (u64, u64)) -> f32 {
+    let elapsed = total.saturating_sub(total_before);
+    if elapsed == 0 {
+        return 0.0;
+    }
+    busy.saturating_sub(busy_before) as f32 / elapsed as f32
+}
+
+// Fraction of memory not available to start new programs, from `/proc/meminfo`
+fn read_memory_use() -> Option<f32> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(':')?
+                .split_whitespace()
+                .next()?
+                .parse::<f32>()
+                .ok()
+        })
+    };
+
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable")?;
+    Some(1.0 - available / total.max(1.0))
+}